@@ -0,0 +1,63 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use rusqlite::Connection;
+use rusqlite_utils::{bench, Table, TryFromRow};
+use serde::{Deserialize, Serialize};
+
+#[derive(Table, TryFromRow, Debug)]
+struct Event {
+    kind: String,
+    value: i64,
+    note: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Document {
+    title: String,
+    tags: Vec<String>,
+    score: f64,
+    views: u32,
+}
+
+fn setup() -> Connection {
+    let conn = Connection::open_in_memory().expect("failed to open connection");
+    Event::create_table(&conn).expect("failed to create table");
+    conn
+}
+
+fn event(i: usize) -> Event {
+    Event {
+        kind: format!("kind {}", i % 10),
+        value: i as i64,
+        note: i.is_multiple_of(2).then(|| "note".to_string()),
+    }
+}
+
+fn inserts(c: &mut Criterion) {
+    bench::bulk_insert(c, "events", 10_000, setup, event);
+}
+
+fn reads(c: &mut Criterion) {
+    let conn = setup();
+    let rows: Vec<_> = (0..1000).map(event).collect();
+    bench::insert_all(&conn, &rows).expect("failed to insert rows");
+    bench::read_rows(c, "events", &conn, "select * from event", |row| {
+        Ok(Event {
+            kind: row.get(0)?,
+            value: row.get(1)?,
+            note: row.get(2)?,
+        })
+    });
+}
+
+fn encodings(c: &mut Criterion) {
+    let document = Document {
+        title: "A reasonably sized document".into(),
+        tags: vec!["sqlite".into(), "rust".into(), "serde".into()],
+        score: 4.5,
+        views: 1234,
+    };
+    bench::encodings(c, "document", &document);
+}
+
+criterion_group!(benches, inserts, reads, encodings);
+criterion_main!(benches);
@@ -1,4 +1,5 @@
 #![cfg(test)]
+#![allow(dead_code)]
 
 use rusqlite::Connection;
 use rusqlite_utils_macros::TryFromRow;
@@ -28,3 +29,845 @@ fn retrieve_row() {
         db.query_row("select * from foo limit 1", (), |row| row.try_into());
     assert!(res.is_ok(), "Failed to retrieve row: {:?}", res);
 }
+
+#[test]
+fn rename_column() {
+    #[derive(TryFromRow, Debug, PartialEq)]
+    struct Foo {
+        a: i64,
+        #[try_from_row(column = "created_at")]
+        created: String,
+    }
+
+    let db = Connection::open_in_memory().expect("failed to open in-memory db");
+    db.execute("create table foo(a integer, created_at text)", ())
+        .expect("failed to create table");
+    db.execute("insert into foo values (10, '2024-01-01')", ())
+        .expect("failed to insert row");
+
+    let res: rusqlite::Result<Foo> =
+        db.query_row("select * from foo limit 1", (), |row| row.try_into());
+    assert!(res.is_ok(), "Failed to retrieve row: {:?}", res);
+    assert_eq!(res.unwrap().created, "2024-01-01");
+    assert_eq!(
+        <Foo as rusqlite_utils::row::Columns>::COLUMNS,
+        &["a", "created_at"]
+    );
+}
+
+#[test]
+fn skip_field() {
+    #[derive(TryFromRow, Debug, PartialEq)]
+    struct Foo {
+        a: i64,
+        #[try_from_row(skip)]
+        cached: Option<String>,
+        #[try_from_row(skip)]
+        hits: u32,
+    }
+
+    let db = Connection::open_in_memory().expect("failed to open in-memory db");
+    let res: rusqlite::Result<Foo> = db.query_row("select 10 as a", (), |row| row.try_into());
+    assert!(res.is_ok(), "Failed to retrieve row: {:?}", res);
+    assert_eq!(
+        res.unwrap(),
+        Foo {
+            a: 10,
+            cached: None,
+            hits: 0
+        }
+    );
+    assert_eq!(<Foo as rusqlite_utils::row::Columns>::COLUMNS, &["a"]);
+}
+
+#[test]
+fn flatten_nested_structs() {
+    #[derive(TryFromRow, Debug, PartialEq)]
+    struct Address {
+        city: String,
+        #[try_from_row(column = "zip")]
+        postcode: String,
+    }
+    #[derive(TryFromRow, Debug, PartialEq)]
+    struct Customer {
+        name: String,
+        #[try_from_row(flatten, prefix = "addr_")]
+        address: Address,
+    }
+    #[derive(TryFromRow, Debug, PartialEq)]
+    struct Order {
+        id: i64,
+        #[try_from_row(flatten)]
+        customer: Customer,
+    }
+
+    let db = Connection::open_in_memory().expect("failed to open in-memory db");
+    let res: rusqlite::Result<Order> = db.query_row(
+        "select 1 as id, 'ada' as customer_name, 'london' as customer_addr_city,
+            'N1' as customer_addr_zip",
+        (),
+        |row| row.try_into(),
+    );
+    assert!(res.is_ok(), "Failed to retrieve row: {:?}", res);
+    assert_eq!(
+        res.unwrap(),
+        Order {
+            id: 1,
+            customer: Customer {
+                name: "ada".into(),
+                address: Address {
+                    city: "london".into(),
+                    postcode: "N1".into(),
+                },
+            },
+        }
+    );
+}
+
+#[test]
+fn generic_struct() {
+    #[derive(TryFromRow, Debug, PartialEq)]
+    struct Wrapper<T: rusqlite::types::FromSql, U>
+    where
+        U: rusqlite::types::FromSql,
+    {
+        value: T,
+        other: U,
+    }
+
+    let db = Connection::open_in_memory().expect("failed to open in-memory db");
+    let res: rusqlite::Result<Wrapper<i64, String>> =
+        db.query_row("select 1 as value, 'a' as other", (), |row| row.try_into());
+    assert!(res.is_ok(), "Failed to retrieve row: {:?}", res);
+    assert_eq!(
+        res.unwrap(),
+        Wrapper {
+            value: 1,
+            other: "a".to_string()
+        }
+    );
+    assert_eq!(
+        <Wrapper<i64, String> as rusqlite_utils::row::Columns>::COLUMNS,
+        &["value", "other"]
+    );
+}
+
+#[test]
+fn convert_with_function() {
+    fn yes_no(value: rusqlite::types::ValueRef<'_>) -> rusqlite::Result<bool> {
+        match value.as_str()? {
+            "Y" => Ok(true),
+            "N" => Ok(false),
+            other => Err(rusqlite::Error::FromSqlConversionFailure(
+                0,
+                rusqlite::types::Type::Text,
+                format!("expected Y or N, got {}", other).into(),
+            )),
+        }
+    }
+    #[derive(TryFromRow, Debug, PartialEq)]
+    struct Flags {
+        #[try_from_row(with = "yes_no")]
+        active: bool,
+        #[try_from_row(column = "is_admin", with = "yes_no")]
+        admin: bool,
+    }
+
+    let db = Connection::open_in_memory().expect("failed to open in-memory db");
+    let res: rusqlite::Result<Flags> =
+        db.query_row("select 'Y' as active, 'N' as is_admin", (), |row| {
+            row.try_into()
+        });
+    assert!(res.is_ok(), "Failed to retrieve row: {:?}", res);
+    assert_eq!(
+        res.unwrap(),
+        Flags {
+            active: true,
+            admin: false
+        }
+    );
+
+    let res: rusqlite::Result<Flags> =
+        db.query_row("select 'Y' as active, 'maybe' as is_admin", (), |row| {
+            row.try_into()
+        });
+    assert!(res.is_err());
+}
+
+#[test]
+fn default_for_null_or_missing() {
+    #[derive(TryFromRow, Debug, PartialEq)]
+    struct Settings {
+        name: String,
+        #[try_from_row(default)]
+        retries: i64,
+        #[try_from_row(default = "String::from(\"light\")")]
+        theme: String,
+        #[try_from_row(skip, default = "7")]
+        local: i64,
+    }
+
+    let db = Connection::open_in_memory().expect("failed to open in-memory db");
+    let res: rusqlite::Result<Settings> =
+        db.query_row("select 'a' as name, null as retries", (), |row| {
+            row.try_into()
+        });
+    assert!(res.is_ok(), "Failed to retrieve row: {:?}", res);
+    assert_eq!(
+        res.unwrap(),
+        Settings {
+            name: "a".into(),
+            retries: 0,
+            theme: "light".into(),
+            local: 7,
+        }
+    );
+
+    let res: rusqlite::Result<Settings> = db.query_row(
+        "select 'a' as name, 3 as retries, 'dark' as theme",
+        (),
+        |row| row.try_into(),
+    );
+    assert_eq!(res.unwrap().theme, "dark");
+
+    // Fields without a default still fail.
+    let res: rusqlite::Result<Settings> =
+        db.query_row("select 3 as retries", (), |row| row.try_into());
+    assert!(res.is_err());
+}
+
+#[test]
+fn enum_with_tag() {
+    #[derive(TryFromRow, Debug, PartialEq)]
+    #[try_from_row(tag = "kind")]
+    enum Shape {
+        #[try_from_row(rename = "circle")]
+        Circle {
+            radius: f64,
+        },
+        Rectangle {
+            width: f64,
+            #[try_from_row(column = "length")]
+            height: f64,
+        },
+        Point,
+    }
+
+    let db = Connection::open_in_memory().expect("failed to open in-memory db");
+    db.execute_batch(
+        "create table shape(kind text, radius real, width real, length real);
+        insert into shape values ('circle', 1.5, null, null), ('Rectangle', null, 2, 3),
+            ('Point', null, null, null), ('hexagon', null, null, null);",
+    )
+    .expect("failed to set up table");
+
+    let mut stmt = db.prepare("select * from shape").unwrap();
+    let shapes: Vec<rusqlite::Result<Shape>> = stmt
+        .query_map((), |row| Ok(Shape::try_from(row)))
+        .unwrap()
+        .map(|r| r.unwrap())
+        .collect();
+    assert_eq!(shapes[0].as_ref().unwrap(), &Shape::Circle { radius: 1.5 });
+    assert_eq!(
+        shapes[1].as_ref().unwrap(),
+        &Shape::Rectangle {
+            width: 2.0,
+            height: 3.0
+        }
+    );
+    assert_eq!(shapes[2].as_ref().unwrap(), &Shape::Point);
+    assert!(
+        matches!(
+            shapes[3],
+            Err(rusqlite::Error::FromSqlConversionFailure(0, _, _))
+        ),
+        "Read an unknown variant: {:?}",
+        shapes[3]
+    );
+    assert_eq!(
+        <Shape as rusqlite_utils::row::Columns>::COLUMNS,
+        &["kind", "radius", "width", "length"]
+    );
+}
+
+#[test]
+fn bind_named_params() {
+    use rusqlite_utils::{params::ToParams, ToParams};
+
+    #[derive(ToParams, TryFromRow, Debug, PartialEq)]
+    struct User {
+        id: i64,
+        #[to_params(name = "user_name")]
+        name: String,
+        #[to_params(skip)]
+        #[try_from_row(skip)]
+        cached: bool,
+    }
+
+    let db = Connection::open_in_memory().expect("failed to open in-memory db");
+    db.execute("create table user(id integer, name text)", ())
+        .expect("failed to create table");
+    let user = User {
+        id: 1,
+        name: "ada".into(),
+        cached: true,
+    };
+    assert_eq!(User::NAMES, &[":id", ":user_name"]);
+    let res = db.execute(
+        "insert into user(id, name) values (:id, :user_name)",
+        &*user.to_params(),
+    );
+    assert!(res.is_ok(), "Failed to insert row: {:?}", res);
+
+    let res: rusqlite::Result<User> = db.query_row("select * from user", (), |row| row.try_into());
+    assert_eq!(
+        res.unwrap(),
+        User {
+            cached: false,
+            ..user
+        }
+    );
+}
+
+#[test]
+fn insert_row() {
+    use rusqlite_utils::{crud::Insert, Insert};
+
+    #[derive(Insert, TryFromRow, Debug, PartialEq)]
+    #[table = "events"]
+    struct Event {
+        kind: String,
+        #[try_from_row(column = "event_count")]
+        count: i64,
+        #[try_from_row(skip)]
+        cached: bool,
+    }
+
+    let db = Connection::open_in_memory().expect("failed to open in-memory db");
+    db.execute(
+        "create table events(id integer primary key, kind text, event_count integer)",
+        (),
+    )
+    .expect("failed to create table");
+    assert_eq!(
+        Event::insert_sql(),
+        r#"insert into "events"("kind", "event_count") values (?1, ?2)"#
+    );
+    let event = Event {
+        kind: "click".into(),
+        count: 3,
+        cached: true,
+    };
+    for expected in [1, 2] {
+        let res = event.insert(&db);
+        assert!(res.is_ok(), "Failed to insert row: {:?}", res);
+        assert_eq!(res.unwrap(), expected);
+    }
+
+    let res: rusqlite::Result<Event> =
+        db.query_row("select * from events where id = 2", (), |row| {
+            row.try_into()
+        });
+    assert_eq!(
+        res.unwrap(),
+        Event {
+            cached: false,
+            ..event
+        }
+    );
+}
+
+#[test]
+fn update_by_id() {
+    use rusqlite_utils::{
+        crud::{Insert, Update},
+        Insert, Update,
+    };
+
+    #[derive(Insert, Update, TryFromRow, Debug, PartialEq)]
+    struct Note {
+        #[id]
+        id: Option<i64>,
+        body: String,
+        pinned: bool,
+    }
+
+    let db = Connection::open_in_memory().expect("failed to open in-memory db");
+    db.execute(
+        "create table note(id integer primary key, body text, pinned integer)",
+        (),
+    )
+    .expect("failed to create table");
+    assert_eq!(
+        Note::update_sql(),
+        r#"update "note" set "body" = ?1, "pinned" = ?2 where "id" = ?3"#
+    );
+    let mut note = Note {
+        id: None,
+        body: "draft".into(),
+        pinned: false,
+    };
+    note.id = Some(note.insert(&db).expect("failed to insert row"));
+    note.body = "final".into();
+    note.pinned = true;
+    let res = note.update(&db);
+    assert!(res.is_ok(), "Failed to update row: {:?}", res);
+    assert_eq!(res.unwrap(), 1);
+
+    let res: rusqlite::Result<Note> = db.query_row("select * from note", (), |row| row.try_into());
+    assert_eq!(res.unwrap(), note);
+    note.id = Some(10);
+    assert_eq!(note.update(&db).unwrap(), 0);
+}
+
+#[test]
+fn upsert_on_conflict() {
+    use rusqlite_utils::{crud::Upsert, Upsert};
+
+    #[derive(Upsert, TryFromRow, Debug, PartialEq)]
+    #[table = "stock"]
+    #[conflict_target(warehouse, sku)]
+    struct Stock {
+        warehouse: String,
+        sku: String,
+        #[try_from_row(column = "qty")]
+        quantity: i64,
+    }
+
+    let db = Connection::open_in_memory().expect("failed to open in-memory db");
+    db.execute(
+        "create table stock(warehouse text, sku text, qty integer, primary key (warehouse, sku))",
+        (),
+    )
+    .expect("failed to create table");
+    assert_eq!(
+        Stock::upsert_sql(),
+        r#"insert into "stock"("warehouse", "sku", "qty") values (?1, ?2, ?3) on conflict("warehouse", "sku") do update set "qty" = excluded."qty""#
+    );
+    for quantity in [5, 8] {
+        let stock = Stock {
+            warehouse: "north".into(),
+            sku: "A-1".into(),
+            quantity,
+        };
+        let res = stock.upsert(&db);
+        assert!(res.is_ok(), "Failed to upsert row: {:?}", res);
+        assert_eq!(res.unwrap(), 1);
+    }
+
+    let res: rusqlite::Result<Vec<Stock>> = db
+        .prepare("select * from stock")
+        .unwrap()
+        .query_map((), |row| row.try_into())
+        .unwrap()
+        .collect();
+    assert_eq!(
+        res.unwrap(),
+        vec![Stock {
+            warehouse: "north".into(),
+            sku: "A-1".into(),
+            quantity: 8
+        }]
+    );
+}
+
+#[test]
+fn delete_by_id() {
+    use rusqlite_utils::{
+        crud::{Delete, Insert},
+        Delete, Insert, IntegerId,
+    };
+
+    #[derive(Insert, Delete)]
+    struct Comment {
+        #[id]
+        id: Option<IntegerId<Comment>>,
+        body: String,
+    }
+
+    let db = Connection::open_in_memory().expect("failed to open in-memory db");
+    db.execute(
+        "create table comment(id integer primary key, body text)",
+        (),
+    )
+    .expect("failed to create table");
+    Comment {
+        id: None,
+        body: "first".into(),
+    }
+    .insert(&db)
+    .expect("failed to insert row");
+    let id: IntegerId<Comment> = db
+        .query_row("select id from comment", (), |row| row.get(0))
+        .unwrap();
+
+    let res = Comment::delete(&db, &id);
+    assert!(res.is_ok(), "Failed to delete row: {:?}", res);
+    assert!(res.unwrap());
+    assert!(!Comment::delete(&db, &id).unwrap());
+}
+
+#[test]
+fn find_and_list() {
+    use rusqlite_utils::{crud::Queryable, Queryable};
+
+    #[derive(Queryable, TryFromRow, Debug, PartialEq)]
+    #[table = "person"]
+    struct User {
+        #[id]
+        #[try_from_row(column = "user_id")]
+        id: i64,
+        name: String,
+    }
+
+    let db = Connection::open_in_memory().expect("failed to open in-memory db");
+    db.execute_batch(
+        "create table person(user_id integer primary key, name text);
+        insert into person values (2, 'bob'), (1, 'alice'), (3, 'carol');",
+    )
+    .expect("failed to create table");
+
+    let res = User::find(&db, &2);
+    assert!(res.is_ok(), "Failed to find row: {:?}", res);
+    assert_eq!(
+        res.unwrap(),
+        Some(User {
+            id: 2,
+            name: "bob".into()
+        })
+    );
+    assert_eq!(User::find(&db, &4).unwrap(), None);
+
+    let names: Vec<_> = User::list(&db)
+        .unwrap()
+        .into_iter()
+        .map(|u| u.name)
+        .collect();
+    assert_eq!(names, vec!["alice", "bob", "carol"]);
+
+    let res = User::find_where(&db, "name > ?1 order by name desc limit 1", ("alice",));
+    assert!(res.is_ok(), "Failed to find rows: {:?}", res);
+    assert_eq!(res.unwrap()[0].name, "carol");
+}
+
+#[test]
+fn patch_set_fields() {
+    use rusqlite_utils::Patch;
+
+    #[derive(Patch, TryFromRow, Debug, PartialEq)]
+    struct Account {
+        #[id]
+        id: i64,
+        email: String,
+        #[try_from_row(column = "display_name")]
+        name: Option<String>,
+        #[generated]
+        #[allow(dead_code)]
+        updated: i64,
+    }
+
+    let db = Connection::open_in_memory().expect("failed to open in-memory db");
+    db.execute_batch(
+        "create table account(id integer primary key, email text, display_name text, updated integer default 0);
+        insert into account(id, email, display_name) values (1, 'a@example.com', 'A');",
+    )
+    .expect("failed to create table");
+
+    let patch = AccountPatch {
+        name: Some(None),
+        ..Default::default()
+    };
+    assert!(!patch.is_empty());
+    let res = patch.apply(&db, &1);
+    assert!(res.is_ok(), "Failed to apply patch: {:?}", res);
+    assert_eq!(res.unwrap(), 1);
+    // A concurrent change to another column isn't clobbered.
+    db.execute("update account set email = 'b@example.com'", ())
+        .unwrap();
+    AccountPatch {
+        name: Some(Some("B".into())),
+        ..Default::default()
+    }
+    .apply(&db, &1)
+    .unwrap();
+
+    let account: Account = db
+        .query_row("select * from account", (), |row| row.try_into())
+        .unwrap();
+    assert_eq!(account.email, "b@example.com");
+    assert_eq!(account.name.as_deref(), Some("B"));
+    assert!(AccountPatch::default().is_empty());
+    assert_eq!(AccountPatch::default().apply(&db, &1).unwrap(), 0);
+    assert_eq!(patch.apply(&db, &2).unwrap(), 0);
+}
+
+#[test]
+fn read_by_index() {
+    #[derive(TryFromRow, Debug, PartialEq)]
+    #[try_from_row(by_index)]
+    struct Reading {
+        sensor: String,
+        #[try_from_row(skip)]
+        cached: bool,
+        #[try_from_row(column = "val")]
+        value: f64,
+        #[try_from_row(default)]
+        unit: String,
+    }
+
+    let db = Connection::open_in_memory().expect("failed to open in-memory db");
+    db.execute_batch(
+        "create table reading(sensor text, val real);
+        insert into reading values ('t1', 21.5);",
+    )
+    .expect("failed to create table");
+
+    // Columns are read by position, whatever their names.
+    let res: rusqlite::Result<Reading> =
+        db.query_row("select sensor as s, val as v from reading", (), |row| {
+            row.try_into()
+        });
+    assert!(res.is_ok(), "Failed to retrieve row: {:?}", res);
+    assert_eq!(
+        res.unwrap(),
+        Reading {
+            sensor: "t1".into(),
+            cached: false,
+            value: 21.5,
+            unit: String::new()
+        }
+    );
+    assert_eq!(
+        <Reading as rusqlite_utils::row::Columns>::COLUMNS,
+        &["sensor", "val", "unit"]
+    );
+}
+
+#[test]
+fn explicit_nullability() {
+    fn upper(value: rusqlite::types::ValueRef<'_>) -> rusqlite::Result<String> {
+        Ok(value.as_str()?.to_uppercase())
+    }
+
+    #[derive(TryFromRow, Debug, PartialEq)]
+    struct Contact {
+        #[try_from_row(not_null)]
+        name: String,
+        #[try_from_row(nullable, with = "upper")]
+        nickname: Option<String>,
+    }
+
+    let db = Connection::open_in_memory().expect("failed to open in-memory db");
+    db.execute_batch(
+        "create table contact(name text, nickname text);
+        insert into contact values ('ada', 'countess'), ('grace', null), (null, 'x');",
+    )
+    .expect("failed to create table");
+
+    let mut stmt = db
+        .prepare("select name, nickname from contact order by rowid")
+        .unwrap();
+    let contacts: Vec<rusqlite::Result<Contact>> = stmt
+        .query_map((), |row| Ok(row.try_into()))
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(
+        contacts[0].as_ref().unwrap(),
+        &Contact {
+            name: "ada".into(),
+            nickname: Some("COUNTESS".into())
+        }
+    );
+    assert_eq!(contacts[1].as_ref().unwrap().nickname, None);
+
+    let err = contacts[2].as_ref().unwrap_err();
+    assert!(
+        matches!(
+            err,
+            rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Null, _)
+        ),
+        "Unexpected error: {:?}",
+        err
+    );
+    assert!(
+        err.to_string()
+            .contains("column \"name\" is NULL, but field `name` isn't nullable"),
+        "Error doesn't name the column: {}",
+        err
+    );
+}
+
+#[test]
+fn newtype_delegation() {
+    use rusqlite_utils::column_type::SqliteColumnType;
+    use rusqlite_utils_macros::SqlNewtype;
+
+    #[derive(SqlNewtype, Debug, PartialEq)]
+    #[sql_newtype(column = "user_name")]
+    struct UserName(String);
+
+    #[derive(SqlNewtype, Debug, PartialEq)]
+    struct Score<T> {
+        points: T,
+    }
+
+    let db = Connection::open_in_memory().expect("failed to open in-memory db");
+    db.execute_batch("create table user(user_name text, score integer)")
+        .expect("failed to create table");
+    db.execute(
+        "insert into user values (?, ?)",
+        (UserName("ada".into()), Score { points: 42i64 }),
+    )
+    .expect("failed to insert row");
+
+    let res: rusqlite::Result<(UserName, Score<i64>)> =
+        db.query_row("select user_name, score from user", (), |row| {
+            Ok((row.try_into()?, row.get("score")?))
+        });
+    assert!(res.is_ok(), "Failed to retrieve row: {:?}", res);
+    assert_eq!(res.unwrap(), (UserName("ada".into()), Score { points: 42 }));
+    assert_eq!(UserName::DECL_TYPE, Some("text"));
+    assert_eq!(<Score<Option<i64>>>::DECL_TYPE, Some("integer"));
+}
+
+#[test]
+fn errors_name_field() {
+    use rusqlite_utils::row::FieldError;
+
+    #[derive(TryFromRow, Debug)]
+    struct Order {
+        id: i64,
+        #[try_from_row(column = "qty")]
+        quantity: u8,
+    }
+
+    let db = Connection::open_in_memory().expect("failed to open in-memory db");
+    let res: rusqlite::Result<Order> =
+        db.query_row("select 1 as id, 'many' as qty", (), |row| row.try_into());
+    let err = res.expect_err("Read text into an integer");
+    match &err {
+        rusqlite::Error::FromSqlConversionFailure(1, rusqlite::types::Type::Text, e) => {
+            let e = e.downcast_ref::<FieldError>().expect("Not a field error");
+            assert_eq!(
+                (e.ty, e.field, e.column.as_str()),
+                ("Order", "quantity", "qty")
+            );
+        }
+        other => panic!("Unexpected error: {:?}", other),
+    }
+    assert!(
+        err.to_string()
+            .contains("can't read column \"qty\" into `Order::quantity`"),
+        "Error doesn't name the field: {}",
+        err
+    );
+
+    let res: rusqlite::Result<Order> =
+        db.query_row("select 1 as id, 300 as qty", (), |row| row.try_into());
+    assert!(
+        matches!(
+            res,
+            Err(rusqlite::Error::FromSqlConversionFailure(
+                1,
+                rusqlite::types::Type::Integer,
+                _
+            ))
+        ),
+        "Unexpected result: {:?}",
+        res
+    );
+
+    // Missing columns are reported as before.
+    let res: rusqlite::Result<Order> = db.query_row("select 1 as id", (), |row| row.try_into());
+    assert!(
+        matches!(res, Err(rusqlite::Error::InvalidColumnName(ref c)) if c == "qty"),
+        "Unexpected result: {:?}",
+        res
+    );
+}
+
+#[test]
+fn select_list() {
+    use rusqlite_utils::row::Columns;
+
+    #[derive(TryFromRow, Debug, PartialEq)]
+    struct Item {
+        name: String,
+        #[try_from_row(column = "unit price")]
+        price: i64,
+    }
+
+    assert_eq!(Item::select_list(), "\"name\", \"unit price\"");
+    assert_eq!(
+        Item::select_list_from("i"),
+        "\"i\".\"name\", \"i\".\"unit price\""
+    );
+
+    // Columns are selected in field order, whatever the table's order.
+    let db = Connection::open_in_memory().expect("failed to open in-memory db");
+    db.execute_batch(
+        "create table item(\"unit price\" integer, sku text, name text);
+        insert into item values (250, 'x-1', 'widget');",
+    )
+    .expect("failed to create table");
+    let res: rusqlite::Result<Item> = db.query_row(
+        &format!("select {} from item i", Item::select_list_from("i")),
+        (),
+        |row| row.try_into(),
+    );
+    assert!(res.is_ok(), "Failed to retrieve row: {:?}", res);
+    assert_eq!(
+        res.unwrap(),
+        Item {
+            name: "widget".into(),
+            price: 250
+        }
+    );
+}
+
+#[test]
+fn borrowed_fields() {
+    #[derive(TryFromRow, Debug, PartialEq)]
+    struct Line<'a> {
+        id: i64,
+        #[try_from_row(column = "text")]
+        body: &'a str,
+        data: &'a [u8],
+        note: Option<&'a str>,
+    }
+
+    let db = Connection::open_in_memory().expect("failed to open in-memory db");
+    db.execute_batch(
+        "create table line(id integer primary key, text text, data blob, note text);
+        insert into line values (1, 'first', x'0102', null), (2, 'second', x'', 'hi');",
+    )
+    .expect("failed to create table");
+
+    let mut stmt = db.prepare("select * from line order by id").unwrap();
+    let mut rows = stmt.query(()).unwrap();
+    let mut bodies = vec![];
+    while let Some(row) = rows.next().unwrap() {
+        let res = Line::try_from(row);
+        assert!(res.is_ok(), "Failed to read row: {:?}", res);
+        let line = res.unwrap();
+        if line.id == 1 {
+            assert_eq!(line.data, &[1, 2]);
+            assert_eq!(line.note, None);
+        }
+        bodies.push(line.body.to_string());
+    }
+    assert_eq!(bodies, vec!["first", "second"]);
+
+    let res = db.query_row(
+        "select 1 as id, 2 as text, x'' as data, null as note",
+        (),
+        |row| Line::try_from(row).map(|_| ()),
+    );
+    match res {
+        Err(rusqlite::Error::FromSqlConversionFailure(1, rusqlite::types::Type::Integer, e)) => {
+            assert!(e.to_string().contains("`Line::body`"), "{}", e)
+        }
+        other => panic!("Read an integer as text: {:?}", other),
+    }
+}
@@ -0,0 +1,75 @@
+use quote::quote;
+use syn::{Attribute, Data, Ident};
+
+use crate::table::table_name;
+
+pub fn impl_checksummed(
+    ident: Ident,
+    attrs: Vec<Attribute>,
+    data: Data,
+) -> proc_macro2::TokenStream {
+    let table = match table_name(&ident, &attrs) {
+        Ok(table) => table,
+        Err(e) => return e.to_compile_error(),
+    };
+    let fields = match data {
+        Data::Struct(syn::DataStruct {
+            fields: syn::Fields::Named(f),
+            ..
+        }) => f.named,
+        _ => {
+            return syn::Error::new_spanned(
+                &ident,
+                "Checksummed can only be derived for structs with named fields",
+            )
+            .to_compile_error()
+        }
+    };
+
+    let mut checksum = None;
+    let mut marked = vec![];
+    let mut unmarked = vec![];
+    for field in fields {
+        let field_ident = field.ident.expect("fields are named");
+        if let Some(attr) = field.attrs.iter().find(|a| a.path.is_ident("checksum")) {
+            if checksum.is_some() {
+                return syn::Error::new_spanned(attr, "only one field can be marked #[checksum]")
+                    .to_compile_error();
+            }
+            checksum = Some(field_ident);
+        } else if field.attrs.iter().any(|a| a.path.is_ident("signed")) {
+            marked.push(field_ident);
+        } else {
+            unmarked.push(field_ident);
+        }
+    }
+    let checksum = match checksum {
+        Some(checksum) => checksum,
+        None => {
+            return syn::Error::new_spanned(&ident, "expected a field marked #[checksum]")
+                .to_compile_error()
+        }
+    };
+    let checksum_str = checksum.to_string();
+    // Without any #[signed] fields, every other field is covered.
+    let signed = if marked.is_empty() { unmarked } else { marked };
+    let signed_strs = signed.iter().map(|f| f.to_string());
+
+    quote! {
+        impl ::rusqlite_utils::checksum::Checksummed for #ident {
+            const TABLE: &'static str = #table;
+            const CHECKSUM_COLUMN: &'static str = #checksum_str;
+            const SIGNED_COLUMNS: &'static [&'static str] = &[#(#signed_strs),*];
+
+            fn signed_values(&self) -> Vec<&dyn rusqlite::ToSql> {
+                vec![#(&self.#signed as &dyn rusqlite::ToSql),*]
+            }
+            fn stored_checksum(&self) -> Option<&::rusqlite_utils::checksum::Checksum> {
+                ::rusqlite_utils::checksum::ChecksumField::checksum(&self.#checksum)
+            }
+            fn set_checksum(&mut self, checksum: ::rusqlite_utils::checksum::Checksum) {
+                self.#checksum = checksum.into();
+            }
+        }
+    }
+}
@@ -0,0 +1,587 @@
+use quote::{format_ident, quote, ToTokens};
+use syn::{punctuated::Punctuated, Attribute, Data, Generics, Ident, Token, Type, Visibility};
+
+use crate::{
+    table::table_name,
+    util::{Encoding, FieldOptions},
+};
+
+/// A field written to a column, or to several.
+struct WrittenField {
+    ident: Ident,
+    /// The column, or for multi-column fields the prefix of their columns.
+    column: String,
+    ty: Type,
+    /// Whether the field is marked `#[try_from_row(multi_column)]`.
+    multi_column: bool,
+    /// Whether the field is marked `#[id]`, as (part of) the primary key.
+    id: bool,
+    /// Whether the field is marked `#[tenant]`, as the tenant owning the
+    /// row.
+    tenant: bool,
+    /// The encoding from `#[try_from_row(json)]` or `#[try_from_row(bson)]`.
+    encoding: Option<Encoding>,
+    /// Whether the field is marked `#[auto_now]`, as the time the row was
+    /// last written.
+    auto_now: bool,
+    /// Whether the field is marked `#[auto_now_add]`, as the time the row
+    /// was inserted.
+    auto_now_add: bool,
+}
+
+impl WrittenField {
+    /// A statement pushing the field's column names onto `columns`.
+    fn push_columns(&self) -> proc_macro2::TokenStream {
+        let (column, ty) = (&self.column, &self.ty);
+        match self.multi_column {
+            true => quote! {
+                columns.extend(
+                    <#ty as ::rusqlite_utils::multi_column::MultiColumn>::COLUMNS
+                        .iter()
+                        .map(|c| format!("{}{}", #column, c)),
+                );
+            },
+            false => quote! { columns.push(#column.to_string()); },
+        }
+    }
+
+    /// A statement pushing the field's parameters onto `params`.
+    fn push_params(&self) -> proc_macro2::TokenStream {
+        let ident = &self.ident;
+        self.push_params_of(quote! { self.#ident })
+    }
+
+    /// A statement pushing the parameters of `value`, a place of the
+    /// field's type, onto `params`.
+    fn push_params_of(&self, value: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+        let ty = &self.ty;
+        match (self.multi_column, self.encoding) {
+            (true, _) => quote! {
+                params.extend(
+                    <#ty as ::rusqlite_utils::multi_column::MultiColumn>::to_columns(&#value),
+                );
+            },
+            (false, Some(encoding)) => {
+                let param = encoding.param(value, ty);
+                quote! { params.push(#param); }
+            }
+            (false, None) => quote! { params.push(&#value as &dyn rusqlite::ToSql); },
+        }
+    }
+}
+
+/// The `with_*_params` method calling `f` with the parameters of `fields`,
+/// those for which `stamped` holds being set from the clock; or nothing if
+/// there are none, leaving the default.
+fn with_stamped_params(
+    method: Ident,
+    fields: &[&WrittenField],
+    stamped: impl Fn(&WrittenField) -> bool,
+) -> proc_macro2::TokenStream {
+    if !fields.iter().any(|f| stamped(f)) {
+        return quote! {};
+    }
+    let stamps = fields.iter().filter(|f| stamped(f)).map(|f| {
+        let (local, ty) = (format_ident!("stamped_{}", f.ident), &f.ty);
+        quote! {
+            let #local: #ty = ::rusqlite_utils::date_time::clock::AutoNow::from_now(now);
+        }
+    });
+    let params = fields.iter().map(|f| match stamped(f) {
+        true => f.push_params_of(format_ident!("stamped_{}", f.ident).into_token_stream()),
+        false => f.push_params(),
+    });
+    quote! {
+        fn #method<R>(
+            &self,
+            clock: &dyn ::rusqlite_utils::date_time::Clock,
+            f: impl FnOnce(&[&dyn rusqlite::ToSql]) -> R,
+        ) -> R {
+            let now = ::rusqlite_utils::date_time::Clock::now(clock);
+            #(#stamps)*
+            let mut params: Vec<&dyn rusqlite::ToSql> = vec![];
+            #(#params)*
+            f(&params)
+        }
+    }
+}
+
+/// The fields written by the statements of the CRUD derives: all but those
+/// marked `#[generated]`, `#[try_from_row(skip)]` or `#[try_from_row(lazy)]`,
+/// in the columns they're read from by `TryFromRow`.
+fn written_fields(derive: &str, ident: &Ident, data: Data) -> syn::Result<Vec<WrittenField>> {
+    let unsupported = || {
+        format!(
+            "{} can only be derived for structs with named fields",
+            derive
+        )
+    };
+    let fields = match data {
+        Data::Struct(s) => match s.fields {
+            syn::Fields::Named(f) => f.named,
+            other => return Err(syn::Error::new_spanned(other, unsupported())),
+        },
+        _ => return Err(syn::Error::new_spanned(ident, unsupported())),
+    };
+
+    let mut written = vec![];
+    for field in fields {
+        let options = FieldOptions::parse(&field.attrs)?;
+        if options.flatten {
+            return Err(syn::Error::new_spanned(
+                &field,
+                format!("{} doesn't support flattened fields", derive),
+            ));
+        }
+        let generated = field.attrs.iter().any(|a| a.path.is_ident("generated"));
+        if options.skip || options.lazy.is_some() || generated {
+            continue;
+        }
+        let ident = field.ident.expect("fields are named");
+        let column = if options.multi_column {
+            options.prefix.unwrap_or_else(|| format!("{}_", ident))
+        } else {
+            options.column.unwrap_or_else(|| ident.to_string())
+        };
+        written.push(WrittenField {
+            column,
+            ty: field.ty,
+            multi_column: options.multi_column,
+            id: field.attrs.iter().any(|a| a.path.is_ident("id")),
+            tenant: field.attrs.iter().any(|a| a.path.is_ident("tenant")),
+            encoding: options.encoding,
+            auto_now: field.attrs.iter().any(|a| a.path.is_ident("auto_now")),
+            auto_now_add: field.attrs.iter().any(|a| a.path.is_ident("auto_now_add")),
+            ident,
+        });
+    }
+    Ok(written)
+}
+
+pub fn impl_insert(
+    ident: Ident,
+    attrs: Vec<Attribute>,
+    generics: Generics,
+    data: Data,
+) -> proc_macro2::TokenStream {
+    let table = match table_name(&ident, &attrs) {
+        Ok(table) => table,
+        Err(e) => return e.to_compile_error(),
+    };
+    let fields = match written_fields("Insert", &ident, data) {
+        Ok(fields) => fields,
+        Err(e) => return e.to_compile_error(),
+    };
+    let columns = fields.iter().map(WrittenField::push_columns);
+    let params = fields.iter().map(WrittenField::push_params);
+    let with_params = with_stamped_params(
+        format_ident!("with_insert_params"),
+        &fields.iter().collect::<Vec<_>>(),
+        |f| f.auto_now || f.auto_now_add,
+    );
+
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    quote! {
+        impl #impl_generics ::rusqlite_utils::crud::Insert for #ident #ty_generics #where_clause {
+            fn insert_sql() -> String {
+                let mut columns: Vec<String> = vec![];
+                #(#columns)*
+                ::rusqlite_utils::crud::insert_sql(#table, &columns)
+            }
+
+            fn insert_params(&self) -> Vec<&dyn rusqlite::ToSql> {
+                let mut params: Vec<&dyn rusqlite::ToSql> = vec![];
+                #(#params)*
+                params
+            }
+
+            #with_params
+        }
+    }
+}
+
+pub fn impl_update(
+    ident: Ident,
+    attrs: Vec<Attribute>,
+    generics: Generics,
+    data: Data,
+) -> proc_macro2::TokenStream {
+    let table = match table_name(&ident, &attrs) {
+        Ok(table) => table,
+        Err(e) => return e.to_compile_error(),
+    };
+    let fields = match written_fields("Update", &ident, data) {
+        Ok(fields) => fields,
+        Err(e) => return e.to_compile_error(),
+    };
+    // Columns marked `#[auto_now_add]` keep the time the row was inserted.
+    let (ids, others): (Vec<_>, Vec<_>) = fields
+        .iter()
+        .filter(|f| f.id || !f.auto_now_add)
+        .partition(|f| f.id);
+    if ids.is_empty() {
+        return syn::Error::new_spanned(&ident, "Update needs the primary key marked #[id]")
+            .to_compile_error();
+    }
+    if others.is_empty() {
+        return syn::Error::new_spanned(&ident, "Update needs a field besides the #[id]")
+            .to_compile_error();
+    }
+    let set_columns = others.iter().map(|f| f.push_columns());
+    let key_columns = ids.iter().map(|f| f.push_columns());
+    // The updated columns are bound first, then the key.
+    let params = others.iter().chain(&ids).map(|f| f.push_params());
+    let with_params = with_stamped_params(
+        format_ident!("with_update_params"),
+        &others.iter().chain(&ids).copied().collect::<Vec<_>>(),
+        |f| f.auto_now && !f.id,
+    );
+
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    quote! {
+        impl #impl_generics ::rusqlite_utils::crud::Update for #ident #ty_generics #where_clause {
+            fn update_sql() -> String {
+                let mut columns: Vec<String> = vec![];
+                #(#set_columns)*
+                let set = std::mem::take(&mut columns);
+                #(#key_columns)*
+                ::rusqlite_utils::crud::update_sql(#table, &set, &columns)
+            }
+
+            fn update_params(&self) -> Vec<&dyn rusqlite::ToSql> {
+                let mut params: Vec<&dyn rusqlite::ToSql> = vec![];
+                #(#params)*
+                params
+            }
+
+            #with_params
+        }
+    }
+}
+
+/// The fields named by `#[conflict_target(...)]`, or else those marked
+/// `#[id]`.
+fn conflict_target<'f>(
+    ident: &Ident,
+    attrs: &[Attribute],
+    fields: &'f [WrittenField],
+) -> syn::Result<Vec<&'f WrittenField>> {
+    let attr = match attrs.iter().find(|a| a.path.is_ident("conflict_target")) {
+        Some(attr) => attr,
+        None => {
+            let ids: Vec<_> = fields.iter().filter(|f| f.id).collect();
+            if ids.is_empty() {
+                return Err(syn::Error::new_spanned(
+                    ident,
+                    "Upsert needs #[conflict_target(...)] or fields marked #[id]",
+                ));
+            }
+            return Ok(ids);
+        }
+    };
+    let names = attr.parse_args_with(Punctuated::<Ident, Token![,]>::parse_terminated)?;
+    if names.is_empty() {
+        return Err(syn::Error::new_spanned(
+            attr,
+            "expected the fields of a unique key",
+        ));
+    }
+    names
+        .iter()
+        .map(|name| {
+            fields
+                .iter()
+                .find(|f| f.ident == *name)
+                .ok_or_else(|| syn::Error::new_spanned(name, "not a written field"))
+        })
+        .collect()
+}
+
+pub fn impl_upsert(
+    ident: Ident,
+    attrs: Vec<Attribute>,
+    generics: Generics,
+    data: Data,
+) -> proc_macro2::TokenStream {
+    let table = match table_name(&ident, &attrs) {
+        Ok(table) => table,
+        Err(e) => return e.to_compile_error(),
+    };
+    let fields = match written_fields("Upsert", &ident, data) {
+        Ok(fields) => fields,
+        Err(e) => return e.to_compile_error(),
+    };
+    let target = match conflict_target(&ident, &attrs, &fields) {
+        Ok(target) => target,
+        Err(e) => return e.to_compile_error(),
+    };
+    let target_columns = target.iter().map(|f| f.push_columns());
+    let target_params = target.iter().map(|f| f.push_params());
+    let columns = fields.iter().map(WrittenField::push_columns);
+    let kept_columns = fields
+        .iter()
+        .filter(|f| f.auto_now_add)
+        .map(WrittenField::push_columns);
+    let params = fields.iter().map(WrittenField::push_params);
+    let with_params = with_stamped_params(
+        format_ident!("with_upsert_params"),
+        &fields.iter().collect::<Vec<_>>(),
+        |f| f.auto_now || f.auto_now_add,
+    );
+
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    quote! {
+        impl #impl_generics ::rusqlite_utils::crud::Upsert for #ident #ty_generics #where_clause {
+            const TABLE: &'static str = #table;
+
+            fn upsert_sql() -> String {
+                let mut columns: Vec<String> = vec![];
+                #(#kept_columns)*
+                let kept = std::mem::take(&mut columns);
+                #(#columns)*
+                ::rusqlite_utils::crud::upsert_sql_keeping(
+                    Self::TABLE,
+                    &columns,
+                    &Self::conflict_target(),
+                    &kept,
+                )
+            }
+
+            fn upsert_params(&self) -> Vec<&dyn rusqlite::ToSql> {
+                let mut params: Vec<&dyn rusqlite::ToSql> = vec![];
+                #(#params)*
+                params
+            }
+
+            #with_params
+
+            fn conflict_target() -> Vec<String> {
+                let mut columns: Vec<String> = vec![];
+                #(#target_columns)*
+                columns
+            }
+
+            fn conflict_params(&self) -> Vec<&dyn rusqlite::ToSql> {
+                let mut params: Vec<&dyn rusqlite::ToSql> = vec![];
+                #(#target_params)*
+                params
+            }
+        }
+    }
+}
+
+/// `T` if `ty` is `Option<T>`.
+pub(crate) fn option_inner(ty: &Type) -> Option<&Type> {
+    let segment = match ty {
+        Type::Path(path) if path.qself.is_none() => path.path.segments.last()?,
+        _ => return None,
+    };
+    match &segment.arguments {
+        syn::PathArguments::AngleBracketed(args) if segment.ident == "Option" => {
+            match args.args.first()? {
+                syn::GenericArgument::Type(ty) if args.args.len() == 1 => Some(ty),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// The type and column of the single-column primary key marked `#[id]`,
+/// for the derives looking rows up by key.
+fn primary_key(derive: &str, ident: &Ident, data: Data) -> syn::Result<(Type, String)> {
+    let fields = written_fields(derive, ident, data)?;
+    match fields.into_iter().filter(|f| f.id).collect::<Vec<_>>()[..] {
+        [ref id] if !id.multi_column => {
+            // An optional id is only unset before the row is inserted.
+            let ty = option_inner(&id.ty).unwrap_or(&id.ty).clone();
+            Ok((ty, id.column.clone()))
+        }
+        _ => Err(syn::Error::new_spanned(
+            ident,
+            format!("{} needs a single-column primary key, marked #[id]", derive),
+        )),
+    }
+}
+
+pub fn impl_delete(
+    ident: Ident,
+    attrs: Vec<Attribute>,
+    generics: Generics,
+    data: Data,
+) -> proc_macro2::TokenStream {
+    let table = match table_name(&ident, &attrs) {
+        Ok(table) => table,
+        Err(e) => return e.to_compile_error(),
+    };
+    let (id_ty, id_column) = match primary_key("Delete", &ident, data) {
+        Ok(key) => key,
+        Err(e) => return e.to_compile_error(),
+    };
+
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    quote! {
+        impl #impl_generics ::rusqlite_utils::crud::Delete for #ident #ty_generics #where_clause {
+            type Id = #id_ty;
+            const TABLE: &'static str = #table;
+            const ID_COLUMN: &'static str = #id_column;
+
+            fn delete_sql() -> String {
+                ::rusqlite_utils::crud::delete_sql(#table, #id_column)
+            }
+        }
+    }
+}
+
+pub fn impl_queryable(
+    ident: Ident,
+    attrs: Vec<Attribute>,
+    generics: Generics,
+    data: Data,
+) -> proc_macro2::TokenStream {
+    let table = match table_name(&ident, &attrs) {
+        Ok(table) => table,
+        Err(e) => return e.to_compile_error(),
+    };
+    let (id_ty, id_column) = match primary_key("Queryable", &ident, data) {
+        Ok(key) => key,
+        Err(e) => return e.to_compile_error(),
+    };
+
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    quote! {
+        impl #impl_generics ::rusqlite_utils::crud::Queryable for #ident #ty_generics #where_clause {
+            type Id = #id_ty;
+            const TABLE: &'static str = #table;
+            const ID_COLUMN: &'static str = #id_column;
+        }
+    }
+}
+
+pub fn impl_patch(
+    ident: Ident,
+    vis: Visibility,
+    attrs: Vec<Attribute>,
+    generics: Generics,
+    data: Data,
+) -> proc_macro2::TokenStream {
+    let table = match table_name(&ident, &attrs) {
+        Ok(table) => table,
+        Err(e) => return e.to_compile_error(),
+    };
+    let fields = match written_fields("Patch", &ident, data) {
+        Ok(fields) => fields,
+        Err(e) => return e.to_compile_error(),
+    };
+    let (ids, others): (Vec<_>, Vec<_>) = fields.into_iter().partition(|f| f.id);
+    let id = match &ids[..] {
+        [id] if !id.multi_column => id,
+        _ => {
+            return syn::Error::new_spanned(
+                &ident,
+                "Patch needs a single-column primary key, marked #[id]",
+            )
+            .to_compile_error()
+        }
+    };
+    let id_ty = option_inner(&id.ty).unwrap_or(&id.ty);
+    let id_column = &id.column;
+
+    let patch = format_ident!("{}Patch", ident);
+    let doc = format!(
+        "Changes to some of the fields of a [`{}`], for `UPDATE`s writing only those.",
+        ident
+    );
+    let names: Vec<_> = others.iter().map(|f| &f.ident).collect();
+    let types = others.iter().map(|f| &f.ty);
+    let set = others.iter().map(|f| {
+        let name = &f.ident;
+        let columns = f.push_columns();
+        let params = f.push_params_of(quote! { (*value) });
+        quote! {
+            if let Some(value) = &self.#name {
+                #columns
+                #params
+            }
+        }
+    });
+
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    quote! {
+        #[doc = #doc]
+        #[derive(Default)]
+        #vis struct #patch #impl_generics #where_clause {
+            #(#vis #names: Option<#types>,)*
+        }
+
+        impl #impl_generics #patch #ty_generics #where_clause {
+            /// Whether no field is set.
+            pub fn is_empty(&self) -> bool {
+                true #(&& self.#names.is_none())*
+            }
+
+            /// Set the fields which are `Some` in the row with key `id`,
+            /// leaving its other columns as they are. Returns the number of
+            /// rows changed, which is 0 if there's no such row or no field
+            /// is set.
+            pub fn apply(
+                &self,
+                conn: &rusqlite::Connection,
+                id: &#id_ty,
+            ) -> rusqlite::Result<usize> {
+                let mut columns: Vec<String> = vec![];
+                let mut params: Vec<&dyn rusqlite::ToSql> = vec![];
+                #(#set)*
+                if columns.is_empty() {
+                    return Ok(0);
+                }
+                params.push(id);
+                let sql = ::rusqlite_utils::crud::update_sql(
+                    #table,
+                    &columns,
+                    &[#id_column.to_string()],
+                );
+                conn.prepare_cached(&sql)?.execute(&*params)
+            }
+        }
+    }
+}
+
+pub fn impl_tenant_scoped(
+    ident: Ident,
+    attrs: Vec<Attribute>,
+    generics: Generics,
+    data: Data,
+) -> proc_macro2::TokenStream {
+    let table = match table_name(&ident, &attrs) {
+        Ok(table) => table,
+        Err(e) => return e.to_compile_error(),
+    };
+    let fields = match written_fields("TenantScoped", &ident, data) {
+        Ok(fields) => fields,
+        Err(e) => return e.to_compile_error(),
+    };
+    let tenant = match fields.iter().filter(|f| f.tenant).collect::<Vec<_>>()[..] {
+        [tenant] if !tenant.multi_column => tenant,
+        _ => {
+            return syn::Error::new_spanned(
+                &ident,
+                "TenantScoped needs a single field marked #[tenant]",
+            )
+            .to_compile_error()
+        }
+    };
+    let (tenant_ident, tenant_column) = (&tenant.ident, &tenant.column);
+
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    quote! {
+        impl #impl_generics ::rusqlite_utils::scoped::TenantScoped for #ident #ty_generics #where_clause {
+            const TABLE: &'static str = #table;
+            const TENANT_COLUMN: &'static str = #tenant_column;
+
+            fn tenant(&self) -> ::rusqlite_utils::scoped::TenantId {
+                self.#tenant_ident
+            }
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use quote::quote;
+use syn::{Attribute, Data, Ident, Lit, LitStr, Meta, NestedMeta, Variant};
+
+/// The variants of a fieldless enum.
+fn unit_variants(derive: &str, ident: &Ident, data: Data) -> syn::Result<Vec<Variant>> {
+    let unsupported = || format!("{} can only be derived for fieldless enums", derive);
+    let variants = match data {
+        Data::Enum(e) => e.variants,
+        _ => return Err(syn::Error::new_spanned(ident, unsupported())),
+    };
+    for variant in &variants {
+        if !matches!(variant.fields, syn::Fields::Unit) {
+            return Err(syn::Error::new_spanned(&variant.fields, unsupported()));
+        }
+    }
+    Ok(variants.into_iter().collect())
+}
+
+/// `#[enum_as_text(rename_all = "...")]`: how variant names are written,
+/// with the case conventions supported by serde.
+#[derive(Copy, Clone)]
+enum RenameRule {
+    Lower,
+    Upper,
+    Pascal,
+    Camel,
+    Snake,
+    ScreamingSnake,
+    Kebab,
+    ScreamingKebab,
+}
+
+impl RenameRule {
+    fn parse(lit: &LitStr) -> syn::Result<Self> {
+        Ok(match lit.value().as_str() {
+            "lowercase" => Self::Lower,
+            "UPPERCASE" => Self::Upper,
+            "PascalCase" => Self::Pascal,
+            "camelCase" => Self::Camel,
+            "snake_case" => Self::Snake,
+            "SCREAMING_SNAKE_CASE" => Self::ScreamingSnake,
+            "kebab-case" => Self::Kebab,
+            "SCREAMING-KEBAB-CASE" => Self::ScreamingKebab,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    lit,
+                    "expected lowercase, UPPERCASE, PascalCase, camelCase, snake_case, \
+                    SCREAMING_SNAKE_CASE, kebab-case or SCREAMING-KEBAB-CASE",
+                ))
+            }
+        })
+    }
+
+    /// `variant`, a Pascal case name, following the rule.
+    fn apply(self, variant: &str) -> String {
+        let mut words: Vec<String> = vec![];
+        for c in variant.chars() {
+            match words.last_mut() {
+                Some(word) if !c.is_uppercase() => word.push(c),
+                _ => words.push(c.to_string()),
+            }
+        }
+        let joined = |separator: &str, upper: bool| {
+            let words: Vec<_> = words
+                .iter()
+                .map(|w| match upper {
+                    true => w.to_uppercase(),
+                    false => w.to_lowercase(),
+                })
+                .collect();
+            words.join(separator)
+        };
+        match self {
+            Self::Lower => variant.to_lowercase(),
+            Self::Upper => variant.to_uppercase(),
+            Self::Pascal => variant.to_string(),
+            Self::Camel => {
+                let mut chars = variant.chars();
+                chars
+                    .next()
+                    .map(|c| c.to_lowercase().chain(chars).collect())
+                    .unwrap_or_default()
+            }
+            Self::Snake => joined("_", false),
+            Self::ScreamingSnake => joined("_", true),
+            Self::Kebab => joined("-", false),
+            Self::ScreamingKebab => joined("-", true),
+        }
+    }
+}
+
+/// The `name = "value"` options of `#[enum_as_text(...)]` attributes.
+fn text_options(attrs: &[Attribute]) -> syn::Result<Vec<(Ident, LitStr)>> {
+    let mut options = vec![];
+    for attr in attrs.iter().filter(|a| a.path.is_ident("enum_as_text")) {
+        let list = match attr.parse_meta()? {
+            Meta::List(list) => list,
+            meta => {
+                return Err(syn::Error::new_spanned(
+                    meta,
+                    "expected #[enum_as_text(...)]",
+                ))
+            }
+        };
+        for nested in list.nested {
+            match nested {
+                NestedMeta::Meta(Meta::NameValue(syn::MetaNameValue {
+                    path,
+                    lit: Lit::Str(s),
+                    ..
+                })) if path.get_ident().is_some() => {
+                    options.push((path.get_ident().unwrap().clone(), s))
+                }
+                other => {
+                    return Err(syn::Error::new_spanned(
+                        other,
+                        "unknown enum_as_text option",
+                    ))
+                }
+            }
+        }
+    }
+    Ok(options)
+}
+
+pub fn impl_enum_as_integer(ident: Ident, data: Data) -> proc_macro2::TokenStream {
+    let variants = match unit_variants("EnumAsInteger", &ident, data) {
+        Ok(variants) => variants,
+        Err(e) => return e.to_compile_error(),
+    };
+    let variants: Vec<_> = variants.into_iter().map(|v| v.ident).collect();
+    let name = ident.to_string();
+
+    quote! {
+        impl rusqlite::ToSql for #ident {
+            fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+                let n = match self {
+                    #(Self::#variants => Self::#variants as i64,)*
+                };
+                Ok(rusqlite::types::ToSqlOutput::from(n))
+            }
+        }
+        impl rusqlite::types::FromSql for #ident {
+            fn column_result(
+                value: rusqlite::types::ValueRef<'_>,
+            ) -> rusqlite::types::FromSqlResult<Self> {
+                let n = value.as_i64()?;
+                #(
+                    if n == Self::#variants as i64 {
+                        return Ok(Self::#variants);
+                    }
+                )*
+                Err(::rusqlite_utils::enums::unknown_variant(#name, n.to_string()))
+            }
+        }
+        impl ::rusqlite_utils::column_type::SqliteColumnType for #ident {
+            const DECL_TYPE: Option<&'static str> = Some("integer");
+            const STORAGE: Option<::rusqlite_utils::column_type::StorageClass> =
+                Some(::rusqlite_utils::column_type::StorageClass::Integer);
+        }
+    }
+}
+
+pub fn impl_enum_as_text(
+    ident: Ident,
+    attrs: Vec<Attribute>,
+    data: Data,
+) -> proc_macro2::TokenStream {
+    match enum_as_text(ident, attrs, data) {
+        Ok(tokens) => tokens,
+        Err(e) => e.to_compile_error(),
+    }
+}
+
+fn enum_as_text(
+    ident: Ident,
+    attrs: Vec<Attribute>,
+    data: Data,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let mut rule = None;
+    for (option, value) in text_options(&attrs)? {
+        if option != "rename_all" {
+            return Err(syn::Error::new_spanned(option, "expected rename_all"));
+        }
+        rule = Some(RenameRule::parse(&value)?);
+    }
+
+    let mut variants = vec![];
+    let mut texts = vec![];
+    for variant in unit_variants("EnumAsText", &ident, data)? {
+        let mut text = None;
+        for (option, value) in text_options(&variant.attrs)? {
+            if option != "rename" {
+                return Err(syn::Error::new_spanned(option, "expected rename"));
+            }
+            text = Some(value.value());
+        }
+        let name = variant.ident.to_string();
+        let text = text.unwrap_or_else(|| match rule {
+            Some(rule) => rule.apply(&name),
+            None => name,
+        });
+        if texts.contains(&text) {
+            return Err(syn::Error::new_spanned(
+                variant,
+                format!("another variant is also stored as {:?}", text),
+            ));
+        }
+        variants.push(variant.ident);
+        texts.push(text);
+    }
+    let name = ident.to_string();
+
+    Ok(quote! {
+        impl ::std::fmt::Display for #ident {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+                f.write_str(match self {
+                    #(Self::#variants => #texts,)*
+                })
+            }
+        }
+        impl ::std::str::FromStr for #ident {
+            type Err = ::rusqlite_utils::enums::UnknownVariant;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                match s {
+                    #(#texts => Ok(Self::#variants),)*
+                    _ => Err(::rusqlite_utils::enums::UnknownVariant {
+                        ty: #name,
+                        value: s.to_string(),
+                    }),
+                }
+            }
+        }
+        impl rusqlite::ToSql for #ident {
+            fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+                let text: &'static str = match self {
+                    #(Self::#variants => #texts,)*
+                };
+                Ok(rusqlite::types::ToSqlOutput::from(text))
+            }
+        }
+        impl rusqlite::types::FromSql for #ident {
+            fn column_result(
+                value: rusqlite::types::ValueRef<'_>,
+            ) -> rusqlite::types::FromSqlResult<Self> {
+                value
+                    .as_str()?
+                    .parse()
+                    .map_err(|e| rusqlite::types::FromSqlError::Other(Box::new(e)))
+            }
+        }
+        impl ::rusqlite_utils::column_type::SqliteColumnType for #ident {
+            const DECL_TYPE: Option<&'static str> = Some("text");
+            const STORAGE: Option<::rusqlite_utils::column_type::StorageClass> =
+                Some(::rusqlite_utils::column_type::StorageClass::Text);
+        }
+    })
+}
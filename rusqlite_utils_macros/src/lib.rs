@@ -1,13 +1,257 @@
+//! Derive macros for `rusqlite_utils`, which re-exports them; depend on it
+//! rather than on this crate.
+//!
+//! The generated code refers to items through `::rusqlite_utils::...` and
+//! `rusqlite::...` paths, so both crates must be dependencies under those
+//! names. Renaming either in `Cargo.toml` (eg `db = { package =
+//! "rusqlite_utils", ... }`) breaks the derives. Within `rusqlite_utils`
+//! itself, `extern crate self as rusqlite_utils` resolves the same paths.
+
 use proc_macro::TokenStream;
 use syn::{parse_macro_input, DeriveInput};
 
+mod checksum;
+mod crud;
+mod enums;
+mod newtype;
+mod params;
+mod relations;
+mod table;
 mod util;
+use checksum::impl_checksummed;
+use crud::{
+    impl_delete, impl_insert, impl_patch, impl_queryable, impl_tenant_scoped, impl_update,
+    impl_upsert,
+};
+use enums::{impl_enum_as_integer, impl_enum_as_text};
+use newtype::impl_sql_newtype;
+use params::impl_to_params;
+use relations::impl_relations;
+use table::impl_table;
 use util::impl_try_from_row;
 
-#[proc_macro_derive(TryFromRow)]
+#[proc_macro_derive(TryFromRow, attributes(generated, try_from_row))]
 pub fn try_from_row(input: TokenStream) -> TokenStream {
+    let DeriveInput {
+        ident,
+        attrs,
+        generics,
+        data,
+        ..
+    } = parse_macro_input!(input);
+    let impl_block = impl_try_from_row(ident, attrs, generics, data);
+
+    impl_block.into()
+}
+
+#[proc_macro_derive(
+    Table,
+    attributes(
+        table,
+        strict,
+        primary_key,
+        not_null,
+        unique,
+        generated,
+        auto_now,
+        auto_now_add,
+        pii,
+        projection
+    )
+)]
+pub fn table(input: TokenStream) -> TokenStream {
+    let DeriveInput {
+        ident,
+        vis,
+        attrs,
+        data,
+        ..
+    } = parse_macro_input!(input);
+    let impl_block = impl_table(ident, vis, attrs, data);
+
+    impl_block.into()
+}
+
+#[proc_macro_derive(Checksummed, attributes(table, checksum, signed))]
+pub fn checksummed(input: TokenStream) -> TokenStream {
+    let DeriveInput {
+        ident, attrs, data, ..
+    } = parse_macro_input!(input);
+    let impl_block = impl_checksummed(ident, attrs, data);
+
+    impl_block.into()
+}
+
+#[proc_macro_derive(ToParams, attributes(to_params))]
+pub fn to_params(input: TokenStream) -> TokenStream {
+    let DeriveInput {
+        ident,
+        generics,
+        data,
+        ..
+    } = parse_macro_input!(input);
+    let impl_block = impl_to_params(ident, generics, data);
+
+    impl_block.into()
+}
+
+#[proc_macro_derive(
+    Insert,
+    attributes(table, id, generated, auto_now, auto_now_add, try_from_row)
+)]
+pub fn insert(input: TokenStream) -> TokenStream {
+    let DeriveInput {
+        ident,
+        attrs,
+        generics,
+        data,
+        ..
+    } = parse_macro_input!(input);
+    let impl_block = impl_insert(ident, attrs, generics, data);
+
+    impl_block.into()
+}
+
+#[proc_macro_derive(
+    Update,
+    attributes(table, id, generated, auto_now, auto_now_add, try_from_row)
+)]
+pub fn update(input: TokenStream) -> TokenStream {
+    let DeriveInput {
+        ident,
+        attrs,
+        generics,
+        data,
+        ..
+    } = parse_macro_input!(input);
+    let impl_block = impl_update(ident, attrs, generics, data);
+
+    impl_block.into()
+}
+
+#[proc_macro_derive(
+    Upsert,
+    attributes(
+        table,
+        conflict_target,
+        id,
+        generated,
+        auto_now,
+        auto_now_add,
+        try_from_row
+    )
+)]
+pub fn upsert(input: TokenStream) -> TokenStream {
+    let DeriveInput {
+        ident,
+        attrs,
+        generics,
+        data,
+        ..
+    } = parse_macro_input!(input);
+    let impl_block = impl_upsert(ident, attrs, generics, data);
+
+    impl_block.into()
+}
+
+#[proc_macro_derive(Delete, attributes(table, id, generated, try_from_row))]
+pub fn delete(input: TokenStream) -> TokenStream {
+    let DeriveInput {
+        ident,
+        attrs,
+        generics,
+        data,
+        ..
+    } = parse_macro_input!(input);
+    let impl_block = impl_delete(ident, attrs, generics, data);
+
+    impl_block.into()
+}
+
+#[proc_macro_derive(Queryable, attributes(table, id, generated, try_from_row))]
+pub fn queryable(input: TokenStream) -> TokenStream {
+    let DeriveInput {
+        ident,
+        attrs,
+        generics,
+        data,
+        ..
+    } = parse_macro_input!(input);
+    let impl_block = impl_queryable(ident, attrs, generics, data);
+
+    impl_block.into()
+}
+
+#[proc_macro_derive(Patch, attributes(table, id, generated, try_from_row))]
+pub fn patch(input: TokenStream) -> TokenStream {
+    let DeriveInput {
+        ident,
+        vis,
+        attrs,
+        generics,
+        data,
+    } = parse_macro_input!(input);
+    let impl_block = impl_patch(ident, vis, attrs, generics, data);
+
+    impl_block.into()
+}
+
+#[proc_macro_derive(EnumAsInteger)]
+pub fn enum_as_integer(input: TokenStream) -> TokenStream {
     let DeriveInput { ident, data, .. } = parse_macro_input!(input);
-    let impl_block = impl_try_from_row(ident, data);
+    let impl_block = impl_enum_as_integer(ident, data);
+
+    impl_block.into()
+}
+
+#[proc_macro_derive(EnumAsText, attributes(enum_as_text))]
+pub fn enum_as_text(input: TokenStream) -> TokenStream {
+    let DeriveInput {
+        ident, attrs, data, ..
+    } = parse_macro_input!(input);
+    let impl_block = impl_enum_as_text(ident, attrs, data);
+
+    impl_block.into()
+}
+
+#[proc_macro_derive(SqlNewtype, attributes(sql_newtype))]
+pub fn sql_newtype(input: TokenStream) -> TokenStream {
+    let DeriveInput {
+        ident,
+        attrs,
+        generics,
+        data,
+        ..
+    } = parse_macro_input!(input);
+    let impl_block = impl_sql_newtype(ident, attrs, generics, data);
+
+    impl_block.into()
+}
+
+#[proc_macro_derive(TenantScoped, attributes(table, tenant, generated, try_from_row))]
+pub fn tenant_scoped(input: TokenStream) -> TokenStream {
+    let DeriveInput {
+        ident,
+        attrs,
+        generics,
+        data,
+        ..
+    } = parse_macro_input!(input);
+    let impl_block = impl_tenant_scoped(ident, attrs, generics, data);
+
+    impl_block.into()
+}
+
+#[proc_macro_derive(Relations, attributes(id, has_many, belongs_to))]
+pub fn relations(input: TokenStream) -> TokenStream {
+    let DeriveInput {
+        ident,
+        attrs,
+        generics,
+        data,
+        ..
+    } = parse_macro_input!(input);
+    let impl_block = impl_relations(ident, attrs, generics, data);
 
     impl_block.into()
 }
@@ -0,0 +1,113 @@
+use quote::quote;
+use syn::{parse_quote, Attribute, Data, Generics, Ident, Lit, Meta, NestedMeta};
+
+/// `#[sql_newtype(column = "name")]`: the column `TryFrom<&Row>` reads.
+fn row_column(attrs: &[Attribute]) -> syn::Result<Option<String>> {
+    let mut column = None;
+    for attr in attrs.iter().filter(|a| a.path.is_ident("sql_newtype")) {
+        let list = match attr.parse_meta()? {
+            Meta::List(list) => list,
+            meta => {
+                return Err(syn::Error::new_spanned(
+                    meta,
+                    "expected #[sql_newtype(...)]",
+                ))
+            }
+        };
+        for nested in list.nested {
+            match nested {
+                NestedMeta::Meta(Meta::NameValue(syn::MetaNameValue {
+                    path,
+                    lit: Lit::Str(s),
+                    ..
+                })) if path.is_ident("column") => column = Some(s.value()),
+                other => return Err(syn::Error::new_spanned(other, "unknown sql_newtype option")),
+            }
+        }
+    }
+    Ok(column)
+}
+
+pub fn impl_sql_newtype(
+    ident: Ident,
+    attrs: Vec<Attribute>,
+    mut generics: Generics,
+    data: Data,
+) -> proc_macro2::TokenStream {
+    let unsupported = || {
+        syn::Error::new_spanned(
+            &ident,
+            "SqlNewtype can only be derived for structs with a single field",
+        )
+        .to_compile_error()
+    };
+    let field = match data {
+        Data::Struct(s) if s.fields.len() == 1 => s.fields.into_iter().next().unwrap(),
+        _ => return unsupported(),
+    };
+    let column = match row_column(&attrs) {
+        Ok(column) => column,
+        Err(e) => return e.to_compile_error(),
+    };
+    let ty = &field.ty;
+    let access = match &field.ident {
+        Some(name) => quote! { #name },
+        None => quote! { 0 },
+    };
+    let construct = match &field.ident {
+        Some(name) => quote! { Self { #name: inner } },
+        None => quote! { Self(inner) },
+    };
+
+    generics.make_where_clause().predicates.push(parse_quote! {
+        #ty: rusqlite::ToSql
+            + rusqlite::types::FromSql
+            + ::rusqlite_utils::column_type::SqliteColumnType
+    });
+    let mut row_generics = generics.clone();
+    row_generics.params.insert(0, parse_quote! { 'stmt });
+    let (row_impl_generics, _, _) = row_generics.split_for_impl();
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let try_from_row = column.map(|column| {
+        quote! {
+            impl #row_impl_generics TryFrom<&rusqlite::Row<'stmt>> for #ident #ty_generics
+                #where_clause
+            {
+                type Error = rusqlite::Error;
+
+                fn try_from(row: &rusqlite::Row<'stmt>) -> Result<Self, Self::Error> {
+                    row.get(#column)
+                }
+            }
+        }
+    });
+
+    quote! {
+        impl #impl_generics rusqlite::ToSql for #ident #ty_generics #where_clause {
+            fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+                rusqlite::ToSql::to_sql(&self.#access)
+            }
+        }
+        impl #impl_generics rusqlite::types::FromSql for #ident #ty_generics #where_clause {
+            fn column_result(
+                value: rusqlite::types::ValueRef<'_>,
+            ) -> rusqlite::types::FromSqlResult<Self> {
+                let inner = <#ty as rusqlite::types::FromSql>::column_result(value)?;
+                Ok(#construct)
+            }
+        }
+        impl #impl_generics ::rusqlite_utils::column_type::SqliteColumnType
+            for #ident #ty_generics #where_clause
+        {
+            const DECL_TYPE: Option<&'static str> =
+                <#ty as ::rusqlite_utils::column_type::SqliteColumnType>::DECL_TYPE;
+            const STORAGE: Option<::rusqlite_utils::column_type::StorageClass> =
+                <#ty as ::rusqlite_utils::column_type::SqliteColumnType>::STORAGE;
+            const NULLABLE: bool =
+                <#ty as ::rusqlite_utils::column_type::SqliteColumnType>::NULLABLE;
+            const DEFAULT: Option<&'static str> =
+                <#ty as ::rusqlite_utils::column_type::SqliteColumnType>::DEFAULT;
+        }
+        #try_from_row
+    }
+}
@@ -0,0 +1,91 @@
+use quote::quote;
+use syn::{Attribute, Data, Generics, Ident, Lit, Meta, NestedMeta};
+
+/// Options from `#[to_params(...)]` on a field.
+#[derive(Default)]
+struct FieldOptions {
+    /// `name = "..."`: the parameter name, without the leading `:`, if not
+    /// named after the field.
+    name: Option<String>,
+    /// `skip`: the field isn't bound.
+    skip: bool,
+}
+
+impl FieldOptions {
+    fn parse(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut options = Self::default();
+        for attr in attrs.iter().filter(|a| a.path.is_ident("to_params")) {
+            let list = match attr.parse_meta()? {
+                Meta::List(list) => list,
+                meta => return Err(syn::Error::new_spanned(meta, "expected #[to_params(...)]")),
+            };
+            for nested in list.nested {
+                match nested {
+                    NestedMeta::Meta(Meta::NameValue(syn::MetaNameValue {
+                        path,
+                        lit: Lit::Str(s),
+                        ..
+                    })) if path.is_ident("name") => options.name = Some(s.value()),
+                    NestedMeta::Meta(Meta::Path(path)) if path.is_ident("skip") => {
+                        options.skip = true
+                    }
+                    other => {
+                        return Err(syn::Error::new_spanned(other, "unknown to_params option"))
+                    }
+                }
+            }
+        }
+        Ok(options)
+    }
+}
+
+pub fn impl_to_params(ident: Ident, generics: Generics, data: Data) -> proc_macro2::TokenStream {
+    let fields = match data {
+        Data::Struct(s) => match s.fields {
+            syn::Fields::Named(f) => f.named,
+            other => {
+                return syn::Error::new_spanned(
+                    other,
+                    "ToParams can only be derived for structs with named fields",
+                )
+                .to_compile_error()
+            }
+        },
+        _ => {
+            return syn::Error::new_spanned(
+                &ident,
+                "ToParams can only be derived for structs with named fields",
+            )
+            .to_compile_error()
+        }
+    };
+
+    let mut names = vec![];
+    let mut values = vec![];
+    for field in fields {
+        let options = match FieldOptions::parse(&field.attrs) {
+            Ok(options) => options,
+            Err(e) => return e.to_compile_error(),
+        };
+        if options.skip {
+            continue;
+        }
+        let field_ident = field.ident.expect("fields are named");
+        names.push(format!(
+            ":{}",
+            options.name.unwrap_or_else(|| field_ident.to_string())
+        ));
+        values.push(field_ident);
+    }
+
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    quote! {
+        impl #impl_generics ::rusqlite_utils::params::ToParams for #ident #ty_generics #where_clause {
+            const NAMES: &'static [&'static str] = &[#(#names),*];
+
+            fn to_params(&self) -> Vec<(&'static str, &dyn rusqlite::ToSql)> {
+                vec![#((#names, &self.#values as &dyn rusqlite::ToSql)),*]
+            }
+        }
+    }
+}
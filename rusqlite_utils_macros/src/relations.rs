@@ -0,0 +1,250 @@
+use quote::{format_ident, quote};
+use syn::{Attribute, Data, Generics, Ident, Lit, Meta, NestedMeta, Path, Type};
+
+use crate::{crud::option_inner, table::snake_case};
+
+/// `#[has_many(Child, fk = "column")]` or `#[belongs_to(Parent)]`, with an
+/// optional `name = "method"`.
+struct Relation {
+    target: Path,
+    fk: Option<String>,
+    name: Option<String>,
+}
+
+impl Relation {
+    fn parse(attr: &Attribute) -> syn::Result<Self> {
+        let kind = attr
+            .path
+            .get_ident()
+            .expect("relation attributes are idents");
+        let list = match attr.parse_meta()? {
+            Meta::List(list) => list,
+            meta => {
+                return Err(syn::Error::new_spanned(
+                    meta,
+                    format!("expected #[{}(Type, ...)]", kind),
+                ))
+            }
+        };
+        let mut nested = list.nested.into_iter();
+        let target = match nested.next() {
+            Some(NestedMeta::Meta(Meta::Path(path))) => path,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    attr,
+                    format!("expected the related type first in #[{}(...)]", kind),
+                ))
+            }
+        };
+        let mut relation = Self {
+            target,
+            fk: None,
+            name: None,
+        };
+        for option in nested {
+            match option {
+                NestedMeta::Meta(Meta::NameValue(syn::MetaNameValue {
+                    path,
+                    lit: Lit::Str(s),
+                    ..
+                })) if path.is_ident("name") => relation.name = Some(s.value()),
+                NestedMeta::Meta(Meta::NameValue(syn::MetaNameValue {
+                    path,
+                    lit: Lit::Str(s),
+                    ..
+                })) if path.is_ident("fk") && kind == "has_many" => relation.fk = Some(s.value()),
+                other => {
+                    return Err(syn::Error::new_spanned(
+                        other,
+                        format!("unknown {} option", kind),
+                    ))
+                }
+            }
+        }
+        Ok(relation)
+    }
+
+    /// The related type's name, in snake case.
+    fn target_name(&self) -> String {
+        let ident = &self
+            .target
+            .segments
+            .last()
+            .expect("paths aren't empty")
+            .ident;
+        snake_case(ident)
+    }
+}
+
+pub fn impl_relations(
+    ident: Ident,
+    attrs: Vec<Attribute>,
+    generics: Generics,
+    data: Data,
+) -> proc_macro2::TokenStream {
+    match relations(&ident, &attrs, data) {
+        Ok(methods) => {
+            let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+            quote! {
+                impl #impl_generics #ident #ty_generics #where_clause {
+                    #(#methods)*
+                }
+            }
+        }
+        Err(e) => e.to_compile_error(),
+    }
+}
+
+fn relations(
+    ident: &Ident,
+    attrs: &[Attribute],
+    data: Data,
+) -> syn::Result<Vec<proc_macro2::TokenStream>> {
+    let fields = match data {
+        Data::Struct(s) => match s.fields {
+            syn::Fields::Named(f) => f.named,
+            other => {
+                return Err(syn::Error::new_spanned(
+                    other,
+                    "Relations can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                ident,
+                "Relations can only be derived for structs with named fields",
+            ))
+        }
+    };
+    let has_attr =
+        |field: &syn::Field, name: &str| field.attrs.iter().any(|a| a.path.is_ident(name));
+
+    let mut methods = vec![];
+    let has_many: Vec<_> = attrs
+        .iter()
+        .filter(|a| a.path.is_ident("has_many"))
+        .collect();
+    if !has_many.is_empty() {
+        let id = match fields
+            .iter()
+            .filter(|f| has_attr(f, "id"))
+            .collect::<Vec<_>>()[..]
+        {
+            [id] => id,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    ident,
+                    "has_many needs a single-column primary key, marked #[id]",
+                ))
+            }
+        };
+        for attr in has_many {
+            let relation = Relation::parse(attr)?;
+            let fk = relation.fk.clone().ok_or_else(|| {
+                syn::Error::new_spanned(attr, "expected #[has_many(Child, fk = \"column\")]")
+            })?;
+            let name = relation
+                .name
+                .clone()
+                .unwrap_or_else(|| format!("{}s", relation.target_name()));
+            methods.push(has_many_methods(&relation.target, &fk, &name, id));
+        }
+    }
+    for field in &fields {
+        for attr in field.attrs.iter().filter(|a| a.path.is_ident("belongs_to")) {
+            let relation = Relation::parse(attr)?;
+            let field_name = field.ident.as_ref().expect("fields are named").to_string();
+            let name =
+                relation
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| match field_name.strip_suffix("_id") {
+                        Some(name) if !name.is_empty() => name.to_string(),
+                        _ => relation.target_name(),
+                    });
+            methods.push(belongs_to_methods(&relation.target, &name, field));
+        }
+    }
+    Ok(methods)
+}
+
+/// `name(&self, conn)` loading the children of a row, and
+/// `load_name(conn, rows)` loading those of many.
+fn has_many_methods(
+    child: &Path,
+    fk: &str,
+    name: &str,
+    id: &syn::Field,
+) -> proc_macro2::TokenStream {
+    let id_field = &id.ident;
+    let method = format_ident!("{}", name);
+    let load = format_ident!("load_{}", name);
+    let (key, key_ty): (_, &Type) = match option_inner(&id.ty) {
+        // Rows without an id haven't been inserted, so have no children.
+        Some(inner) => (quote! { row.#id_field.clone() }, inner),
+        None => (quote! { Some(row.#id_field.clone()) }, &id.ty),
+    };
+    quote! {
+        /// The rows referencing this one, read in a single query.
+        pub fn #method(
+            &self,
+            conn: &rusqlite::Connection,
+        ) -> rusqlite::Result<Vec<#child>> {
+            ::rusqlite_utils::relations::children::<#child>(conn, #fk, &self.#id_field)
+        }
+
+        /// The rows referencing each of `rows`, by id, read in a single
+        /// query.
+        pub fn #load(
+            conn: &rusqlite::Connection,
+            rows: &[Self],
+        ) -> rusqlite::Result<::std::collections::HashMap<#key_ty, Vec<#child>>> {
+            let ids: Vec<#key_ty> = rows.iter().filter_map(|row| #key).collect();
+            ::rusqlite_utils::relations::children_of::<#child, #key_ty>(conn, #fk, &ids)
+        }
+    }
+}
+
+/// `name(&self, conn)` loading the row a foreign key references, and
+/// `load_name(conn, rows)` loading those of many.
+fn belongs_to_methods(parent: &Path, name: &str, field: &syn::Field) -> proc_macro2::TokenStream {
+    let fk_field = &field.ident;
+    let method = format_ident!("{}", name);
+    let load = format_ident!("load_{}", name);
+    let id_ty = quote! { <#parent as ::rusqlite_utils::crud::Delete>::Id };
+    let (get, key) = match option_inner(&field.ty) {
+        Some(_) => (
+            quote! {
+                match &self.#fk_field {
+                    Some(id) => ::rusqlite_utils::relations::parent::<#parent>(conn, id),
+                    None => Ok(None),
+                }
+            },
+            quote! { row.#fk_field.clone() },
+        ),
+        None => (
+            quote! { ::rusqlite_utils::relations::parent::<#parent>(conn, &self.#fk_field) },
+            quote! { Some(row.#fk_field.clone()) },
+        ),
+    };
+    quote! {
+        /// The row this one references, or `None` if it doesn't exist.
+        pub fn #method(
+            &self,
+            conn: &rusqlite::Connection,
+        ) -> rusqlite::Result<Option<#parent>> {
+            #get
+        }
+
+        /// The rows referenced by each of `rows`, by id, read in a single
+        /// query.
+        pub fn #load(
+            conn: &rusqlite::Connection,
+            rows: &[Self],
+        ) -> rusqlite::Result<::std::collections::HashMap<#id_ty, #parent>> {
+            let ids: Vec<#id_ty> = rows.iter().filter_map(|row| #key).collect();
+            ::rusqlite_utils::relations::parents_of::<#parent>(conn, &ids)
+        }
+    }
+}
@@ -0,0 +1,378 @@
+use quote::quote;
+use syn::{
+    ext::IdentExt, parse::ParseStream, punctuated::Punctuated, Attribute, Data, Field, Ident, Lit,
+    LitStr, Meta, NestedMeta, Path, Token, Visibility,
+};
+
+use crate::util::FieldOptions;
+
+pub fn impl_table(
+    ident: Ident,
+    vis: Visibility,
+    attrs: Vec<Attribute>,
+    data: Data,
+) -> proc_macro2::TokenStream {
+    table(ident, vis, attrs, data).unwrap_or_else(|e| e.to_compile_error())
+}
+
+fn table(
+    ident: Ident,
+    vis: Visibility,
+    attrs: Vec<Attribute>,
+    data: Data,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let table = table_name(&ident, &attrs)?;
+    let strict = attrs.iter().any(|a| a.path.is_ident("strict"));
+    let fields = match data {
+        Data::Struct(syn::DataStruct {
+            fields: syn::Fields::Named(f),
+            ..
+        }) => f.named,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &ident,
+                "Table can only be derived for structs with named fields",
+            ))
+        }
+    };
+    let projections = attrs
+        .iter()
+        .filter(|a| a.path.is_ident("projection"))
+        .map(|a| {
+            impl_projection(a, &vis, &table, &fields).unwrap_or_else(|e| e.to_compile_error())
+        });
+    let projections: Vec<_> = projections.collect();
+    let has_attr =
+        |field: &syn::Field, name: &str| field.attrs.iter().any(|a| a.path.is_ident(name));
+
+    // The columns are those `TryFromRow` reads, less skipped and lazy fields.
+    let mut stored = vec![];
+    for field in fields {
+        let options = FieldOptions::parse(&field.attrs)?;
+        if options.flatten || options.multi_column {
+            return Err(syn::Error::new_spanned(
+                &field,
+                "Table doesn't support flattened or multi-column fields",
+            ));
+        }
+        if options.skip || options.lazy.is_some() {
+            continue;
+        }
+        let column = options
+            .column
+            .clone()
+            .unwrap_or_else(|| field.ident.as_ref().expect("fields are named").to_string());
+        stored.push((field, column, options.encoding));
+    }
+    // A primary key of several columns is declared after them.
+    let key: Vec<_> = stored
+        .iter()
+        .filter(|(f, ..)| has_attr(f, "primary_key"))
+        .map(|(_, column, _)| quote_ident(column))
+        .collect();
+    let create_start = format!("create table if not exists {}( ", quote_ident(&table));
+
+    let mut columns = vec![];
+    let mut create = vec![quote! { #create_start }];
+    let mut params = vec![];
+    let mut stamped = vec![];
+    let mut stamped_on_insert = vec![];
+    for (field, column_name_str, encoding) in stored {
+        let field_ident = field.ident.clone().expect("fields are named");
+        let ty = &match encoding {
+            Some(encoding) => encoding.stored_type(&field.ty),
+            None => field.ty.clone(),
+        };
+        let column_type = quote! { <#ty as ::rusqlite_utils::column_type::SqliteColumnType> };
+        let mut column = quote! {
+            ::rusqlite_utils::schema::Column::of::<#ty>(#column_name_str)
+        };
+
+        // The pieces of the column's definition, for `concatcp!`, matching
+        // `Column::definition_sql`.
+        let quoted = quote_ident(&column_name_str);
+        let mut definition = vec![quote! { #quoted }];
+        if strict {
+            definition.push(quote! {
+                " ", ::rusqlite_utils::schema::strict_type(#column_type::STORAGE)
+            });
+        } else {
+            definition.push(quote! {
+                match #column_type::DECL_TYPE { Some(_) => " ", None => "" },
+                match #column_type::DECL_TYPE { Some(t) => t, None => "" }
+            });
+        }
+        if has_attr(&field, "primary_key") {
+            column = quote! { #column.primary_key() };
+            if key.len() == 1 {
+                definition.push(quote! { " primary key" });
+            }
+        }
+        if has_attr(&field, "unique") {
+            column = quote! { #column.unique() };
+            definition.push(quote! { " unique" });
+        }
+        if has_attr(&field, "not_null") {
+            column = quote! { #column.not_null() };
+            definition.push(quote! { " not null" });
+        } else {
+            definition.push(quote! { if #column_type::NULLABLE { "" } else { " not null" } });
+        }
+        definition.push(quote! {
+            match #column_type::DEFAULT { Some(_) => " default (", None => "" },
+            match #column_type::DEFAULT { Some(d) => d, None => "" },
+            match #column_type::DEFAULT { Some(_) => ")", None => "" }
+        });
+
+        let generated = field
+            .attrs
+            .iter()
+            .find(|a| a.path.is_ident("generated"))
+            .map(|a| a.parse_args_with(parse_generated))
+            .transpose()?;
+        match generated {
+            Some((expr, stored)) => {
+                column = quote! { #column.generated(#expr, #stored) };
+                let generated = format!(
+                    " generated always as ({}) {}",
+                    expr,
+                    if stored { "stored" } else { "virtual" }
+                );
+                definition.push(quote! { #generated });
+            }
+            None => params.push(match encoding {
+                Some(encoding) => encoding.param(quote! { self.#field_ident }, &field.ty),
+                None => quote! { &self.#field_ident as &dyn rusqlite::ToSql },
+            }),
+        }
+        if let Some(attr) = field.attrs.iter().find(|a| a.path.is_ident("pii")) {
+            let scrub = parse_pii(attr)?;
+            column = quote! { #column.pii(#scrub) };
+        }
+        if !columns.is_empty() {
+            create.push(quote! { ", " });
+        }
+        columns.push(column);
+        create.extend(definition);
+
+        if has_attr(&field, "auto_now") {
+            stamped.push(field_ident);
+        } else if has_attr(&field, "auto_now_add") {
+            stamped_on_insert.push(field_ident);
+        }
+    }
+    let touch = if stamped.is_empty() && stamped_on_insert.is_empty() {
+        quote! {}
+    } else {
+        quote! {
+            fn touch(&mut self, clock: &dyn ::rusqlite_utils::date_time::Clock, inserting: bool) {
+                let now = clock.now();
+                #(self.#stamped = ::rusqlite_utils::date_time::clock::AutoNow::from_now(now);)*
+                if inserting {
+                    #(self.#stamped_on_insert = ::rusqlite_utils::date_time::clock::AutoNow::from_now(now);)*
+                }
+            }
+        }
+    };
+    let (strict_schema, strict_sql) = if strict {
+        (quote! { .strict() }, " strict")
+    } else {
+        (quote! {}, "")
+    };
+    let mut create_end = String::new();
+    if key.len() > 1 {
+        create_end.push_str(&format!(", primary key ({})", key.join(", ")));
+    }
+    create_end.push_str(" )");
+    create_end.push_str(strict_sql);
+    create.push(quote! { #create_end });
+
+    Ok(quote! {
+        impl #ident {
+            /// The `CREATE TABLE` statement of the table, as
+            /// `TableSchema::create_sql`.
+            pub const CREATE_SQL: &'static str =
+                ::rusqlite_utils::const_format::concatcp!(#(#create),*);
+        }
+        impl ::rusqlite_utils::schema::Table for #ident {
+            fn schema() -> ::rusqlite_utils::schema::TableSchema {
+                ::rusqlite_utils::schema::TableSchema::new(#table)
+                    #(.column(#columns))*
+                    #strict_schema
+            }
+            fn params(&self) -> Vec<&dyn rusqlite::ToSql> {
+                vec![#(#params),*]
+            }
+            #touch
+        }
+        #(#projections)*
+    })
+}
+
+/// `#[projection(Name, fields(a, b, ...))]`, optionally with
+/// `derive(...)`: a struct of some of the table's fields, read with their
+/// own `SELECT` so that list views can leave out large columns.
+fn impl_projection(
+    attr: &Attribute,
+    vis: &Visibility,
+    table: &str,
+    fields: &Punctuated<Field, Token![,]>,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let expected = || {
+        syn::Error::new_spanned(
+            attr,
+            "expected #[projection(Name, fields(...))], optionally with derive(...)",
+        )
+    };
+    let nested = match attr.parse_meta()? {
+        Meta::List(list) => list.nested,
+        _ => return Err(expected()),
+    };
+    let mut nested = nested.into_iter();
+    let name = match nested.next() {
+        Some(NestedMeta::Meta(Meta::Path(path))) => {
+            path.get_ident().cloned().ok_or_else(expected)?
+        }
+        _ => return Err(expected()),
+    };
+    let paths = |list: syn::MetaList| -> syn::Result<Vec<Path>> {
+        list.nested
+            .into_iter()
+            .map(|n| match n {
+                NestedMeta::Meta(Meta::Path(path)) => Ok(path),
+                other => Err(syn::Error::new_spanned(other, "expected a name")),
+            })
+            .collect()
+    };
+    let (mut names, mut derives) = (None, vec![]);
+    for option in nested {
+        match option {
+            NestedMeta::Meta(Meta::List(list)) if list.path.is_ident("fields") => {
+                names = Some(paths(list)?)
+            }
+            NestedMeta::Meta(Meta::List(list)) if list.path.is_ident("derive") => {
+                derives = paths(list)?
+            }
+            other => return Err(syn::Error::new_spanned(other, "unknown projection option")),
+        }
+    }
+    let names = names.ok_or_else(expected)?;
+
+    // The fields must be the table's, and are read as it reads them.
+    let mut projected = vec![];
+    for path in names {
+        let field = fields
+            .iter()
+            .find(|f| path.get_ident().is_some() && f.ident.as_ref() == path.get_ident())
+            .ok_or_else(|| {
+                syn::Error::new_spanned(&path, format!("no such field of `{}`", table))
+            })?;
+        let (ident, ty) = (&field.ident, &field.ty);
+        let options = field
+            .attrs
+            .iter()
+            .filter(|a| a.path.is_ident("try_from_row"));
+        projected.push(quote! { #(#options)* #vis #ident: #ty });
+    }
+    let doc = format!("Some of the columns of `{}`.", table);
+
+    Ok(quote! {
+        #[doc = #doc]
+        #[derive(::rusqlite_utils::TryFromRow, #(#derives),*)]
+        #vis struct #name {
+            #(#projected,)*
+        }
+
+        impl #name {
+            /// `SELECT` the projected columns from the table, to be followed
+            /// by any conditions.
+            pub fn select_sql() -> String {
+                format!(
+                    "select {} from {}",
+                    <Self as ::rusqlite_utils::row::Columns>::select_list(),
+                    ::rusqlite_utils::util::quote_ident(#table),
+                )
+            }
+        }
+    })
+}
+
+/// `name` quoted as an SQL identifier.
+pub fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// `#[table = "name"]`, or the struct name in snake case.
+pub fn table_name(ident: &Ident, attrs: &[Attribute]) -> syn::Result<String> {
+    if let Some(attr) = attrs.iter().find(|a| a.path.is_ident("table")) {
+        return match attr.parse_meta() {
+            Ok(Meta::NameValue(syn::MetaNameValue {
+                lit: Lit::Str(s), ..
+            })) => Ok(s.value()),
+            _ => Err(syn::Error::new_spanned(
+                attr,
+                "expected #[table = \"name\"]",
+            )),
+        };
+    }
+    Ok(snake_case(ident))
+}
+
+/// `ident` in snake case.
+pub fn snake_case(ident: &Ident) -> String {
+    let mut name = String::new();
+    for (i, c) in ident.to_string().chars().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            name.push('_');
+        }
+        name.extend(c.to_lowercase());
+    }
+    name
+}
+
+/// `#[generated("expr")]`, optionally followed by `stored` or `virtual`.
+fn parse_generated(input: ParseStream) -> syn::Result<(String, bool)> {
+    let expr: LitStr = input.parse()?;
+    if input.is_empty() {
+        return Ok((expr.value(), false));
+    }
+    input.parse::<syn::Token![,]>()?;
+    let kind = Ident::parse_any(input)?;
+    match kind.to_string().as_str() {
+        "stored" => Ok((expr.value(), true)),
+        "virtual" => Ok((expr.value(), false)),
+        _ => Err(syn::Error::new(
+            kind.span(),
+            "expected `stored` or `virtual`",
+        )),
+    }
+}
+
+/// `#[pii]` (hashed), `#[pii(hash)]`, `#[pii(null)]` or
+/// `#[pii(replace = "expr")]`.
+fn parse_pii(attr: &Attribute) -> syn::Result<proc_macro2::TokenStream> {
+    let scrub = quote! { ::rusqlite_utils::scrub::Scrub };
+    let nested = match attr.parse_meta()? {
+        Meta::Path(_) => return Ok(quote! { #scrub::Hash }),
+        Meta::List(list) if list.nested.len() == 1 => list.nested.into_iter().next(),
+        meta => {
+            return Err(syn::Error::new_spanned(
+                meta,
+                "expected a single scrub method",
+            ))
+        }
+    };
+    match nested {
+        Some(NestedMeta::Meta(Meta::Path(p))) if p.is_ident("hash") => Ok(quote! { #scrub::Hash }),
+        Some(NestedMeta::Meta(Meta::Path(p))) if p.is_ident("null") => Ok(quote! { #scrub::Null }),
+        Some(NestedMeta::Meta(Meta::NameValue(syn::MetaNameValue {
+            path,
+            lit: Lit::Str(expr),
+            ..
+        }))) if path.is_ident("replace") => Ok(quote! { #scrub::Replace(#expr.to_string()) }),
+        other => Err(syn::Error::new_spanned(
+            other,
+            "expected `hash`, `null` or `replace = \"expr\"`",
+        )),
+    }
+}
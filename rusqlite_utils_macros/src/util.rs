@@ -1,41 +1,734 @@
 use quote::quote;
-use syn::{Data, Ident};
+use syn::{parse_quote, Attribute, Data, Generics, Ident, Lit, Meta, NestedMeta};
 
-pub fn impl_try_from_row(ident: Ident, data: Data) -> proc_macro2::TokenStream {
-    let field_conversions;
-    if let Data::Struct(s) = data {
-        field_conversions = match s.fields {
-            syn::Fields::Named(f) => f
-                .named
-                .into_iter()
-                .map(|f| {
-                    let field_ident = f.ident.expect("fields are named");
-                    let column_name_str = field_ident.to_string();
-                    quote! {
-                        #field_ident: row.get(#column_name_str)?
+use crate::crud::option_inner;
+
+/// Options from `#[try_from_row(...)]` on a field.
+#[derive(Default)]
+pub(crate) struct FieldOptions {
+    /// `column = "name"`: the column the field is read from, if not named
+    /// after the field.
+    pub(crate) column: Option<String>,
+    /// `skip`: the field isn't read from the row, but set to its default.
+    pub(crate) skip: bool,
+    /// `flatten`: the field is itself read from the row, from the columns
+    /// starting with the prefix (by default the field name and `_`).
+    pub(crate) flatten: bool,
+    /// `multi_column`: the field's type implements `MultiColumn`, reading
+    /// the columns starting with the prefix (as for `flatten`).
+    pub(crate) multi_column: bool,
+    pub(crate) prefix: Option<String>,
+    /// `with = "path"`: the function converting the column's `ValueRef` to
+    /// the field, returning `rusqlite::Result`.
+    pub(crate) with: Option<syn::Path>,
+    /// `default` or `default = "expr"`: the value used when the column is
+    /// NULL or missing from the row, rather than failing.
+    pub(crate) default: Option<proc_macro2::TokenStream>,
+    /// `nullable`: the field is an `Option`, which is `None` if the column
+    /// is NULL (without calling the `with` function).
+    pub(crate) nullable: bool,
+    /// `not_null`: a NULL in the column fails with an error naming it,
+    /// rather than as a type mismatch.
+    pub(crate) not_null: bool,
+    /// `json` or `bson`: how the field is encoded.
+    pub(crate) encoding: Option<Encoding>,
+    /// `lazy = "table"`: the field is a `Lazy` handle on its column of
+    /// `table`, read from the row's rowid rather than the column.
+    pub(crate) lazy: Option<String>,
+    /// `rowid = "name"`: the column a `lazy` field reads the rowid from, if
+    /// not `rowid`.
+    pub(crate) rowid: Option<String>,
+}
+
+/// An encoding of a field as a document, as if it were wrapped in a
+/// `JsonObject` or `BsonObject` (inside the `Option`, for an optional field,
+/// so that `None` is NULL).
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Encoding {
+    /// `json`: JSON text.
+    Json,
+    /// `bson`: a BSON blob.
+    Bson,
+}
+
+impl Encoding {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Bson => "bson",
+        }
+    }
+
+    /// The wrapper type, without its parameter.
+    fn wrapper(self) -> proc_macro2::TokenStream {
+        match self {
+            Self::Json => quote! { ::rusqlite_utils::object::JsonObject },
+            Self::Bson => quote! { ::rusqlite_utils::object::BsonObject },
+        }
+    }
+
+    /// The parameter binding `value`, a field of type `ty`. An `Option`
+    /// field is bound as NULL if `None`, rather than as an encoded `null`.
+    pub(crate) fn param(
+        self,
+        value: proc_macro2::TokenStream,
+        ty: &syn::Type,
+    ) -> proc_macro2::TokenStream {
+        let wrapper = self.wrapper();
+        match option_inner(ty) {
+            Some(_) => quote! {
+                match &#value {
+                    Some(v) => #wrapper::from_ref(v) as &dyn rusqlite::ToSql,
+                    None => &rusqlite::types::Null as &dyn rusqlite::ToSql,
+                }
+            },
+            None => quote! { #wrapper::from_ref(&#value) as &dyn rusqlite::ToSql },
+        }
+    }
+
+    /// The type stored for a field of type `ty`.
+    pub(crate) fn stored_type(self, ty: &syn::Type) -> syn::Type {
+        let wrapper = self.wrapper();
+        match option_inner(ty) {
+            Some(inner) => parse_quote! { Option<#wrapper<#inner>> },
+            None => parse_quote! { #wrapper<#ty> },
+        }
+    }
+}
+
+impl FieldOptions {
+    pub(crate) fn parse(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut options = Self::default();
+        for attr in attrs.iter().filter(|a| a.path.is_ident("try_from_row")) {
+            let list = match attr.parse_meta()? {
+                Meta::List(list) => list,
+                meta => {
+                    return Err(syn::Error::new_spanned(
+                        meta,
+                        "expected #[try_from_row(...)]",
+                    ))
+                }
+            };
+            for nested in list.nested {
+                match nested {
+                    NestedMeta::Meta(Meta::NameValue(syn::MetaNameValue {
+                        path,
+                        lit: Lit::Str(s),
+                        ..
+                    })) if path.is_ident("column") => options.column = Some(s.value()),
+                    NestedMeta::Meta(Meta::NameValue(syn::MetaNameValue {
+                        path,
+                        lit: Lit::Str(s),
+                        ..
+                    })) if path.is_ident("prefix") => options.prefix = Some(s.value()),
+                    NestedMeta::Meta(Meta::NameValue(syn::MetaNameValue {
+                        path,
+                        lit: Lit::Str(s),
+                        ..
+                    })) if path.is_ident("with") => options.with = Some(s.parse()?),
+                    NestedMeta::Meta(Meta::NameValue(syn::MetaNameValue {
+                        path,
+                        lit: Lit::Str(s),
+                        ..
+                    })) if path.is_ident("lazy") => options.lazy = Some(s.value()),
+                    NestedMeta::Meta(Meta::NameValue(syn::MetaNameValue {
+                        path,
+                        lit: Lit::Str(s),
+                        ..
+                    })) if path.is_ident("rowid") => options.rowid = Some(s.value()),
+                    NestedMeta::Meta(Meta::NameValue(syn::MetaNameValue {
+                        path,
+                        lit: Lit::Str(s),
+                        ..
+                    })) if path.is_ident("default") => {
+                        let expr: syn::Expr = s.parse()?;
+                        options.default = Some(quote! { #expr });
+                    }
+                    NestedMeta::Meta(Meta::Path(path)) if path.is_ident("default") => {
+                        options.default = Some(quote! { Default::default() })
+                    }
+                    NestedMeta::Meta(Meta::Path(path)) if path.is_ident("skip") => {
+                        options.skip = true
+                    }
+                    NestedMeta::Meta(Meta::Path(path)) if path.is_ident("flatten") => {
+                        options.flatten = true
+                    }
+                    NestedMeta::Meta(Meta::Path(path)) if path.is_ident("multi_column") => {
+                        options.multi_column = true
+                    }
+                    NestedMeta::Meta(Meta::Path(path)) if path.is_ident("json") => {
+                        options.encoding = Some(Encoding::Json)
+                    }
+                    NestedMeta::Meta(Meta::Path(path)) if path.is_ident("bson") => {
+                        options.encoding = Some(Encoding::Bson)
+                    }
+                    NestedMeta::Meta(Meta::Path(path)) if path.is_ident("nullable") => {
+                        options.nullable = true
+                    }
+                    NestedMeta::Meta(Meta::Path(path)) if path.is_ident("not_null") => {
+                        options.not_null = true
+                    }
+                    other => {
+                        return Err(syn::Error::new_spanned(
+                            other,
+                            "unknown try_from_row option",
+                        ))
+                    }
+                }
+            }
+        }
+        let nested = options.flatten || options.multi_column;
+        if options.prefix.is_some() && !nested {
+            return Err(syn::Error::new_spanned(
+                &attrs[0],
+                "prefix is only used with flatten or multi_column",
+            ));
+        }
+        if options.flatten && options.multi_column {
+            return Err(syn::Error::new_spanned(
+                &attrs[0],
+                "flatten can't be used with multi_column",
+            ));
+        }
+        if options.with.is_some() && (options.skip || nested) {
+            return Err(syn::Error::new_spanned(
+                &attrs[0],
+                "with can't be used with skip, flatten or multi_column",
+            ));
+        }
+        if options.default.is_some() && nested {
+            return Err(syn::Error::new_spanned(
+                &attrs[0],
+                "default can't be used with flatten or multi_column",
+            ));
+        }
+        if let Some(encoding) = options.encoding {
+            if options.with.is_some() || options.skip || nested {
+                return Err(syn::Error::new_spanned(
+                    &attrs[0],
+                    format!(
+                        "{} can't be used with with, skip, flatten or multi_column",
+                        encoding.name()
+                    ),
+                ));
+            }
+        }
+        let null_option = options.nullable || options.not_null;
+        if options.nullable && options.not_null {
+            return Err(syn::Error::new_spanned(
+                &attrs[0],
+                "nullable can't be used with not_null",
+            ));
+        }
+        if null_option && (options.skip || nested || options.default.is_some()) {
+            return Err(syn::Error::new_spanned(
+                &attrs[0],
+                "nullable and not_null can't be used with skip, flatten, multi_column or default",
+            ));
+        }
+        if options.rowid.is_some() && options.lazy.is_none() {
+            return Err(syn::Error::new_spanned(
+                &attrs[0],
+                "rowid is only used with lazy",
+            ));
+        }
+        let converted = options.with.is_some() || options.encoding.is_some() || null_option;
+        if options.lazy.is_some()
+            && (converted || options.skip || nested || options.default.is_some())
+        {
+            return Err(syn::Error::new_spanned(
+                &attrs[0],
+                "lazy can only be used with column and rowid",
+            ));
+        }
+        Ok(options)
+    }
+}
+
+/// The string value of `#[try_from_row(name = "...")]`, the only option
+/// allowed where these appear (on enums and their variants).
+fn name_value_option(attrs: &[Attribute], name: &str) -> syn::Result<Option<String>> {
+    let mut value = None;
+    for attr in attrs.iter().filter(|a| a.path.is_ident("try_from_row")) {
+        let list = match attr.parse_meta()? {
+            Meta::List(list) => list,
+            meta => {
+                return Err(syn::Error::new_spanned(
+                    meta,
+                    "expected #[try_from_row(...)]",
+                ))
+            }
+        };
+        for nested in list.nested {
+            match nested {
+                NestedMeta::Meta(Meta::NameValue(syn::MetaNameValue {
+                    path,
+                    lit: Lit::Str(s),
+                    ..
+                })) if path.is_ident(name) => value = Some(s.value()),
+                other => {
+                    return Err(syn::Error::new_spanned(
+                        other,
+                        format!("expected {} = \"...\"", name),
+                    ))
+                }
+            }
+        }
+    }
+    Ok(value)
+}
+
+/// The conversions of a struct's (or variant's) fields, and the columns
+/// they read.
+#[derive(Default)]
+struct Fields {
+    conversions: Vec<proc_macro2::TokenStream>,
+    columns: Vec<String>,
+    /// Whether any fields are flattened (or multi-column), so that not all
+    /// columns are known.
+    flattened: bool,
+    /// Whether columns are read by their position rather than their name.
+    by_index: bool,
+    /// Whether any fields borrow their column (as `&str` or `&[u8]`), so
+    /// that the struct borrows the row.
+    borrowed: bool,
+}
+
+/// How the conversions of fields find their columns.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Lookup {
+    /// By name, with the prefix.
+    Name,
+    /// By position.
+    Index,
+    /// Through the index resolved by a `ColumnMap`.
+    Map,
+}
+
+/// The conversions of `fields` of `owner` (the struct or variant, named in
+/// errors), finding their columns by `lookup`.
+fn convert_fields(
+    owner: &str,
+    fields: impl IntoIterator<Item = syn::Field>,
+    lookup: Lookup,
+) -> syn::Result<Fields> {
+    let by_index = lookup == Lookup::Index;
+    let mut out = Fields {
+        by_index,
+        ..Fields::default()
+    };
+    for f in fields {
+        let options = FieldOptions::parse(&f.attrs)?;
+        if by_index && (options.flatten || options.multi_column) {
+            return Err(syn::Error::new_spanned(
+                &f,
+                "fields read by index can't be flattened or multi-column",
+            ));
+        }
+        let field_ident = f.ident.expect("fields are named");
+        if options.skip {
+            let default = options
+                .default
+                .unwrap_or_else(|| quote! { Default::default() });
+            out.conversions.push(quote! {
+                #field_ident: #default
+            });
+            continue;
+        }
+        if options.flatten {
+            let prefix = options
+                .prefix
+                .unwrap_or_else(|| format!("{}_", field_ident));
+            out.conversions.push(quote! {
+                #field_ident: ::rusqlite_utils::row::FromPrefixedRow::from_prefixed_row(
+                    row,
+                    &format!("{}{}", prefix, #prefix),
+                )?
+            });
+            out.flattened = true;
+            continue;
+        }
+        if options.multi_column {
+            let prefix = options
+                .prefix
+                .unwrap_or_else(|| format!("{}_", field_ident));
+            let ty = f.ty;
+            out.conversions.push(quote! {
+                #field_ident: <#ty as ::rusqlite_utils::multi_column::MultiColumn>::from_columns(
+                    row,
+                    &format!("{}{}", prefix, #prefix),
+                )?
+            });
+            out.flattened = true;
+            continue;
+        }
+        match (options.nullable, option_inner(&f.ty).is_some()) {
+            (true, false) => {
+                return Err(syn::Error::new_spanned(
+                    &f.ty,
+                    "nullable fields must be an Option",
+                ))
+            }
+            (false, true) if options.not_null => {
+                return Err(syn::Error::new_spanned(
+                    &f.ty,
+                    "not_null fields can't be an Option",
+                ))
+            }
+            _ => {}
+        }
+        let column_name_str = options.column.unwrap_or_else(|| field_ident.to_string());
+        // A lazy field reads the rowid, and its column only when accessed.
+        let (column_name_str, lazy) = match options.lazy {
+            Some(table) => {
+                let rowid = options.rowid.unwrap_or_else(|| "rowid".to_string());
+                (rowid, Some((table, column_name_str)))
+            }
+            None => (column_name_str, None),
+        };
+        // The column, and its value as a `Result` (so that missing columns
+        // can fall back to the default).
+        let index = out.columns.len();
+        let (column, value) = match lookup {
+            Lookup::Name => {
+                let column = quote! { &*::rusqlite_utils::row::prefixed(prefix, #column_name_str) };
+                (column.clone(), quote! { row.get_ref(#column) })
+            }
+            Lookup::Index => (quote! { #index }, quote! { row.get_ref(#index) }),
+            Lookup::Map => (
+                quote! { map.index(#index, #column_name_str)? },
+                quote! { map.index(#index, #column_name_str).and_then(|i| row.get_ref(i)) },
+            ),
+        };
+        // Conversion errors are wrapped with the names of the struct, field
+        // and column.
+        let column_str = match lookup {
+            Lookup::Name => quote! { &::rusqlite_utils::row::prefixed(prefix, #column_name_str) },
+            Lookup::Index => quote! { row.as_ref().column_name(#index).unwrap_or_default() },
+            Lookup::Map => quote! { #column_name_str },
+        };
+        let field = field_ident.to_string();
+        let wrap = quote! {
+            .map_err(|e| ::rusqlite_utils::row::field_error(#owner, #field, #column_str, e))?
+        };
+        let conversion = match (lazy, options.with, options.encoding) {
+            (Some((table, lazy_column)), _, _) => quote! {
+                ::rusqlite_utils::lazy::Lazy::new(#table, #lazy_column, row.get(#column)#wrap)
+            },
+            (None, Some(with), _) => quote! { #with(row.get_ref(#column)?)#wrap },
+            // An optional field read as `nullable` is already unwrapped.
+            (None, None, Some(encoding)) if option_inner(&f.ty).is_some() && !options.nullable => {
+                let wrapper = encoding.wrapper();
+                quote! { row.get::<_, Option<#wrapper<_>>>(#column)#wrap.map(#wrapper::unwrap) }
+            }
+            (None, None, Some(encoding)) => {
+                let wrapper = encoding.wrapper();
+                quote! { row.get::<_, #wrapper<_>>(#column)#wrap.unwrap() }
+            }
+            (None, None, None) if is_reference(option_inner(&f.ty).unwrap_or(&f.ty)) => {
+                out.borrowed = true;
+                quote! { ::rusqlite_utils::row::get_borrowed(row, #column)#wrap }
+            }
+            (None, None, None) => quote! { row.get(#column)#wrap },
+        };
+        if options.nullable {
+            out.conversions.push(quote! {
+                #field_ident: match #value? {
+                    rusqlite::types::ValueRef::Null => None,
+                    _ => Some(#conversion),
+                }
+            });
+            out.columns.push(column_name_str);
+            continue;
+        }
+        if options.not_null {
+            let column_index = match lookup {
+                Lookup::Name => quote! { row.as_ref().column_index(#column)? },
+                _ => column,
+            };
+            out.conversions.push(quote! {
+                #field_ident: match #value? {
+                    rusqlite::types::ValueRef::Null => {
+                        return Err(::rusqlite_utils::row::unexpected_null(
+                            row,
+                            #column_index,
+                            #field,
+                        ))
                     }
-                })
-                .collect::<Vec<_>>(),
+                    _ => #conversion,
+                }
+            });
+            out.columns.push(column_name_str);
+            continue;
+        }
+        out.conversions.push(match options.default {
+            Some(default) => quote! {
+                #field_ident: match #value {
+                    Ok(rusqlite::types::ValueRef::Null)
+                    | Err(rusqlite::Error::InvalidColumnName(_))
+                    | Err(rusqlite::Error::InvalidColumnIndex(_)) => #default,
+                    _ => #conversion,
+                }
+            },
+            None => quote! { #field_ident: #conversion },
+        });
+        out.columns.push(column_name_str);
+    }
+    Ok(out)
+}
+
+fn is_reference(ty: &syn::Type) -> bool {
+    matches!(ty, syn::Type::Reference(_))
+}
+
+fn unsupported<T: quote::ToTokens>(tokens: T) -> syn::Error {
+    syn::Error::new_spanned(
+        tokens,
+        "TryFromRow can only be derived for structs with named fields, and enums with a tag",
+    )
+}
 
-            syn::Fields::Unnamed(_) => {
-                unimplemented!("This macro is only implemented for named structs.")
+/// Whether a struct is marked `#[try_from_row(by_index)]`, the only option
+/// used on structs.
+fn struct_options(attrs: &[Attribute]) -> syn::Result<bool> {
+    let mut by_index = false;
+    for attr in attrs.iter().filter(|a| a.path.is_ident("try_from_row")) {
+        let list = match attr.parse_meta()? {
+            Meta::List(list) => list,
+            meta => {
+                return Err(syn::Error::new_spanned(
+                    meta,
+                    "expected #[try_from_row(...)]",
+                ))
             }
-            syn::Fields::Unit => {
-                unimplemented!("This macro is only implemented for named structs.")
+        };
+        for nested in list.nested {
+            match nested {
+                NestedMeta::Meta(Meta::Path(path)) if path.is_ident("by_index") => by_index = true,
+                NestedMeta::Meta(Meta::NameValue(meta)) if meta.path.is_ident("tag") => {
+                    return Err(syn::Error::new_spanned(meta, "tag is only used on enums"))
+                }
+                other => {
+                    return Err(syn::Error::new_spanned(
+                        other,
+                        "unknown try_from_row option",
+                    ))
+                }
             }
+        }
+    }
+    Ok(by_index)
+}
+
+/// The body of `from_prefixed_row` for a struct (or of `try_from`, if read
+/// by index), the body of `try_from_row_with_map` if its columns are known
+/// and looked up by name, and its columns.
+fn struct_body(
+    ident: &Ident,
+    attrs: &[Attribute],
+    data: syn::DataStruct,
+) -> syn::Result<(
+    proc_macro2::TokenStream,
+    Option<proc_macro2::TokenStream>,
+    Fields,
+)> {
+    let lookup = if struct_options(attrs)? {
+        Lookup::Index
+    } else {
+        Lookup::Name
+    };
+    let named = match data.fields {
+        syn::Fields::Named(f) => f.named,
+        syn::Fields::Unnamed(f) => return Err(unsupported(f)),
+        syn::Fields::Unit => return Err(unsupported(ident)),
+    };
+    let owner = ident.to_string();
+    let fields = convert_fields(&owner, named.clone(), lookup)?;
+    let conversions = &fields.conversions;
+    let body = quote! {
+        Ok(Self {
+            #(#conversions),*
+        })
+    };
+    let map_body = if lookup == Lookup::Name && !fields.flattened {
+        let conversions = convert_fields(&owner, named, Lookup::Map)?.conversions;
+        Some(quote! {
+            Ok(Self {
+                #(#conversions),*
+            })
+        })
+    } else {
+        None
+    };
+    Ok((body, map_body, fields))
+}
+
+/// The body of `from_prefixed_row` for an enum whose variant is named by the
+/// tag column, and its columns: the tag's, then every variant's.
+fn enum_body(
+    ident: &Ident,
+    attrs: &[Attribute],
+    data: syn::DataEnum,
+) -> syn::Result<(proc_macro2::TokenStream, Fields)> {
+    let tag = name_value_option(attrs, "tag")?.ok_or_else(|| {
+        syn::Error::new_spanned(
+            ident,
+            "enums need the column naming their variant, as #[try_from_row(tag = \"...\")]",
+        )
+    })?;
+    let mut columns = Fields {
+        columns: vec![tag.clone()],
+        ..Fields::default()
+    };
+    let mut arms = vec![];
+    for variant in data.variants {
+        let name = name_value_option(&variant.attrs, "rename")?
+            .unwrap_or_else(|| variant.ident.to_string());
+        let fields = match variant.fields {
+            syn::Fields::Named(f) => convert_fields(
+                &format!("{}::{}", ident, variant.ident),
+                f.named,
+                Lookup::Name,
+            )?,
+            syn::Fields::Unit => Fields::default(),
+            syn::Fields::Unnamed(f) => return Err(unsupported(f)),
         };
+        for column in fields.columns {
+            if !columns.columns.contains(&column) {
+                columns.columns.push(column);
+            }
+        }
+        columns.flattened |= fields.flattened;
+        columns.borrowed |= fields.borrowed;
+        let variant_ident = variant.ident;
+        let conversions = fields.conversions;
+        arms.push(quote! {
+            #name => Ok(Self::#variant_ident {
+                #(#conversions),*
+            })
+        });
+    }
+    let body = quote! {
+        let tag_column = ::rusqlite_utils::row::prefixed(prefix, #tag);
+        let tag: String = row.get(&*tag_column)?;
+        match tag.as_str() {
+            #(#arms,)*
+            other => Err(rusqlite::Error::FromSqlConversionFailure(
+                row.as_ref().column_index(&*tag_column)?,
+                rusqlite::types::Type::Text,
+                format!("unknown variant {:?} of {}", other, stringify!(#ident)).into(),
+            )),
+        }
+    };
+    Ok((body, columns))
+}
+
+pub fn impl_try_from_row(
+    ident: Ident,
+    attrs: Vec<Attribute>,
+    generics: Generics,
+    data: Data,
+) -> proc_macro2::TokenStream {
+    let res = match data {
+        Data::Struct(s) => struct_body(&ident, &attrs, s),
+        Data::Enum(e) => enum_body(&ident, &attrs, e).map(|(body, fields)| (body, None, fields)),
+        Data::Union(u) => Err(unsupported(u.union_token)),
+    };
+    let (body, map_body, fields) = match res {
+        Ok(res) => res,
+        Err(e) => return e.to_compile_error(),
+    };
+
+    // The columns of flattened fields depend on their type, so aren't known
+    // here.
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let columns = if fields.flattened {
+        quote! {}
     } else {
-        unimplemented!("This macro is only implemented for named structs.")
+        let column_names = fields.columns;
+        quote! {
+            impl #impl_generics ::rusqlite_utils::row::Columns for #ident #ty_generics #where_clause {
+                const COLUMNS: &'static [&'static str] = &[#(#column_names),*];
+            }
+        }
+    };
+
+    // The impl of `TryFrom` also needs the lifetime of the row.
+    let mut row_generics = generics.clone();
+    row_generics.params.insert(0, parse_quote!('stmt));
+    let (row_impl_generics, _, _) = row_generics.split_for_impl();
+
+    let with_map = match map_body {
+        Some(body) => quote! {
+            impl #impl_generics ::rusqlite_utils::column_map::TryFromRowWithMap for #ident #ty_generics #where_clause {
+                #[allow(unused_variables)]
+                fn try_from_row_with_map(
+                    row: &rusqlite::Row<'_>,
+                    map: &::rusqlite_utils::column_map::ColumnMap<Self>,
+                ) -> Result<Self, rusqlite::Error> {
+                    #body
+                }
+            }
+        },
+        None => quote! {},
+    };
+
+    // Borrowed fields live as long as the borrow of the row, which must
+    // then be the struct's (first) lifetime, so it can only be read from a
+    // row borrowed for as long.
+    if fields.borrowed {
+        let lifetime = match generics.lifetimes().next() {
+            Some(def) => &def.lifetime,
+            None => {
+                return syn::Error::new_spanned(
+                    &ident,
+                    "structs with borrowed fields need a lifetime, eg `struct Foo<'a>`",
+                )
+                .to_compile_error()
+            }
+        };
+        return quote! {
+            impl #row_impl_generics TryFrom<&#lifetime rusqlite::Row<'stmt>> for #ident #ty_generics #where_clause {
+                type Error = rusqlite::Error;
+                fn try_from(row: &#lifetime rusqlite::Row<'stmt>) -> Result<Self, rusqlite::Error> {
+                    #[allow(unused_variables)]
+                    let prefix = "";
+                    #body
+                }
+            }
+            #columns
+        };
     }
 
+    // Columns read by index can't be prefixed, so the struct can't be
+    // flattened into another.
+    if fields.by_index {
+        return quote! {
+            impl #row_impl_generics TryFrom<&rusqlite::Row<'stmt>> for #ident #ty_generics #where_clause {
+                type Error = rusqlite::Error;
+                fn try_from(row: &rusqlite::Row<'stmt>) -> Result<Self, rusqlite::Error> {
+                    #body
+                }
+            }
+            #columns
+        };
+    }
     quote! {
-        impl<'stmt> TryFrom<&rusqlite::Row<'stmt>> for #ident {
+        impl #row_impl_generics TryFrom<&rusqlite::Row<'stmt>> for #ident #ty_generics #where_clause {
             type Error = rusqlite::Error;
-            fn try_from(row: &rusqlite::Row<'stmt>) -> Result<#ident, rusqlite::Error> {
-                Ok(Self {
-                    #(#field_conversions),*
-                })
+            fn try_from(row: &rusqlite::Row<'stmt>) -> Result<Self, rusqlite::Error> {
+                ::rusqlite_utils::row::FromPrefixedRow::from_prefixed_row(row, "")
+            }
+        }
+        impl #impl_generics ::rusqlite_utils::row::FromPrefixedRow for #ident #ty_generics #where_clause {
+            fn from_prefixed_row(
+                row: &rusqlite::Row<'_>,
+                prefix: &str,
+            ) -> Result<Self, rusqlite::Error> {
+                #body
             }
         }
+        #columns
+        #with_map
     }
 }
@@ -0,0 +1,232 @@
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    stats::{FragmentationReport, Suggestion},
+    util::quote_ident,
+};
+
+/// Runs `ANALYZE`, collecting the statistics the query planner uses to
+/// choose indexes, eg after a bulk load.
+///
+/// With a limit, each index is only sampled (see `PRAGMA analysis_limit`),
+/// which keeps analyzing large tables fast enough to run outside of a
+/// maintenance window. The connection's previous limit is restored after.
+#[derive(Clone, Debug, Default)]
+pub struct Analyze {
+    limit: Option<u32>,
+    tables: Option<Vec<String>>,
+}
+
+impl Analyze {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Examine roughly `rows` rows of each index. Around 400 is typically
+    /// enough for good plans.
+    pub fn limit(mut self, rows: u32) -> Self {
+        self.limit = Some(rows);
+        self
+    }
+    /// Only analyze `tables` (and their indexes), rather than the whole
+    /// database.
+    pub fn tables(mut self, tables: &[&str]) -> Self {
+        self.tables = Some(tables.iter().map(|t| t.to_string()).collect());
+        self
+    }
+
+    pub fn run(&self, conn: &Connection) -> rusqlite::Result<()> {
+        let previous: Option<i64> = match self.limit {
+            Some(limit) => {
+                let previous = conn.query_row("pragma analysis_limit", (), |row| row.get(0))?;
+                conn.execute_batch(&format!("pragma analysis_limit = {}", limit))?;
+                Some(previous)
+            }
+            None => None,
+        };
+        let res = match &self.tables {
+            Some(tables) => tables
+                .iter()
+                .try_for_each(|t| conn.execute_batch(&format!("analyze {}", quote_ident(t)))),
+            None => conn.execute_batch("analyze"),
+        };
+        if let Some(previous) = previous {
+            conn.execute_batch(&format!("pragma analysis_limit = {}", previous))?;
+        }
+        res
+    }
+}
+
+/// A row of `sqlite_stat1`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stat {
+    pub table: String,
+    /// The index, or `None` for the table's row count.
+    pub index: Option<String>,
+    pub stat: String,
+}
+
+/// The contents of `sqlite_stat1`, saved so that they can be restored
+/// after a table is rebuilt (eg by a migration copying it into a new
+/// table), which drops its statistics, without running `ANALYZE` again.
+/// Serializable, so they can also be kept outside of the database.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlannerStats {
+    pub stats: Vec<Stat>,
+}
+
+impl PlannerStats {
+    /// Save the statistics, which are empty if the database has never been
+    /// analyzed.
+    pub fn save(conn: &Connection) -> rusqlite::Result<Self> {
+        let exists = conn
+            .query_row(
+                "select 1 from sqlite_schema where name = 'sqlite_stat1'",
+                (),
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some();
+        if !exists {
+            return Ok(Self::default());
+        }
+        let mut stmt = conn.prepare("select tbl, idx, stat from sqlite_stat1 order by tbl, idx")?;
+        let stats = stmt
+            .query_map((), |row| {
+                Ok(Stat {
+                    table: row.get(0)?,
+                    index: row.get(1)?,
+                    stat: row.get(2)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(Self { stats })
+    }
+
+    /// Replace the statistics of the saved tables with the saved ones, and
+    /// have the query planner reload them. Tables and indexes which no
+    /// longer exist are skipped, and other tables are left alone. Returns
+    /// the number of rows restored.
+    pub fn restore(&self, conn: &Connection) -> rusqlite::Result<usize> {
+        conn.execute_batch("savepoint restore_stats")?;
+        let res = self.write(conn);
+        match res {
+            Ok(_) => conn.execute_batch("release restore_stats")?,
+            Err(_) => conn.execute_batch("rollback to restore_stats; release restore_stats")?,
+        }
+        res
+    }
+
+    fn write(&self, conn: &Connection) -> rusqlite::Result<usize> {
+        // Creates sqlite_stat1 if needed, without analyzing anything.
+        conn.execute_batch("analyze sqlite_schema")?;
+        let mut exists = conn.prepare(
+            "select 1 from sqlite_schema where type = 'table' and name = ?1
+                and (?2 is null or exists (
+                    select 1 from sqlite_schema where type = 'index' and name = ?2 and tbl_name = ?1
+                ))",
+        )?;
+        let mut delete = conn.prepare("delete from sqlite_stat1 where tbl = ?")?;
+        let mut insert =
+            conn.prepare("insert into sqlite_stat1(tbl, idx, stat) values (?, ?, ?)")?;
+        let mut deleted = vec![];
+        let mut restored = 0;
+        for stat in &self.stats {
+            if !exists.exists((&stat.table, &stat.index))? {
+                continue;
+            }
+            if !deleted.contains(&stat.table) {
+                delete.execute((&stat.table,))?;
+                deleted.push(stat.table.clone());
+            }
+            restored += insert.execute((&stat.table, &stat.index, &stat.stat))?;
+        }
+        conn.execute_batch("analyze sqlite_schema")?;
+        Ok(restored)
+    }
+}
+
+/// `REINDEX` the indexes `report` suggests rebuilding, returning their
+/// names. A suggested `VACUUM` is left to the caller, as it rewrites the
+/// whole file.
+pub fn reindex(conn: &Connection, report: &FragmentationReport) -> rusqlite::Result<Vec<String>> {
+    let mut reindexed = vec![];
+    for suggestion in report.suggestions() {
+        if let Suggestion::Reindex(index) = suggestion {
+            conn.execute_batch(&format!("reindex {}", quote_ident(&index)))?;
+            reindexed.push(index);
+        }
+    }
+    Ok(reindexed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::stats::fragmentation;
+
+    fn setup() -> Connection {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch(
+            "create table event( kind integer, payload text );
+            create index event_kind on event(kind);
+            with recursive n(i) as (select 1 union all select i + 1 from n where i < 2000)
+            insert into event select i % 10, hex(randomblob(32)) from n;",
+        )
+        .expect("Failed to set up database");
+        db
+    }
+
+    #[test]
+    fn analyze_and_restore() {
+        let db = setup();
+        let res = Analyze::new().limit(100).tables(&["event"]).run(&db);
+        assert!(res.is_ok(), "Failed to analyze: {:?}", res);
+        let limit: i64 = db
+            .query_row("pragma analysis_limit", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(limit, 0, "Analysis limit was not restored");
+
+        let saved = PlannerStats::save(&db).expect("Failed to save stats");
+        assert_eq!(saved.stats.len(), 1);
+        assert_eq!(saved.stats[0].index.as_deref(), Some("event_kind"));
+
+        // Rebuild the table, as a migration would.
+        db.execute_batch(
+            "create table event_new( kind integer, payload text, created integer );
+            insert into event_new(kind, payload) select kind, payload from event;
+            drop table event;
+            alter table event_new rename to event;
+            create index event_kind on event(kind);",
+        )
+        .unwrap();
+        assert!(PlannerStats::save(&db).unwrap().stats.is_empty());
+
+        let res = saved.restore(&db);
+        assert!(res.is_ok(), "Failed to restore stats: {:?}", res);
+        assert_eq!(res.unwrap(), 1);
+        assert_eq!(PlannerStats::save(&db).unwrap(), saved);
+
+        db.execute_batch("drop index event_kind").unwrap();
+        assert_eq!(saved.restore(&db).unwrap(), 0);
+    }
+
+    #[test]
+    fn reindex_fragmented() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch(
+            "create table tag( name text );
+            create index tag_name on tag(name);
+            with recursive n(i) as (select 1 union all select i + 1 from n where i < 2000)
+            insert into tag select hex(randomblob(32)) from n;",
+        )
+        .unwrap();
+        let report = fragmentation(&db).unwrap().expect("dbstat is available");
+        let res = reindex(&db, &report);
+        assert!(res.is_ok(), "Failed to reindex: {:?}", res);
+        assert_eq!(res.unwrap(), vec!["tag_name".to_string()]);
+
+        let report = fragmentation(&db).unwrap().unwrap();
+        assert!(reindex(&db, &report).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,167 @@
+use rusqlite::{Connection, Params};
+use thiserror::Error;
+
+use crate::crud::{Insert, Update, Upsert};
+
+/// The result of an operation of a `Batch`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// The rowid of an inserted row.
+    Inserted(i64),
+    /// The number of rows changed.
+    Changed(usize),
+}
+
+type Operation<'a> = Box<dyn FnOnce(&Connection) -> rusqlite::Result<Outcome> + 'a>;
+
+/// Operations queued to run together in one `BEGIN IMMEDIATE` transaction,
+/// so that either all of them or none are applied, without each paying for
+/// its own transaction.
+#[derive(Default)]
+pub struct Batch<'a> {
+    operations: Vec<Operation<'a>>,
+}
+
+impl<'a> Batch<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn insert<T: Insert>(mut self, row: &'a T) -> Self {
+        self.operations.push(Box::new(move |conn| {
+            row.insert(conn).map(Outcome::Inserted)
+        }));
+        self
+    }
+    pub fn update<T: Update>(mut self, row: &'a T) -> Self {
+        self.operations
+            .push(Box::new(move |conn| row.update(conn).map(Outcome::Changed)));
+        self
+    }
+    pub fn upsert<T: Upsert>(mut self, row: &'a T) -> Self {
+        self.operations
+            .push(Box::new(move |conn| row.upsert(conn).map(Outcome::Changed)));
+        self
+    }
+    /// Run a statement which doesn't return rows.
+    pub fn sql<P: Params + 'a>(mut self, sql: &'a str, params: P) -> Self {
+        self.operations.push(Box::new(move |conn| {
+            conn.prepare_cached(sql)?
+                .execute(params)
+                .map(Outcome::Changed)
+        }));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.operations.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+
+    /// Run the operations in order in a transaction, returning the outcome
+    /// of each. If one fails, the transaction is rolled back. `conn` must
+    /// not already be in a transaction.
+    pub fn run(self, conn: &Connection) -> Result<Vec<Outcome>, Error> {
+        conn.execute_batch("begin immediate")?;
+        let mut outcomes = Vec::with_capacity(self.operations.len());
+        for (index, operation) in self.operations.into_iter().enumerate() {
+            match operation(conn) {
+                Ok(outcome) => outcomes.push(outcome),
+                Err(source) => {
+                    conn.execute_batch("rollback")?;
+                    return Err(Error::Operation { index, source });
+                }
+            }
+        }
+        if let Err(e) = conn.execute_batch("commit") {
+            conn.execute_batch("rollback")?;
+            return Err(e.into());
+        }
+        Ok(outcomes)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("operation {index} of the batch failed: {source}")]
+    Operation {
+        index: usize,
+        source: rusqlite::Error,
+    },
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::{Insert, Update};
+
+    #[derive(Insert, Update)]
+    struct Task {
+        #[id]
+        id: i64,
+        title: String,
+    }
+
+    fn titles(db: &Connection) -> Vec<String> {
+        db.prepare("select title from task order by id")
+            .unwrap()
+            .query_map((), |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn run_batch() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch("create table task( id integer primary key, title text )")
+            .expect("Failed to create table");
+        let first = Task {
+            id: 1,
+            title: "write".into(),
+        };
+        let renamed = Task {
+            id: 1,
+            title: "write docs".into(),
+        };
+        let second = Task {
+            id: 2,
+            title: "review".into(),
+        };
+
+        let res = Batch::new()
+            .insert(&first)
+            .insert(&second)
+            .update(&renamed)
+            .sql("delete from task where id = ?", (3,))
+            .run(&db);
+        assert!(res.is_ok(), "Failed to run batch: {:?}", res);
+        assert_eq!(
+            res.unwrap(),
+            vec![
+                Outcome::Inserted(1),
+                Outcome::Inserted(2),
+                Outcome::Changed(1),
+                Outcome::Changed(0)
+            ]
+        );
+        assert_eq!(titles(&db), vec!["write docs", "review"]);
+
+        let third = Task {
+            id: 3,
+            title: "ship".into(),
+        };
+        let res = Batch::new().insert(&third).insert(&second).run(&db);
+        assert!(
+            matches!(res, Err(Error::Operation { index: 1, .. })),
+            "Batch didn't fail: {:?}",
+            res
+        );
+        assert_eq!(titles(&db), vec!["write docs", "review"]);
+        assert!(db.is_autocommit());
+    }
+}
@@ -0,0 +1,171 @@
+use std::hint::black_box;
+
+use criterion::{BatchSize, Criterion, Throughput};
+use rusqlite::{Connection, Row};
+use serde::{de::DeserializeOwned, Serialize};
+use thiserror::Error;
+
+use crate::schema::Table;
+
+/// Insert `rows` into the table of `T` in one savepoint, as a bulk load
+/// would.
+pub fn insert_all<T: Table>(conn: &Connection, rows: &[T]) -> rusqlite::Result<()> {
+    conn.execute_batch("savepoint bench_insert")?;
+    let res = conn
+        .prepare_cached(&T::schema().insert_sql())
+        .and_then(|mut stmt| {
+            rows.iter()
+                .try_for_each(|row| stmt.execute(&*row.params()).map(|_| ()))
+        });
+    match res {
+        Ok(()) => conn.execute_batch("release bench_insert"),
+        Err(e) => {
+            conn.execute_batch("rollback to bench_insert; release bench_insert")?;
+            Err(e)
+        }
+    }
+}
+
+/// Benchmark inserting `rows` rows made by `make_row` into a fresh database
+/// from `setup`, which should create the table of `T`.
+pub fn bulk_insert<T, S, F>(c: &mut Criterion, name: &str, rows: usize, setup: S, make_row: F)
+where
+    T: Table,
+    S: Fn() -> Connection,
+    F: Fn(usize) -> T,
+{
+    let mut group = c.benchmark_group(name);
+    group.throughput(Throughput::Elements(rows as u64));
+    group.bench_function("bulk_insert", |b| {
+        b.iter_batched(
+            || (setup(), (0..rows).map(&make_row).collect::<Vec<_>>()),
+            |(conn, rows)| insert_all(&conn, &rows).expect("failed to insert rows"),
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+/// Benchmark reading the rows of `sql` with `T`'s `TryFrom<&Row>` (eg from
+/// `#[derive(TryFromRow)]`, looking columns up by name) against
+/// `by_index`, which should read the same struct by column index.
+pub fn read_rows<T, F>(c: &mut Criterion, name: &str, conn: &Connection, sql: &str, by_index: F)
+where
+    for<'r, 'stmt> T: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error>,
+    F: Fn(&Row<'_>) -> rusqlite::Result<T>,
+{
+    let read = |f: &dyn Fn(&Row<'_>) -> rusqlite::Result<T>| {
+        let mut stmt = conn.prepare_cached(sql).expect("failed to prepare query");
+        let rows = stmt
+            .query_map((), f)
+            .and_then(|rows| rows.collect::<rusqlite::Result<Vec<_>>>())
+            .expect("failed to read rows");
+        black_box(rows);
+    };
+    let mut group = c.benchmark_group(name);
+    group.bench_function("by_name", |b| b.iter(|| read(&|row| T::try_from(row))));
+    group.bench_function("by_index", |b| b.iter(|| read(&by_index)));
+    group.finish();
+}
+
+/// The size in bytes of a value encoded as it would be stored by
+/// `JsonObject`, `BsonObject` and as MessagePack.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EncodedSizes {
+    pub json: usize,
+    pub bson: usize,
+    pub msgpack: usize,
+}
+
+pub fn encoded_sizes<T: Serialize>(value: &T) -> Result<EncodedSizes, Error> {
+    Ok(EncodedSizes {
+        json: serde_json::to_vec(value)?.len(),
+        bson: bson::to_vec(value)?.len(),
+        msgpack: rmp_serde::to_vec_named(value)?.len(),
+    })
+}
+
+/// Benchmark encoding and decoding `value` as JSON, BSON and MessagePack.
+/// Throughput is reported in encoded bytes, so the encodings' sizes can be
+/// compared too. `value` must serialize to a map, as BSON requires.
+pub fn encodings<T: Serialize + DeserializeOwned>(c: &mut Criterion, name: &str, value: &T) {
+    let json = serde_json::to_vec(value).expect("failed to encode JSON");
+    let bson = bson::to_vec(value).expect("failed to encode BSON");
+    let msgpack = rmp_serde::to_vec_named(value).expect("failed to encode MessagePack");
+
+    let mut group = c.benchmark_group(name);
+    group.throughput(Throughput::Bytes(json.len() as u64));
+    group.bench_function("json_encode", |b| {
+        b.iter(|| serde_json::to_vec(black_box(value)).unwrap())
+    });
+    group.bench_function("json_decode", |b| {
+        b.iter(|| serde_json::from_slice::<T>(black_box(&json)).unwrap())
+    });
+    group.throughput(Throughput::Bytes(bson.len() as u64));
+    group.bench_function("bson_encode", |b| {
+        b.iter(|| bson::to_vec(black_box(value)).unwrap())
+    });
+    group.bench_function("bson_decode", |b| {
+        b.iter(|| bson::from_slice::<T>(black_box(&bson)).unwrap())
+    });
+    group.throughput(Throughput::Bytes(msgpack.len() as u64));
+    group.bench_function("msgpack_encode", |b| {
+        b.iter(|| rmp_serde::to_vec_named(black_box(value)).unwrap())
+    });
+    group.bench_function("msgpack_decode", |b| {
+        b.iter(|| rmp_serde::from_slice::<T>(black_box(&msgpack)).unwrap())
+    });
+    group.finish();
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Bson(#[from] bson::ser::Error),
+    #[error(transparent)]
+    MsgPack(#[from] rmp_serde::encode::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde::Deserialize;
+
+    use crate::Table;
+
+    #[derive(Table, Serialize, Deserialize)]
+    struct Point {
+        x: i64,
+        y: i64,
+    }
+
+    #[test]
+    fn measure_sizes() {
+        let res = encoded_sizes(&Point { x: 1, y: 2 });
+        assert!(res.is_ok(), "Failed to encode value: {:?}", res);
+        assert_eq!(
+            res.unwrap(),
+            EncodedSizes {
+                json: 13,
+                bson: 27,
+                msgpack: 7
+            }
+        );
+    }
+
+    #[test]
+    fn insert_rows() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        Point::create_table(&db).expect("Failed to create table");
+        let rows: Vec<_> = (0..10).map(|i| Point { x: i, y: -i }).collect();
+        let res = insert_all(&db, &rows);
+        assert!(res.is_ok(), "Failed to insert rows: {:?}", res);
+        let count: i64 = db
+            .query_row("select count(*) from point", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 10);
+    }
+}
@@ -0,0 +1,239 @@
+use std::collections::HashSet;
+
+use rusqlite::{types::ValueRef, Connection};
+use sha2::{Digest, Sha256};
+
+use crate::util::quote_ident;
+
+/// What could be saved by deduplicating or compressing the values of a
+/// column.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColumnBlobStats {
+    pub table: String,
+    pub column: String,
+    /// The number of blob and text values (ignoring those below the size
+    /// threshold).
+    pub values: u64,
+    pub bytes: u64,
+    pub distinct: u64,
+    /// The bytes of values equal to an earlier value, which storing each
+    /// distinct value once would save.
+    pub duplicate_bytes: u64,
+    /// The bytes of the values sampled for compressibility.
+    pub sampled_bytes: u64,
+    /// The estimated size of the sampled values once compressed.
+    pub sampled_compressed_bytes: u64,
+}
+
+impl ColumnBlobStats {
+    /// The fraction of values which are duplicates.
+    pub fn duplicate_ratio(&self) -> f64 {
+        if self.values == 0 {
+            return 0.0;
+        }
+        (self.values - self.distinct) as f64 / self.values as f64
+    }
+    /// The estimated compressed size as a fraction of the original, from
+    /// the sample.
+    pub fn compression_ratio(&self) -> f64 {
+        if self.sampled_bytes == 0 {
+            return 1.0;
+        }
+        self.sampled_compressed_bytes as f64 / self.sampled_bytes as f64
+    }
+    /// The bytes deduplication would save.
+    pub fn dedup_savings(&self) -> u64 {
+        self.duplicate_bytes
+    }
+    /// The bytes compressing every value would save, extrapolated from the
+    /// sample.
+    pub fn compression_savings(&self) -> u64 {
+        (self.bytes as f64 * (1.0 - self.compression_ratio())) as u64
+    }
+    /// The bytes saved by deduplicating, then compressing the distinct
+    /// values.
+    pub fn combined_savings(&self) -> u64 {
+        let distinct_bytes = self.bytes - self.duplicate_bytes;
+        self.duplicate_bytes + (distinct_bytes as f64 * (1.0 - self.compression_ratio())) as u64
+    }
+}
+
+/// Scans the blob and text columns of a database, estimating how much
+/// content-addressed storage (keeping each distinct value once) and
+/// compression would save, to find the columns worth migrating.
+///
+/// Duplicates are found exactly, by hashing every value. Compressibility
+/// is estimated from the byte entropy of a sample of each column's values,
+/// which ignores repeated sequences, so real compressors usually do better
+/// on text.
+#[derive(Clone, Debug)]
+pub struct BlobStats {
+    tables: Option<Vec<String>>,
+    min_bytes: usize,
+    sample: usize,
+}
+
+impl Default for BlobStats {
+    fn default() -> Self {
+        Self {
+            tables: None,
+            min_bytes: 0,
+            sample: 100,
+        }
+    }
+}
+
+impl BlobStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Only scan `tables` (by default, every table).
+    pub fn tables(mut self, tables: &[&str]) -> Self {
+        self.tables = Some(tables.iter().map(|t| t.to_string()).collect());
+        self
+    }
+    /// Ignore values smaller than `bytes`, which aren't worth storing
+    /// separately or compressing.
+    pub fn min_bytes(mut self, bytes: usize) -> Self {
+        self.min_bytes = bytes;
+        self
+    }
+    /// The number of values of each column sampled for compressibility (100
+    /// by default).
+    pub fn sample(mut self, values: usize) -> Self {
+        self.sample = values;
+        self
+    }
+
+    /// The stats of every column holding blob or text values, most
+    /// combined savings first.
+    pub fn analyze(&self, conn: &Connection) -> rusqlite::Result<Vec<ColumnBlobStats>> {
+        let mut stmt = conn.prepare(
+            "select m.name, c.name from sqlite_master m, pragma_table_info(m.name) c
+            where m.type = 'table' and m.name not like 'sqlite\\_%' escape '\\'
+                and m.sql not like 'create virtual %'
+            order by m.name, c.cid",
+        )?;
+        let columns = stmt
+            .query_map((), |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<(String, String)>>>()?;
+
+        let mut stats = vec![];
+        for (table, column) in columns {
+            if let Some(only) = &self.tables {
+                if !only.contains(&table) {
+                    continue;
+                }
+            }
+            let column = self.analyze_column(conn, table, column)?;
+            if column.values > 0 {
+                stats.push(column);
+            }
+        }
+        stats.sort_by_key(|s| std::cmp::Reverse(s.combined_savings()));
+        Ok(stats)
+    }
+
+    fn analyze_column(
+        &self,
+        conn: &Connection,
+        table: String,
+        column: String,
+    ) -> rusqlite::Result<ColumnBlobStats> {
+        let mut stmt = conn.prepare(&format!(
+            "select {c} from {t} where typeof({c}) in ('blob', 'text') and length(cast({c} as blob)) >= ?",
+            c = quote_ident(&column),
+            t = quote_ident(&table)
+        ))?;
+        let mut stats = ColumnBlobStats {
+            table,
+            column,
+            values: 0,
+            bytes: 0,
+            distinct: 0,
+            duplicate_bytes: 0,
+            sampled_bytes: 0,
+            sampled_compressed_bytes: 0,
+        };
+        let mut seen = HashSet::new();
+        let mut rows = stmt.query((self.min_bytes as i64,))?;
+        while let Some(row) = rows.next()? {
+            let bytes = match row.get_ref(0)? {
+                ValueRef::Blob(b) | ValueRef::Text(b) => b,
+                _ => continue,
+            };
+            stats.values += 1;
+            stats.bytes += bytes.len() as u64;
+            if seen.insert(<[u8; 32]>::from(Sha256::digest(bytes))) {
+                stats.distinct += 1;
+                if stats.distinct <= self.sample as u64 {
+                    stats.sampled_bytes += bytes.len() as u64;
+                    stats.sampled_compressed_bytes += entropy_bytes(bytes);
+                }
+            } else {
+                stats.duplicate_bytes += bytes.len() as u64;
+            }
+        }
+        Ok(stats)
+    }
+}
+
+/// The size of `bytes` under an ideal order-0 entropy coder.
+fn entropy_bytes(bytes: &[u8]) -> u64 {
+    let mut counts = [0u64; 256];
+    for b in bytes {
+        counts[*b as usize] += 1;
+    }
+    let len = bytes.len() as f64;
+    let bits: f64 = counts
+        .iter()
+        .filter(|&&n| n > 0)
+        .map(|&n| {
+            let p = n as f64 / len;
+            -(n as f64) * p.log2()
+        })
+        .sum();
+    (bits / 8.0).ceil() as u64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn analyze_blobs() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch(
+            "create table attachment( id integer primary key, name text, data blob, size integer );
+            with recursive n(i) as (select 1 union all select i + 1 from n where i < 100)
+            insert into attachment(name, data, size)
+            select 'file' || i, zeroblob(1000), 1000 from n;
+            insert into attachment(name, data) values ('random', randomblob(1000));",
+        )
+        .expect("Failed to create table");
+
+        let res = BlobStats::new().min_bytes(10).analyze(&db);
+        assert!(res.is_ok(), "Failed to analyze blobs: {:?}", res);
+        let stats = res.unwrap();
+        // `name` is below the threshold, and `size` holds integers.
+        assert_eq!(stats.len(), 1);
+        let data = &stats[0];
+        assert_eq!(data.column, "data");
+        assert_eq!(data.values, 101);
+        assert_eq!(data.distinct, 2);
+        assert_eq!(data.duplicate_bytes, 99 * 1000);
+        assert!(data.duplicate_ratio() > 0.97);
+
+        // The zeroes compress to nothing, unlike the random bytes.
+        assert_eq!(data.sampled_bytes, 2000);
+        assert!(
+            data.sampled_compressed_bytes > 800 && data.sampled_compressed_bytes <= 1000,
+            "Estimated {} compressed bytes",
+            data.sampled_compressed_bytes
+        );
+        assert!(data.combined_savings() >= data.dedup_savings());
+
+        let stats = BlobStats::new().tables(&["missing"]).analyze(&db).unwrap();
+        assert!(stats.is_empty());
+    }
+}
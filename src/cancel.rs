@@ -0,0 +1,175 @@
+use std::{
+    panic::RefUnwindSafe,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use rusqlite::{Connection, ErrorCode};
+
+use crate::error::Error;
+
+/// Signals cancellation to queries running on another thread. Clones share
+/// the same state.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// How many virtual machine instructions run between checks of the token.
+const CHECK_INTERVAL: i32 = 1000;
+
+/// Run `f`, interrupting any statement it executes once `token` is
+/// cancelled. An interrupted run returns `Error::Cancelled`, and `f` is not
+/// started at all if the token is already cancelled.
+///
+/// This installs the connection's progress handler for the duration of `f`,
+/// replacing any other handler.
+pub fn with_cancellation<T, F>(
+    conn: &Connection,
+    token: &CancellationToken,
+    f: F,
+) -> Result<T, Error>
+where
+    F: FnOnce(&Connection) -> rusqlite::Result<T>,
+{
+    if token.is_cancelled() {
+        return Err(Error::Cancelled);
+    }
+    let handler_token = token.clone();
+    interruptible(conn, move || handler_token.is_cancelled(), f).map_err(|e| match e {
+        Some(e) => Error::Sqlite(e),
+        None => Error::Cancelled,
+    })
+}
+
+/// Run `f`, interrupting any statement still running once `timeout` has
+/// elapsed. An interrupted run returns `Error::Timeout`. Time spent outside
+/// of SQLite counts towards the timeout, but only statements are
+/// interrupted.
+///
+/// Like `with_cancellation`, this replaces the connection's progress
+/// handler for the duration of `f`.
+pub fn with_timeout<T, F>(conn: &Connection, timeout: Duration, f: F) -> Result<T, Error>
+where
+    F: FnOnce(&Connection) -> rusqlite::Result<T>,
+{
+    let deadline = Instant::now() + timeout;
+    interruptible(conn, move || Instant::now() >= deadline, f).map_err(|e| match e {
+        Some(e) => Error::Sqlite(e),
+        None => Error::Timeout(timeout),
+    })
+}
+
+/// Run `f` with `interrupt` installed as the progress handler. Errors are
+/// `None` if a statement was interrupted because `interrupt` returned true.
+fn interruptible<T, I, F>(
+    conn: &Connection,
+    interrupt: I,
+    f: F,
+) -> Result<T, Option<rusqlite::Error>>
+where
+    I: Fn() -> bool + Send + RefUnwindSafe + 'static,
+    F: FnOnce(&Connection) -> rusqlite::Result<T>,
+{
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let handler_interrupted = interrupted.clone();
+    conn.progress_handler(
+        CHECK_INTERVAL,
+        Some(move || {
+            let interrupt = interrupt();
+            if interrupt {
+                handler_interrupted.store(true, Ordering::Relaxed);
+            }
+            interrupt
+        }),
+    );
+    let res = f(conn);
+    conn.progress_handler(0, None::<fn() -> bool>);
+
+    match res {
+        Err(e) if is_interrupt(&e) && interrupted.load(Ordering::Relaxed) => Err(None),
+        res => res.map_err(Some),
+    }
+}
+
+pub(crate) fn is_interrupt(e: &rusqlite::Error) -> bool {
+    e.sqlite_error_code() == Some(ErrorCode::OperationInterrupted)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::thread;
+
+    const SLOW_QUERY: &str = "with recursive n(i) as (select 1 union all select i + 1 from n)
+        select count(*) from n";
+
+    #[test]
+    fn cancel_from_another_thread() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        let token = CancellationToken::new();
+        let canceller = token.clone();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            canceller.cancel();
+        });
+
+        let res = with_cancellation(&db, &token, |conn| {
+            conn.query_row(SLOW_QUERY, (), |row| row.get::<_, i64>(0))
+        });
+        handle.join().unwrap();
+        assert!(
+            matches!(res, Err(Error::Cancelled)),
+            "Expected cancellation: {:?}",
+            res
+        );
+
+        let res = db.query_row("select 1", (), |row| row.get::<_, i64>(0));
+        assert_eq!(res.ok(), Some(1), "Progress handler was not removed");
+    }
+
+    #[test]
+    fn uncancelled_runs_complete() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        let res = with_cancellation(&db, &CancellationToken::new(), |conn| {
+            conn.query_row("select 1", (), |row| row.get::<_, i64>(0))
+        });
+        assert_eq!(res.ok(), Some(1));
+        let res = with_cancellation(&db, &CancellationToken::new(), |conn| {
+            conn.execute("select * from missing", ())
+        });
+        assert!(matches!(res, Err(Error::Sqlite(_))));
+    }
+
+    #[test]
+    fn timeout() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        let res = with_timeout(&db, Duration::from_millis(50), |conn| {
+            conn.query_row(SLOW_QUERY, (), |row| row.get::<_, i64>(0))
+        });
+        assert!(
+            matches!(res, Err(Error::Timeout(_))),
+            "Expected a timeout: {:?}",
+            res
+        );
+
+        let res = with_timeout(&db, Duration::from_secs(10), |conn| {
+            conn.query_row("select 1", (), |row| row.get::<_, i64>(0))
+        });
+        assert_eq!(res.ok(), Some(1));
+    }
+}
@@ -0,0 +1,156 @@
+use std::{cmp::Ordering, fmt::Debug};
+
+use serde_json::{Map, Value};
+
+use super::Change;
+
+/// A remote change to a row which was also changed locally, ie which still
+/// has changes in the local changelog. Prune the log once changes have been
+/// shipped to the other replicas, or every later remote change to the same
+/// rows is treated as a conflict.
+#[derive(Debug)]
+pub struct Conflict<'a> {
+    pub remote: &'a Change,
+    /// The local row as a JSON object, or `None` if it was deleted locally.
+    pub local: Option<&'a Value>,
+    /// The Lamport timestamp of the latest local change to the row.
+    pub local_lamport: i64,
+}
+
+impl Conflict<'_> {
+    /// Whether the remote change happened after the local one by Lamport
+    /// clock. Ties are broken by comparing the rows, so that both replicas
+    /// agree.
+    fn remote_is_later(&self) -> bool {
+        match self.remote.lamport.cmp(&self.local_lamport) {
+            Ordering::Equal => {
+                self.remote.row.as_ref().map(Value::to_string) >= self.local.map(Value::to_string)
+            }
+            ordering => ordering.is_gt(),
+        }
+    }
+}
+
+/// How a conflict was settled.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Resolution {
+    /// The local row was kept and the remote change dropped.
+    KeepLocal,
+    /// The remote change was applied as usual.
+    TakeRemote,
+    /// This row (a JSON object of its columns) replaced the local row.
+    Merged(Value),
+}
+
+type ResolveFn = Box<dyn Fn(&Conflict<'_>) -> Resolution>;
+
+/// How `Changelog::apply_with` settles conflicts.
+pub enum ConflictStrategy {
+    /// Keep whichever row has the later value in the named column, eg a
+    /// `Timestamp` maintained with `#[auto_now]`. Deletes, and rows with
+    /// equal or missing timestamps, are ordered by Lamport clock instead.
+    LastWriterWins(String),
+    /// Merge the named column, holding a `JsonObject`, key by key. For keys
+    /// set on both sides, and for the other columns, the values of the later
+    /// change by Lamport clock win, so that both replicas reach the same
+    /// row. Deletes are ordered by Lamport clock.
+    MergeFields(String),
+    Custom(ResolveFn),
+}
+
+impl ConflictStrategy {
+    pub fn custom<F: Fn(&Conflict<'_>) -> Resolution + 'static>(resolve: F) -> Self {
+        ConflictStrategy::Custom(Box::new(resolve))
+    }
+
+    pub(super) fn resolve(&self, conflict: &Conflict<'_>) -> Resolution {
+        let by_lamport = || {
+            if conflict.remote_is_later() {
+                Resolution::TakeRemote
+            } else {
+                Resolution::KeepLocal
+            }
+        };
+        let (local, remote) = match (conflict.local, &conflict.remote.row) {
+            (Some(Value::Object(local)), Some(Value::Object(remote))) => (local, remote),
+            _ => match self {
+                ConflictStrategy::Custom(resolve) => return resolve(conflict),
+                _ => return by_lamport(),
+            },
+        };
+        match self {
+            ConflictStrategy::LastWriterWins(column) => {
+                match compare(local.get(column), remote.get(column)) {
+                    Some(Ordering::Less) => Resolution::TakeRemote,
+                    Some(Ordering::Greater) => Resolution::KeepLocal,
+                    _ => by_lamport(),
+                }
+            }
+            ConflictStrategy::MergeFields(column) => {
+                let (older, newer) = if conflict.remote_is_later() {
+                    (local, remote)
+                } else {
+                    (remote, local)
+                };
+                let mut merged = newer.clone();
+                if let Some(value) = merge(older.get(column), newer.get(column)) {
+                    merged.insert(column.clone(), value);
+                }
+                Resolution::Merged(Value::Object(merged))
+            }
+            ConflictStrategy::Custom(resolve) => resolve(conflict),
+        }
+    }
+}
+
+impl Debug for ConflictStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConflictStrategy::LastWriterWins(c) => {
+                f.debug_tuple("LastWriterWins").field(c).finish()
+            }
+            ConflictStrategy::MergeFields(c) => f.debug_tuple("MergeFields").field(c).finish(),
+            ConflictStrategy::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+/// Compare timestamps stored as numbers or as text.
+fn compare(a: Option<&Value>, b: Option<&Value>) -> Option<Ordering> {
+    match (a?, b?) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64()?.partial_cmp(&b.as_f64()?),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+/// Merge two JSON objects stored as text, the newer one's values winning.
+fn merge(older: Option<&Value>, newer: Option<&Value>) -> Option<Value> {
+    let object = |v: Option<&Value>| -> Option<Map<String, Value>> {
+        match v? {
+            Value::String(s) => serde_json::from_str(s).ok(),
+            Value::Object(o) => Some(o.clone()),
+            _ => None,
+        }
+    };
+    let mut merged = object(older)?;
+    merged.extend(object(newer)?);
+    Some(Value::String(Value::Object(merged).to_string()))
+}
+
+/// A conflict settled while applying changes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Resolved {
+    pub remote: Change,
+    pub local: Option<Value>,
+    pub resolution: Resolution,
+}
+
+/// The outcome of `Changelog::apply_with`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConflictReport {
+    /// Changes applied, including merged ones.
+    pub applied: usize,
+    /// The conflicts, in the order of the changes.
+    pub conflicts: Vec<Resolved>,
+}
@@ -0,0 +1,641 @@
+use rusqlite::{types::Value, Connection, OptionalExtension};
+use serde_json::Map;
+use thiserror::Error;
+
+use crate::{trigger::Trigger, util::quote_ident};
+
+mod conflict;
+
+pub use conflict::{Conflict, ConflictReport, ConflictStrategy, Resolution, Resolved};
+
+/// The kind of change recorded in a `Changelog`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Op {
+    Insert,
+    Update,
+    Delete,
+}
+impl Op {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Op::Insert => "insert",
+            Op::Update => "update",
+            Op::Delete => "delete",
+        }
+    }
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "insert" => Some(Op::Insert),
+            "update" => Some(Op::Update),
+            "delete" => Some(Op::Delete),
+            _ => None,
+        }
+    }
+}
+
+/// One change to a tracked table.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Change {
+    /// Position in the local log.
+    pub seq: i64,
+    pub op: Op,
+    pub table: String,
+    /// The primary key (or rowid) of the changed row.
+    pub key: Value,
+    /// The row after the change as a JSON object, or `None` for deletes.
+    pub row: Option<serde_json::Value>,
+    /// The Lamport timestamp of the change, for ordering changes from
+    /// several replicas.
+    pub lamport: i64,
+}
+
+/// Records every insert, update and delete to a set of tables in a
+/// changelog table, using triggers, so that changes can be shipped to and
+/// applied on other replicas. Changes applied from another replica aren't
+/// logged again.
+///
+/// Rows are logged as JSON objects, so tracked tables can't have `BLOB`
+/// columns. Each table must have a single column primary key, or is keyed
+/// by rowid.
+#[derive(Clone, Debug)]
+pub struct Changelog {
+    table: String,
+    tracked: Vec<String>,
+}
+
+impl Changelog {
+    /// A changelog stored in `table`, with its clock in `<table>_state`.
+    pub fn new(table: &str) -> Self {
+        Self {
+            table: table.to_string(),
+            tracked: vec![],
+        }
+    }
+    pub fn track(mut self, table: &str) -> Self {
+        self.tracked.push(table.to_string());
+        self
+    }
+
+    fn state_table(&self) -> String {
+        quote_ident(&format!("{}_state", self.table))
+    }
+
+    /// Create the changelog and install the triggers on the tracked tables,
+    /// replacing any previous versions. Run this again after changing the
+    /// columns of a tracked table.
+    pub fn install(&self, conn: &Connection) -> Result<(), Error> {
+        conn.execute_batch(&format!(
+            "create table if not exists {log}(
+                seq integer primary key autoincrement,
+                op text not null,
+                tbl text not null,
+                key,
+                row text,
+                lamport integer not null
+            );
+            create table if not exists {state}(
+                id integer primary key check (id = 0),
+                lamport integer not null,
+                applying integer not null
+            );
+            insert or ignore into {state} values (0, 0, 0);",
+            log = quote_ident(&self.table),
+            state = self.state_table(),
+        ))?;
+        for table in &self.tracked {
+            for trigger in self.triggers(conn, table)? {
+                trigger.replace(conn)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn triggers(&self, conn: &Connection, table: &str) -> Result<Vec<Trigger>, Error> {
+        let (columns, key) = table_columns(conn, table)?;
+        let row = |alias: &str| {
+            let fields: Vec<_> = columns
+                .iter()
+                .map(|c| format!("'{}', {}.{}", c.replace('\'', "''"), alias, quote_ident(c)))
+                .collect();
+            format!("json_object({})", fields.join(", "))
+        };
+        let log = |op: Op, alias: &str, row: &str| {
+            format!(
+                "update {state} set lamport = lamport + 1;
+                insert into {log}(op, tbl, key, row, lamport)
+                values ('{op}', '{table}', {alias}.{key}, {row}, (select lamport from {state}))",
+                state = self.state_table(),
+                log = quote_ident(&self.table),
+                op = op.as_str(),
+                table = table.replace('\'', "''"),
+                key = quote_ident(&key),
+            )
+        };
+        let not_applying = format!("(select applying from {}) = 0", self.state_table());
+        let name = |op: Op| format!("{}_{}_{}", self.table, table, op.as_str());
+        Ok(vec![
+            Trigger::new(&name(Op::Insert), table)
+                .after()
+                .on_insert()
+                .when(&not_applying)
+                .then(&log(Op::Insert, "new", &row("new"))),
+            Trigger::new(&name(Op::Update), table)
+                .after()
+                .on_update()
+                .when(&not_applying)
+                .then(&log(Op::Update, "new", &row("new"))),
+            Trigger::new(&name(Op::Delete), table)
+                .after()
+                .on_delete()
+                .when(&not_applying)
+                .then(&log(Op::Delete, "old", "null")),
+        ])
+    }
+
+    /// Up to `limit` changes after `seq`, oldest first.
+    pub fn read(&self, conn: &Connection, after: i64, limit: usize) -> Result<Vec<Change>, Error> {
+        let mut stmt = conn.prepare(&format!(
+            "select seq, op, tbl, key, row, lamport from {} where seq > ? order by seq limit ?",
+            quote_ident(&self.table)
+        ))?;
+        let rows = stmt
+            .query_map((after, limit as i64), |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, Value>(3)?,
+                    row.get::<_, Option<String>>(4)?,
+                    row.get::<_, i64>(5)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        rows.into_iter()
+            .map(|(seq, op, table, key, row, lamport)| {
+                Ok(Change {
+                    seq,
+                    op: Op::parse(&op).ok_or(Error::InvalidOp(op))?,
+                    table,
+                    key,
+                    row: row
+                        .map(|r| serde_json::from_str(&r))
+                        .transpose()
+                        .map_err(Error::InvalidRow)?,
+                    lamport,
+                })
+            })
+            .collect()
+    }
+
+    /// The local Lamport clock.
+    pub fn lamport(&self, conn: &Connection) -> Result<i64, Error> {
+        Ok(conn.query_row(
+            &format!("select lamport from {}", self.state_table()),
+            (),
+            |row| row.get(0),
+        )?)
+    }
+
+    /// Apply changes read from another replica's changelog, in one
+    /// savepoint, without logging them locally. Inserts and updates
+    /// replace the whole row. The local clock is advanced past the latest
+    /// change.
+    pub fn apply(&self, conn: &Connection, changes: &[Change]) -> Result<(), Error> {
+        self.apply_in_savepoint(conn, changes, None).map(|_| ())
+    }
+    /// Apply changes as with `apply`, settling conflicts with local changes
+    /// which haven't been pruned from the log by `strategy`.
+    pub fn apply_with(
+        &self,
+        conn: &Connection,
+        changes: &[Change],
+        strategy: &ConflictStrategy,
+    ) -> Result<ConflictReport, Error> {
+        self.apply_in_savepoint(conn, changes, Some(strategy))
+    }
+    fn apply_in_savepoint(
+        &self,
+        conn: &Connection,
+        changes: &[Change],
+        strategy: Option<&ConflictStrategy>,
+    ) -> Result<ConflictReport, Error> {
+        conn.execute_batch("savepoint changelog_apply")?;
+        let res = self.apply_changes(conn, changes, strategy);
+        match res {
+            Ok(_) => conn.execute_batch("release changelog_apply")?,
+            Err(_) => conn.execute_batch("rollback to changelog_apply; release changelog_apply")?,
+        }
+        res
+    }
+    fn apply_changes(
+        &self,
+        conn: &Connection,
+        changes: &[Change],
+        strategy: Option<&ConflictStrategy>,
+    ) -> Result<ConflictReport, Error> {
+        conn.execute(
+            &format!("update {} set applying = 1", self.state_table()),
+            (),
+        )?;
+        let mut report = ConflictReport::default();
+        for change in changes {
+            if !self.tracked.contains(&change.table) {
+                return Err(Error::UntrackedTable(change.table.clone()));
+            }
+            let resolution = match strategy {
+                Some(strategy) => self.resolve(conn, change, strategy, &mut report)?,
+                None => Resolution::TakeRemote,
+            };
+            match (resolution, change.op, &change.row) {
+                (Resolution::KeepLocal, _, _) => continue,
+                (Resolution::Merged(serde_json::Value::Object(row)), _, _) => {
+                    replace_row(conn, &change.table, &row)?;
+                }
+                (Resolution::Merged(_), _, _) => return Err(Error::MissingRow(change.seq)),
+                (Resolution::TakeRemote, Op::Delete, _) => {
+                    let (_, key) = table_columns(conn, &change.table)?;
+                    conn.execute(
+                        &format!(
+                            "delete from {} where {} = ?",
+                            quote_ident(&change.table),
+                            quote_ident(&key)
+                        ),
+                        (&change.key,),
+                    )?;
+                }
+                (Resolution::TakeRemote, _, Some(serde_json::Value::Object(row))) => {
+                    replace_row(conn, &change.table, row)?;
+                }
+                _ => return Err(Error::MissingRow(change.seq)),
+            }
+            report.applied += 1;
+        }
+        let latest = changes.iter().map(|c| c.lamport).max().unwrap_or(0);
+        conn.execute(
+            &format!(
+                "update {} set applying = 0, lamport = max(lamport, ?) + 1",
+                self.state_table()
+            ),
+            (latest,),
+        )?;
+        Ok(report)
+    }
+    /// Settle `change` if the row still has local changes in the log,
+    /// recording the conflict in `report`.
+    fn resolve(
+        &self,
+        conn: &Connection,
+        change: &Change,
+        strategy: &ConflictStrategy,
+        report: &mut ConflictReport,
+    ) -> Result<Resolution, Error> {
+        let local_lamport: Option<i64> = conn
+            .query_row(
+                &format!(
+                    "select lamport from {} where tbl = ? and key = ? order by seq desc limit 1",
+                    quote_ident(&self.table)
+                ),
+                (&change.table, &change.key),
+                |row| row.get(0),
+            )
+            .optional()?;
+        let local_lamport = match local_lamport {
+            Some(lamport) => lamport,
+            None => return Ok(Resolution::TakeRemote),
+        };
+        let local = local_row(conn, &change.table, &change.key)?;
+        let resolution = strategy.resolve(&Conflict {
+            remote: change,
+            local: local.as_ref(),
+            local_lamport,
+        });
+        report.conflicts.push(Resolved {
+            remote: change.clone(),
+            local,
+            resolution: resolution.clone(),
+        });
+        Ok(resolution)
+    }
+
+    /// Delete the changes up to and including `seq`, eg once every replica
+    /// has received them.
+    pub fn prune(&self, conn: &Connection, seq: i64) -> Result<usize, Error> {
+        Ok(conn.execute(
+            &format!("delete from {} where seq <= ?", quote_ident(&self.table)),
+            (seq,),
+        )?)
+    }
+}
+
+/// The columns of `table` and its key column.
+fn table_columns(conn: &Connection, table: &str) -> Result<(Vec<String>, String), Error> {
+    let mut stmt = conn.prepare("select name, pk from pragma_table_info(?) order by cid")?;
+    let info = stmt
+        .query_map((table,), |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    if info.is_empty() {
+        return Err(Error::UntrackedTable(table.to_string()));
+    }
+    let pk: Vec<_> = info.iter().filter(|(_, pk)| *pk > 0).collect();
+    let key = match pk.as_slice() {
+        [] => "rowid".to_string(),
+        [(name, _)] => name.clone(),
+        _ => return Err(Error::CompositeKey(table.to_string())),
+    };
+    Ok((info.into_iter().map(|(name, _)| name).collect(), key))
+}
+
+/// The row of `table` with `key` as a JSON object, if there is one.
+fn local_row(
+    conn: &Connection,
+    table: &str,
+    key: &Value,
+) -> Result<Option<serde_json::Value>, Error> {
+    let (columns, key_column) = table_columns(conn, table)?;
+    let fields: Vec<_> = columns
+        .iter()
+        .map(|c| format!("'{}', {}", c.replace('\'', "''"), quote_ident(c)))
+        .collect();
+    let row: Option<String> = conn
+        .query_row(
+            &format!(
+                "select json_object({}) from {} where {} = ?",
+                fields.join(", "),
+                quote_ident(table),
+                quote_ident(&key_column)
+            ),
+            (key,),
+            |row| row.get(0),
+        )
+        .optional()?;
+    row.map(|r| serde_json::from_str(&r))
+        .transpose()
+        .map_err(Error::InvalidRow)
+}
+
+fn replace_row(
+    conn: &Connection,
+    table: &str,
+    row: &Map<String, serde_json::Value>,
+) -> Result<(), Error> {
+    let columns: Vec<_> = row.keys().map(|c| quote_ident(c)).collect();
+    let values: Vec<Value> = row
+        .values()
+        .map(|v| match v {
+            serde_json::Value::Null => Value::Null,
+            serde_json::Value::Bool(b) => Value::Integer(*b as i64),
+            serde_json::Value::Number(n) => n
+                .as_i64()
+                .map(Value::Integer)
+                .unwrap_or_else(|| Value::Real(n.as_f64().unwrap_or_default())),
+            serde_json::Value::String(s) => Value::Text(s.clone()),
+            other => Value::Text(other.to_string()),
+        })
+        .collect();
+    conn.execute(
+        &format!(
+            "insert or replace into {}({}) values ({})",
+            quote_ident(table),
+            columns.join(", "),
+            vec!["?"; columns.len()].join(", ")
+        ),
+        rusqlite::params_from_iter(values),
+    )?;
+    Ok(())
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("table {0} is not tracked by the changelog")]
+    UntrackedTable(String),
+    #[error("table {0} has a composite primary key")]
+    CompositeKey(String),
+    #[error("unknown changelog operation {0}")]
+    InvalidOp(String),
+    #[error("changelog row is not valid JSON")]
+    InvalidRow(#[source] serde_json::Error),
+    #[error("change {0} has no row to apply")]
+    MissingRow(i64),
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn replica() -> (Connection, Changelog) {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch(
+            "create table notes( id integer primary key, title text, score real );
+            create table untracked( a );",
+        )
+        .expect("Failed to create tables");
+        let log = Changelog::new("changelog").track("notes");
+        let res = log.install(&db);
+        assert!(res.is_ok(), "Failed to install changelog: {:?}", res);
+        (db, log)
+    }
+
+    #[test]
+    fn record_changes() {
+        let (db, log) = replica();
+        db.execute_batch(
+            "insert into notes values (1, 'a', 1.5), (2, 'b', null);
+            update notes set title = 'c' where id = 1;
+            delete from notes where id = 2;
+            insert into untracked values (1);",
+        )
+        .unwrap();
+
+        let res = log.read(&db, 0, 100);
+        assert!(res.is_ok(), "Failed to read changelog: {:?}", res);
+        let changes = res.unwrap();
+        let summary: Vec<_> = changes
+            .iter()
+            .map(|c| (c.op, c.key.clone(), c.lamport))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (Op::Insert, Value::Integer(1), 1),
+                (Op::Insert, Value::Integer(2), 2),
+                (Op::Update, Value::Integer(1), 3),
+                (Op::Delete, Value::Integer(2), 4),
+            ]
+        );
+        assert_eq!(
+            changes[2].row,
+            Some(serde_json::json!({"id": 1, "title": "c", "score": 1.5}))
+        );
+        assert_eq!(changes[3].row, None);
+
+        assert_eq!(log.read(&db, 2, 1).unwrap()[0].seq, 3);
+        assert_eq!(log.prune(&db, 3).unwrap(), 3);
+        assert_eq!(log.read(&db, 0, 100).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn apply_changes_from_another_replica() {
+        let (source, source_log) = replica();
+        let (target, target_log) = replica();
+        source
+            .execute_batch(
+                "insert into notes values (1, 'a', 1.5), (2, 'b', null), (3, 'it''s', 2);
+                update notes set score = 3 where id = 2;
+                delete from notes where id = 1;",
+            )
+            .unwrap();
+        target
+            .execute("insert into notes values (9, 'local', null)", ())
+            .unwrap();
+
+        let changes = source_log.read(&source, 0, 100).unwrap();
+        let res = target_log.apply(&target, &changes);
+        assert!(res.is_ok(), "Failed to apply changes: {:?}", res);
+
+        let mut stmt = target
+            .prepare("select id, title, score from notes order by id")
+            .unwrap();
+        let rows: Vec<(i64, String, Option<f64>)> = stmt
+            .query_map((), |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                (2, "b".into(), Some(3.0)),
+                (3, "it's".into(), Some(2.0)),
+                (9, "local".into(), None)
+            ]
+        );
+        // Applied changes aren't logged again, and the clock moved past them.
+        assert_eq!(target_log.read(&target, 0, 100).unwrap().len(), 1);
+        assert_eq!(target_log.lamport(&target).unwrap(), 6);
+
+        let res = target_log.apply(
+            &target,
+            &[Change {
+                table: "untracked".into(),
+                ..changes[0].clone()
+            }],
+        );
+        assert!(matches!(res, Err(Error::UntrackedTable(_))));
+    }
+
+    fn document_replica() -> (Connection, Changelog) {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute(
+            "create table docs( id integer primary key, body text, updated_at integer, meta text )",
+            (),
+        )
+        .expect("Failed to create table");
+        let log = Changelog::new("changelog").track("docs");
+        log.install(&db).expect("Failed to install changelog");
+        (db, log)
+    }
+
+    fn doc(db: &Connection, id: i64) -> (String, i64, String) {
+        db.query_row(
+            "select body, updated_at, meta from docs where id = ?",
+            (id,),
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn last_writer_wins() {
+        let (a, a_log) = document_replica();
+        let (b, b_log) = document_replica();
+        a.execute_batch("insert into docs values (1, 'from a', 20, '{}'), (2, 'only a', 1, '{}')")
+            .unwrap();
+        b.execute("insert into docs values (1, 'from b', 10, '{}')", ())
+            .unwrap();
+        let a_changes = a_log.read(&a, 0, 100).unwrap();
+        let b_changes = b_log.read(&b, 0, 100).unwrap();
+
+        let strategy = ConflictStrategy::LastWriterWins("updated_at".into());
+        let res = b_log.apply_with(&b, &a_changes, &strategy);
+        assert!(res.is_ok(), "Failed to apply changes: {:?}", res);
+        let report = res.unwrap();
+        assert_eq!(report.applied, 2);
+        assert_eq!(report.conflicts.len(), 1);
+        assert_eq!(report.conflicts[0].remote.key, Value::Integer(1));
+        assert_eq!(
+            report.conflicts[0].local,
+            Some(serde_json::json!({"id": 1, "body": "from b", "updated_at": 10, "meta": "{}"}))
+        );
+        assert_eq!(report.conflicts[0].resolution, Resolution::TakeRemote);
+
+        let report = a_log.apply_with(&a, &b_changes, &strategy).unwrap();
+        assert_eq!(report.applied, 0);
+        assert_eq!(report.conflicts[0].resolution, Resolution::KeepLocal);
+
+        assert_eq!(doc(&a, 1), doc(&b, 1));
+        assert_eq!(doc(&b, 1).0, "from a");
+        assert_eq!(doc(&b, 2).0, "only a");
+    }
+
+    #[test]
+    fn merge_fields() {
+        let (a, a_log) = document_replica();
+        let (b, b_log) = document_replica();
+        a.execute(
+            r#"insert into docs values (1, 'from a', 1, '{"x": 1, "y": 1}')"#,
+            (),
+        )
+        .unwrap();
+        b.execute(
+            r#"insert into docs values (1, 'from b', 1, '{"y": 2, "z": 2}')"#,
+            (),
+        )
+        .unwrap();
+        let a_changes = a_log.read(&a, 0, 100).unwrap();
+        let b_changes = b_log.read(&b, 0, 100).unwrap();
+
+        let strategy = ConflictStrategy::MergeFields("meta".into());
+        let res = b_log.apply_with(&b, &a_changes, &strategy);
+        assert!(res.is_ok(), "Failed to apply changes: {:?}", res);
+        assert!(matches!(
+            res.unwrap().conflicts[0].resolution,
+            Resolution::Merged(_)
+        ));
+        let res = a_log.apply_with(&a, &b_changes, &strategy);
+        assert!(res.is_ok(), "Failed to apply changes: {:?}", res);
+
+        // Both replicas settle on the same row, merging the keys.
+        assert_eq!(doc(&a, 1), doc(&b, 1));
+        let meta: serde_json::Value = serde_json::from_str(&doc(&a, 1).2).unwrap();
+        assert_eq!(meta["x"], 1);
+        assert_eq!(meta["z"], 2);
+    }
+
+    #[test]
+    fn custom_strategy() {
+        let (a, a_log) = document_replica();
+        let (b, b_log) = document_replica();
+        a.execute("insert into docs values (1, 'from a', 2, '{}')", ())
+            .unwrap();
+        b.execute("insert into docs values (1, 'from b', 1, '{}')", ())
+            .unwrap();
+        b.execute("delete from docs where id = 1", ()).unwrap();
+
+        let strategy = ConflictStrategy::custom(|conflict| match conflict.local {
+            None => Resolution::KeepLocal,
+            Some(_) => Resolution::TakeRemote,
+        });
+        let res = b_log.apply_with(&b, &a_log.read(&a, 0, 100).unwrap(), &strategy);
+        assert!(res.is_ok(), "Failed to apply changes: {:?}", res);
+        let report = res.unwrap();
+        assert_eq!(report.conflicts[0].local, None);
+        assert_eq!(report.conflicts[0].resolution, Resolution::KeepLocal);
+        let count: i64 = b
+            .query_row("select count(*) from docs", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+}
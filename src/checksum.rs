@@ -0,0 +1,247 @@
+use hmac::{Hmac, Mac};
+use rusqlite::{
+    types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef},
+    Connection, ToSql,
+};
+use sha2::Sha256;
+
+use crate::{
+    column_type::{column_type, SqliteColumnType, StorageClass},
+    util::quote_ident,
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// An HMAC-SHA256 over some of a row's columns, stored as a 32 byte `BLOB`.
+/// Rows carrying one are tamper-evident: they can't be edited by hand (eg
+/// to extend a license) without the key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Checksum(pub [u8; 32]);
+
+impl FromSql for Checksum {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let blob = value.as_blob()?;
+        blob.try_into()
+            .map(Checksum)
+            .map_err(|_| FromSqlError::InvalidBlobSize {
+                expected_size: 32,
+                blob_size: blob.len(),
+            })
+    }
+}
+impl ToSql for Checksum {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(&self.0[..]))
+    }
+}
+column_type!("blob", Blob: Checksum);
+
+/// A field holding a `Checksum`, which may be missing.
+pub trait ChecksumField {
+    fn checksum(&self) -> Option<&Checksum>;
+}
+impl ChecksumField for Checksum {
+    fn checksum(&self) -> Option<&Checksum> {
+        Some(self)
+    }
+}
+impl ChecksumField for Option<Checksum> {
+    fn checksum(&self) -> Option<&Checksum> {
+        self.as_ref()
+    }
+}
+
+/// A row with a checksum column. Usually derived with
+/// `#[derive(Checksummed)]`: exactly one field, the checksum, is marked
+/// `#[checksum]`, and covers the fields marked `#[signed]`, or all other
+/// fields if none are marked. The table name is read from
+/// `#[table = "..."]` as with `#[derive(Table)]`.
+///
+/// Values are checksummed as SQLite stores them, so a row verifies after a
+/// round trip as long as its columns' affinities don't convert the values
+/// (eg text stored in an `INTEGER` column).
+pub trait Checksummed {
+    const TABLE: &'static str;
+    const CHECKSUM_COLUMN: &'static str;
+    const SIGNED_COLUMNS: &'static [&'static str];
+
+    /// The values of the signed columns, in the order of `SIGNED_COLUMNS`.
+    fn signed_values(&self) -> Vec<&dyn ToSql>;
+    fn stored_checksum(&self) -> Option<&Checksum>;
+    fn set_checksum(&mut self, checksum: Checksum);
+
+    fn compute_checksum(&self, key: &[u8]) -> rusqlite::Result<Checksum> {
+        let values = self
+            .signed_values()
+            .into_iter()
+            .map(|v| v.to_sql())
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(compute(key, values.iter().map(value_ref)))
+    }
+    /// Set the checksum from the current values, before writing the row.
+    fn sign(&mut self, key: &[u8]) -> rusqlite::Result<()> {
+        let checksum = self.compute_checksum(key)?;
+        self.set_checksum(checksum);
+        Ok(())
+    }
+    /// Whether the checksum is present and matches the current values.
+    fn verify(&self, key: &[u8]) -> rusqlite::Result<bool> {
+        let values = self
+            .signed_values()
+            .into_iter()
+            .map(|v| v.to_sql())
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(self
+            .stored_checksum()
+            .is_some_and(|c| matches(key, values.iter().map(value_ref), c)))
+    }
+}
+
+/// The rowids of the rows of `T`'s table whose checksum is missing or
+/// doesn't match, in order.
+pub fn verify_all<T: Checksummed>(conn: &Connection, key: &[u8]) -> rusqlite::Result<Vec<i64>> {
+    let columns: Vec<_> = T::SIGNED_COLUMNS.iter().map(|c| quote_ident(c)).collect();
+    let mut stmt = conn.prepare(&format!(
+        "select rowid, {}, {} from {} order by rowid",
+        quote_ident(T::CHECKSUM_COLUMN),
+        columns.join(", "),
+        quote_ident(T::TABLE)
+    ))?;
+    let mut rows = stmt.query(())?;
+    let mut invalid = vec![];
+    while let Some(row) = rows.next()? {
+        let checksum: Option<Checksum> = row.get(1).unwrap_or(None);
+        let values = (2..columns.len() + 2)
+            .map(|i| row.get_ref(i))
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        if !checksum.is_some_and(|c| matches(key, values.into_iter(), &c)) {
+            invalid.push(row.get(0)?);
+        }
+    }
+    Ok(invalid)
+}
+
+fn value_ref<'a>(output: &'a ToSqlOutput<'a>) -> ValueRef<'a> {
+    match output {
+        ToSqlOutput::Borrowed(v) => *v,
+        ToSqlOutput::Owned(v) => v.into(),
+        // Zeroblobs and the like don't hold values to sign.
+        _ => ValueRef::Null,
+    }
+}
+
+fn mac<'a>(key: &[u8], values: impl Iterator<Item = ValueRef<'a>>) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts any key length");
+    // Each value is prefixed by its type, and variable length values by
+    // their length, so that different rows can't encode the same bytes.
+    for value in values {
+        match value {
+            ValueRef::Null => mac.update(&[0]),
+            ValueRef::Integer(i) => {
+                mac.update(&[1]);
+                mac.update(&i.to_be_bytes());
+            }
+            ValueRef::Real(f) => {
+                mac.update(&[2]);
+                mac.update(&f.to_be_bytes());
+            }
+            ValueRef::Text(t) => {
+                mac.update(&[3]);
+                mac.update(&(t.len() as u64).to_be_bytes());
+                mac.update(t);
+            }
+            ValueRef::Blob(b) => {
+                mac.update(&[4]);
+                mac.update(&(b.len() as u64).to_be_bytes());
+                mac.update(b);
+            }
+        }
+    }
+    mac
+}
+fn compute<'a>(key: &[u8], values: impl Iterator<Item = ValueRef<'a>>) -> Checksum {
+    Checksum(mac(key, values).finalize().into_bytes().into())
+}
+fn matches<'a>(
+    key: &[u8],
+    values: impl Iterator<Item = ValueRef<'a>>,
+    checksum: &Checksum,
+) -> bool {
+    mac(key, values).verify_slice(&checksum.0).is_ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::{Checksummed, Table, TryFromRow};
+
+    const KEY: &[u8] = b"secret key";
+
+    #[derive(Table, TryFromRow, Checksummed, Debug, PartialEq)]
+    struct License {
+        #[signed]
+        owner: String,
+        #[signed]
+        seats: i64,
+        note: Option<String>,
+        #[checksum]
+        checksum: Option<Checksum>,
+    }
+
+    #[test]
+    fn sign_and_verify() {
+        let mut license = License {
+            owner: "ada".into(),
+            seats: 5,
+            note: None,
+            checksum: None,
+        };
+        assert!(!license.verify(KEY).unwrap());
+        license.sign(KEY).unwrap();
+        assert!(license.verify(KEY).unwrap());
+        assert!(!license.verify(b"other key").unwrap());
+
+        license.note = Some("unsigned".into());
+        assert!(license.verify(KEY).unwrap());
+        license.seats = 500;
+        assert!(!license.verify(KEY).unwrap());
+    }
+
+    #[test]
+    fn detect_tampering() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        License::create_table(&db).expect("Failed to create table");
+        for (owner, seats) in [("ada", 5), ("grace", 10), ("alan", 1)] {
+            let mut license = License {
+                owner: owner.into(),
+                seats,
+                note: None,
+                checksum: None,
+            };
+            license.sign(KEY).unwrap();
+            db.execute(&License::schema().insert_sql(), &*license.params())
+                .expect("Failed to insert row");
+        }
+        db.execute("update license set seats = 1000 where owner = 'grace'", ())
+            .unwrap();
+        db.execute("update license set note = 'hello' where owner = 'ada'", ())
+            .unwrap();
+        db.execute(
+            "update license set checksum = null where owner = 'alan'",
+            (),
+        )
+        .unwrap();
+
+        let res = verify_all::<License>(&db, KEY);
+        assert!(res.is_ok(), "Failed to verify rows: {:?}", res);
+        assert_eq!(res.unwrap(), vec![2, 3]);
+
+        let license = db
+            .query_row("select * from license where owner = 'ada'", (), |row| {
+                License::try_from(row)
+            })
+            .unwrap();
+        assert!(license.verify(KEY).unwrap());
+    }
+}
@@ -0,0 +1,350 @@
+//! Generating Rust structs from the schema of an existing database, as a
+//! starting point for using the crate with it.
+
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use rusqlite::Connection;
+use thiserror::Error;
+
+use crate::pragma::{table_info, TableColumn};
+
+/// Generates a struct deriving `TryFromRow` for each table of a database.
+///
+/// Fields are typed by the columns' declared types, following SQLite's
+/// affinity rules, and are `Option`s unless the column is `NOT NULL`. Some
+/// columns get the crate's wrapper types: an `INTEGER PRIMARY KEY` is an
+/// `IntegerId` of the struct, a column defaulting to the current Unix time
+/// a `UnixEpoch` (or `TimestampMillis`, if scaled by 1000), and a column
+/// declared `JSON` or checked with `json_valid` a `JsonObject`. Columns
+/// which aren't valid field names are renamed with
+/// `#[try_from_row(column = "...")]`.
+#[derive(Clone, Debug, Default)]
+pub struct Codegen {
+    tables: Option<Vec<String>>,
+    derives: Vec<String>,
+}
+
+impl Codegen {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Only generate structs for `tables` (by default, every table).
+    pub fn tables(mut self, tables: &[&str]) -> Self {
+        self.tables = Some(tables.iter().map(|t| t.to_string()).collect());
+        self
+    }
+    /// Derive `path` as well as `Debug`, `Clone`, `PartialEq` and
+    /// `TryFromRow`.
+    pub fn derive(mut self, path: &str) -> Self {
+        self.derives.push(path.to_string());
+        self
+    }
+
+    /// The source of the structs, in table name order.
+    pub fn generate(&self, conn: &Connection) -> rusqlite::Result<String> {
+        let mut stmt = conn.prepare(
+            "select name, sql from sqlite_master
+            where type = 'table' and name not like 'sqlite\\_%' escape '\\'
+            order by name",
+        )?;
+        let tables = stmt
+            .query_map((), |row| Ok((row.get::<_, String>(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<(String, String)>>>()?;
+        // Virtual tables are skipped, along with the tables holding their
+        // data, which are named after them.
+        let virtual_tables: Vec<_> = tables
+            .iter()
+            .filter(|(_, sql)| sql.to_ascii_lowercase().starts_with("create virtual "))
+            .map(|(name, _)| format!("{}_", name))
+            .collect();
+        let tables = tables.into_iter().filter(|(name, sql)| {
+            !sql.to_ascii_lowercase().starts_with("create virtual ")
+                && !virtual_tables.iter().any(|v| name.starts_with(v.as_str()))
+        });
+
+        let mut out = String::from(
+            "// Generated by rusqlite_utils::codegen from the schema of a database.\n",
+        );
+        for (table, sql) in tables {
+            if let Some(only) = &self.tables {
+                if !only.contains(&table) {
+                    continue;
+                }
+            }
+            out.push('\n');
+            self.write_struct(conn, &table, &sql, &mut out)?;
+        }
+        Ok(out)
+    }
+
+    fn write_struct(
+        &self,
+        conn: &Connection,
+        table: &str,
+        sql: &str,
+        out: &mut String,
+    ) -> rusqlite::Result<()> {
+        let columns = table_info(conn, table)?;
+        let single_key = columns.iter().filter(|c| c.pk > 0).count() == 1;
+        // The primary key of a `WITHOUT ROWID` table can't be NULL, though
+        // it isn't reported as `NOT NULL`.
+        let without_rowid = sql
+            .to_ascii_lowercase()
+            .split_whitespace()
+            .collect::<String>()
+            .ends_with("withoutrowid");
+
+        let name = struct_name(table);
+        let mut derives = vec![
+            "Debug".to_string(),
+            "Clone".to_string(),
+            "PartialEq".to_string(),
+        ];
+        derives.extend(self.derives.iter().cloned());
+        derives.push("rusqlite_utils::TryFromRow".to_string());
+        // Writing to a `String` can't fail.
+        let _ = writeln!(out, "/// The `{}` table.", table.replace('`', "'"));
+        let _ = writeln!(out, "#[derive({})]", derives.join(", "));
+        let _ = writeln!(out, "pub struct {} {{", name);
+        for column in &columns {
+            let pk = column.pk > 0;
+            let rowid = single_key && pk && column.decl_type.eq_ignore_ascii_case("integer");
+            let ty = if rowid {
+                format!("rusqlite_utils::id::IntegerId<{}>", name)
+            } else {
+                rust_type(column, sql)
+            };
+            let ty = if rowid || column.not_null || (pk && without_rowid) {
+                ty
+            } else {
+                format!("Option<{}>", ty)
+            };
+            let field = field_name(&column.name);
+            if field != column.name {
+                let _ = writeln!(out, "    #[try_from_row(column = {:?})]", column.name);
+            }
+            let _ = writeln!(out, "    pub {}: {},", field, ty);
+        }
+        out.push_str("}\n");
+        Ok(())
+    }
+}
+
+/// The type of the column's field, without the `Option` of nullable
+/// columns. `table_sql` is the table's `CREATE TABLE` statement, for its
+/// checks.
+fn rust_type(column: &TableColumn, table_sql: &str) -> String {
+    let decl_type = column.decl_type.to_ascii_uppercase();
+    if decl_type == "JSON" || json_checked(column, table_sql) {
+        return "rusqlite_utils::object::JsonObject<serde_json::Value>".to_string();
+    }
+    if let Some(default) = &column.default {
+        let default = default.to_ascii_lowercase().replace(' ', "");
+        if default.contains("unixepoch(") || default.contains("strftime('%s','now')") {
+            return if default.contains("*1000") {
+                "rusqlite_utils::date_time::TimestampMillis".to_string()
+            } else {
+                "rusqlite_utils::date_time::UnixEpoch".to_string()
+            };
+        }
+    }
+    // The affinity rules, in order.
+    let ty = if decl_type.contains("INT") {
+        "i64"
+    } else if ["CHAR", "CLOB", "TEXT"]
+        .iter()
+        .any(|t| decl_type.contains(t))
+    {
+        "String"
+    } else if decl_type.contains("BLOB") {
+        "Vec<u8>"
+    } else if decl_type.is_empty() {
+        "rusqlite::types::Value"
+    } else if decl_type.starts_with("BOOL") {
+        "bool"
+    } else {
+        "f64"
+    };
+    ty.to_string()
+}
+
+/// Whether the table checks the column with `json_valid`.
+fn json_checked(column: &TableColumn, table_sql: &str) -> bool {
+    let sql = table_sql.to_ascii_lowercase().replace(' ', "");
+    let name = column.name.to_ascii_lowercase();
+    [
+        format!("json_valid({})", name),
+        format!("json_valid(\"{}\")", name.replace('"', "\"\"")),
+        format!("json_valid(`{}`)", name),
+        format!("json_valid([{}])", name),
+    ]
+    .iter()
+    .any(|check| sql.contains(&check.replace(' ', "")))
+}
+
+/// A table name in upper camel case.
+fn struct_name(table: &str) -> String {
+    let mut name = String::new();
+    for word in table.split(|c: char| !c.is_ascii_alphanumeric()) {
+        let mut chars = word.chars();
+        if let Some(first) = chars.next() {
+            name.push(first.to_ascii_uppercase());
+            name.extend(chars);
+        }
+    }
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        name.insert(0, 'T');
+    }
+    name
+}
+
+/// A column name as a snake case field name, avoiding keywords.
+fn field_name(column: &str) -> String {
+    let mut name = String::new();
+    for (i, c) in column.char_indices() {
+        if c.is_ascii_uppercase() {
+            let after_lower = column[..i].ends_with(|p: char| p.is_ascii_lowercase());
+            if after_lower {
+                name.push('_');
+            }
+            name.push(c.to_ascii_lowercase());
+        } else if c.is_ascii_alphanumeric() {
+            name.push(c);
+        } else if !name.ends_with('_') {
+            name.push('_');
+        }
+    }
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert(0, '_');
+    }
+    const KEYWORDS: &[&str] = &[
+        "as", "async", "await", "box", "break", "const", "continue", "crate", "dyn", "else",
+        "enum", "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod",
+        "move", "mut", "pub", "ref", "return", "self", "static", "struct", "super", "trait",
+        "true", "try", "type", "unsafe", "use", "where", "while", "yield",
+    ];
+    if KEYWORDS.contains(&name.as_str()) {
+        name.push('_');
+    }
+    name
+}
+
+/// Generate structs for the database at `db` into `file` in `OUT_DIR`, for
+/// use in a build script, and have Cargo rerun the script if the database
+/// changes. The file can then be included with
+/// `include!(concat!(env!("OUT_DIR"), "/file.rs"))`.
+pub fn build_script<P: AsRef<Path>>(
+    db: P,
+    file: &str,
+    codegen: &Codegen,
+) -> Result<PathBuf, Error> {
+    let db = db.as_ref();
+    let out_dir = std::env::var_os("OUT_DIR").ok_or(Error::NoOutDir)?;
+    let conn = Connection::open_with_flags(db, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let source = codegen.generate(&conn)?;
+    let path = Path::new(&out_dir).join(file);
+    std::fs::write(&path, source)?;
+    println!("cargo:rerun-if-changed={}", db.display());
+    Ok(path)
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("OUT_DIR isn't set; build_script must be called from a build script")]
+    NoOutDir,
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // The output of `generate_structs`, which must compile.
+    #[derive(Debug, Clone, PartialEq, rusqlite_utils::TryFromRow)]
+    pub struct AuditLog {
+        pub id: rusqlite_utils::id::IntegerId<AuditLog>,
+        #[try_from_row(column = "userName")]
+        pub user_name: String,
+        pub created: rusqlite_utils::date_time::UnixEpoch,
+        pub payload: Option<rusqlite_utils::object::JsonObject<serde_json::Value>>,
+        #[try_from_row(column = "type")]
+        pub type_: Option<rusqlite::types::Value>,
+        pub score: Option<f64>,
+        pub image: Option<Vec<u8>>,
+    }
+
+    const SCHEMA: &str = "create table audit_log(
+            id integer primary key,
+            userName varchar(40) not null,
+            created integer not null default (unixepoch()),
+            payload text check (json_valid(payload)),
+            type,
+            score decimal(5, 2),
+            image blob
+        );
+        create table settings( key text primary key, value json not null ) without rowid;
+        create virtual table search using fts5(body);";
+
+    #[test]
+    fn generate_structs() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch(SCHEMA).expect("Failed to create tables");
+
+        let res = Codegen::new().generate(&db);
+        assert!(res.is_ok(), "Failed to generate structs: {:?}", res);
+        let expected = r#"// Generated by rusqlite_utils::codegen from the schema of a database.
+
+/// The `audit_log` table.
+#[derive(Debug, Clone, PartialEq, rusqlite_utils::TryFromRow)]
+pub struct AuditLog {
+    pub id: rusqlite_utils::id::IntegerId<AuditLog>,
+    #[try_from_row(column = "userName")]
+    pub user_name: String,
+    pub created: rusqlite_utils::date_time::UnixEpoch,
+    pub payload: Option<rusqlite_utils::object::JsonObject<serde_json::Value>>,
+    #[try_from_row(column = "type")]
+    pub type_: Option<rusqlite::types::Value>,
+    pub score: Option<f64>,
+    pub image: Option<Vec<u8>>,
+}
+
+/// The `settings` table.
+#[derive(Debug, Clone, PartialEq, rusqlite_utils::TryFromRow)]
+pub struct Settings {
+    pub key: String,
+    pub value: rusqlite_utils::object::JsonObject<serde_json::Value>,
+}
+"#;
+        assert_eq!(res.unwrap(), expected);
+
+        db.execute(
+            "insert into audit_log(userName, payload) values ('ada', '{\"a\": 1}')",
+            (),
+        )
+        .unwrap();
+        let res: rusqlite::Result<AuditLog> =
+            db.query_row("select * from audit_log", (), |row| row.try_into());
+        assert!(res.is_ok(), "Failed to read generated struct: {:?}", res);
+    }
+
+    #[test]
+    fn generate_selected_tables() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch(SCHEMA).expect("Failed to create tables");
+        let source = Codegen::new()
+            .tables(&["settings"])
+            .derive("serde::Serialize")
+            .generate(&db)
+            .unwrap();
+        assert!(!source.contains("AuditLog"));
+        assert!(source.contains(
+            "#[derive(Debug, Clone, PartialEq, serde::Serialize, rusqlite_utils::TryFromRow)]"
+        ));
+    }
+}
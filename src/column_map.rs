@@ -0,0 +1,134 @@
+use std::marker::PhantomData;
+
+use rusqlite::{Connection, Params, Row, Statement};
+
+use crate::row::Columns;
+
+/// The positions of the columns of `T` in the results of a statement,
+/// resolved once so that reading each row doesn't look its columns up by
+/// name (a scan of the statement's columns per field).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ColumnMap<T> {
+    /// The index of each of `T::COLUMNS`, if the statement returns it.
+    indices: Vec<Option<usize>>,
+    _columns: PhantomData<fn() -> T>,
+}
+
+impl<T: Columns> ColumnMap<T> {
+    /// Resolve `T::COLUMNS` against the columns of `stmt`. Names match
+    /// regardless of ASCII case, as for `Row::get`.
+    pub fn new(stmt: &Statement<'_>) -> Self {
+        let names = stmt.column_names();
+        let indices = T::COLUMNS
+            .iter()
+            .map(|column| names.iter().position(|n| n.eq_ignore_ascii_case(column)))
+            .collect();
+        Self {
+            indices,
+            _columns: PhantomData,
+        }
+    }
+}
+
+impl<T> ColumnMap<T> {
+    /// The index of the `i`th of `T::COLUMNS`, named `name`, failing with
+    /// `InvalidColumnName` if the statement doesn't return it.
+    pub fn index(&self, i: usize, name: &str) -> rusqlite::Result<usize> {
+        self.indices
+            .get(i)
+            .copied()
+            .flatten()
+            .ok_or_else(|| rusqlite::Error::InvalidColumnName(name.to_string()))
+    }
+}
+
+/// A type read from a row through a `ColumnMap`. Implemented by
+/// `#[derive(TryFromRow)]` for structs whose columns are known (ie without
+/// flattened fields) and read by name.
+pub trait TryFromRowWithMap: Columns + Sized {
+    fn try_from_row_with_map(row: &Row<'_>, map: &ColumnMap<Self>) -> rusqlite::Result<Self>;
+}
+
+/// Read every row of a statement, resolving the columns once.
+pub fn query_mapped<T, P>(stmt: &mut Statement<'_>, params: P) -> rusqlite::Result<Vec<T>>
+where
+    T: TryFromRowWithMap,
+    P: Params,
+{
+    let map = ColumnMap::new(stmt);
+    let rows = stmt
+        .query_map(params, |row| T::try_from_row_with_map(row, &map))?
+        .collect();
+    rows
+}
+
+/// As `ConnectionExt::query_all`, resolving the columns once.
+pub fn query_all<T, P>(conn: &Connection, sql: &str, params: P) -> rusqlite::Result<Vec<T>>
+where
+    T: TryFromRowWithMap,
+    P: Params,
+{
+    query_mapped(&mut *conn.prepare_cached(sql)?, params)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::TryFromRow;
+
+    #[derive(TryFromRow, Debug, PartialEq)]
+    struct Order {
+        id: i64,
+        #[try_from_row(column = "customer_name")]
+        customer: String,
+        #[try_from_row(default)]
+        note: Option<String>,
+        #[try_from_row(skip)]
+        loaded: bool,
+    }
+
+    #[test]
+    fn read_through_map() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch(
+            "create table orders( id integer, customer_name text );
+            insert into orders values (1, 'ada'), (2, 'grace');",
+        )
+        .expect("Failed to create table");
+
+        let stmt = db.prepare("select customer_name, id from orders").unwrap();
+        let map = ColumnMap::<Order>::new(&stmt);
+        assert_eq!(map.index(0, "id").unwrap(), 1);
+        assert_eq!(map.index(1, "customer_name").unwrap(), 0);
+        assert!(map.index(2, "note").is_err());
+
+        let res =
+            query_all::<Order, _>(&db, "select Customer_Name, ID from orders order by id", ());
+        assert!(res.is_ok(), "Failed to read rows: {:?}", res);
+        assert_eq!(
+            res.unwrap(),
+            vec![
+                Order {
+                    id: 1,
+                    customer: "ada".into(),
+                    note: None,
+                    loaded: false
+                },
+                Order {
+                    id: 2,
+                    customer: "grace".into(),
+                    note: None,
+                    loaded: false
+                }
+            ]
+        );
+
+        let res = query_all::<Order, _>(&db, "select id from orders", ());
+        assert!(
+            matches!(res, Err(rusqlite::Error::InvalidColumnName(ref c)) if c == "customer_name"),
+            "Read rows missing a column: {:?}",
+            res
+        );
+    }
+}
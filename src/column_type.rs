@@ -0,0 +1,146 @@
+/// The storage classes of SQLite values, other than NULL.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum StorageClass {
+    Integer,
+    Real,
+    Text,
+    Blob,
+}
+impl StorageClass {
+    /// The name returned by `typeof()`.
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            StorageClass::Integer => "integer",
+            StorageClass::Real => "real",
+            StorageClass::Text => "text",
+            StorageClass::Blob => "blob",
+        }
+    }
+}
+
+/// The type affinity of a column, which SQLite derives from its declared
+/// type and uses to convert the values written to it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Affinity {
+    Integer,
+    Text,
+    Blob,
+    Real,
+    Numeric,
+}
+impl Affinity {
+    /// The affinity of a column declared as `decl_type`, by SQLite's rules
+    /// (so an empty type has `BLOB` affinity).
+    pub fn of(decl_type: &str) -> Self {
+        let decl_type = decl_type.to_ascii_uppercase();
+        if decl_type.contains("INT") {
+            Self::Integer
+        } else if ["CHAR", "CLOB", "TEXT"]
+            .iter()
+            .any(|t| decl_type.contains(t))
+        {
+            Self::Text
+        } else if decl_type.contains("BLOB") || decl_type.is_empty() {
+            Self::Blob
+        } else if ["REAL", "FLOA", "DOUB"]
+            .iter()
+            .any(|t| decl_type.contains(t))
+        {
+            Self::Real
+        } else {
+            Self::Numeric
+        }
+    }
+    /// Whether values of `storage` written to the column read back as
+    /// their Rust type: text written to a numeric column may be converted
+    /// to a number, and integers written to a `REAL` column to reals.
+    pub fn holds(self, storage: StorageClass) -> bool {
+        match storage {
+            StorageClass::Blob => true,
+            StorageClass::Text => matches!(self, Self::Text | Self::Blob),
+            StorageClass::Integer => self != Self::Real && self != Self::Text,
+            // Integral reals may be stored as integers, which read as reals.
+            StorageClass::Real => self != Self::Text,
+        }
+    }
+}
+
+/// How a Rust type is stored in a column: its declared type, the storage
+/// class of its values, whether it may be NULL and the column's default.
+/// `#[derive(Table)]` builds column definitions from it (so every field
+/// type must implement it), `TableSchema::verify` checks stored values
+/// against it, and the declared type guides `row::row_to_json`.
+///
+/// Every item has a default, so wrapper types in other crates can opt in
+/// with an empty impl and override only what they know.
+pub trait SqliteColumnType {
+    /// The declared type, eg `integer`.
+    const DECL_TYPE: Option<&'static str> = None;
+    /// The storage class of every non-NULL value, if there is just one.
+    const STORAGE: Option<StorageClass> = None;
+    const NULLABLE: bool = false;
+    /// The column's `DEFAULT` expression.
+    const DEFAULT: Option<&'static str> = None;
+}
+
+macro_rules! column_type {
+    ($decl_type:literal, $storage:ident: $($ty:ty),*) => {
+        $(
+            impl SqliteColumnType for $ty {
+                const DECL_TYPE: Option<&'static str> = Some($decl_type);
+                const STORAGE: Option<StorageClass> = Some(StorageClass::$storage);
+            }
+        )*
+    };
+}
+pub(crate) use column_type;
+
+column_type!("integer", Integer: i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+column_type!("real", Real: f32, f64);
+column_type!("text", Text: String);
+column_type!("blob", Blob: Vec<u8>);
+// Declared as boolean so that `row_to_json` exports them as such; the
+// affinity is still numeric.
+column_type!("boolean", Integer: bool);
+
+impl<T: SqliteColumnType> SqliteColumnType for Option<T> {
+    const DECL_TYPE: Option<&'static str> = T::DECL_TYPE;
+    const STORAGE: Option<StorageClass> = T::STORAGE;
+    const NULLABLE: bool = true;
+    const DEFAULT: Option<&'static str> = T::DEFAULT;
+}
+
+#[cfg(test)]
+mod test {
+    use rusqlite::Connection;
+    use serde_json::json;
+
+    use crate::{object::JsonObject, row::query_to_json, Table};
+
+    #[derive(Table)]
+    struct Flag {
+        name: String,
+        enabled: bool,
+        data: Option<JsonObject<serde_json::Value>>,
+    }
+
+    #[test]
+    fn export_declared_types() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        Flag::create_table(&db).expect("Failed to create table");
+        let flag = Flag {
+            name: "beta".into(),
+            enabled: true,
+            data: Some(JsonObject::new(json!({"rollout": 10}))),
+        };
+        db.execute(&Flag::schema().insert_sql(), &*flag.params())
+            .expect("Failed to insert row");
+
+        let res = query_to_json(&db, "select * from flag", ());
+        assert!(res.is_ok(), "Failed to export rows: {:?}", res);
+        assert_eq!(
+            res.unwrap(),
+            vec![json!({"name": "beta", "enabled": true, "data": {"rollout": 10}})]
+        );
+    }
+}
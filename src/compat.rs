@@ -0,0 +1,324 @@
+use std::fmt::Display;
+
+use rusqlite::{Connection, ToSql};
+use thiserror::Error;
+
+use crate::util::quote_ident;
+
+/// SQLite features which some helpers use when available.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Feature {
+    /// `INSERT ... ON CONFLICT DO UPDATE`.
+    Upsert,
+    GeneratedColumns,
+    Returning,
+    DropColumn,
+    StrictTables,
+    /// The binary JSON format and its `jsonb` functions.
+    Jsonb,
+}
+impl Feature {
+    /// The first version supporting the feature, as `sqlite3_libversion_number`.
+    pub fn min_version(&self) -> i32 {
+        match self {
+            Feature::Upsert => 3_024_000,
+            Feature::GeneratedColumns => 3_031_000,
+            Feature::Returning => 3_035_000,
+            Feature::DropColumn => 3_035_000,
+            Feature::StrictTables => 3_037_000,
+            Feature::Jsonb => 3_045_000,
+        }
+    }
+}
+impl Display for Feature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Feature::Upsert => "upsert",
+            Feature::GeneratedColumns => "generated columns",
+            Feature::Returning => "RETURNING",
+            Feature::DropColumn => "DROP COLUMN",
+            Feature::StrictTables => "strict tables",
+            Feature::Jsonb => "JSONB",
+        };
+        f.write_str(name)
+    }
+}
+
+/// What to do when a feature isn't supported.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Policy {
+    /// Use SQL which works on older versions, even if it behaves slightly
+    /// differently (eg tables which aren't strict).
+    #[default]
+    Fallback,
+    /// Fail with `Error::Unsupported`.
+    Require,
+}
+
+/// The features of the SQLite a connection uses, probed once and kept
+/// alongside the connection, and SQL which falls back to older syntax when
+/// they're missing.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Compat {
+    version: i32,
+    policy: Policy,
+}
+
+impl Compat {
+    /// Read the version of SQLite used by `conn`.
+    pub fn probe(conn: &Connection) -> rusqlite::Result<Self> {
+        let version: String = conn.query_row("select sqlite_version()", (), |row| row.get(0))?;
+        let mut parts = version.split('.').map(|p| p.parse::<i32>().unwrap_or(0));
+        let mut part = || parts.next().unwrap_or(0);
+        Ok(Self::with_version(
+            part() * 1_000_000 + part() * 1000 + part(),
+        ))
+    }
+    /// Assume a version, eg to test fallbacks.
+    pub fn with_version(version: i32) -> Self {
+        Self {
+            version,
+            policy: Policy::default(),
+        }
+    }
+    pub fn policy(mut self, policy: Policy) -> Self {
+        self.policy = policy;
+        self
+    }
+    /// The version, as `sqlite3_libversion_number`.
+    pub fn version(&self) -> i32 {
+        self.version
+    }
+
+    pub fn supports(&self, feature: Feature) -> bool {
+        self.version >= feature.min_version()
+    }
+    pub fn require(&self, feature: Feature) -> Result<(), Error> {
+        if self.supports(feature) {
+            Ok(())
+        } else {
+            Err(Error::Unsupported {
+                feature,
+                version: self.version,
+            })
+        }
+    }
+    /// Whether to use `feature`, failing if it's missing under
+    /// `Policy::Require`.
+    fn choose(&self, feature: Feature) -> Result<bool, Error> {
+        match self.policy {
+            Policy::Fallback => Ok(self.supports(feature)),
+            Policy::Require => self.require(feature).map(|_| true),
+        }
+    }
+
+    /// `create` (a `CREATE TABLE` statement) made strict, if strict tables
+    /// are supported. Types which strict tables don't allow should be
+    /// avoided.
+    pub fn strict_table(&self, create: &str) -> Result<String, Error> {
+        Ok(if self.choose(Feature::StrictTables)? {
+            format!("{} strict", create.trim_end().trim_end_matches(';'))
+        } else {
+            create.to_string()
+        })
+    }
+    /// An expression converting the JSON `expr` to the format it's stored
+    /// in: `jsonb(expr)` if supported, otherwise `json(expr)`. Stored values
+    /// are read back as text with `json(column)` either way.
+    pub fn json_value(&self, expr: &str) -> Result<String, Error> {
+        Ok(if self.choose(Feature::Jsonb)? {
+            format!("jsonb({})", expr)
+        } else {
+            format!("json({})", expr)
+        })
+    }
+
+    /// Insert a row into `table`, or update its other columns if one with
+    /// the same `key` columns exists. `values` are in the order of
+    /// `columns`, which must include the key columns.
+    ///
+    /// Without `ON CONFLICT`, this runs an update and then an insert if no
+    /// row was updated, in a savepoint. Returns the rows changed.
+    pub fn upsert(
+        &self,
+        conn: &Connection,
+        table: &str,
+        columns: &[&str],
+        key: &[&str],
+        values: &[&dyn ToSql],
+    ) -> Result<usize, Error> {
+        // Each column is bound to the parameter numbered by its position.
+        let param = |column: &str| columns.iter().position(|c| *c == column).map(|i| i + 1);
+        let condition = key
+            .iter()
+            .map(|c| {
+                param(c)
+                    .map(|p| format!("{} = ?{}", quote_ident(c), p))
+                    .ok_or_else(|| Error::MissingKey(c.to_string()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let others: Vec<_> = columns.iter().filter(|c| !key.contains(c)).collect();
+        let insert = |or: &str| {
+            format!(
+                "insert {}into {}({}) values ({})",
+                or,
+                quote_ident(table),
+                columns
+                    .iter()
+                    .map(|c| quote_ident(c))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                (1..=columns.len())
+                    .map(|i| format!("?{}", i))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        };
+
+        if self.choose(Feature::Upsert)? {
+            let action = if others.is_empty() {
+                "nothing".to_string()
+            } else {
+                let updates: Vec<_> = others
+                    .iter()
+                    .map(|c| format!("{c} = excluded.{c}", c = quote_ident(c)))
+                    .collect();
+                format!("update set {}", updates.join(", "))
+            };
+            let key: Vec<_> = key.iter().map(|c| quote_ident(c)).collect();
+            let sql = format!(
+                "{} on conflict({}) do {}",
+                insert(""),
+                key.join(", "),
+                action
+            );
+            return Ok(conn.execute(&sql, values)?);
+        }
+
+        let update = if others.is_empty() {
+            None
+        } else {
+            let updates: Vec<_> = others
+                .iter()
+                .map(|c| format!("{} = ?{}", quote_ident(c), param(c).unwrap_or_default()))
+                .collect();
+            Some(format!(
+                "update {} set {} where {}",
+                quote_ident(table),
+                updates.join(", "),
+                condition.join(" and ")
+            ))
+        };
+        conn.execute_batch("savepoint compat_upsert")?;
+        let res = update
+            .map_or(Ok(0), |sql| conn.execute(&sql, values))
+            .and_then(|updated| match updated {
+                0 => conn.execute(&insert("or ignore "), values),
+                n => Ok(n),
+            });
+        match res {
+            Ok(_) => conn.execute_batch("release compat_upsert")?,
+            Err(_) => conn.execute_batch("rollback to compat_upsert; release compat_upsert")?,
+        }
+        Ok(res?)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{feature} is not supported by SQLite {version}")]
+    Unsupported { feature: Feature, version: i32 },
+    #[error("key column {0} is not among the columns")]
+    MissingKey(String),
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const OLD: i32 = 3_020_000;
+
+    #[test]
+    fn probe_version() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        let res = Compat::probe(&db);
+        assert!(res.is_ok(), "Failed to probe version: {:?}", res);
+        let compat = res.unwrap();
+        assert_eq!(compat.version(), rusqlite::version_number());
+        assert!(compat.supports(Feature::Upsert));
+        assert!(!Compat::with_version(OLD).supports(Feature::Upsert));
+    }
+
+    #[test]
+    fn fall_back_or_fail() {
+        let create = "create table t( a integer );";
+        let old = Compat::with_version(OLD);
+        assert_eq!(old.strict_table(create).unwrap(), create);
+        assert_eq!(
+            Compat::with_version(3_037_000)
+                .strict_table(create)
+                .unwrap(),
+            "create table t( a integer ) strict"
+        );
+        assert_eq!(old.json_value("?").unwrap(), "json(?)");
+
+        let res = old.policy(Policy::Require).strict_table(create);
+        assert!(
+            matches!(
+                res,
+                Err(Error::Unsupported {
+                    feature: Feature::StrictTables,
+                    version: OLD
+                })
+            ),
+            "Fell back despite policy: {:?}",
+            res
+        );
+    }
+
+    #[test]
+    fn upsert_rows() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch(
+            "create table kv( ns text, k text, v integer, primary key (ns, k) );
+            create table tags( name text primary key );",
+        )
+        .expect("Failed to create tables");
+
+        for compat in [Compat::probe(&db).unwrap(), Compat::with_version(OLD)] {
+            db.execute_batch("delete from kv; delete from tags")
+                .unwrap();
+            for v in [1, 2] {
+                let res = compat.upsert(
+                    &db,
+                    "kv",
+                    &["ns", "k", "v"],
+                    &["ns", "k"],
+                    &[&"a", &"x", &v],
+                );
+                assert!(res.is_ok(), "Failed to upsert row: {:?}", res);
+                assert_eq!(res.unwrap(), 1);
+                compat
+                    .upsert(&db, "tags", &["name"], &["name"], &[&"t"])
+                    .unwrap();
+            }
+            let rows: Vec<(String, i64)> = db
+                .prepare("select k, v from kv")
+                .unwrap()
+                .query_map((), |row| Ok((row.get(0)?, row.get(1)?)))
+                .unwrap()
+                .collect::<rusqlite::Result<_>>()
+                .unwrap();
+            assert_eq!(rows, vec![("x".to_string(), 2)]);
+            let tags: i64 = db
+                .query_row("select count(*) from tags", (), |row| row.get(0))
+                .unwrap();
+            assert_eq!(tags, 1);
+        }
+
+        let res = Compat::with_version(OLD).upsert(&db, "kv", &["v"], &["k"], &[&1]);
+        assert!(matches!(res, Err(Error::MissingKey(ref k)) if k == "k"));
+    }
+}
@@ -0,0 +1,223 @@
+use std::{path::Path, time::Duration};
+
+use rusqlite::{Connection, OpenFlags, OptionalExtension, Params, Row};
+use thiserror::Error;
+
+use crate::{
+    cancel::{self, CancellationToken},
+    migration::{self, Migrations},
+};
+
+/// Opens connections with a consistent configuration: open flags, pragmas
+/// and migrations. Builders are cheap to clone, so one can be shared by
+/// everything that opens the same kind of database.
+#[derive(Clone, Debug, Default)]
+pub struct ConnectionBuilder {
+    flags: OpenFlags,
+    busy_timeout: Option<Duration>,
+    wal: bool,
+    foreign_keys: Option<bool>,
+    pragmas: Vec<(String, String)>,
+    migrations: Option<Migrations>,
+}
+
+impl ConnectionBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn flags(mut self, flags: OpenFlags) -> Self {
+        self.flags = flags;
+        self
+    }
+    /// How long to wait for locks held by other connections before failing
+    /// with `SQLITE_BUSY`.
+    pub fn busy_timeout(mut self, timeout: Duration) -> Self {
+        self.busy_timeout = Some(timeout);
+        self
+    }
+    /// Use write-ahead logging, so readers don't block the writer.
+    pub fn wal(mut self) -> Self {
+        self.wal = true;
+        self
+    }
+    pub fn foreign_keys(mut self, enabled: bool) -> Self {
+        self.foreign_keys = Some(enabled);
+        self
+    }
+    /// Set an arbitrary pragma. `value` is interpolated as is.
+    pub fn pragma(mut self, name: &str, value: &str) -> Self {
+        self.pragmas.push((name.to_string(), value.to_string()));
+        self
+    }
+    /// Apply pending migrations when opening.
+    pub fn migrations(mut self, migrations: Migrations) -> Self {
+        self.migrations = Some(migrations);
+        self
+    }
+
+    pub fn open<P: AsRef<Path>>(&self, path: P) -> Result<Connection, Error> {
+        let conn = Connection::open_with_flags(path, self.flags)?;
+        self.configure(&conn)?;
+        Ok(conn)
+    }
+    pub fn open_in_memory(&self) -> Result<Connection, Error> {
+        let conn = Connection::open_in_memory_with_flags(self.flags)?;
+        self.configure(&conn)?;
+        Ok(conn)
+    }
+
+    /// Apply the configuration to an already open connection.
+    pub fn configure(&self, conn: &Connection) -> Result<(), Error> {
+        if let Some(timeout) = self.busy_timeout {
+            conn.busy_timeout(timeout)?;
+        }
+        if self.wal {
+            // Returns the resulting mode, which stays "memory" for in-memory
+            // databases.
+            conn.query_row("pragma journal_mode = wal", (), |_| Ok(()))?;
+        }
+        if let Some(enabled) = self.foreign_keys {
+            conn.execute_batch(&format!("pragma foreign_keys = {}", enabled))?;
+        }
+        for (name, value) in &self.pragmas {
+            conn.execute_batch(&format!("pragma {} = {}", name, value))?;
+        }
+        if let Some(migrations) = &self.migrations {
+            migrations.apply(conn)?;
+        }
+        Ok(())
+    }
+}
+
+/// Query helpers for types converted with `TryFrom<&Row>`, such as those
+/// deriving `TryFromRow`.
+pub trait ConnectionExt {
+    fn query_all<T, P>(&self, sql: &str, params: P) -> rusqlite::Result<Vec<T>>
+    where
+        P: Params,
+        for<'r, 'stmt> T: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error>;
+    /// The first row of a query, failing with `QueryReturnedNoRows` if
+    /// there is none.
+    fn query_one<T, P>(&self, sql: &str, params: P) -> rusqlite::Result<T>
+    where
+        P: Params,
+        for<'r, 'stmt> T: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error>;
+    fn query_optional<T, P>(&self, sql: &str, params: P) -> rusqlite::Result<Option<T>>
+    where
+        P: Params,
+        for<'r, 'stmt> T: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error>;
+
+    /// Run `f`, interrupting it once `token` is cancelled; see
+    /// `cancel::with_cancellation`.
+    fn with_cancellation<T, F>(&self, token: &CancellationToken, f: F) -> Result<T, crate::Error>
+    where
+        F: FnOnce(&Connection) -> rusqlite::Result<T>;
+    /// Run `f`, interrupting it once `timeout` has elapsed; see
+    /// `cancel::with_timeout`.
+    fn with_timeout<T, F>(&self, timeout: Duration, f: F) -> Result<T, crate::Error>
+    where
+        F: FnOnce(&Connection) -> rusqlite::Result<T>;
+}
+
+impl ConnectionExt for Connection {
+    fn query_all<T, P>(&self, sql: &str, params: P) -> rusqlite::Result<Vec<T>>
+    where
+        P: Params,
+        for<'r, 'stmt> T: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error>,
+    {
+        let mut stmt = self.prepare_cached(sql)?;
+        let rows = stmt.query_map(params, |row| T::try_from(row))?.collect();
+        rows
+    }
+    fn query_one<T, P>(&self, sql: &str, params: P) -> rusqlite::Result<T>
+    where
+        P: Params,
+        for<'r, 'stmt> T: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error>,
+    {
+        self.query_row(sql, params, |row| T::try_from(row))
+    }
+    fn query_optional<T, P>(&self, sql: &str, params: P) -> rusqlite::Result<Option<T>>
+    where
+        P: Params,
+        for<'r, 'stmt> T: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error>,
+    {
+        self.query_one(sql, params).optional()
+    }
+
+    fn with_cancellation<T, F>(&self, token: &CancellationToken, f: F) -> Result<T, crate::Error>
+    where
+        F: FnOnce(&Connection) -> rusqlite::Result<T>,
+    {
+        cancel::with_cancellation(self, token, f)
+    }
+    fn with_timeout<T, F>(&self, timeout: Duration, f: F) -> Result<T, crate::Error>
+    where
+        F: FnOnce(&Connection) -> rusqlite::Result<T>,
+    {
+        cancel::with_timeout(self, timeout, f)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Migration(#[from] migration::Error),
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn configure_connection() {
+        let dir = tempfile::tempdir().expect("Failed to create directory");
+        let builder = ConnectionBuilder::new()
+            .wal()
+            .foreign_keys(false)
+            .busy_timeout(Duration::from_millis(100))
+            .pragma("cache_size", "-4096")
+            .migrations(Migrations::new().add("create foo", "create table foo( a )"));
+
+        let res = builder.open(dir.path().join("test.db"));
+        assert!(res.is_ok(), "Failed to open connection: {:?}", res);
+        let conn = res.unwrap();
+        let pragma = |name: &str| -> String {
+            conn.query_row(&format!("pragma {}", name), (), |row| {
+                row.get::<_, rusqlite::types::Value>(0)
+            })
+            .map(|v| format!("{:?}", v))
+            .unwrap()
+        };
+        assert_eq!(pragma("journal_mode"), "Text(\"wal\")");
+        assert_eq!(pragma("foreign_keys"), "Integer(0)");
+        assert_eq!(pragma("cache_size"), "Integer(-4096)");
+        assert_eq!(Migrations::current_version(&conn).unwrap(), 1);
+    }
+
+    #[test]
+    fn query_helpers() {
+        #[derive(crate::TryFromRow, Debug, PartialEq)]
+        struct Foo {
+            a: i64,
+        }
+        let conn = Connection::open_in_memory().expect("Failed to open connection");
+        conn.execute_batch("create table foo( a integer ); insert into foo values (1), (2);")
+            .unwrap();
+
+        let res: rusqlite::Result<Vec<Foo>> = conn.query_all("select a from foo order by a", ());
+        assert!(res.is_ok(), "Failed to query rows: {:?}", res);
+        assert_eq!(res.unwrap(), vec![Foo { a: 1 }, Foo { a: 2 }]);
+        let res: rusqlite::Result<Foo> = conn.query_one("select a from foo where a = ?", (2,));
+        assert_eq!(res.ok(), Some(Foo { a: 2 }));
+        let res: rusqlite::Result<Option<Foo>> =
+            conn.query_optional("select a from foo where a = ?", (3,));
+        assert_eq!(res.ok(), Some(None));
+
+        let res = conn.with_timeout(Duration::from_secs(10), |conn| {
+            conn.query_all::<Foo, _>("select a from foo", ())
+        });
+        assert_eq!(res.map(|rows| rows.len()).ok(), Some(2));
+    }
+}
@@ -0,0 +1,287 @@
+use rusqlite::{Connection, OptionalExtension, Params, Row, ToSql};
+
+use crate::{
+    date_time::{Clock, SystemClock},
+    row::Columns,
+    util::quote_ident,
+};
+
+/// A struct inserted as a row. Usually derived with `#[derive(Insert)]`,
+/// which reads the table name from `#[table = "..."]` (defaulting to the
+/// struct name in snake case) and binds every field to the column it's read
+/// from by `#[derive(TryFromRow)]`, so `#[try_from_row(column = "...")]`
+/// renames it, `#[try_from_row(multi_column)]` spreads it over several
+/// columns and `#[try_from_row(skip)]` leaves it out. Fields marked
+/// `#[generated]` are left out too.
+///
+/// `#[id]` marks the primary key for the other derives, but is inserted like
+/// any other field; an `Option<i64>` id left as `None` is assigned a rowid.
+///
+/// Fields marked `#[auto_now]` or `#[auto_now_add]` are written as the
+/// time of a `Clock` (the system's for `insert`) rather than their value,
+/// as their types' `AutoNow` makes it. `Update` writes only those marked
+/// `#[auto_now]`, leaving the others at the time the row was inserted.
+///
+/// Unlike `Table::insert`, this doesn't need the table's schema; where both
+/// are in scope, call it as `Insert::insert(&row, &conn)`.
+pub trait Insert {
+    /// The `INSERT` statement, with a numbered parameter per column.
+    fn insert_sql() -> String;
+
+    /// The parameters of `insert_sql`.
+    fn insert_params(&self) -> Vec<&dyn ToSql>;
+
+    /// Call `f` with the parameters of `insert_sql`, with the fields marked
+    /// `#[auto_now]` or `#[auto_now_add]` stamped from `clock`.
+    fn with_insert_params<R>(&self, _clock: &dyn Clock, f: impl FnOnce(&[&dyn ToSql]) -> R) -> R {
+        f(&self.insert_params())
+    }
+
+    /// Insert the row, returning its rowid.
+    fn insert(&self, conn: &Connection) -> rusqlite::Result<i64> {
+        self.insert_at(conn, &SystemClock)
+    }
+    /// Insert the row with its stamped fields set from `clock`, returning
+    /// its rowid.
+    fn insert_at(&self, conn: &Connection, clock: &dyn Clock) -> rusqlite::Result<i64> {
+        let mut stmt = conn.prepare_cached(&Self::insert_sql())?;
+        self.with_insert_params(clock, |params| stmt.execute(params))?;
+        Ok(conn.last_insert_rowid())
+    }
+}
+
+/// A struct updated by primary key. Usually derived with
+/// `#[derive(Update)]`, which sets every column written by
+/// `#[derive(Insert)]` in the row whose fields marked `#[id]` match.
+pub trait Update {
+    /// The `UPDATE` statement, with numbered parameters for the updated
+    /// columns and then the key.
+    fn update_sql() -> String;
+
+    /// The parameters of `update_sql`.
+    fn update_params(&self) -> Vec<&dyn ToSql>;
+
+    /// Call `f` with the parameters of `update_sql`, with the fields marked
+    /// `#[auto_now]` stamped from `clock`.
+    fn with_update_params<R>(&self, _clock: &dyn Clock, f: impl FnOnce(&[&dyn ToSql]) -> R) -> R {
+        f(&self.update_params())
+    }
+
+    /// Update the row, returning the number of rows changed (0 if there's no
+    /// row with this key).
+    fn update(&self, conn: &Connection) -> rusqlite::Result<usize> {
+        self.update_at(conn, &SystemClock)
+    }
+    /// Update the row with its stamped fields set from `clock`.
+    fn update_at(&self, conn: &Connection, clock: &dyn Clock) -> rusqlite::Result<usize> {
+        let mut stmt = conn.prepare_cached(&Self::update_sql())?;
+        self.with_update_params(clock, |params| stmt.execute(params))
+    }
+}
+
+/// A struct inserted as a row, or updating the row it conflicts with.
+/// Usually derived with `#[derive(Upsert)]`, which writes the columns of
+/// `#[derive(Insert)]`, and updates every column but those of the conflict
+/// target: the fields named by `#[conflict_target(...)]` on the struct, or
+/// else those marked `#[id]`. The target must be the columns of a unique
+/// index or the primary key.
+///
+/// `ON CONFLICT` needs SQLite 3.24; `compat::Compat::upsert` falls back on
+/// older versions.
+pub trait Upsert {
+    /// The table the row is written to.
+    const TABLE: &'static str;
+
+    /// The `INSERT ... ON CONFLICT DO UPDATE` statement, with a numbered
+    /// parameter per column.
+    fn upsert_sql() -> String;
+
+    /// The parameters of `upsert_sql`.
+    fn upsert_params(&self) -> Vec<&dyn ToSql>;
+
+    /// Call `f` with the parameters of `upsert_sql`, with the fields marked
+    /// `#[auto_now]` or `#[auto_now_add]` stamped from `clock`. A conflicting
+    /// row keeps its `#[auto_now_add]` columns.
+    fn with_upsert_params<R>(&self, _clock: &dyn Clock, f: impl FnOnce(&[&dyn ToSql]) -> R) -> R {
+        f(&self.upsert_params())
+    }
+
+    /// The columns of the conflict target, which identify the row.
+    fn conflict_target() -> Vec<String>;
+
+    /// The values of the conflict target's columns.
+    fn conflict_params(&self) -> Vec<&dyn ToSql>;
+
+    /// Insert or update the row, returning the number of rows changed (0 if
+    /// the row conflicts and every column is in the target, so there's
+    /// nothing to update).
+    fn upsert(&self, conn: &Connection) -> rusqlite::Result<usize> {
+        self.upsert_at(conn, &SystemClock)
+    }
+    /// Insert or update the row with its stamped fields set from `clock`.
+    fn upsert_at(&self, conn: &Connection, clock: &dyn Clock) -> rusqlite::Result<usize> {
+        let mut stmt = conn.prepare_cached(&Self::upsert_sql())?;
+        self.with_upsert_params(clock, |params| stmt.execute(params))
+    }
+}
+
+/// A struct whose rows can be deleted by primary key. Usually derived with
+/// `#[derive(Delete)]`, taking the key from the single field marked `#[id]`
+/// (an `Option<T>` field has a key of `T`). With a typed key such as
+/// `IntegerId<Self>`, another table's id can't be passed by mistake.
+pub trait Delete {
+    type Id: ToSql;
+    const TABLE: &'static str;
+    /// The primary key column.
+    const ID_COLUMN: &'static str;
+
+    /// The `DELETE` statement, with the key as its parameter.
+    fn delete_sql() -> String;
+
+    /// Delete the row with key `id`, returning whether there was one.
+    fn delete(conn: &Connection, id: &Self::Id) -> rusqlite::Result<bool> {
+        let deleted = conn
+            .prepare_cached(&Self::delete_sql())?
+            .execute([id as &dyn ToSql])?;
+        Ok(deleted > 0)
+    }
+}
+
+/// A struct read from its table by primary key or condition: the common
+/// lookups of a small application, without writing their `SELECT`s.
+/// Usually derived with `#[derive(Queryable)]`, which reads the table name
+/// and key as `#[derive(Delete)]` does, alongside `#[derive(TryFromRow)]`,
+/// which reads the rows.
+pub trait Queryable {
+    type Id: ToSql;
+    const TABLE: &'static str;
+    /// The primary key column.
+    const ID_COLUMN: &'static str;
+
+    /// The row with key `id`, or `None` if there isn't one.
+    fn find(conn: &Connection, id: &Self::Id) -> rusqlite::Result<Option<Self>>
+    where
+        for<'r, 'stmt> Self: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error> + Columns,
+    {
+        let sql = format!(
+            "select {} from {} where {} = ?1",
+            Self::select_list(),
+            quote_ident(Self::TABLE),
+            quote_ident(Self::ID_COLUMN)
+        );
+        conn.prepare_cached(&sql)?
+            .query_row([id], |row| Self::try_from(row))
+            .optional()
+    }
+
+    /// Every row, in key order.
+    fn list(conn: &Connection) -> rusqlite::Result<Vec<Self>>
+    where
+        for<'r, 'stmt> Self: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error> + Columns,
+    {
+        let order = format!("true order by {}", quote_ident(Self::ID_COLUMN));
+        Self::find_where(conn, &order, ())
+    }
+
+    /// The rows matching `clause`, a condition (optionally followed by
+    /// `order by` and `limit`) with parameters `params`.
+    fn find_where<P: Params>(
+        conn: &Connection,
+        clause: &str,
+        params: P,
+    ) -> rusqlite::Result<Vec<Self>>
+    where
+        for<'r, 'stmt> Self: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error> + Columns,
+    {
+        let sql = format!(
+            "select {} from {} where {}",
+            Self::select_list(),
+            quote_ident(Self::TABLE),
+            clause
+        );
+        let mut stmt = conn.prepare_cached(&sql)?;
+        let rows = stmt.query_map(params, |row| Self::try_from(row))?.collect();
+        rows
+    }
+}
+
+/// `insert into table(columns) values (?1, ...)`, or with `default values`
+/// if there are no columns.
+pub fn insert_sql(table: &str, columns: &[String]) -> String {
+    if columns.is_empty() {
+        return format!("insert into {} default values", quote_ident(table));
+    }
+    format!(
+        "insert into {}({}) values ({})",
+        quote_ident(table),
+        columns
+            .iter()
+            .map(|c| quote_ident(c))
+            .collect::<Vec<_>>()
+            .join(", "),
+        (1..=columns.len())
+            .map(|i| format!("?{}", i))
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
+
+/// `update table set set = ?1, ... where key = ?n and ...`, numbering the
+/// parameters of the `set` columns first.
+pub fn update_sql(table: &str, set: &[String], key: &[String]) -> String {
+    let mut params = 1..;
+    let mut assign = |c: &String| format!("{} = ?{}", quote_ident(c), params.next().unwrap());
+    let set: Vec<_> = set.iter().map(&mut assign).collect();
+    let key: Vec<_> = key.iter().map(&mut assign).collect();
+    format!(
+        "update {} set {} where {}",
+        quote_ident(table),
+        set.join(", "),
+        key.join(" and ")
+    )
+}
+
+/// `insert_sql`, updating the other columns of the row conflicting on the
+/// `target` columns (or doing nothing if there are none).
+pub fn upsert_sql(table: &str, columns: &[String], target: &[String]) -> String {
+    upsert_sql_keeping(table, columns, target, &[])
+}
+
+/// `upsert_sql`, with the `kept` columns of a conflicting row (eg its
+/// creation time) not updated either.
+pub fn upsert_sql_keeping(
+    table: &str,
+    columns: &[String],
+    target: &[String],
+    kept: &[String],
+) -> String {
+    let updates: Vec<_> = columns
+        .iter()
+        .filter(|c| !target.contains(c) && !kept.contains(c))
+        .map(|c| format!("{c} = excluded.{c}", c = quote_ident(c)))
+        .collect();
+    let action = if updates.is_empty() {
+        "nothing".to_string()
+    } else {
+        format!("update set {}", updates.join(", "))
+    };
+    format!(
+        "{} on conflict({}) do {}",
+        insert_sql(table, columns),
+        target
+            .iter()
+            .map(|c| quote_ident(c))
+            .collect::<Vec<_>>()
+            .join(", "),
+        action
+    )
+}
+
+/// `delete from table where id = ?1`.
+pub fn delete_sql(table: &str, id: &str) -> String {
+    format!(
+        "delete from {} where {} = ?1",
+        quote_ident(table),
+        quote_ident(id)
+    )
+}
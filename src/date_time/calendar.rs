@@ -0,0 +1,314 @@
+use chrono::{Datelike, NaiveDate};
+use rusqlite::{
+    types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef},
+    ToSql,
+};
+use serde::{Deserialize, Serialize};
+
+use super::Scale;
+use crate::column_type::{column_type, SqliteColumnType, StorageClass};
+
+const WEEKDAYS: [chrono::Weekday; 7] = [
+    chrono::Weekday::Mon,
+    chrono::Weekday::Tue,
+    chrono::Weekday::Wed,
+    chrono::Weekday::Thu,
+    chrono::Weekday::Fri,
+    chrono::Weekday::Sat,
+    chrono::Weekday::Sun,
+];
+const MONTHS: [chrono::Month; 12] = [
+    chrono::Month::January,
+    chrono::Month::February,
+    chrono::Month::March,
+    chrono::Month::April,
+    chrono::Month::May,
+    chrono::Month::June,
+    chrono::Month::July,
+    chrono::Month::August,
+    chrono::Month::September,
+    chrono::Month::October,
+    chrono::Month::November,
+    chrono::Month::December,
+];
+
+/// A day of the week, stored as an INTEGER from 1 (Monday) to 7 (Sunday), as
+/// in ISO 8601.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Weekday(pub chrono::Weekday);
+
+impl Weekday {
+    /// The ISO 8601 number of the day, from 1 (Monday) to 7 (Sunday).
+    pub fn number(&self) -> u32 {
+        self.0.number_from_monday()
+    }
+}
+impl From<chrono::Weekday> for Weekday {
+    fn from(v: chrono::Weekday) -> Self {
+        Self(v)
+    }
+}
+impl From<Weekday> for chrono::Weekday {
+    fn from(v: Weekday) -> Self {
+        v.0
+    }
+}
+impl FromSql for Weekday {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let n = value.as_i64()?;
+        match n {
+            1..=7 => Ok(Self(WEEKDAYS[n as usize - 1])),
+            _ => Err(FromSqlError::OutOfRange(n)),
+        }
+    }
+}
+impl ToSql for Weekday {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.number()))
+    }
+}
+column_type!("integer", Integer: Weekday);
+
+/// A month of the year, stored as an INTEGER from 1 (January) to 12
+/// (December).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MonthOfYear(pub chrono::Month);
+
+impl MonthOfYear {
+    /// The number of the month, from 1 (January) to 12 (December).
+    pub fn number(&self) -> u32 {
+        self.0.number_from_month()
+    }
+}
+impl From<chrono::Month> for MonthOfYear {
+    fn from(v: chrono::Month) -> Self {
+        Self(v)
+    }
+}
+impl From<MonthOfYear> for chrono::Month {
+    fn from(v: MonthOfYear) -> Self {
+        v.0
+    }
+}
+impl FromSql for MonthOfYear {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let n = value.as_i64()?;
+        match n {
+            1..=12 => Ok(Self(MONTHS[n as usize - 1])),
+            _ => Err(FromSqlError::OutOfRange(n)),
+        }
+    }
+}
+impl ToSql for MonthOfYear {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.number()))
+    }
+}
+column_type!("integer", Integer: MonthOfYear);
+
+/// An ISO 8601 week, stored as an INTEGER `year * 100 + week`, eg 202453 for
+/// the last week of 2024. Stored weeks sort chronologically.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct IsoWeek {
+    year: i32,
+    week: u32,
+}
+
+impl IsoWeek {
+    /// Week `week` of the ISO year `year`, if the year has that many weeks.
+    pub fn new(year: i32, week: u32) -> Option<Self> {
+        NaiveDate::from_isoywd_opt(year, week, chrono::Weekday::Mon).map(|_| Self { year, week })
+    }
+    /// The week containing `date`.
+    pub fn of<D: Datelike>(date: &D) -> Self {
+        date.iso_week().into()
+    }
+    /// The ISO year, which differs from the calendar year for some days
+    /// around new year.
+    pub fn year(&self) -> i32 {
+        self.year
+    }
+    pub fn week(&self) -> u32 {
+        self.week
+    }
+    /// The Monday starting the week.
+    pub fn first_day(&self) -> NaiveDate {
+        NaiveDate::from_isoywd_opt(self.year, self.week, chrono::Weekday::Mon)
+            .expect("week was validated")
+    }
+    pub fn encode(&self) -> i64 {
+        i64::from(self.year) * 100 + i64::from(self.week)
+    }
+}
+impl From<chrono::IsoWeek> for IsoWeek {
+    fn from(v: chrono::IsoWeek) -> Self {
+        Self {
+            year: v.year(),
+            week: v.week(),
+        }
+    }
+}
+impl FromSql for IsoWeek {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let n = value.as_i64()?;
+        i32::try_from(n.div_euclid(100))
+            .ok()
+            .and_then(|year| Self::new(year, n.rem_euclid(100) as u32))
+            .ok_or(FromSqlError::OutOfRange(n))
+    }
+}
+impl ToSql for IsoWeek {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.encode()))
+    }
+}
+column_type!("integer", Integer: IsoWeek);
+
+/// SQL expression converting an INTEGER timestamp column stored at `S` scale
+/// to whole seconds, rounding towards negative infinity.
+fn unix_seconds_sql<S: Scale>(column: &str) -> String {
+    if S::PER_SECOND == 1 {
+        column.to_string()
+    } else {
+        format!(
+            "(({c} - (({c} % {p}) + {p}) % {p}) / {p})",
+            c = column,
+            p = S::PER_SECOND
+        )
+    }
+}
+
+/// SQL expression for the ISO weekday (1 for Monday to 7 for Sunday) of an
+/// INTEGER timestamp column stored at `S` scale, in UTC. Compare it against a
+/// bound `Weekday`, eg `format!("where {} = ?", weekday_sql::<Seconds>("ts"))`.
+pub fn weekday_sql<S: Scale>(column: &str) -> String {
+    format!(
+        "((strftime('%w', {}, 'unixepoch') + 6) % 7 + 1)",
+        unix_seconds_sql::<S>(column)
+    )
+}
+
+/// SQL expression for the month (1 to 12) of an INTEGER timestamp column
+/// stored at `S` scale, in UTC. Compare it against a bound `MonthOfYear`.
+pub fn month_sql<S: Scale>(column: &str) -> String {
+    format!(
+        "cast(strftime('%m', {}, 'unixepoch') as integer)",
+        unix_seconds_sql::<S>(column)
+    )
+}
+
+/// SQL expression for the ISO week, encoded as an `IsoWeek` is, of an INTEGER
+/// timestamp column stored at `S` scale, in UTC. Compare it against a bound
+/// `IsoWeek`.
+pub fn iso_week_sql<S: Scale>(column: &str) -> String {
+    // The ISO week and year are those of the Thursday in the same week.
+    let thursday = format!(
+        "date({s}, 'unixepoch', (3 - (strftime('%w', {s}, 'unixepoch') + 6) % 7) || ' days')",
+        s = unix_seconds_sql::<S>(column)
+    );
+    format!(
+        "(strftime('%Y', {t}) * 100 + (strftime('%j', {t}) - 1) / 7 + 1)",
+        t = thursday
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use rusqlite::Connection;
+
+    use crate::date_time::{Milliseconds, Seconds, TimestampMillis, UnixEpoch};
+
+    #[test]
+    fn store_and_retrieve_calendar_types() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        let week = IsoWeek::new(2020, 53).expect("2020 has 53 weeks");
+        let res = db.query_row(
+            "select ?, ?, ?",
+            (
+                Weekday(chrono::Weekday::Sun),
+                MonthOfYear(chrono::Month::March),
+                week,
+            ),
+            |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, i64>(2)?,
+                    row.get::<_, Weekday>(0)?,
+                    row.get::<_, MonthOfYear>(1)?,
+                    row.get::<_, IsoWeek>(2)?,
+                ))
+            },
+        );
+        assert!(res.is_ok(), "Failed to retrieve values: {:?}", res);
+        let (weekday_raw, week_raw, weekday, month, retrieved_week) = res.unwrap();
+        assert_eq!((weekday_raw, week_raw), (7, 202053));
+        assert_eq!(weekday, Weekday(chrono::Weekday::Sun));
+        assert_eq!(month.number(), 3);
+        assert_eq!(retrieved_week, week);
+        assert_eq!(
+            week.first_day(),
+            NaiveDate::from_ymd_opt(2020, 12, 28).unwrap()
+        );
+
+        assert!(IsoWeek::new(2021, 53).is_none());
+        assert!(db
+            .query_row("select 202153", (), |row| row.get::<_, IsoWeek>(0))
+            .is_err());
+        assert!(db
+            .query_row("select 0", (), |row| row.get::<_, Weekday>(0))
+            .is_err());
+    }
+
+    #[test]
+    fn sql_matches_chrono() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        let dates = [
+            (1969, 12, 31),
+            (2020, 12, 31),
+            (2021, 1, 3),
+            (2021, 1, 4),
+            (2024, 2, 29),
+            (2024, 12, 30),
+            (2026, 10, 16),
+        ];
+        for (y, m, d) in dates {
+            let date = NaiveDate::from_ymd_opt(y, m, d)
+                .unwrap()
+                .and_hms_opt(23, 59, 59)
+                .unwrap();
+            let ts: UnixEpoch = chrono::DateTime::from_utc(date, chrono::Utc).into();
+            let ts_ms: TimestampMillis = ts.unwrap().into();
+            let res = db.query_row(
+                &format!(
+                    "select {}, {}, {}, {} from (select ? as ts, ? as ts_ms)",
+                    weekday_sql::<Seconds>("ts"),
+                    month_sql::<Seconds>("ts"),
+                    iso_week_sql::<Seconds>("ts"),
+                    iso_week_sql::<Milliseconds>("ts_ms"),
+                ),
+                (ts, ts_ms),
+                |row| {
+                    Ok((
+                        row.get::<_, Weekday>(0)?,
+                        row.get::<_, MonthOfYear>(1)?,
+                        row.get::<_, IsoWeek>(2)?,
+                        row.get::<_, IsoWeek>(3)?,
+                    ))
+                },
+            );
+            assert!(res.is_ok(), "Failed to evaluate expressions: {:?}", res);
+            let (weekday, month, week, week_ms) = res.unwrap();
+            assert_eq!(
+                weekday,
+                Weekday(date.weekday()),
+                "Wrong weekday for {}",
+                date
+            );
+            assert_eq!(month.number(), date.month(), "Wrong month for {}", date);
+            assert_eq!(week, IsoWeek::of(&date), "Wrong week for {}", date);
+            assert_eq!(week_ms, week, "Wrong week for {} in millis", date);
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use chrono::{DateTime, Utc};
+
+use super::Timestamp;
+
+/// A source of the current time, so code stamping rows can be tested with a
+/// fixed time.
+pub trait Clock {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The system's wall clock.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock which always reads the same time.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FixedClock(pub DateTime<Utc>);
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
+
+/// A field type which can be set from a `Clock`, as with `#[auto_now]` and
+/// `#[auto_now_add]` fields in `#[derive(Table)]` and the CRUD derives.
+pub trait AutoNow {
+    fn from_now(now: DateTime<Utc>) -> Self;
+}
+impl AutoNow for DateTime<Utc> {
+    fn from_now(now: DateTime<Utc>) -> Self {
+        now
+    }
+}
+impl<S> AutoNow for Timestamp<S> {
+    fn from_now(now: DateTime<Utc>) -> Self {
+        now.into()
+    }
+}
+impl<T: AutoNow> AutoNow for Option<T> {
+    fn from_now(now: DateTime<Utc>) -> Self {
+        Some(T::from_now(now))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use rusqlite::Connection;
+
+    use crate::{
+        crud::{Insert, Update, Upsert},
+        date_time::UnixEpoch,
+        Table, TryFromRow,
+    };
+
+    #[derive(Table, TryFromRow, Debug, PartialEq)]
+    struct Post {
+        title: String,
+        #[auto_now_add]
+        created: Option<UnixEpoch>,
+        #[auto_now]
+        updated: Option<UnixEpoch>,
+    }
+
+    fn clock(secs: i64) -> FixedClock {
+        FixedClock(DateTime::from_utc(
+            chrono::NaiveDateTime::from_timestamp_opt(secs, 0).unwrap(),
+            Utc,
+        ))
+    }
+
+    #[test]
+    fn stamp_fields_on_write() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        Post::create_table(&db).expect("Failed to create table");
+
+        let mut post = Post {
+            title: "hello".into(),
+            created: None,
+            updated: None,
+        };
+        let res = post.insert(&db, &clock(10));
+        assert!(res.is_ok(), "Failed to insert row: {:?}", res);
+        assert_eq!(post.created, Some(clock(10).now().into()));
+        assert_eq!(post.updated, post.created);
+
+        post.touch(&clock(20), false);
+        assert_eq!(post.created, Some(clock(10).now().into()));
+        assert_eq!(post.updated, Some(clock(20).now().into()));
+
+        let res = db.query_row("select * from post", (), |row| Post::try_from(row));
+        assert!(res.is_ok(), "Failed to retrieve row: {:?}", res);
+        let stored = res.unwrap();
+        assert_eq!(stored.created, Some(clock(10).now().into()));
+        assert_eq!(stored.updated, Some(clock(10).now().into()));
+    }
+
+    #[derive(crate::Insert, crate::Update, crate::Upsert, TryFromRow, Debug, PartialEq)]
+    #[table = "comment"]
+    struct Comment {
+        #[id]
+        id: i64,
+        body: String,
+        #[auto_now_add]
+        created: Option<UnixEpoch>,
+        #[auto_now]
+        updated: Option<UnixEpoch>,
+    }
+
+    #[test]
+    fn stamp_fields_in_crud_derives() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch("create table comment(id integer primary key, body, created, updated)")
+            .expect("Failed to create table");
+        let read = |db: &Connection| {
+            db.query_row("select * from comment", (), |row| Comment::try_from(row))
+                .expect("Failed to retrieve row")
+        };
+        let mut comment = Comment {
+            id: 1,
+            body: "first".into(),
+            created: None,
+            updated: None,
+        };
+
+        let res = comment.insert(&db);
+        assert!(res.is_ok(), "Failed to insert row: {:?}", res);
+        let stored = read(&db);
+        assert!(stored.created.is_some() && stored.updated.is_some());
+
+        db.execute("delete from comment", ()).unwrap();
+        comment.insert_at(&db, &clock(10)).unwrap();
+        comment.body = "edited".into();
+        let res = comment.update_at(&db, &clock(20));
+        assert!(res.is_ok(), "Failed to update row: {:?}", res);
+        assert_eq!(res.unwrap(), 1);
+        let stored = read(&db);
+        assert_eq!(stored.body, "edited");
+        assert_eq!(stored.created, Some(clock(10).now().into()));
+        assert_eq!(stored.updated, Some(clock(20).now().into()));
+        assert_eq!(comment.updated, None, "The row itself is left as it was");
+
+        comment.upsert_at(&db, &clock(30)).unwrap();
+        let stored = read(&db);
+        assert_eq!(stored.created, Some(clock(10).now().into()));
+        assert_eq!(stored.updated, Some(clock(30).now().into()));
+
+        comment.update(&db).unwrap();
+        assert!(read(&db).updated > Some(clock(30).now().into()));
+    }
+}
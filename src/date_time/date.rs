@@ -0,0 +1,174 @@
+use chrono::{Datelike, NaiveDate};
+use rusqlite::{
+    types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef},
+    ToSql,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::column_type::{column_type, SqliteColumnType, StorageClass};
+
+/// Stores a calendar date as ISO 8601 TEXT (`YYYY-MM-DD`), the format
+/// understood by SQLite's date functions.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Date(NaiveDate);
+
+impl Date {
+    pub const FORMAT: &'static str = "%Y-%m-%d";
+
+    pub fn unwrap(self) -> NaiveDate {
+        self.0
+    }
+    /// The current date in UTC.
+    pub fn today() -> Self {
+        Self(chrono::Utc::now().naive_utc().date())
+    }
+
+    /// Whole years from this date until `today`; see `years_between`.
+    pub fn age_on(&self, today: NaiveDate) -> i32 {
+        years_between(self.0, today)
+    }
+    /// Whole years from this date until today, in UTC.
+    pub fn age(&self) -> i32 {
+        self.age_on(Self::today().0)
+    }
+}
+impl From<NaiveDate> for Date {
+    fn from(v: NaiveDate) -> Self {
+        Self(v)
+    }
+}
+impl From<Date> for NaiveDate {
+    fn from(v: Date) -> Self {
+        v.0
+    }
+}
+impl FromSql for Date {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        NaiveDate::parse_from_str(value.as_str()?, Self::FORMAT)
+            .map(Self)
+            .map_err(|e| FromSqlError::Other(Box::new(e)))
+    }
+}
+impl ToSql for Date {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.0.format(Self::FORMAT).to_string()))
+    }
+}
+column_type!("text", Text: Date);
+
+/// Whole years from `from` until `to`, negative if `to` is earlier. A year is
+/// complete once the month and day of `from` are reached, so an anniversary
+/// of the 29th of February falls on the 1st of March in common years.
+pub fn years_between(from: NaiveDate, to: NaiveDate) -> i32 {
+    if to < from {
+        return -years_between(to, from);
+    }
+    let years = to.year() - from.year();
+    if (to.month(), to.day()) < (from.month(), from.day()) {
+        years - 1
+    } else {
+        years
+    }
+}
+/// Whole months from `from` until `to`, negative if `to` is earlier. A month
+/// is complete once the day of `from` is reached, so a month from the 31st of
+/// January ends on the 1st of March.
+pub fn months_between(from: NaiveDate, to: NaiveDate) -> i32 {
+    if to < from {
+        return -months_between(to, from);
+    }
+    let months = (to.year() - from.year()) * 12 + to.month() as i32 - from.month() as i32;
+    if to.day() < from.day() {
+        months - 1
+    } else {
+        months
+    }
+}
+
+/// SQL expression computing `years_between` for two date expressions, eg
+/// columns holding a `Date`.
+pub fn years_between_sql(from: &str, to: &str) -> String {
+    format!(
+        "(case when {t} < {f} then -1 else 1 end * (
+            strftime('%Y', max({f}, {t})) - strftime('%Y', min({f}, {t}))
+            - (strftime('%m-%d', max({f}, {t})) < strftime('%m-%d', min({f}, {t})))
+        ))",
+        f = from,
+        t = to
+    )
+}
+/// SQL expression computing `months_between` for two date expressions, eg
+/// columns holding a `Date`.
+pub fn months_between_sql(from: &str, to: &str) -> String {
+    format!(
+        "(case when {t} < {f} then -1 else 1 end * (
+            (strftime('%Y', max({f}, {t})) - strftime('%Y', min({f}, {t}))) * 12
+            + strftime('%m', max({f}, {t})) - strftime('%m', min({f}, {t}))
+            - (strftime('%d', max({f}, {t})) < strftime('%d', min({f}, {t})))
+        ))",
+        f = from,
+        t = to
+    )
+}
+/// SQL expression for the age in whole years of a date column, as of the
+/// database's current date in UTC.
+pub fn age_sql(column: &str) -> String {
+    years_between_sql(column, "date('now')")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use rusqlite::Connection;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn insert_date_and_retrieve() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        let stored = Date::from(date(2000, 2, 29));
+        let res = db.query_row("select ?, date(?, '+1 day')", (stored, stored), |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, Date>(1)?))
+        });
+        assert!(res.is_ok(), "Failed to retrieve date: {:?}", res);
+        let (raw, next) = res.unwrap();
+        assert_eq!(raw, "2000-02-29");
+        assert_eq!(next.unwrap(), date(2000, 3, 1));
+    }
+
+    #[test]
+    fn ages_match_sql() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        let sql = format!(
+            "select {}, {} from (select ? as a, ? as b)",
+            years_between_sql("a", "b"),
+            months_between_sql("a", "b")
+        );
+        let cases = [
+            (date(2000, 2, 29), date(2001, 2, 28), 0, 11),
+            (date(2000, 2, 29), date(2001, 3, 1), 1, 12),
+            (date(2000, 2, 29), date(2004, 2, 29), 4, 48),
+            (date(1990, 6, 15), date(2026, 6, 14), 35, 431),
+            (date(2024, 1, 31), date(2024, 2, 29), 0, 0),
+            (date(2024, 1, 31), date(2024, 3, 1), 0, 1),
+            (date(2026, 6, 14), date(1990, 6, 15), -35, -431),
+        ];
+        for (from, to, years, months) in cases {
+            assert_eq!(years_between(from, to), years, "{} to {}", from, to);
+            assert_eq!(months_between(from, to), months, "{} to {}", from, to);
+            let res = db.query_row(&sql, (Date(from), Date(to)), |row| {
+                Ok((row.get::<_, i32>(0)?, row.get::<_, i32>(1)?))
+            });
+            assert_eq!(res.ok(), Some((years, months)), "{} to {}", from, to);
+        }
+
+        let born = Date(date(1990, 6, 15));
+        let res = db.query_row(&format!("select {}", age_sql("?1")), (born,), |row| {
+            row.get::<_, i32>(0)
+        });
+        assert_eq!(res.ok(), Some(born.age()));
+    }
+}
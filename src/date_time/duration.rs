@@ -6,12 +6,14 @@ use rusqlite::{
 };
 use thiserror::Error;
 
-use super::{Microseconds, Milliseconds, Nanoseconds, Seconds};
+use super::{Microseconds, Milliseconds, Nanoseconds, RealSeconds, Seconds};
+use crate::column_type::{column_type, SqliteColumnType, StorageClass};
 
 pub type DurationSeconds = Duration<Seconds>;
 pub type DurationMillis = Duration<Milliseconds>;
 pub type DurationMicros = Duration<Microseconds>;
 pub type DurationNanos = Duration<Nanoseconds>;
+pub type DurationRealSeconds = Duration<RealSeconds>;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Duration<Scale>(chrono::Duration, PhantomData<Scale>);
@@ -31,7 +33,6 @@ impl<Scale> TryFrom<std::time::Duration> for Duration<Scale> {
     fn try_from(v: std::time::Duration) -> Result<Self, Self::Error> {
         Ok(Self(chrono::Duration::from_std(v)?, PhantomData))
     }
-
 }
 impl FromSql for Duration<Seconds> {
     fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
@@ -133,6 +134,44 @@ impl ToSql for Duration<Nanoseconds> {
     }
 }
 
+/// Reads both REAL and INTEGER columns as seconds, with nanosecond precision.
+impl FromSql for Duration<RealSeconds> {
+    fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
+        let db_seconds = match value {
+            rusqlite::types::ValueRef::Integer(_) => {
+                return Duration::<Seconds>::column_result(value).map(|d| Self(d.0, PhantomData));
+            }
+            _ => value.as_f64()?,
+        };
+        let nanos = (db_seconds * 1e9).round();
+        if nanos.is_finite() && nanos.abs() < i64::MAX as f64 {
+            Ok(Self(
+                chrono::Duration::nanoseconds(nanos as i64),
+                PhantomData,
+            ))
+        } else {
+            Err(FromSqlError::InvalidType)
+        }
+    }
+}
+impl ToSql for Duration<RealSeconds> {
+    fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+        let seconds = match self.0.num_nanoseconds() {
+            Some(ns) => ns as f64 / 1e9,
+            None => self.0.num_milliseconds() as f64 / 1e3,
+        };
+        Ok(ToSqlOutput::from(seconds))
+    }
+}
+column_type!(
+    "integer",
+    Integer: Duration<Seconds>,
+    Duration<Milliseconds>,
+    Duration<Microseconds>,
+    Duration<Nanoseconds>
+);
+column_type!("real", Real: Duration<RealSeconds>);
+
 #[derive(Clone, Copy, Error, Debug)]
 pub enum Error {
     #[error("Overflow")]
@@ -260,4 +299,39 @@ mod test {
             "Stored duration does not equal retrieved duration"
         );
     }
+
+    #[test]
+    fn insert_duration_real_and_retrieve() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+
+        db.execute("create table foo( a real )", ())
+            .expect("failed to create table");
+        let stored_duration = chrono::Duration::milliseconds(1_500);
+        let res = db.query_row(
+            "insert into foo(a) values(?) returning *",
+            (DurationRealSeconds::from(stored_duration),),
+            |row| {
+                let v: (f64, DurationRealSeconds) = (row.get("a")?, row.get("a")?);
+                Ok(v)
+            },
+        );
+        assert!(
+            res.is_ok(),
+            "Failed to retrieve duration from database: {:?}",
+            res
+        );
+        let (raw, retrieved_duration) = res.unwrap();
+        assert_eq!(raw, 1.5);
+        assert_eq!(stored_duration, retrieved_duration.unwrap());
+
+        let res = db.query_row("select 3, -0.25", (), |row| {
+            Ok((
+                row.get::<_, DurationRealSeconds>(0)?,
+                row.get::<_, DurationRealSeconds>(1)?,
+            ))
+        });
+        let (from_integer, negative) = res.expect("Failed to read durations");
+        assert_eq!(from_integer.unwrap(), chrono::Duration::seconds(3));
+        assert_eq!(negative.unwrap(), chrono::Duration::milliseconds(-250));
+    }
 }
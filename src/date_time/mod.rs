@@ -1,10 +1,27 @@
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
+pub mod calendar;
+pub mod clock;
+pub mod date;
 pub mod duration;
+pub mod monotonic;
+pub mod period;
+pub mod stopwatch;
 pub mod timestamp;
 
-pub use duration::{Duration, DurationMicros, DurationMillis, DurationNanos, DurationSeconds};
-pub use timestamp::{TimestampMicros, TimestampMillis, TimestampNanos, UnixEpoch};
+pub use calendar::{IsoWeek, MonthOfYear, Weekday};
+pub use clock::{Clock, FixedClock, SystemClock};
+pub use date::Date;
+pub use duration::{
+    Duration, DurationMicros, DurationMillis, DurationNanos, DurationRealSeconds, DurationSeconds,
+};
+pub use monotonic::MonotonicCheck;
+pub use period::{MonthPeriod, Period, PeriodRange, QuarterPeriod, YearPeriod};
+pub use stopwatch::Stopwatch;
+pub use timestamp::{
+    db_now, Timestamp, TimestampMicros, TimestampMillis, TimestampNanos, UnixEpoch,
+};
 
 /// Record timestamps at the second scale.
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
@@ -21,3 +38,105 @@ pub struct Microseconds {}
 /// Record timestamps at the nanosecond scale.
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Nanoseconds {}
+
+/// Record durations as fractional seconds in a REAL column, as written by eg
+/// Python's `time.time()` differences. This is a storage format rather than
+/// an integer `Scale`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct RealSeconds {}
+
+/// The unit in which a `Timestamp` or `Duration` is stored.
+pub trait Scale {
+    /// Number of units in a second.
+    const PER_SECOND: i64;
+
+    /// Convert a duration into a whole number of units, truncating towards zero
+    /// and saturating on overflow.
+    fn units(d: chrono::Duration) -> i64 {
+        const NANOS_PER_SECOND: i64 = 1_000_000_000;
+        const MILLIS_PER_SECOND: i64 = 1_000;
+
+        match d.num_nanoseconds() {
+            Some(ns) => ns / (NANOS_PER_SECOND / Self::PER_SECOND),
+            None => {
+                let units = i128::from(d.num_milliseconds()) * i128::from(Self::PER_SECOND)
+                    / i128::from(MILLIS_PER_SECOND);
+                units.clamp(i64::MIN.into(), i64::MAX.into()) as i64
+            }
+        }
+    }
+}
+impl Scale for Seconds {
+    const PER_SECOND: i64 = 1;
+}
+impl Scale for Milliseconds {
+    const PER_SECOND: i64 = 1_000;
+}
+impl Scale for Microseconds {
+    const PER_SECOND: i64 = 1_000_000;
+}
+impl Scale for Nanoseconds {
+    const PER_SECOND: i64 = 1_000_000_000;
+}
+
+/// SQL expression rounding an INTEGER timestamp column stored at `S` scale
+/// down to the start of its `width`-long bucket, eg for `GROUP BY`. Rounding is
+/// towards negative infinity so that pre-epoch timestamps bucket correctly.
+///
+/// Fails if `width` is less than one unit of `S`.
+pub fn time_bucket_sql<S: Scale>(
+    column: &str,
+    width: chrono::Duration,
+) -> Result<String, BucketWidthError> {
+    let w = S::units(width);
+    if w <= 0 {
+        return Err(BucketWidthError(width));
+    }
+    Ok(format!(
+        "({c} - (({c} % {w}) + {w}) % {w})",
+        c = column,
+        w = w
+    ))
+}
+
+/// A bucket width shorter than one unit of the timestamps' scale.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("Bucket width {0} is less than one unit of the timestamp scale")]
+pub struct BucketWidthError(pub chrono::Duration);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use rusqlite::Connection;
+
+    #[test]
+    fn scale_units() {
+        let d = chrono::Duration::milliseconds(1_500);
+        assert_eq!(Seconds::units(d), 1);
+        assert_eq!(Milliseconds::units(d), 1_500);
+        assert_eq!(Nanoseconds::units(d), 1_500_000_000);
+        assert_eq!(Nanoseconds::units(chrono::Duration::max_value()), i64::MAX);
+    }
+
+    #[test]
+    fn bucket_expression() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        let sql = format!(
+            "select {} from (select ? as ts)",
+            time_bucket_sql::<Seconds>("ts", chrono::Duration::minutes(1)).unwrap()
+        );
+        for (ts, bucket) in [(0, 0), (59, 0), (60, 60), (125, 120), (-1, -60)] {
+            let res: i64 = db
+                .query_row(&sql, (ts,), |row| row.get(0))
+                .expect("Failed to evaluate bucket");
+            assert_eq!(res, bucket, "Wrong bucket for {}", ts);
+        }
+        let width = chrono::Duration::milliseconds(500);
+        assert_eq!(
+            time_bucket_sql::<Seconds>("ts", width),
+            Err(BucketWidthError(width))
+        );
+        assert!(time_bucket_sql::<Milliseconds>("ts", width).is_ok());
+    }
+}
@@ -0,0 +1,198 @@
+use std::marker::PhantomData;
+
+use rusqlite::{
+    types::{FromSql, Value},
+    Connection, ErrorCode,
+};
+
+use super::{Scale, Timestamp};
+use crate::{execute::Executor, trigger::Trigger, util::quote_ident};
+
+/// A row whose timestamp is earlier than the row appended before it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Violation<S> {
+    pub rowid: i64,
+    /// The value of the partition column, if the check is partitioned.
+    pub partition: Option<Value>,
+    pub previous: Timestamp<S>,
+    pub timestamp: Timestamp<S>,
+}
+impl<S: Copy> Violation<S> {
+    /// How far the timestamp went backwards.
+    pub fn skew(&self) -> chrono::Duration {
+        self.previous.unwrap() - self.timestamp.unwrap()
+    }
+}
+
+/// Checks that the timestamps in an append-only table never go backwards in
+/// insertion (rowid) order, either across the whole table or within each
+/// partition, eg per device. Existing rows can be scanned for violations,
+/// and a trigger can reject new ones.
+#[derive(Clone, Debug)]
+pub struct MonotonicCheck<S> {
+    table: String,
+    column: String,
+    partition: Option<String>,
+    _scale: PhantomData<S>,
+}
+
+impl<S: Scale> MonotonicCheck<S>
+where
+    Timestamp<S>: FromSql,
+{
+    /// The message of the error raised by the trigger.
+    pub const MESSAGE: &'static str = "timestamp is earlier than the previous row";
+
+    /// Check the INTEGER timestamp `column` of `table`, stored at `S` scale.
+    pub fn new(table: &str, column: &str) -> Self {
+        Self {
+            table: table.to_string(),
+            column: column.to_string(),
+            partition: None,
+            _scale: PhantomData,
+        }
+    }
+    /// Only compare rows with the same value in `column`.
+    pub fn partition_by(mut self, column: &str) -> Self {
+        self.partition = Some(column.to_string());
+        self
+    }
+
+    /// Find every row whose timestamp is earlier than the previous row's.
+    pub fn violations(&self, conn: &Connection) -> rusqlite::Result<Vec<Violation<S>>> {
+        let (partition, partition_by) = match &self.partition {
+            Some(p) => (quote_ident(p), format!("partition by {}", quote_ident(p))),
+            None => ("null".to_string(), String::new()),
+        };
+        let mut stmt = conn.prepare(&format!(
+            "select rowid, partition, previous, ts from (
+                select rowid, {partition} as partition, {column} as ts,
+                    lag({column}) over ({partition_by} order by rowid) as previous
+                from {table}
+            ) where ts < previous order by rowid",
+            column = quote_ident(&self.column),
+            table = quote_ident(&self.table),
+        ))?;
+        let violations = stmt
+            .query_map((), |row| {
+                let partition: Value = row.get(1)?;
+                Ok(Violation {
+                    rowid: row.get(0)?,
+                    partition: self.partition.as_ref().map(|_| partition),
+                    previous: row.get(2)?,
+                    timestamp: row.get(3)?,
+                })
+            })?
+            .collect();
+        violations
+    }
+
+    /// A trigger aborting inserts whose timestamp is earlier than that of
+    /// any existing row (in the same partition). An index on the timestamp
+    /// column (after the partition column) keeps it cheap.
+    pub fn trigger(&self) -> Trigger {
+        let column = quote_ident(&self.column);
+        let filter = match &self.partition {
+            Some(p) => format!(" where {p} is new.{p}", p = quote_ident(p)),
+            None => String::new(),
+        };
+        Trigger::new(
+            &format!("{}_{}_monotonic", self.table, self.column),
+            &self.table,
+        )
+        .before()
+        .on_insert()
+        .when(&format!(
+            "new.{column} < (select max({column}) from {table}{filter})",
+            table = quote_ident(&self.table),
+        ))
+        .then(&format!("select raise(abort, '{}')", Self::MESSAGE))
+    }
+    /// Install the trigger, replacing any previous version.
+    pub fn install_trigger<E: Executor + ?Sized>(&self, exec: &E) -> rusqlite::Result<()> {
+        self.trigger().replace(exec)
+    }
+
+    /// Whether `e` was raised by the trigger.
+    pub fn is_violation(e: &rusqlite::Error) -> bool {
+        matches!(
+            e,
+            rusqlite::Error::SqliteFailure(f, Some(msg))
+                if f.code == ErrorCode::ConstraintViolation && msg == Self::MESSAGE
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::date_time::{Seconds, UnixEpoch};
+
+    fn at(secs: i64) -> UnixEpoch {
+        chrono::DateTime::<chrono::Utc>::from_utc(
+            chrono::NaiveDateTime::from_timestamp_opt(secs, 0).unwrap(),
+            chrono::Utc,
+        )
+        .into()
+    }
+
+    fn setup() -> Connection {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute(
+            "create table events( device text, ts integer not null )",
+            (),
+        )
+        .expect("Failed to create table");
+        db
+    }
+
+    #[test]
+    fn scan_for_violations() {
+        let db = setup();
+        for (device, ts) in [("a", 10), ("b", 5), ("a", 20), ("a", 15), ("b", 6)] {
+            db.execute("insert into events values (?, ?)", (device, at(ts)))
+                .unwrap();
+        }
+
+        let res = MonotonicCheck::<Seconds>::new("events", "ts").violations(&db);
+        assert!(res.is_ok(), "Failed to scan table: {:?}", res);
+        let rowids: Vec<_> = res.unwrap().iter().map(|v| v.rowid).collect();
+        assert_eq!(rowids, vec![2, 4, 5]);
+
+        let res = MonotonicCheck::<Seconds>::new("events", "ts")
+            .partition_by("device")
+            .violations(&db);
+        assert!(res.is_ok(), "Failed to scan table: {:?}", res);
+        assert_eq!(
+            res.unwrap(),
+            vec![Violation {
+                rowid: 4,
+                partition: Some(Value::Text("a".into())),
+                previous: at(20),
+                timestamp: at(15),
+            }]
+        );
+    }
+
+    #[test]
+    fn trigger_rejects_violations() {
+        let db = setup();
+        let check = MonotonicCheck::<Seconds>::new("events", "ts").partition_by("device");
+        let res = check.install_trigger(&db);
+        assert!(res.is_ok(), "Failed to install trigger: {:?}", res);
+
+        for (device, ts) in [("a", 10), ("b", 5), ("a", 10), ("a", 11)] {
+            let res = db.execute("insert into events values (?, ?)", (device, at(ts)));
+            assert!(res.is_ok(), "Rejected monotonic insert: {:?}", res);
+        }
+        let res = db.execute("insert into events values (?, ?)", ("a", at(9)));
+        assert!(
+            res.as_ref()
+                .is_err_and(MonotonicCheck::<Seconds>::is_violation),
+            "Accepted insert going backwards: {:?}",
+            res
+        );
+        assert!(check.violations(&db).unwrap().is_empty());
+    }
+}
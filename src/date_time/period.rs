@@ -0,0 +1,300 @@
+use std::marker::PhantomData;
+
+use chrono::{Datelike, NaiveDate};
+use rusqlite::{
+    types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef},
+    ToSql,
+};
+use serde::{Deserialize, Serialize};
+
+use super::Date;
+use crate::column_type::{SqliteColumnType, StorageClass};
+
+pub type YearPeriod = Period<Years>;
+pub type QuarterPeriod = Period<Quarters>;
+pub type MonthPeriod = Period<Months>;
+
+/// Periods of a whole year, stored as the year, eg 2024.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Years {}
+
+/// Periods of a quarter, stored as `year * 10 + quarter`, eg 20241.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Quarters {}
+
+/// Periods of a month, stored as `year * 100 + month`, eg 202403.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Months {}
+
+/// The length of a `Period`.
+pub trait Granularity: Copy + Ord {
+    /// Number of periods in a year.
+    const PER_YEAR: u32;
+    /// Multiplier of the year in the INTEGER encoding.
+    const FACTOR: i64;
+}
+impl Granularity for Years {
+    const PER_YEAR: u32 = 1;
+    const FACTOR: i64 = 1;
+}
+impl Granularity for Quarters {
+    const PER_YEAR: u32 = 4;
+    const FACTOR: i64 = 10;
+}
+impl Granularity for Months {
+    const PER_YEAR: u32 = 12;
+    const FACTOR: i64 = 100;
+}
+
+/// A calendar year, quarter or month, stored as an INTEGER which sorts
+/// chronologically, for aggregation tables keyed by period.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Period<G> {
+    /// Periods since the start of year 0.
+    index: i64,
+    _marker: PhantomData<G>,
+}
+
+impl<G: Granularity> Period<G> {
+    /// The `number`th period (starting from 1) of `year`, if valid.
+    pub fn new(year: i32, number: u32) -> Option<Self> {
+        if !(1..=G::PER_YEAR).contains(&number) {
+            return None;
+        }
+        let period =
+            Self::from_index(i64::from(year) * i64::from(G::PER_YEAR) + i64::from(number) - 1);
+        period.checked_first_day().map(|_| period)
+    }
+    /// The period containing `date`.
+    pub fn of<D: Datelike>(date: &D) -> Self {
+        let number = date.month0() * G::PER_YEAR / 12;
+        Self::from_index(i64::from(date.year()) * i64::from(G::PER_YEAR) + i64::from(number))
+    }
+
+    pub fn year(&self) -> i32 {
+        self.index.div_euclid(i64::from(G::PER_YEAR)) as i32
+    }
+    /// The number of the period within its year, starting from 1.
+    pub fn number(&self) -> u32 {
+        self.index.rem_euclid(i64::from(G::PER_YEAR)) as u32 + 1
+    }
+    pub fn encode(&self) -> i64 {
+        if G::PER_YEAR == 1 {
+            i64::from(self.year())
+        } else {
+            i64::from(self.year()) * G::FACTOR + i64::from(self.number())
+        }
+    }
+
+    pub fn first_day(&self) -> NaiveDate {
+        self.checked_first_day().expect("period was validated")
+    }
+    pub fn last_day(&self) -> NaiveDate {
+        self.succ()
+            .checked_first_day()
+            .and_then(|d| d.pred_opt())
+            .unwrap_or(NaiveDate::MAX)
+    }
+    pub fn contains<D: Datelike>(&self, date: &D) -> bool {
+        Self::of(date) == *self
+    }
+    /// The next period.
+    pub fn succ(&self) -> Self {
+        Self::from_index(self.index + 1)
+    }
+    /// The previous period.
+    pub fn pred(&self) -> Self {
+        Self::from_index(self.index - 1)
+    }
+    /// The periods from this one through `end`.
+    pub fn to(self, end: Self) -> PeriodRange<G> {
+        PeriodRange::new(self, end)
+    }
+
+    fn from_index(index: i64) -> Self {
+        Self {
+            index,
+            _marker: PhantomData,
+        }
+    }
+    fn checked_first_day(&self) -> Option<NaiveDate> {
+        let month = (self.number() - 1) * 12 / G::PER_YEAR + 1;
+        NaiveDate::from_ymd_opt(self.year(), month, 1)
+    }
+}
+impl<G: Granularity> FromSql for Period<G> {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let n = value.as_i64()?;
+        let (year, number) = if G::PER_YEAR == 1 {
+            (n, 1)
+        } else {
+            (n.div_euclid(G::FACTOR), n.rem_euclid(G::FACTOR) as u32)
+        };
+        i32::try_from(year)
+            .ok()
+            .and_then(|year| Self::new(year, number))
+            .ok_or(FromSqlError::OutOfRange(n))
+    }
+}
+impl<G: Granularity> ToSql for Period<G> {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.encode()))
+    }
+}
+impl<G> SqliteColumnType for Period<G> {
+    const DECL_TYPE: Option<&'static str> = Some("integer");
+    const STORAGE: Option<StorageClass> = Some(StorageClass::Integer);
+}
+
+/// An inclusive range of periods, iterating from `start` through `end`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PeriodRange<G> {
+    start: Period<G>,
+    end: Period<G>,
+}
+
+impl<G: Granularity> PeriodRange<G> {
+    pub fn new(start: Period<G>, end: Period<G>) -> Self {
+        Self { start, end }
+    }
+    pub fn start(&self) -> Period<G> {
+        self.start
+    }
+    pub fn end(&self) -> Period<G> {
+        self.end
+    }
+    pub fn len(&self) -> usize {
+        (self.end.index - self.start.index + 1).max(0) as usize
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// SQL condition matching a column of encoded periods within the range.
+    pub fn between_sql(&self, column: &str) -> String {
+        format!(
+            "{} between {} and {}",
+            column,
+            self.start.encode(),
+            self.end.encode()
+        )
+    }
+    /// SQL condition matching a column of `Date`s within the range.
+    pub fn dates_between_sql(&self, column: &str) -> String {
+        format!(
+            "{} between '{}' and '{}'",
+            column,
+            self.start.first_day().format(Date::FORMAT),
+            self.end.last_day().format(Date::FORMAT)
+        )
+    }
+}
+impl<G: Granularity> Iterator for PeriodRange<G> {
+    type Item = Period<G>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.start > self.end {
+            return None;
+        }
+        let next = self.start;
+        self.start = next.succ();
+        Some(next)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use rusqlite::Connection;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn encode_periods() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        let month = MonthPeriod::new(2024, 3).unwrap();
+        let quarter = QuarterPeriod::of(&date(2024, 12, 31));
+        let year = YearPeriod::of(&date(2024, 6, 1));
+        let res = db.query_row("select ?, ?, ?", (month, quarter, year), |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, i64>(2)?,
+                row.get::<_, MonthPeriod>(0)?,
+                row.get::<_, QuarterPeriod>(1)?,
+                row.get::<_, YearPeriod>(2)?,
+            ))
+        });
+        assert!(res.is_ok(), "Failed to retrieve periods: {:?}", res);
+        let (m, q, y, retrieved_month, retrieved_quarter, retrieved_year) = res.unwrap();
+        assert_eq!((m, q, y), (202403, 20244, 2024));
+        assert_eq!(
+            (retrieved_month, retrieved_quarter, retrieved_year),
+            (month, quarter, year)
+        );
+        assert!(db
+            .query_row("select 202413", (), |row| row.get::<_, MonthPeriod>(0))
+            .is_err());
+        assert!(MonthPeriod::new(2024, 0).is_none());
+
+        assert_eq!(quarter.first_day(), date(2024, 10, 1));
+        assert_eq!(quarter.last_day(), date(2024, 12, 31));
+        assert_eq!(
+            MonthPeriod::new(2024, 2).unwrap().last_day(),
+            date(2024, 2, 29)
+        );
+        assert_eq!(quarter.succ(), QuarterPeriod::new(2025, 1).unwrap());
+        assert!(month < MonthPeriod::new(2024, 4).unwrap());
+    }
+
+    #[test]
+    fn query_period_ranges() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch(
+            "create table sales( day text, amount integer );
+            insert into sales values
+                ('2023-12-31', 1), ('2024-01-01', 2), ('2024-02-29', 4), ('2024-03-01', 8);",
+        )
+        .expect("Failed to set up database");
+
+        let range = MonthPeriod::new(2023, 12)
+            .unwrap()
+            .to(MonthPeriod::new(2024, 2).unwrap());
+        assert_eq!(range.len(), 3);
+        let months: Vec<_> = range.map(|p| p.encode()).collect();
+        assert_eq!(months, vec![202312, 202401, 202402]);
+
+        let q1 = QuarterPeriod::new(2024, 1).unwrap();
+        let total: i64 = db
+            .query_row(
+                &format!(
+                    "select sum(amount) from sales where {}",
+                    q1.to(q1).dates_between_sql("day")
+                ),
+                (),
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(total, 14);
+
+        db.execute_batch(
+            "create table monthly( period integer primary key, total integer );
+            insert into monthly values (202312, 1), (202401, 2), (202402, 4), (202403, 8);",
+        )
+        .unwrap();
+        let total: i64 = db
+            .query_row(
+                &format!(
+                    "select sum(total) from monthly where {}",
+                    range.between_sql("period")
+                ),
+                (),
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(total, 7);
+    }
+}
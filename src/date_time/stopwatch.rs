@@ -0,0 +1,79 @@
+use std::time::Instant;
+
+use super::Duration;
+
+/// Measures elapsed time with the monotonic clock, for recording how long
+/// something took. Unlike subtracting two `Timestamp::now()`s, the result is
+/// unaffected by the wall clock being adjusted in between.
+#[derive(Copy, Clone, Debug)]
+pub struct Stopwatch {
+    start: Instant,
+}
+
+impl Stopwatch {
+    pub fn start() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+
+    /// Time elapsed since the stopwatch was started.
+    pub fn elapsed(&self) -> std::time::Duration {
+        self.start.elapsed()
+    }
+    /// Time elapsed since the stopwatch was started, ready to be stored at
+    /// any scale. Saturates at `chrono::Duration::max_value()`.
+    pub fn elapsed_as<Scale>(&self) -> Duration<Scale> {
+        let elapsed = chrono::Duration::from_std(self.elapsed())
+            .unwrap_or_else(|_| chrono::Duration::max_value());
+        Duration::from(elapsed)
+    }
+    /// Time elapsed since the stopwatch was started, restarting it.
+    pub fn lap<Scale>(&mut self) -> Duration<Scale> {
+        let elapsed = self.elapsed_as();
+        self.start = Instant::now();
+        elapsed
+    }
+}
+
+/// Run `f`, returning its result along with how long it took.
+pub fn time<T, Scale>(f: impl FnOnce() -> T) -> (T, Duration<Scale>) {
+    let stopwatch = Stopwatch::start();
+    let res = f();
+    (res, stopwatch.elapsed_as())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use rusqlite::Connection;
+
+    use crate::date_time::{DurationMillis, DurationNanos};
+
+    #[test]
+    fn record_elapsed_time() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute("create table jobs( took integer )", ())
+            .expect("Failed to create table");
+
+        let mut stopwatch = Stopwatch::start();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        let lap: DurationMillis = stopwatch.lap();
+        assert!(lap.unwrap() >= chrono::Duration::milliseconds(5));
+        assert!(
+            stopwatch.elapsed()
+                < std::time::Duration::from_millis(5) + lap.unwrap().to_std().unwrap()
+        );
+
+        let (res, took): (_, DurationNanos) =
+            time(|| db.execute("insert into jobs(took) values (0)", ()));
+        assert!(res.is_ok(), "Failed to insert row: {:?}", res);
+        let res = db.execute("update jobs set took = ?", (took,));
+        assert!(res.is_ok(), "Failed to store duration: {:?}", res);
+        let stored: DurationNanos = db
+            .query_row("select took from jobs", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(stored, took);
+    }
+}
@@ -3,11 +3,12 @@ use std::marker::PhantomData;
 use chrono::NaiveDateTime;
 use rusqlite::{
     types::{FromSql, FromSqlError, ToSqlOutput},
-    ToSql,
+    Connection, ToSql,
 };
 use serde::{Deserialize, Serialize};
 
 use super::{Microseconds, Milliseconds, Nanoseconds, Seconds};
+use crate::column_type::{SqliteColumnType, StorageClass};
 
 pub type UnixEpoch = Timestamp<Seconds>;
 pub type TimestampMillis = Timestamp<Milliseconds>;
@@ -124,6 +125,25 @@ impl ToSql for Timestamp<Nanoseconds> {
         Ok(ToSqlOutput::from(self.0.timestamp_nanos()))
     }
 }
+impl<S> SqliteColumnType for Timestamp<S> {
+    const DECL_TYPE: Option<&'static str> = Some("integer");
+    const STORAGE: Option<StorageClass> = Some(StorageClass::Integer);
+}
+
+/// The current time according to the database's clock, at millisecond
+/// precision, for consistency with column defaults such as `unixepoch()`
+/// rather than mixing the application's and the database's clocks. SQLite
+/// keeps `'now'` fixed for the duration of a statement, not a transaction.
+pub fn db_now<S>(conn: &Connection) -> rusqlite::Result<Timestamp<S>> {
+    // julianday() has millisecond precision, unlike unixepoch(), and is
+    // available in all SQLite versions.
+    let now: TimestampMillis = conn.query_row(
+        "select cast(round((julianday('now') - 2440587.5) * 86400000) as integer)",
+        (),
+        |row| row.get(0),
+    )?;
+    Ok(now.0.into())
+}
 
 #[cfg(test)]
 mod test {
@@ -292,4 +312,24 @@ mod test {
         let rt_dt: _UtcDateTime = retrieved_time.into();
         assert_eq!(st_dt.timestamp_nanos(), rt_dt.timestamp_nanos());
     }
+
+    #[test]
+    fn read_database_clock() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+
+        let res = db_now::<Seconds>(&db);
+        assert!(res.is_ok(), "Failed to read database clock: {:?}", res);
+        let db_seconds: i64 = db
+            .query_row("select unixepoch()", (), |row| row.get(0))
+            .unwrap();
+        assert!((res.unwrap().unwrap().timestamp() - db_seconds).abs() <= 1);
+
+        let db_time: _UtcDateTime = db_now::<Nanoseconds>(&db).unwrap().into();
+        let delta = db_time - chrono::Utc::now();
+        assert!(
+            delta.num_milliseconds().abs() < 1_000,
+            "Timestamps are improbably far apart (DB: {:?}).",
+            db_time
+        );
+    }
 }
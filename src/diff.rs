@@ -0,0 +1,326 @@
+use std::path::Path;
+
+use rusqlite::{types::Value, Connection, Row};
+use thiserror::Error;
+
+use crate::util::quote_ident;
+
+const OTHER: &str = "diff_other";
+
+/// A difference in one row between two databases, identified by its
+/// primary key (or rowid, for tables without one).
+#[derive(Clone, Debug, PartialEq)]
+pub enum RowDiff {
+    /// Only in the second database.
+    Added { key: Vec<Value>, row: Vec<Value> },
+    /// Only in the first database.
+    Removed { key: Vec<Value>, row: Vec<Value> },
+    Changed {
+        key: Vec<Value>,
+        before: Vec<Value>,
+        after: Vec<Value>,
+    },
+}
+
+/// The differences in one table. Rows are in the order of `columns`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TableDiff {
+    pub table: String,
+    pub columns: Vec<String>,
+    pub rows: Vec<RowDiff>,
+}
+
+/// Compare every table of the main database of `conn` with the database at
+/// `other`, which must have the same tables and columns. `other` is attached
+/// for the comparison. Only tables with differences are returned, ordered by
+/// name, with their rows ordered by key.
+pub fn diff_databases<P: AsRef<Path>>(
+    conn: &Connection,
+    other: P,
+) -> Result<Vec<TableDiff>, Error> {
+    conn.execute(
+        &format!("attach database ? as {}", OTHER),
+        (other.as_ref().to_string_lossy().into_owned(),),
+    )?;
+    let res = diff_attached(conn);
+    conn.execute_batch(&format!("detach database {}", OTHER))?;
+    res
+}
+
+fn diff_attached(conn: &Connection) -> Result<Vec<TableDiff>, Error> {
+    let tables = table_names(conn, "main")?;
+    if let Some(t) = table_names(conn, OTHER)?
+        .into_iter()
+        .find(|t| !tables.contains(t))
+    {
+        return Err(Error::SchemaMismatch(t));
+    }
+    let mut diffs = vec![];
+    for table in tables {
+        let diff = diff_table(conn, &table)?;
+        if !diff.rows.is_empty() {
+            diffs.push(diff);
+        }
+    }
+    Ok(diffs)
+}
+
+fn table_names(conn: &Connection, schema: &str) -> rusqlite::Result<Vec<String>> {
+    let mut stmt = conn.prepare(&format!(
+        "select name from {}.sqlite_schema
+        where type = 'table' and name not like 'sqlite_%' order by name",
+        quote_ident(schema)
+    ))?;
+    let names = stmt.query_map((), |row| row.get(0))?.collect();
+    names
+}
+
+/// The columns of `table`, and the names of its primary key columns.
+fn columns(
+    conn: &Connection,
+    schema: &str,
+    table: &str,
+) -> rusqlite::Result<(Vec<String>, Vec<String>)> {
+    let mut stmt = conn.prepare("select name, pk from pragma_table_info(?1, ?2) order by cid")?;
+    let info = stmt
+        .query_map((table, schema), |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let mut pk: Vec<_> = info.iter().filter(|(_, pk)| *pk > 0).collect();
+    pk.sort_by_key(|(_, pk)| *pk);
+    let pk = pk.into_iter().map(|(name, _)| name.clone()).collect();
+    Ok((info.into_iter().map(|(name, _)| name).collect(), pk))
+}
+
+fn diff_table(conn: &Connection, table: &str) -> Result<TableDiff, Error> {
+    let (columns, mut key) = columns(conn, "main", table)?;
+    if columns != self::columns(conn, OTHER, table)?.0 {
+        return Err(Error::SchemaMismatch(table.to_string()));
+    }
+    if key.is_empty() {
+        key.push("rowid".to_string());
+    }
+
+    let main = format!("main.{}", quote_ident(table));
+    let other = format!("{}.{}", OTHER, quote_ident(table));
+    let select = |alias: &str| -> String {
+        key.iter()
+            .chain(&columns)
+            .map(|c| format!("{}.{}", alias, quote_ident(c)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let same = |cols: &[String]| -> String {
+        cols.iter()
+            .map(|c| format!("a.{c} is b.{c}", c = quote_ident(c)))
+            .collect::<Vec<_>>()
+            .join(" and ")
+    };
+    let order = (1..=key.len())
+        .map(|i| i.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let n = columns.len();
+    let k = key.len();
+
+    let mut rows = vec![];
+    let only_in = |from: &str, not_in: &str| {
+        format!(
+            "select {} from {} a where not exists (select 1 from {} b where {}) order by {}",
+            select("a"),
+            from,
+            not_in,
+            same(&key),
+            order
+        )
+    };
+    for (sql, added) in [
+        (only_in(&other, &main), true),
+        (only_in(&main, &other), false),
+    ] {
+        let mut stmt = conn.prepare(&sql)?;
+        let found = stmt
+            .query_map((), |row| {
+                let key = values(row, 0, k)?;
+                let row = values(row, k, n)?;
+                Ok(if added {
+                    RowDiff::Added { key, row }
+                } else {
+                    RowDiff::Removed { key, row }
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        rows.extend(found);
+    }
+    let mut stmt = conn.prepare(&format!(
+        "select {}, {} from {} a join {} b on {} where not ({}) order by {}",
+        select("a"),
+        select("b"),
+        main,
+        other,
+        same(&key),
+        same(&columns),
+        order
+    ))?;
+    let changed = stmt
+        .query_map((), |row| {
+            Ok(RowDiff::Changed {
+                key: values(row, 0, k)?,
+                before: values(row, k, n)?,
+                after: values(row, 2 * k + n, n)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    rows.extend(changed);
+    rows.sort_by(|a, b| compare_keys(key_of(a), key_of(b)));
+
+    Ok(TableDiff {
+        table: table.to_string(),
+        columns,
+        rows,
+    })
+}
+
+fn values(row: &Row<'_>, start: usize, len: usize) -> rusqlite::Result<Vec<Value>> {
+    (start..start + len).map(|i| row.get(i)).collect()
+}
+fn key_of(diff: &RowDiff) -> &[Value] {
+    match diff {
+        RowDiff::Added { key, .. }
+        | RowDiff::Removed { key, .. }
+        | RowDiff::Changed { key, .. } => key,
+    }
+}
+/// Order keys as SQLite would: NULL, then numbers, text and blobs.
+fn compare_keys(a: &[Value], b: &[Value]) -> std::cmp::Ordering {
+    fn rank(v: &Value) -> u8 {
+        match v {
+            Value::Null => 0,
+            Value::Integer(_) | Value::Real(_) => 1,
+            Value::Text(_) => 2,
+            Value::Blob(_) => 3,
+        }
+    }
+    fn number(v: &Value) -> f64 {
+        match v {
+            Value::Integer(i) => *i as f64,
+            Value::Real(f) => *f,
+            _ => 0.0,
+        }
+    }
+    for (a, b) in a.iter().zip(b) {
+        let ordering = rank(a).cmp(&rank(b)).then_with(|| match (a, b) {
+            (Value::Integer(a), Value::Integer(b)) => a.cmp(b),
+            (Value::Text(a), Value::Text(b)) => a.cmp(b),
+            (Value::Blob(a), Value::Blob(b)) => a.cmp(b),
+            _ => number(a).total_cmp(&number(b)),
+        });
+        if ordering.is_ne() {
+            return ordering;
+        }
+    }
+    std::cmp::Ordering::Equal
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("table {0} differs in schema between the databases")]
+    SchemaMismatch(String),
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SCHEMA: &str = "create table users( id integer primary key, name text, email text );
+        create table tags( name text, note text );
+        create table pairs( a integer, b integer, value text, primary key (a, b) );";
+
+    fn open(path: &Path, data: &str) -> Connection {
+        let db = Connection::open(path).expect("Failed to open connection");
+        db.execute_batch(SCHEMA).expect("Failed to create tables");
+        db.execute_batch(data).expect("Failed to insert rows");
+        db
+    }
+
+    #[test]
+    fn diff_tables() {
+        let dir = tempfile::tempdir().expect("Failed to create directory");
+        let a = open(
+            &dir.path().join("a.db"),
+            "insert into users values (1, 'ada', 'ada@a'), (2, 'bob', null), (3, 'cy', 'cy@c');
+            insert into tags values ('x', null);
+            insert into pairs values (1, 1, 'p'), (1, 2, 'q');",
+        );
+        open(
+            &dir.path().join("b.db"),
+            "insert into users values (1, 'ada', 'ada@a'), (2, 'bob', 'bob@b'), (4, 'dee', null);
+            insert into tags values ('x', null);
+            insert into pairs values (1, 1, 'p'), (1, 2, 'r');",
+        );
+
+        let res = diff_databases(&a, dir.path().join("b.db"));
+        assert!(res.is_ok(), "Failed to diff databases: {:?}", res);
+        let diffs = res.unwrap();
+        assert_eq!(
+            diffs.iter().map(|d| d.table.as_str()).collect::<Vec<_>>(),
+            vec!["pairs", "users"]
+        );
+        assert_eq!(
+            diffs[0].rows,
+            vec![RowDiff::Changed {
+                key: vec![Value::Integer(1), Value::Integer(2)],
+                before: vec![
+                    Value::Integer(1),
+                    Value::Integer(2),
+                    Value::Text("q".into())
+                ],
+                after: vec![
+                    Value::Integer(1),
+                    Value::Integer(2),
+                    Value::Text("r".into())
+                ],
+            }]
+        );
+        let users: Vec<_> = diffs[1]
+            .rows
+            .iter()
+            .map(|r| match r {
+                RowDiff::Added { key, .. } => ("added", key[0].clone()),
+                RowDiff::Removed { key, .. } => ("removed", key[0].clone()),
+                RowDiff::Changed { key, .. } => ("changed", key[0].clone()),
+            })
+            .collect();
+        assert_eq!(
+            users,
+            vec![
+                ("changed", Value::Integer(2)),
+                ("removed", Value::Integer(3)),
+                ("added", Value::Integer(4))
+            ]
+        );
+
+        // The other database is detached afterwards.
+        let res = diff_databases(&a, dir.path().join("a.db"));
+        assert!(res.is_ok(), "Failed to diff databases: {:?}", res);
+        assert!(res.unwrap().is_empty());
+    }
+
+    #[test]
+    fn reject_different_schemas() {
+        let dir = tempfile::tempdir().expect("Failed to create directory");
+        let a = open(&dir.path().join("a.db"), "");
+        let b = open(&dir.path().join("b.db"), "");
+        b.execute_batch("alter table tags add column extra")
+            .unwrap();
+        let res = diff_databases(&a, dir.path().join("b.db"));
+        assert!(
+            matches!(res, Err(Error::SchemaMismatch(ref t)) if t == "tags"),
+            "Diffed different schemas: {:?}",
+            res
+        );
+    }
+}
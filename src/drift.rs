@@ -0,0 +1,238 @@
+//! Checking that a struct still matches its table, eg in a test run by CI,
+//! so that a column renamed or retyped by a migration is caught before
+//! reading rows fails with `InvalidColumnName` or `InvalidColumnType`.
+//! `assert_struct_matches_table!` asserts it, failing with a diff.
+
+use std::fmt;
+
+use rusqlite::Connection;
+
+use crate::{
+    column_type::{Affinity, StorageClass},
+    pragma::{table_info, TableColumn},
+    row::Columns,
+    schema::Table,
+};
+
+/// A field of a struct without a column to match in its table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Drift {
+    MissingTable,
+    MissingColumn(String),
+    /// The column's affinity converts the values the field is stored as.
+    Incompatible {
+        column: String,
+        storage: StorageClass,
+        decl_type: String,
+    },
+}
+
+/// The differences between a struct and its table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DriftReport {
+    /// The struct's name.
+    pub ty: String,
+    pub table: String,
+    pub drift: Vec<Drift>,
+}
+
+impl DriftReport {
+    fn new<T>(table: &str) -> Self {
+        let ty = std::any::type_name::<T>();
+        Self {
+            ty: ty.rsplit("::").next().unwrap_or(ty).to_string(),
+            table: table.to_string(),
+            drift: vec![],
+        }
+    }
+    /// Whether the struct matches the table.
+    pub fn is_empty(&self) -> bool {
+        self.drift.is_empty()
+    }
+}
+
+impl fmt::Display for DriftReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "`{}` matches table {:?}", self.ty, self.table);
+        }
+        write!(f, "`{}` doesn't match table {:?}:", self.ty, self.table)?;
+        for drift in &self.drift {
+            match drift {
+                Drift::MissingTable => write!(f, "\n  - the table doesn't exist")?,
+                Drift::MissingColumn(column) => write!(f, "\n  - {}: no such column", column)?,
+                Drift::Incompatible {
+                    column,
+                    storage,
+                    decl_type,
+                } => write!(
+                    f,
+                    "\n  ~ {}: stored as {}, but the column is declared {:?} ({:?} affinity)",
+                    column,
+                    storage.as_str(),
+                    decl_type,
+                    Affinity::of(decl_type)
+                )?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn columns(conn: &Connection, report: &mut DriftReport) -> rusqlite::Result<Vec<TableColumn>> {
+    let columns = table_info(conn, &report.table)?;
+    if columns.is_empty() {
+        report.drift.push(Drift::MissingTable);
+    }
+    Ok(columns)
+}
+
+/// Compare the columns of `T`'s schema with its table in `conn`: each must
+/// exist, with a declared type whose affinity holds the values of the
+/// column's storage class. Unlike `TableSchema::verify`, declared types
+/// needn't be the same, and rows aren't scanned.
+pub fn table_drift<T: Table>(conn: &Connection) -> rusqlite::Result<DriftReport> {
+    let schema = T::schema();
+    let mut report = DriftReport::new::<T>(&schema.name);
+    let existing = columns(conn, &mut report)?;
+    if existing.is_empty() {
+        return Ok(report);
+    }
+    for column in &schema.columns {
+        let found = match existing
+            .iter()
+            .find(|c| c.name.eq_ignore_ascii_case(&column.name))
+        {
+            Some(found) => found,
+            None => {
+                report.drift.push(Drift::MissingColumn(column.name.clone()));
+                continue;
+            }
+        };
+        match column.storage {
+            Some(storage) if !Affinity::of(&found.decl_type).holds(storage) => {
+                report.drift.push(Drift::Incompatible {
+                    column: column.name.clone(),
+                    storage,
+                    decl_type: found.decl_type.clone(),
+                })
+            }
+            _ => {}
+        }
+    }
+    Ok(report)
+}
+
+/// Check that `table` in `conn` has every column `T` reads, for structs
+/// which derive `TryFromRow` but not `Table` (so whose column types
+/// aren't known).
+pub fn columns_drift<T: Columns>(conn: &Connection, table: &str) -> rusqlite::Result<DriftReport> {
+    let mut report = DriftReport::new::<T>(table);
+    let existing = columns(conn, &mut report)?;
+    if existing.is_empty() {
+        return Ok(report);
+    }
+    for column in T::COLUMNS {
+        // The rowid is always there, unless shadowed by a column.
+        let rowid = ["rowid", "oid", "_rowid_"].contains(&column.to_ascii_lowercase().as_str());
+        if !rowid && !existing.iter().any(|c| c.name.eq_ignore_ascii_case(column)) {
+            report.drift.push(Drift::MissingColumn(column.to_string()));
+        }
+    }
+    Ok(report)
+}
+
+/// Assert that a struct matches its table in a connection, failing with a
+/// diff of the fields without a matching column. The struct derives
+/// `Table`, or with a table name, just `TryFromRow` (when only column names
+/// are checked).
+///
+/// ```ignore
+/// assert_struct_matches_table!(&conn, Setting);
+/// assert_struct_matches_table!(&conn, SettingSummary, "setting");
+/// ```
+#[macro_export]
+macro_rules! assert_struct_matches_table {
+    ($conn:expr, $ty:ty) => {
+        match $crate::drift::table_drift::<$ty>($conn) {
+            Ok(report) => assert!(report.is_empty(), "{}", report),
+            Err(e) => panic!("Failed to read the table of `{}`: {}", stringify!($ty), e),
+        }
+    };
+    ($conn:expr, $ty:ty, $table:expr) => {
+        match $crate::drift::columns_drift::<$ty>($conn, $table) {
+            Ok(report) => assert!(report.is_empty(), "{}", report),
+            Err(e) => panic!("Failed to read the table of `{}`: {}", stringify!($ty), e),
+        }
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Table, TryFromRow};
+
+    #[derive(Table)]
+    struct Setting {
+        key: String,
+        value: Option<f64>,
+        enabled: bool,
+    }
+
+    #[derive(TryFromRow)]
+    #[allow(dead_code)]
+    struct SettingKey {
+        key: String,
+        rowid: i64,
+    }
+
+    #[test]
+    fn matching_table() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch("create table setting( key varchar(64), value double, enabled int )")
+            .expect("Failed to create table");
+        let res = table_drift::<Setting>(&db);
+        assert!(res.is_ok(), "Failed to check table: {:?}", res);
+        assert!(res.unwrap().is_empty());
+        assert_struct_matches_table!(&db, Setting);
+        assert_struct_matches_table!(&db, SettingKey, "setting");
+    }
+
+    #[test]
+    fn drifted_table() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        assert_eq!(
+            table_drift::<Setting>(&db).unwrap().drift,
+            vec![Drift::MissingTable]
+        );
+        db.execute_batch("create table setting( name text, value text, enabled numeric )")
+            .unwrap();
+        let report = table_drift::<Setting>(&db).unwrap();
+        assert_eq!(
+            report.drift,
+            vec![
+                Drift::MissingColumn("key".into()),
+                Drift::Incompatible {
+                    column: "value".into(),
+                    storage: StorageClass::Real,
+                    decl_type: "TEXT".into()
+                }
+            ]
+        );
+        assert_eq!(
+            report.to_string(),
+            "`Setting` doesn't match table \"setting\":\n  \
+            - key: no such column\n  \
+            ~ value: stored as real, but the column is declared \"TEXT\" (Text affinity)"
+        );
+        assert_eq!(
+            columns_drift::<SettingKey>(&db, "setting").unwrap().drift,
+            vec![Drift::MissingColumn("key".into())]
+        );
+
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            assert_struct_matches_table!(&db, Setting)
+        }));
+        assert!(res.is_err(), "Drift wasn't caught");
+    }
+}
@@ -0,0 +1,449 @@
+use std::{collections::BTreeMap, marker::PhantomData};
+
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    ChaCha20Poly1305, Nonce,
+};
+use rusqlite::{
+    types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef},
+    Connection, ToSql,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use thiserror::Error;
+
+use crate::{
+    column_type::{SqliteColumnType, StorageClass},
+    util::quote_ident,
+};
+
+/// A 256 bit ChaCha20-Poly1305 key.
+pub type Key = [u8; 32];
+
+const KEY_ID_LEN: usize = 4;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// Supplies the keys values are encrypted with. Keys are identified by a
+/// number stored alongside each value, so old values stay readable after a
+/// new key is introduced.
+pub trait KeyProvider {
+    /// The id of the key new values are encrypted with.
+    fn current_key_id(&self) -> u32;
+    fn key(&self, id: u32) -> Option<Key>;
+}
+
+/// A set of keys held in memory, the most recently added being current.
+#[derive(Clone, Default)]
+pub struct KeyRing {
+    keys: BTreeMap<u32, Key>,
+    current: u32,
+}
+impl KeyRing {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Add a key, which becomes the current key.
+    pub fn add(mut self, id: u32, key: Key) -> Self {
+        self.keys.insert(id, key);
+        self.current = id;
+        self
+    }
+}
+impl KeyProvider for KeyRing {
+    fn current_key_id(&self) -> u32 {
+        self.current
+    }
+    fn key(&self, id: u32) -> Option<Key> {
+        self.keys.get(&id).copied()
+    }
+}
+impl std::fmt::Debug for KeyRing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeyRing")
+            .field("key_ids", &self.keys.keys().collect::<Vec<_>>())
+            .field("current", &self.current)
+            .finish()
+    }
+}
+
+/// A value serialized as JSON and encrypted with ChaCha20-Poly1305, stored as
+/// a `BLOB` of the key id (4 bytes, big endian), nonce and ciphertext.
+/// Values are encrypted and decrypted explicitly with a `KeyProvider`; the
+/// column itself only ever holds ciphertext.
+pub struct Encrypted<T> {
+    data: Vec<u8>,
+    _value: PhantomData<T>,
+}
+
+impl<T: Serialize + DeserializeOwned> Encrypted<T> {
+    /// Encrypt `value` with the current key of `keys`.
+    pub fn encrypt(value: &T, keys: &dyn KeyProvider) -> Result<Self, Error> {
+        let plaintext = serde_json::to_vec(value).map_err(Error::Serialization)?;
+        Ok(Self::from_data(seal(&plaintext, keys)?))
+    }
+    pub fn decrypt(&self, keys: &dyn KeyProvider) -> Result<T, Error> {
+        serde_json::from_slice(&open(&self.data, keys)?).map_err(Error::Serialization)
+    }
+}
+impl<T> Encrypted<T> {
+    /// The id of the key the value was encrypted with.
+    pub fn key_id(&self) -> u32 {
+        key_id(&self.data).expect("length was validated")
+    }
+    fn from_data(data: Vec<u8>) -> Self {
+        Self {
+            data,
+            _value: PhantomData,
+        }
+    }
+}
+impl<T> Clone for Encrypted<T> {
+    fn clone(&self) -> Self {
+        Self::from_data(self.data.clone())
+    }
+}
+impl<T> std::fmt::Debug for Encrypted<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Encrypted")
+            .field("key_id", &self.key_id())
+            .finish_non_exhaustive()
+    }
+}
+impl<T> FromSql for Encrypted<T> {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let data = value.as_blob()?;
+        if data.len() < KEY_ID_LEN + NONCE_LEN + TAG_LEN {
+            return Err(FromSqlError::Other(Box::new(Error::Malformed)));
+        }
+        Ok(Self::from_data(data.to_vec()))
+    }
+}
+impl<T> ToSql for Encrypted<T> {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.data.as_slice()))
+    }
+}
+impl<T> SqliteColumnType for Encrypted<T> {
+    const DECL_TYPE: Option<&'static str> = Some("blob");
+    const STORAGE: Option<StorageClass> = Some(StorageClass::Blob);
+}
+
+fn key_id(data: &[u8]) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(..KEY_ID_LEN)?.try_into().ok()?))
+}
+fn cipher(keys: &dyn KeyProvider, id: u32) -> Result<ChaCha20Poly1305, Error> {
+    let key = keys.key(id).ok_or(Error::UnknownKey(id))?;
+    Ok(ChaCha20Poly1305::new(&key.into()))
+}
+/// Encrypt with the current key, authenticating the key id along with the
+/// plaintext.
+fn seal(plaintext: &[u8], keys: &dyn KeyProvider) -> Result<Vec<u8>, Error> {
+    let id = keys.current_key_id();
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let header = id.to_be_bytes();
+    let ciphertext = cipher(keys, id)?
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad: &header,
+            },
+        )
+        .map_err(|_| Error::Encryption)?;
+    let mut data = Vec::with_capacity(KEY_ID_LEN + NONCE_LEN + ciphertext.len());
+    data.extend_from_slice(&header);
+    data.extend_from_slice(&nonce);
+    data.extend_from_slice(&ciphertext);
+    Ok(data)
+}
+fn open(data: &[u8], keys: &dyn KeyProvider) -> Result<Vec<u8>, Error> {
+    let id = key_id(data).ok_or(Error::Malformed)?;
+    let (header, rest) = data.split_at(KEY_ID_LEN);
+    if rest.len() < NONCE_LEN + TAG_LEN {
+        return Err(Error::Malformed);
+    }
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    cipher(keys, id)?
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: header,
+            },
+        )
+        .map_err(|_| Error::Decryption)
+}
+
+/// Progress of a `KeyRotation`, reported after each batch.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Progress {
+    pub table: String,
+    pub column: String,
+    /// Values re-encrypted in this column so far.
+    pub rotated: usize,
+    /// Values in this column still to re-encrypt.
+    pub remaining: usize,
+}
+
+type ProgressFn<'a> = Box<dyn FnMut(&Progress) + 'a>;
+
+/// Re-encrypts `Encrypted` columns from the keys of one provider to the
+/// current key of another. Each batch is committed separately, and values
+/// already under the new key are skipped, so an interrupted rotation can
+/// simply be run again.
+pub struct KeyRotation<'a> {
+    old: &'a dyn KeyProvider,
+    new: &'a dyn KeyProvider,
+    columns: Vec<(String, String)>,
+    batch_size: usize,
+    progress: Option<ProgressFn<'a>>,
+}
+
+impl<'a> KeyRotation<'a> {
+    pub fn new(old: &'a dyn KeyProvider, new: &'a dyn KeyProvider) -> Self {
+        Self {
+            old,
+            new,
+            columns: vec![],
+            batch_size: 500,
+            progress: None,
+        }
+    }
+    /// Rotate the `Encrypted` values in `column` of `table`.
+    pub fn column(mut self, table: &str, column: &str) -> Self {
+        self.columns.push((table.to_string(), column.to_string()));
+        self
+    }
+    /// Rows re-encrypted per transaction (500 by default).
+    pub fn batch_size(mut self, size: usize) -> Self {
+        self.batch_size = size.max(1);
+        self
+    }
+    pub fn on_progress<F: FnMut(&Progress) + 'a>(mut self, f: F) -> Self {
+        self.progress = Some(Box::new(f));
+        self
+    }
+
+    /// Rotate every column, returning how many values were re-encrypted.
+    pub fn run(mut self, conn: &Connection) -> Result<usize, Error> {
+        let mut total = 0;
+        for (table, column) in std::mem::take(&mut self.columns) {
+            total += self.rotate_column(conn, &table, &column)?;
+        }
+        Ok(total)
+    }
+
+    fn rotate_column(
+        &mut self,
+        conn: &Connection,
+        table: &str,
+        column: &str,
+    ) -> Result<usize, Error> {
+        let new_id = self.new.current_key_id().to_be_bytes();
+        let pending = format!(
+            "{column} is not null and substr({column}, 1, {len}) != ?1",
+            column = quote_ident(column),
+            len = KEY_ID_LEN,
+        );
+        let select = format!(
+            "select rowid, {} from {} where {} and rowid > ?2 order by rowid limit ?3",
+            quote_ident(column),
+            quote_ident(table),
+            pending
+        );
+        let count = format!(
+            "select count(*) from {} where {}",
+            quote_ident(table),
+            pending
+        );
+        let update = format!(
+            "update {} set {} = ? where rowid = ?",
+            quote_ident(table),
+            quote_ident(column)
+        );
+
+        let mut progress = Progress {
+            table: table.to_string(),
+            column: column.to_string(),
+            rotated: 0,
+            remaining: conn.query_row(&count, (&new_id[..],), |row| row.get::<_, i64>(0))? as usize,
+        };
+        let mut last = i64::MIN;
+        loop {
+            let batch = {
+                let mut stmt = conn.prepare_cached(&select)?;
+                let rows = stmt
+                    .query_map((&new_id[..], last, self.batch_size as i64), |row| {
+                        Ok((row.get::<_, i64>(0)?, row.get::<_, Vec<u8>>(1)?))
+                    })?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                rows
+            };
+            let Some(&(rowid, _)) = batch.last() else {
+                break;
+            };
+            last = rowid;
+
+            let mut rotated = Vec::with_capacity(batch.len());
+            for (rowid, data) in &batch {
+                rotated.push((*rowid, seal(&open(data, self.old)?, self.new)?));
+            }
+            conn.execute_batch("savepoint rotate_keys")?;
+            let res = rotated
+                .iter()
+                .try_for_each(|(rowid, data)| conn.execute(&update, (data, rowid)).map(|_| ()));
+            match res {
+                Ok(()) => conn.execute_batch("release rotate_keys")?,
+                Err(e) => {
+                    conn.execute_batch("rollback to rotate_keys; release rotate_keys")?;
+                    return Err(e.into());
+                }
+            }
+
+            progress.rotated += rotated.len();
+            progress.remaining = progress.remaining.saturating_sub(rotated.len());
+            if let Some(f) = &mut self.progress {
+                f(&progress);
+            }
+        }
+        Ok(progress.rotated)
+    }
+}
+
+/// Re-encrypt `columns` (pairs of table and column) from `old` to the
+/// current key of `new`; see `KeyRotation`.
+pub fn rotate_keys(
+    conn: &Connection,
+    old: &dyn KeyProvider,
+    new: &dyn KeyProvider,
+    columns: &[(&str, &str)],
+) -> Result<usize, Error> {
+    columns
+        .iter()
+        .fold(KeyRotation::new(old, new), |rotation, (table, column)| {
+            rotation.column(table, column)
+        })
+        .run(conn)
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("no key with id {0}")]
+    UnknownKey(u32),
+    #[error("failed to encrypt value")]
+    Encryption,
+    #[error("failed to decrypt value; the key is wrong or the data was modified")]
+    Decryption,
+    #[error("encrypted value is malformed")]
+    Malformed,
+    #[error("failed to serialize value")]
+    Serialization(#[source] serde_json::Error),
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn keys(ids: &[u32]) -> KeyRing {
+        ids.iter()
+            .fold(KeyRing::new(), |ring, &id| ring.add(id, [id as u8; 32]))
+    }
+
+    fn setup(keys: &KeyRing) -> Connection {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute(
+            "create table secrets( id integer primary key, value blob )",
+            (),
+        )
+        .expect("Failed to create table");
+        for i in 0..10 {
+            let value = Encrypted::encrypt(&format!("secret {}", i), keys).unwrap();
+            db.execute("insert into secrets(value) values (?)", (value,))
+                .expect("Failed to insert row");
+        }
+        db.execute("insert into secrets(value) values (null)", ())
+            .expect("Failed to insert row");
+        db
+    }
+
+    fn read(db: &Connection, keys: &dyn KeyProvider) -> Vec<(u32, String)> {
+        let mut stmt = db
+            .prepare("select value from secrets where value is not null order by id")
+            .unwrap();
+        let values = stmt
+            .query_map((), |row| row.get::<_, Encrypted<String>>(0))
+            .unwrap()
+            .map(|v| {
+                let v = v.unwrap();
+                (v.key_id(), v.decrypt(keys).unwrap())
+            })
+            .collect();
+        values
+    }
+
+    #[test]
+    fn encrypt_values() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        let keys = keys(&[1]);
+        let value = Encrypted::encrypt(&vec![1, 2, 3], &keys).unwrap();
+        let res = db.query_row("select ?", (&value,), |row| {
+            row.get::<_, Encrypted<Vec<i32>>>(0)
+        });
+        assert!(res.is_ok(), "Failed to retrieve value: {:?}", res);
+        let value = res.unwrap();
+        assert_eq!(value.decrypt(&keys).unwrap(), vec![1, 2, 3]);
+        assert!(matches!(
+            value.decrypt(&KeyRing::new().add(1, [9; 32])),
+            Err(Error::Decryption)
+        ));
+        assert!(matches!(
+            value.decrypt(&KeyRing::new()),
+            Err(Error::UnknownKey(1))
+        ));
+        assert!(db
+            .query_row("select x'00'", (), |row| row.get::<_, Encrypted<i32>>(0))
+            .is_err());
+    }
+
+    #[test]
+    fn rotate_in_batches() {
+        let old = keys(&[1]);
+        let db = setup(&old);
+        let new = keys(&[2]);
+
+        let mut reports = vec![];
+        let res = KeyRotation::new(&old, &new)
+            .column("secrets", "value")
+            .batch_size(4)
+            .on_progress(|p| reports.push((p.rotated, p.remaining)))
+            .run(&db);
+        assert!(res.is_ok(), "Failed to rotate keys: {:?}", res);
+        assert_eq!(res.unwrap(), 10);
+        assert_eq!(reports, vec![(4, 6), (8, 2), (10, 0)]);
+
+        let values = read(&db, &new);
+        assert!(values.iter().all(|(id, _)| *id == 2));
+        assert_eq!(values[3].1, "secret 3");
+    }
+
+    #[test]
+    fn resume_rotation() {
+        let old = keys(&[1]);
+        let db = setup(&old);
+        let new = keys(&[1, 2]);
+
+        // Simulate an interrupted rotation which got through some rows.
+        for id in 1..=3 {
+            let value = Encrypted::encrypt(&format!("secret {}", id - 1), &new).unwrap();
+            db.execute("update secrets set value = ? where id = ?", (value, id))
+                .unwrap();
+        }
+
+        let res = rotate_keys(&db, &old, &new, &[("secrets", "value")]);
+        assert!(res.is_ok(), "Failed to rotate keys: {:?}", res);
+        assert_eq!(res.unwrap(), 7);
+        assert!(read(&db, &new).iter().all(|(id, _)| *id == 2));
+    }
+}
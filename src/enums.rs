@@ -0,0 +1,115 @@
+use rusqlite::types::FromSqlError;
+use thiserror::Error;
+
+/// A stored value which isn't one of the variants of the enum it was read
+/// into, eg after a variant was removed.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("{value} is not a variant of `{ty}`")]
+pub struct UnknownVariant {
+    pub ty: &'static str,
+    pub value: String,
+}
+
+/// The error for reading `value` into the enum `ty`. Used by
+/// `#[derive(EnumAsInteger)]` (`#[derive(EnumAsText)]` returns
+/// `UnknownVariant` from `FromStr`).
+pub fn unknown_variant(ty: &'static str, value: String) -> FromSqlError {
+    FromSqlError::Other(Box::new(UnknownVariant { ty, value }))
+}
+
+#[cfg(test)]
+mod test {
+    use rusqlite::Connection;
+
+    use super::*;
+    use crate::{column_type::SqliteColumnType, EnumAsInteger, EnumAsText};
+
+    #[derive(EnumAsInteger, Debug, PartialEq)]
+    enum Status {
+        Draft,
+        Published = 10,
+        Archived,
+    }
+
+    #[test]
+    fn enum_as_integer() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch("create table post( status integer )")
+            .expect("Failed to create table");
+        for status in [Status::Draft, Status::Published, Status::Archived] {
+            db.execute("insert into post values (?)", (&status,))
+                .expect("Failed to insert status");
+        }
+        let stored: Vec<i64> = db
+            .prepare("select status from post")
+            .unwrap()
+            .query_map((), |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(stored, vec![0, 10, 11]);
+
+        let res: rusqlite::Result<Status> =
+            db.query_row("select status from post where rowid = 3", (), |row| {
+                row.get(0)
+            });
+        assert!(res.is_ok(), "Failed to read status: {:?}", res);
+        assert_eq!(res.unwrap(), Status::Archived);
+        assert_eq!(Status::DECL_TYPE, Some("integer"));
+
+        let res: rusqlite::Result<Status> = db.query_row("select 5", (), |row| row.get(0));
+        match res {
+            Err(rusqlite::Error::FromSqlConversionFailure(_, _, e)) => {
+                let e = e.downcast_ref::<UnknownVariant>().expect("Wrong error");
+                assert_eq!(e.ty, "Status");
+                assert_eq!(e.value, "5");
+            }
+            other => panic!("Read an unknown variant: {:?}", other),
+        }
+    }
+
+    #[derive(EnumAsText, Debug, PartialEq)]
+    #[enum_as_text(rename_all = "snake_case")]
+    enum Plan {
+        Free,
+        ProMonthly,
+        #[enum_as_text(rename = "enterprise")]
+        EnterpriseContract,
+    }
+
+    #[derive(EnumAsText, Debug, PartialEq)]
+    #[enum_as_text(rename_all = "SCREAMING-KEBAB-CASE")]
+    enum Level {
+        VeryHigh,
+    }
+
+    #[test]
+    fn enum_as_text() {
+        assert_eq!(Plan::ProMonthly.to_string(), "pro_monthly");
+        assert_eq!("enterprise".parse(), Ok(Plan::EnterpriseContract));
+        assert_eq!(
+            "EnterpriseContract".parse::<Plan>(),
+            Err(UnknownVariant {
+                ty: "Plan",
+                value: "EnterpriseContract".to_string()
+            })
+        );
+        assert_eq!(Level::VeryHigh.to_string(), "VERY-HIGH");
+        assert_eq!(Plan::DECL_TYPE, Some("text"));
+
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        let res: rusqlite::Result<(String, Plan)> =
+            db.query_row("select ?1, ?1", (Plan::Free,), |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            });
+        assert!(res.is_ok(), "Failed to round trip plan: {:?}", res);
+        assert_eq!(res.unwrap(), ("free".to_string(), Plan::Free));
+
+        let res: rusqlite::Result<Plan> = db.query_row("select 'gold'", (), |row| row.get(0));
+        assert!(
+            matches!(res, Err(rusqlite::Error::FromSqlConversionFailure(..))),
+            "Read an unknown variant: {:?}",
+            res
+        );
+    }
+}
@@ -0,0 +1,15 @@
+use std::time::Duration;
+
+use thiserror::Error;
+
+/// Errors from operations which can fail for reasons other than SQLite
+/// itself, such as cancellation and timeouts.
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Operation was cancelled")]
+    Cancelled,
+    #[error("Operation timed out after {0:?}")]
+    Timeout(Duration),
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+}
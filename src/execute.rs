@@ -0,0 +1,177 @@
+use std::{cell::RefCell, fmt::Display};
+
+use rusqlite::{
+    types::{ToSqlOutput, Value},
+    Connection, ToSql,
+};
+
+use crate::util::split_queries;
+
+/// Destination for the SQL emitted by the crate's schema, migration and
+/// maintenance helpers. A `Connection` executes statements as they arrive,
+/// while a `DryRun` records them into a `Plan` for review.
+pub trait Executor {
+    /// The connection used for reads, eg to determine pending migrations.
+    fn connection(&self) -> &Connection;
+    /// Execute a single statement, returning the number of changed rows.
+    fn run(&self, sql: &str, params: &[&dyn ToSql]) -> rusqlite::Result<usize>;
+    /// Execute a script of statements without parameters.
+    fn run_batch(&self, sql: &str) -> rusqlite::Result<()>;
+    /// Whether statements are recorded rather than executed.
+    fn is_dry_run(&self) -> bool {
+        false
+    }
+}
+
+impl Executor for Connection {
+    fn connection(&self) -> &Connection {
+        self
+    }
+    fn run(&self, sql: &str, params: &[&dyn ToSql]) -> rusqlite::Result<usize> {
+        self.execute(sql, params)
+    }
+    fn run_batch(&self, sql: &str) -> rusqlite::Result<()> {
+        self.execute_batch(sql)
+    }
+}
+
+/// An `Executor` that records statements instead of executing them. Reads
+/// are still served by the wrapped connection, so the plan reflects the
+/// current state of the database.
+pub struct DryRun<'conn> {
+    conn: &'conn Connection,
+    plan: RefCell<Plan>,
+}
+impl<'conn> DryRun<'conn> {
+    pub fn new(conn: &'conn Connection) -> Self {
+        Self {
+            conn,
+            plan: RefCell::new(Plan::default()),
+        }
+    }
+    pub fn into_plan(self) -> Plan {
+        self.plan.into_inner()
+    }
+}
+impl<'conn> Executor for DryRun<'conn> {
+    fn connection(&self) -> &Connection {
+        self.conn
+    }
+    fn run(&self, sql: &str, params: &[&dyn ToSql]) -> rusqlite::Result<usize> {
+        let params = params
+            .iter()
+            .map(|p| p.to_sql().map(to_value))
+            .collect::<rusqlite::Result<_>>()?;
+        self.plan.borrow_mut().statements.push(PlannedStatement {
+            sql: sql.trim().to_string(),
+            params,
+        });
+        Ok(0)
+    }
+    fn run_batch(&self, sql: &str) -> rusqlite::Result<()> {
+        for stmt in split_queries(sql) {
+            self.run(stmt, &[])?;
+        }
+        Ok(())
+    }
+    fn is_dry_run(&self) -> bool {
+        true
+    }
+}
+
+/// The statements a `DryRun` would have executed, in order.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Plan {
+    pub statements: Vec<PlannedStatement>,
+}
+impl Plan {
+    pub fn is_empty(&self) -> bool {
+        self.statements.is_empty()
+    }
+    pub fn len(&self) -> usize {
+        self.statements.len()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &PlannedStatement> {
+        self.statements.iter()
+    }
+}
+impl Display for Plan {
+    /// Renders the plan as a SQL script, with parameters in comments.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for stmt in &self.statements {
+            writeln!(f, "{}", stmt)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlannedStatement {
+    pub sql: String,
+    pub params: Vec<Value>,
+}
+impl Display for PlannedStatement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{};", self.sql)?;
+        if !self.params.is_empty() {
+            write!(f, " -- params: {:?}", self.params)?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn to_value(output: ToSqlOutput<'_>) -> Value {
+    match output {
+        ToSqlOutput::Borrowed(v) => v.into(),
+        ToSqlOutput::Owned(v) => v,
+        ToSqlOutput::ZeroBlob(n) => Value::Blob(vec![0; n.max(0) as usize]),
+        _ => unreachable!("optional rusqlite features producing other outputs are disabled"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dry_run_records_statements() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        let dry_run = DryRun::new(&db);
+
+        dry_run
+            .run_batch("create table foo( a integer ); create index foo_a on foo(a);")
+            .expect("Failed to record batch");
+        let res = dry_run.run("insert into foo(a) values (?)", &[&10]);
+        assert_eq!(res.ok(), Some(0));
+
+        let plan = dry_run.into_plan();
+        assert_eq!(plan.len(), 3);
+        assert_eq!(plan.statements[2].params, vec![Value::Integer(10)]);
+        assert_eq!(
+            plan.to_string(),
+            "create table foo( a integer );\n\
+            create index foo_a on foo(a);\n\
+            insert into foo(a) values (?); -- params: [Integer(10)]\n"
+        );
+
+        let tables: i64 = db
+            .query_row("select count(*) from sqlite_schema", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(tables, 0, "Dry run modified the database");
+    }
+
+    #[test]
+    fn dry_run_zero_blob() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        let dry_run = DryRun::new(&db);
+        let res = dry_run.run(
+            "insert into foo(a) values (?)",
+            &[&rusqlite::blob::ZeroBlob(4)],
+        );
+        assert_eq!(res.ok(), Some(0));
+        assert_eq!(
+            dry_run.into_plan().statements[0].params,
+            vec![Value::Blob(vec![0; 4])]
+        );
+    }
+}
@@ -0,0 +1,210 @@
+//! Checking which typed ids have rows, eg before an importer or a sync
+//! writes rows referencing them, without a `SELECT` per id.
+
+use std::{collections::HashSet, hash::Hash, marker::PhantomData};
+
+use rusqlite::{types::FromSql, Connection};
+
+use crate::{crud::Delete, query::in_list, util::quote_ident};
+
+/// Whether `T` has a row with key `id`.
+pub fn exists<T: Delete>(conn: &Connection, id: &T::Id) -> rusqlite::Result<bool> {
+    let sql = format!(
+        "select 1 from {} where {} = ?",
+        quote_ident(T::TABLE),
+        quote_ident(T::ID_COLUMN)
+    );
+    conn.prepare_cached(&sql)?.exists([id])
+}
+
+/// The ids among `ids` which `T` has rows for, in the order given, checked
+/// with a single query.
+pub fn filter_existing<T>(conn: &Connection, ids: &[T::Id]) -> rusqlite::Result<Vec<T::Id>>
+where
+    T: Delete,
+    T::Id: FromSql + Eq + Hash + Clone,
+{
+    let found = select_existing::<T>(conn, ids)?;
+    Ok(ids
+        .iter()
+        .filter(|id| found.contains(id))
+        .cloned()
+        .collect())
+}
+
+fn select_existing<T>(conn: &Connection, ids: &[T::Id]) -> rusqlite::Result<HashSet<T::Id>>
+where
+    T: Delete,
+    T::Id: FromSql + Eq + Hash,
+{
+    if ids.is_empty() {
+        return Ok(HashSet::new());
+    }
+    let id = quote_ident(T::ID_COLUMN);
+    let condition = in_list(&id, ids)?;
+    let sql = format!(
+        "select {} from {} where {}",
+        id,
+        quote_ident(T::TABLE),
+        condition.sql()
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let found = stmt.query_map(&*condition.params(), |row| row.get(0))?;
+    found.collect()
+}
+
+/// The ids of `T` known to have rows, so that checking them again doesn't
+/// hit the database.
+///
+/// The cache is optimistic: ids are assumed to keep their rows once seen,
+/// so it should only be used while rows aren't being deleted (eg for the
+/// duration of an import), or be told with `forget` or `clear`. Missing
+/// ids aren't cached, since they may be inserted at any time.
+pub struct KnownIds<T: Delete> {
+    known: HashSet<T::Id>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Default for KnownIds<T>
+where
+    T: Delete,
+    T::Id: FromSql + Eq + Hash + Clone,
+{
+    fn default() -> Self {
+        Self {
+            known: HashSet::new(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> KnownIds<T>
+where
+    T: Delete,
+    T::Id: FromSql + Eq + Hash + Clone,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether `T` has a row with key `id`, from the cache if it's known.
+    pub fn exists(&mut self, conn: &Connection, id: &T::Id) -> rusqlite::Result<bool> {
+        if self.known.contains(id) {
+            return Ok(true);
+        }
+        let exists = exists::<T>(conn, id)?;
+        if exists {
+            self.known.insert(id.clone());
+        }
+        Ok(exists)
+    }
+
+    /// The ids among `ids` which `T` has rows for, in the order given.
+    /// Only the ids which aren't known are queried, with a single query.
+    pub fn filter_existing(
+        &mut self,
+        conn: &Connection,
+        ids: &[T::Id],
+    ) -> rusqlite::Result<Vec<T::Id>> {
+        let unknown: Vec<_> = ids
+            .iter()
+            .filter(|id| !self.known.contains(id))
+            .cloned()
+            .collect();
+        self.known.extend(select_existing::<T>(conn, &unknown)?);
+        Ok(ids
+            .iter()
+            .filter(|id| self.known.contains(id))
+            .cloned()
+            .collect())
+    }
+
+    /// Record that `id` has a row, eg after inserting it.
+    pub fn insert(&mut self, id: T::Id) {
+        self.known.insert(id);
+    }
+    /// Record that `id` may no longer have a row, eg after deleting it.
+    pub fn forget(&mut self, id: &T::Id) {
+        self.known.remove(id);
+    }
+    pub fn clear(&mut self) {
+        self.known.clear();
+    }
+    /// The number of ids known to have rows.
+    pub fn len(&self) -> usize {
+        self.known.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.known.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Delete, IntegerId};
+
+    #[derive(Delete)]
+    #[allow(dead_code)]
+    struct Author {
+        #[id]
+        id: IntegerId<Author>,
+        name: String,
+    }
+
+    fn setup() -> Connection {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch(
+            "create table author( id integer primary key, name text );
+            with recursive n(i) as (select 1 union all select i + 1 from n where i < 1500)
+            insert into author select i * 2, 'author ' || i from n;",
+        )
+        .expect("Failed to set up database");
+        db
+    }
+
+    fn author_id(db: &Connection, id: i64) -> IntegerId<Author> {
+        db.query_row("select ?", (id,), |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn exists_and_filter() {
+        let db = setup();
+        let res = exists::<Author>(&db, &author_id(&db, 4));
+        assert!(res.is_ok(), "Failed to check id: {:?}", res);
+        assert!(res.unwrap());
+        assert!(!exists::<Author>(&db, &author_id(&db, 5)).unwrap());
+
+        let ids: Vec<_> = [7, 6, 1, 2].iter().map(|i| author_id(&db, *i)).collect();
+        let res = filter_existing::<Author>(&db, &ids);
+        assert!(res.is_ok(), "Failed to filter ids: {:?}", res);
+        assert_eq!(res.unwrap(), vec![ids[1], ids[3]]);
+        assert!(filter_existing::<Author>(&db, &[]).unwrap().is_empty());
+
+        // More ids than fit in the parameters of a statement.
+        let ids: Vec<_> = (1..=3000).map(|i| author_id(&db, i)).collect();
+        let existing = filter_existing::<Author>(&db, &ids).unwrap();
+        assert_eq!(existing.len(), 1500);
+        assert_eq!(existing[0], author_id(&db, 2));
+    }
+
+    #[test]
+    fn known_ids() {
+        let db = setup();
+        let mut known = KnownIds::<Author>::new();
+        let ids: Vec<_> = [1, 2, 4].iter().map(|i| author_id(&db, *i)).collect();
+        let res = known.filter_existing(&db, &ids);
+        assert!(res.is_ok(), "Failed to filter ids: {:?}", res);
+        assert_eq!(res.unwrap(), vec![ids[1], ids[2]]);
+        assert_eq!(known.len(), 2);
+
+        // Known ids are trusted, missing ones checked again.
+        db.execute_batch("delete from author where id = 2; insert into author values (1, 'new')")
+            .unwrap();
+        assert_eq!(known.filter_existing(&db, &ids).unwrap(), ids);
+        known.forget(&ids[1]);
+        assert!(!known.exists(&db, &ids[1]).unwrap());
+        assert!(known.exists(&db, &ids[0]).unwrap());
+        assert_eq!(known.len(), 2);
+    }
+}
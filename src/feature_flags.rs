@@ -0,0 +1,344 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use rusqlite::{types::Type, Connection, OptionalExtension};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    date_time::TimestampMillis, execute::Executor, object::JsonObject, util::quote_ident,
+    TryFromRow,
+};
+
+/// A row of the feature flag table.
+#[derive(Clone, Debug, PartialEq, TryFromRow)]
+pub struct FeatureFlag {
+    pub name: String,
+    pub enabled: bool,
+    /// Percentage of subjects (0 - 100) for which an enabled flag is active.
+    pub rollout: u8,
+    pub payload: Option<JsonObject<serde_json::Value>>,
+    pub updated_at: TimestampMillis,
+}
+impl FeatureFlag {
+    /// Whether the flag is active for `subject` (eg, a user id), taking the
+    /// rollout percentage into account. The same subject always lands in the
+    /// same bucket, so raising the percentage only ever adds subjects.
+    pub fn is_enabled_for(&self, subject: &str) -> bool {
+        self.enabled && rollout_bucket(&self.name, subject) < u64::from(self.rollout)
+    }
+}
+
+/// Typed access to a table of feature flags, with an in-process cache.
+///
+/// The cache is invalidated by writes made through this type and by commits
+/// from other connections (detected through `PRAGMA data_version`). Changes
+/// made by other code on the same connection should be followed by a call
+/// to `invalidate`, eg from an update hook.
+pub struct FeatureFlags<'conn> {
+    conn: &'conn Connection,
+    /// The quoted table name.
+    table: String,
+    cache: RefCell<Option<Cache>>,
+}
+struct Cache {
+    data_version: i64,
+    flags: HashMap<String, FeatureFlag>,
+}
+
+impl<'conn> FeatureFlags<'conn> {
+    pub const DEFAULT_TABLE: &'static str = "feature_flags";
+
+    pub fn new(conn: &'conn Connection) -> Self {
+        Self::with_table(conn, Self::DEFAULT_TABLE)
+    }
+    pub fn with_table(conn: &'conn Connection, table: &str) -> Self {
+        Self {
+            conn,
+            table: quote_ident(table),
+            cache: RefCell::new(None),
+        }
+    }
+
+    pub fn create_sql(&self) -> String {
+        format!(
+            "create table if not exists {}(
+                name text primary key not null,
+                enabled integer not null default 0,
+                rollout integer not null default 100 check (rollout between 0 and 100),
+                payload text,
+                updated_at integer not null
+            )",
+            self.table
+        )
+    }
+    /// Create the flag table if it does not already exist.
+    pub fn create_table<E: Executor + ?Sized>(&self, exec: &E) -> rusqlite::Result<()> {
+        exec.run_batch(&self.create_sql())
+    }
+
+    pub fn get(&self, name: &str) -> rusqlite::Result<Option<FeatureFlag>> {
+        self.refresh()?;
+        Ok(self
+            .cache
+            .borrow()
+            .as_ref()
+            .and_then(|c| c.flags.get(name).cloned()))
+    }
+    pub fn all(&self) -> rusqlite::Result<Vec<FeatureFlag>> {
+        self.refresh()?;
+        let mut flags: Vec<_> = self
+            .cache
+            .borrow()
+            .as_ref()
+            .map(|c| c.flags.values().cloned().collect())
+            .unwrap_or_default();
+        flags.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(flags)
+    }
+
+    /// Whether the flag is enabled, ignoring the rollout percentage. Unknown
+    /// flags are disabled.
+    pub fn is_enabled(&self, name: &str) -> rusqlite::Result<bool> {
+        Ok(self.get(name)?.map(|f| f.enabled).unwrap_or(false))
+    }
+    /// Whether the flag is enabled for a particular subject. Unknown flags
+    /// are disabled.
+    pub fn is_enabled_for(&self, name: &str, subject: &str) -> rusqlite::Result<bool> {
+        Ok(self
+            .get(name)?
+            .map(|f| f.is_enabled_for(subject))
+            .unwrap_or(false))
+    }
+    /// Deserialize the flag's payload into `T`.
+    pub fn payload<T: DeserializeOwned>(&self, name: &str) -> rusqlite::Result<Option<T>> {
+        match self.get(name)?.and_then(|f| f.payload) {
+            Some(payload) => serde_json::from_value(payload.unwrap())
+                .map(Some)
+                .map_err(|e| rusqlite::Error::FromSqlConversionFailure(3, Type::Text, Box::new(e))),
+            None => Ok(None),
+        }
+    }
+
+    /// Enable or disable a flag, creating it if necessary.
+    pub fn set_enabled(&self, name: &str, enabled: bool) -> rusqlite::Result<()> {
+        self.upsert(name, "enabled", &enabled)
+    }
+    /// Set the rollout percentage of a flag, creating it if necessary.
+    /// Values above 100 are clamped.
+    pub fn set_rollout(&self, name: &str, percent: u8) -> rusqlite::Result<()> {
+        self.upsert(name, "rollout", &percent.min(100))
+    }
+    /// Set the payload of a flag, creating it if necessary.
+    pub fn set_payload<T: Serialize>(&self, name: &str, payload: &T) -> rusqlite::Result<()> {
+        let value = serde_json::to_value(payload)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        self.upsert(name, "payload", &JsonObject::new(value))
+    }
+    /// Delete a flag. Returns whether it existed.
+    pub fn remove(&self, name: &str) -> rusqlite::Result<bool> {
+        let deleted = self.conn.execute(
+            &format!("delete from {} where name = ?", self.table),
+            (name,),
+        )?;
+        self.invalidate();
+        Ok(deleted > 0)
+    }
+
+    /// Drop the cached flags, forcing the next read to hit the database.
+    pub fn invalidate(&self) {
+        self.cache.replace(None);
+    }
+
+    fn upsert(
+        &self,
+        name: &str,
+        column: &str,
+        value: &dyn rusqlite::ToSql,
+    ) -> rusqlite::Result<()> {
+        self.conn.execute(
+            &format!(
+                "insert into {table}(name, {column}, updated_at) values (?, ?, ?)
+                on conflict(name) do update set
+                    {column} = excluded.{column},
+                    updated_at = excluded.updated_at",
+                table = self.table
+            ),
+            (name, value, TimestampMillis::now()),
+        )?;
+        self.invalidate();
+        Ok(())
+    }
+
+    fn refresh(&self) -> rusqlite::Result<()> {
+        let data_version: i64 = self
+            .conn
+            .query_row("pragma data_version", (), |row| row.get(0))?;
+        if let Some(cache) = self.cache.borrow().as_ref() {
+            if cache.data_version == data_version {
+                return Ok(());
+            }
+        }
+
+        let mut stmt = self.conn.prepare(&format!(
+            "select name, enabled, rollout, payload, updated_at from {}",
+            self.table
+        ))?;
+        let flags = stmt
+            .query_map((), |row| FeatureFlag::try_from(row))?
+            .map(|f| f.map(|f| (f.name.clone(), f)))
+            .collect::<rusqlite::Result<_>>()?;
+        self.cache.replace(Some(Cache {
+            data_version,
+            flags,
+        }));
+        Ok(())
+    }
+}
+
+/// Read a single flag without caching.
+pub fn get_flag(
+    conn: &Connection,
+    table: &str,
+    name: &str,
+) -> rusqlite::Result<Option<FeatureFlag>> {
+    conn.query_row(
+        &format!(
+            "select name, enabled, rollout, payload, updated_at from {} where name = ?",
+            quote_ident(table)
+        ),
+        (name,),
+        |row| FeatureFlag::try_from(row),
+    )
+    .optional()
+}
+
+/// Stable bucket in 0..100 for a (flag, subject) pair, using FNV-1a so that
+/// assignments don't change between builds or platforms.
+fn rollout_bucket(flag: &str, subject: &str) -> u64 {
+    const OFFSET: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    let mut hash = OFFSET;
+    for byte in flag
+        .bytes()
+        .chain(std::iter::once(0))
+        .chain(subject.bytes())
+    {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(PRIME);
+    }
+    hash % 100
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde::Deserialize;
+
+    fn setup() -> Connection {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        FeatureFlags::new(&db)
+            .create_table(&db)
+            .expect("Failed to create table");
+        db
+    }
+
+    #[test]
+    fn enable_and_disable() {
+        let db = setup();
+        let flags = FeatureFlags::new(&db);
+
+        assert!(
+            !flags.is_enabled("beta").unwrap(),
+            "Unknown flag is enabled"
+        );
+        flags.set_enabled("beta", true).unwrap();
+        assert!(flags.is_enabled("beta").unwrap());
+        flags.set_enabled("beta", false).unwrap();
+        assert!(!flags.is_enabled("beta").unwrap());
+
+        assert!(flags.remove("beta").unwrap());
+        assert!(flags.get("beta").unwrap().is_none());
+    }
+
+    #[test]
+    fn typed_payload() {
+        let db = setup();
+        let flags = FeatureFlags::new(&db);
+        #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+        struct Limits {
+            max_uploads: i64,
+        }
+
+        flags
+            .set_payload("limits", &Limits { max_uploads: 3 })
+            .unwrap();
+        let res = flags.payload::<Limits>("limits");
+        assert!(res.is_ok(), "Failed to retrieve payload: {:?}", res);
+        assert_eq!(res.unwrap(), Some(Limits { max_uploads: 3 }));
+        assert!(flags.payload::<Limits>("missing").unwrap().is_none());
+    }
+
+    #[test]
+    fn percentage_rollout() {
+        let db = setup();
+        let flags = FeatureFlags::new(&db);
+        flags.set_enabled("new_ui", true).unwrap();
+
+        flags.set_rollout("new_ui", 0).unwrap();
+        assert!((0..100).all(|i| !flags.is_enabled_for("new_ui", &i.to_string()).unwrap()));
+
+        flags.set_rollout("new_ui", 100).unwrap();
+        assert!((0..100).all(|i| flags.is_enabled_for("new_ui", &i.to_string()).unwrap()));
+
+        flags.set_rollout("new_ui", 30).unwrap();
+        let active = (0..1000)
+            .filter(|i| flags.is_enabled_for("new_ui", &i.to_string()).unwrap())
+            .count();
+        assert!(
+            (200..400).contains(&active),
+            "Rollout of 30% enabled {} of 1000 subjects",
+            active
+        );
+
+        // Subjects enabled at 30% remain enabled at 60%
+        let flag = flags.get("new_ui").unwrap().unwrap();
+        let mut widened = flag.clone();
+        widened.rollout = 60;
+        assert!((0..1000)
+            .map(|i| i.to_string())
+            .filter(|s| flag.is_enabled_for(s))
+            .all(|s| widened.is_enabled_for(&s)));
+    }
+
+    #[test]
+    fn cache_invalidation() {
+        let db = setup();
+        let flags = FeatureFlags::new(&db);
+        flags.set_enabled("beta", true).unwrap();
+        assert!(flags.is_enabled("beta").unwrap());
+
+        db.execute("update feature_flags set enabled = 0", ())
+            .expect("Failed to update flag");
+        assert!(
+            flags.is_enabled("beta").unwrap(),
+            "Same-connection writes should be served from cache"
+        );
+        flags.invalidate();
+        assert!(!flags.is_enabled("beta").unwrap());
+
+        let uncached = get_flag(&db, FeatureFlags::DEFAULT_TABLE, "beta").unwrap();
+        assert_eq!(uncached.map(|f| f.enabled), Some(false));
+    }
+
+    #[test]
+    fn quoted_table_name() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        let flags = FeatureFlags::with_table(&db, "order flags");
+        flags.create_table(&db).expect("Failed to create table");
+        flags.set_enabled("beta", true).unwrap();
+        let res = get_flag(&db, "order flags", "beta");
+        assert!(res.is_ok(), "Failed to read flag: {:?}", res);
+        assert_eq!(res.unwrap().map(|f| f.enabled), Some(true));
+    }
+}
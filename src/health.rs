@@ -0,0 +1,334 @@
+use std::fmt::Display;
+
+use rusqlite::Connection;
+
+use crate::migration::Migrations;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Check {
+    ApplicationId,
+    UserVersion,
+    Schema,
+    QuickCheck,
+    ForeignKeys,
+    WalSize,
+}
+
+/// The outcome of one check. Passing checks produce an `Info` finding.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Finding {
+    pub check: Check,
+    pub severity: Severity,
+    pub message: String,
+}
+impl Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[{:?}] {:?}: {}",
+            self.severity, self.check, self.message
+        )
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HealthReport {
+    pub findings: Vec<Finding>,
+}
+impl HealthReport {
+    /// The most severe finding's severity, if there are any findings.
+    pub fn worst(&self) -> Option<Severity> {
+        self.findings.iter().map(|f| f.severity).max()
+    }
+    /// Whether no check produced an `Error` finding.
+    pub fn is_healthy(&self) -> bool {
+        self.worst() < Some(Severity::Error)
+    }
+    pub fn at_least(&self, severity: Severity) -> impl Iterator<Item = &Finding> {
+        self.findings.iter().filter(move |f| f.severity >= severity)
+    }
+}
+impl Display for HealthReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for finding in &self.findings {
+            writeln!(f, "{}", finding)?;
+        }
+        Ok(())
+    }
+}
+
+/// A set of startup checks producing a single `HealthReport`. Only the
+/// configured checks are run.
+#[derive(Clone, Debug, Default)]
+pub struct HealthCheck {
+    application_id: Option<i32>,
+    user_version: Option<i64>,
+    tables: Vec<(String, Vec<String>)>,
+    quick_check: bool,
+    foreign_key_check: bool,
+    wal_size_limit: Option<u64>,
+}
+
+impl HealthCheck {
+    /// Reported problems are capped per check, to keep reports readable.
+    const MAX_PROBLEMS: usize = 10;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn application_id(mut self, id: i32) -> Self {
+        self.application_id = Some(id);
+        self
+    }
+    pub fn user_version(mut self, version: i64) -> Self {
+        self.user_version = Some(version);
+        self
+    }
+    /// Expect the database to be at the latest migration.
+    pub fn migrations(self, migrations: &Migrations) -> Self {
+        self.user_version(migrations.latest_version())
+    }
+    /// Expect a table with (at least) the given columns.
+    pub fn table(mut self, name: &str, columns: &[&str]) -> Self {
+        self.tables.push((
+            name.to_string(),
+            columns.iter().map(|c| c.to_string()).collect(),
+        ));
+        self
+    }
+    pub fn quick_check(mut self) -> Self {
+        self.quick_check = true;
+        self
+    }
+    pub fn foreign_key_check(mut self) -> Self {
+        self.foreign_key_check = true;
+        self
+    }
+    /// Report the WAL size, warning if it exceeds `limit` bytes.
+    pub fn wal_size(mut self, limit: u64) -> Self {
+        self.wal_size_limit = Some(limit);
+        self
+    }
+
+    pub fn run(&self, conn: &Connection) -> rusqlite::Result<HealthReport> {
+        let mut report = HealthReport::default();
+        let mut push = |check, severity, message: String| {
+            report.findings.push(Finding {
+                check,
+                severity,
+                message,
+            })
+        };
+
+        if let Some(expected) = self.application_id {
+            let actual: i32 = conn.query_row("pragma application_id", (), |row| row.get(0))?;
+            if actual == expected {
+                push(Check::ApplicationId, Severity::Info, "ok".into());
+            } else {
+                push(
+                    Check::ApplicationId,
+                    Severity::Error,
+                    format!("expected {:#x}, found {:#x}", expected, actual),
+                );
+            }
+        }
+
+        if let Some(expected) = self.user_version {
+            let actual = Migrations::current_version(conn)?;
+            let (severity, message) = match actual.cmp(&expected) {
+                std::cmp::Ordering::Equal => (Severity::Info, "ok".into()),
+                std::cmp::Ordering::Less => (
+                    Severity::Warning,
+                    format!("version {} is behind expected {}", actual, expected),
+                ),
+                std::cmp::Ordering::Greater => (
+                    Severity::Error,
+                    format!("version {} is newer than expected {}", actual, expected),
+                ),
+            };
+            push(Check::UserVersion, severity, message);
+        }
+
+        for (table, columns) in &self.tables {
+            let mut stmt = conn.prepare("select name from pragma_table_info(?)")?;
+            let actual = stmt
+                .query_map((table,), |row| row.get::<_, String>(0))?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            if actual.is_empty() {
+                push(
+                    Check::Schema,
+                    Severity::Error,
+                    format!("missing table {}", table),
+                );
+                continue;
+            }
+            let missing: Vec<_> = columns
+                .iter()
+                .filter(|c| !actual.iter().any(|a| a.eq_ignore_ascii_case(c)))
+                .map(|c| c.as_str())
+                .collect();
+            if missing.is_empty() {
+                push(Check::Schema, Severity::Info, format!("{} ok", table));
+            } else {
+                push(
+                    Check::Schema,
+                    Severity::Error,
+                    format!("table {} is missing columns {}", table, missing.join(", ")),
+                );
+            }
+        }
+
+        if self.quick_check {
+            let mut stmt = conn.prepare(&format!("pragma quick_check({})", Self::MAX_PROBLEMS))?;
+            let problems = stmt
+                .query_map((), |row| row.get::<_, String>(0))?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            if problems == ["ok"] {
+                push(Check::QuickCheck, Severity::Info, "ok".into());
+            } else {
+                for problem in problems {
+                    push(Check::QuickCheck, Severity::Error, problem);
+                }
+            }
+        }
+
+        if self.foreign_key_check {
+            let mut stmt = conn.prepare("pragma foreign_key_check")?;
+            let mut rows = stmt.query(())?;
+            let mut violations = 0;
+            while let Some(row) = rows.next()? {
+                violations += 1;
+                if violations <= Self::MAX_PROBLEMS {
+                    let table: String = row.get(0)?;
+                    let rowid: Option<i64> = row.get(1)?;
+                    let parent: String = row.get(2)?;
+                    push(
+                        Check::ForeignKeys,
+                        Severity::Error,
+                        format!(
+                            "{} row {} references missing {} row",
+                            table,
+                            rowid.map_or("?".to_string(), |r| r.to_string()),
+                            parent
+                        ),
+                    );
+                }
+            }
+            if violations == 0 {
+                push(Check::ForeignKeys, Severity::Info, "ok".into());
+            } else if violations > Self::MAX_PROBLEMS {
+                push(
+                    Check::ForeignKeys,
+                    Severity::Error,
+                    format!("{} further violations", violations - Self::MAX_PROBLEMS),
+                );
+            }
+        }
+
+        if let Some(limit) = self.wal_size_limit {
+            let journal_mode: String =
+                conn.query_row("pragma journal_mode", (), |row| row.get(0))?;
+            let path = conn.path().filter(|p| !p.as_os_str().is_empty());
+            match path {
+                Some(path) if journal_mode.eq_ignore_ascii_case("wal") => {
+                    let mut wal = path.as_os_str().to_owned();
+                    wal.push("-wal");
+                    let size = std::fs::metadata(wal).map(|m| m.len()).unwrap_or(0);
+                    let severity = if size > limit {
+                        Severity::Warning
+                    } else {
+                        Severity::Info
+                    };
+                    push(
+                        Check::WalSize,
+                        severity,
+                        format!("WAL is {} bytes (limit {})", size, limit),
+                    );
+                }
+                _ => push(
+                    Check::WalSize,
+                    Severity::Info,
+                    format!("not applicable in {} journal mode", journal_mode),
+                ),
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn setup() -> Connection {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch(
+            "pragma application_id = 42;
+            pragma user_version = 3;
+            create table parent( id integer primary key );
+            create table child( id integer primary key, parent_id references parent(id) );",
+        )
+        .expect("Failed to set up database");
+        db
+    }
+
+    #[test]
+    fn healthy_database() {
+        let db = setup();
+        let res = HealthCheck::new()
+            .application_id(42)
+            .user_version(3)
+            .table("child", &["id", "parent_id"])
+            .quick_check()
+            .foreign_key_check()
+            .wal_size(1 << 20)
+            .run(&db);
+        assert!(res.is_ok(), "Failed to run health check: {:?}", res);
+        let report = res.unwrap();
+        assert_eq!(report.findings.len(), 6);
+        assert_eq!(report.worst(), Some(Severity::Info), "{}", report);
+        assert!(report.is_healthy());
+    }
+
+    #[test]
+    fn unhealthy_database() {
+        let db = setup();
+        db.execute_batch(
+            "pragma foreign_keys = off;
+            insert into child(parent_id) values (7);",
+        )
+        .expect("Failed to insert orphan");
+
+        let report = HealthCheck::new()
+            .application_id(7)
+            .user_version(4)
+            .table("child", &["id", "name"])
+            .table("missing", &[])
+            .foreign_key_check()
+            .run(&db)
+            .expect("Failed to run health check");
+        assert!(!report.is_healthy());
+        let problems: Vec<_> = report
+            .at_least(Severity::Warning)
+            .map(|f| (f.check, f.severity))
+            .collect();
+        assert_eq!(
+            problems,
+            vec![
+                (Check::ApplicationId, Severity::Error),
+                (Check::UserVersion, Severity::Warning),
+                (Check::Schema, Severity::Error),
+                (Check::Schema, Severity::Error),
+                (Check::ForeignKeys, Severity::Error),
+            ]
+        );
+    }
+}
@@ -5,6 +5,7 @@ use rusqlite::{
 use std::marker::PhantomData;
 
 use super::Id;
+use crate::column_type::{SqliteColumnType, StorageClass};
 
 /// Represents a column named `id` stored as a SQLite `INTEGER`.
 /// The type parameter allows it to be bound to a particular
@@ -24,7 +25,7 @@ impl<T> std::fmt::Display for IntegerId<T> {
 impl<T> Copy for IntegerId<T> {}
 impl<T> Clone for IntegerId<T> {
     fn clone(&self) -> Self {
-        Self(self.0, PhantomData)
+        *self
     }
 }
 impl<T> std::fmt::Debug for IntegerId<T> {
@@ -46,7 +47,7 @@ impl<T> Ord for IntegerId<T> {
 }
 impl<T> PartialOrd for IntegerId<T> {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.0.partial_cmp(&other.0)
+        Some(self.cmp(other))
     }
 }
 impl<T> std::hash::Hash for IntegerId<T> {
@@ -59,6 +60,10 @@ impl<T> ToSql for IntegerId<T> {
         Ok(ToSqlOutput::from(self.0))
     }
 }
+impl<T> SqliteColumnType for IntegerId<T> {
+    const DECL_TYPE: Option<&'static str> = Some("integer");
+    const STORAGE: Option<StorageClass> = Some(StorageClass::Integer);
+}
 impl<T> FromSql for IntegerId<T> {
     fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
         let v: i64 = value.as_i64()?;
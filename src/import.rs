@@ -0,0 +1,236 @@
+use std::{fmt::Display, marker::PhantomData};
+
+use rusqlite::{Connection, ErrorCode};
+use thiserror::Error;
+
+use crate::{
+    date_time::{Clock, SystemClock},
+    schema::Table,
+};
+
+/// Why a record wasn't imported.
+#[derive(Debug)]
+pub enum Cause {
+    /// The record couldn't be converted, with the converter's message.
+    Invalid(String),
+    /// The database rejected the row, eg for violating a constraint.
+    Rejected(rusqlite::Error),
+}
+impl Display for Cause {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Cause::Invalid(msg) => write!(f, "invalid record: {}", msg),
+            Cause::Rejected(e) => write!(f, "rejected by database: {}", e),
+        }
+    }
+}
+
+/// A record which wasn't imported.
+#[derive(Debug)]
+pub struct Failure {
+    /// The line (or other position) of the record in its source.
+    pub line: usize,
+    pub cause: Cause,
+}
+
+/// The outcome of an import.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub inserted: usize,
+    pub failures: Vec<Failure>,
+}
+impl Report {
+    pub fn is_complete(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// Imports a stream of raw records (eg lines of a CSV or JSONL file) into the
+/// table of `T`. Records are converted by a caller-supplied function and
+/// inserted in batches, each in its own savepoint. Records which fail to
+/// convert or are rejected by a constraint are collected into the `Report`
+/// rather than aborting the import; any other error aborts it, rolling back
+/// the current batch.
+pub struct Importer<'a, T> {
+    batch_size: usize,
+    first_line: usize,
+    max_failures: Option<usize>,
+    clock: &'a dyn Clock,
+    _row: PhantomData<T>,
+}
+
+impl<'a, T: Table> Importer<'a, T> {
+    pub fn new() -> Self {
+        Self {
+            batch_size: 1000,
+            first_line: 1,
+            max_failures: None,
+            clock: &SystemClock,
+            _row: PhantomData,
+        }
+    }
+    /// Records inserted per savepoint (1000 by default).
+    pub fn batch_size(mut self, size: usize) -> Self {
+        self.batch_size = size.max(1);
+        self
+    }
+    /// The line number of the first record, eg 2 for a CSV file with a
+    /// header (1 by default).
+    pub fn first_line(mut self, line: usize) -> Self {
+        self.first_line = line;
+        self
+    }
+    /// Abort the import once more than `n` records have failed.
+    pub fn max_failures(mut self, n: usize) -> Self {
+        self.max_failures = Some(n);
+        self
+    }
+    /// The clock stamping `#[auto_now]` fields; the system clock by default.
+    pub fn clock(mut self, clock: &'a dyn Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn run<R, I, F, E>(
+        &self,
+        conn: &Connection,
+        records: I,
+        mut convert: F,
+    ) -> Result<Report, Error>
+    where
+        I: IntoIterator<Item = R>,
+        F: FnMut(R) -> Result<T, E>,
+        E: Display,
+    {
+        let sql = T::schema().insert_sql();
+        let mut report = Report::default();
+        let mut records = records.into_iter().enumerate().peekable();
+        while records.peek().is_some() {
+            conn.execute_batch("savepoint import")?;
+            let res = (&mut records)
+                .take(self.batch_size)
+                .try_for_each(|(i, record)| {
+                    let line = self.first_line + i;
+                    let cause = match convert(record) {
+                        Ok(mut row) => {
+                            row.touch(self.clock, true);
+                            match conn.execute(&sql, &*row.params()) {
+                                Ok(_) => {
+                                    report.inserted += 1;
+                                    return Ok(());
+                                }
+                                Err(e) if is_constraint_violation(&e) => Cause::Rejected(e),
+                                Err(e) => return Err(Error::Sqlite(e)),
+                            }
+                        }
+                        Err(e) => Cause::Invalid(e.to_string()),
+                    };
+                    report.failures.push(Failure { line, cause });
+                    match self.max_failures {
+                        Some(max) if report.failures.len() > max => Err(Error::TooManyFailures),
+                        _ => Ok(()),
+                    }
+                });
+            if let Err(e) = res {
+                conn.execute_batch("rollback to import; release import")?;
+                return Err(e);
+            }
+            conn.execute_batch("release import")?;
+        }
+        Ok(report)
+    }
+}
+impl<'a, T: Table> Default for Importer<'a, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn is_constraint_violation(e: &rusqlite::Error) -> bool {
+    matches!(e, rusqlite::Error::SqliteFailure(f, _) if f.code == ErrorCode::ConstraintViolation)
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("too many records failed to import")]
+    TooManyFailures,
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::Table;
+
+    #[derive(Table, Debug, PartialEq)]
+    struct Product {
+        sku: String,
+        price: i64,
+    }
+
+    fn parse(line: &str) -> Result<Product, String> {
+        let (sku, price) = line.split_once(',').ok_or("expected 2 fields")?;
+        Ok(Product {
+            sku: sku.to_string(),
+            price: price.parse().map_err(|e| format!("bad price: {}", e))?,
+        })
+    }
+
+    fn setup() -> Connection {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute(
+            "create table product( sku text primary key, price integer check (price >= 0) )",
+            (),
+        )
+        .expect("Failed to create table");
+        db
+    }
+
+    const CSV: &str = "sku,price\na,10\nb,x\nc\na,5\nd,-1\ne,7";
+
+    #[test]
+    fn collect_failures() {
+        let db = setup();
+        let res = Importer::<Product>::new().batch_size(2).first_line(2).run(
+            &db,
+            CSV.lines().skip(1),
+            parse,
+        );
+        assert!(res.is_ok(), "Failed to import records: {:?}", res);
+        let report = res.unwrap();
+        assert_eq!(report.inserted, 2);
+        let failures: Vec<_> = report
+            .failures
+            .iter()
+            .map(|f| (f.line, matches!(f.cause, Cause::Rejected(_))))
+            .collect();
+        assert_eq!(failures, vec![(3, false), (4, false), (5, true), (6, true)]);
+        assert_eq!(
+            report.failures[1].cause.to_string(),
+            "invalid record: expected 2 fields"
+        );
+
+        let count: i64 = db
+            .query_row("select count(*) from product", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn abort_after_too_many_failures() {
+        let db = setup();
+        let res = Importer::<Product>::new()
+            .batch_size(1)
+            .max_failures(1)
+            .run(&db, CSV.lines().skip(1), parse);
+        assert!(matches!(res, Err(Error::TooManyFailures)));
+
+        // The batch in progress was rolled back; earlier batches are kept.
+        let count: i64 = db
+            .query_row("select count(*) from product", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+}
@@ -0,0 +1,218 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use rusqlite::{
+    types::{FromSql, FromSqlResult, ToSqlOutput, ValueRef},
+    Connection, OptionalExtension, ToSql,
+};
+
+use crate::{
+    column_type::{column_type, SqliteColumnType, StorageClass},
+    execute::Executor,
+    util::quote_ident,
+};
+
+/// A dictionary-encoded string, stored as an INTEGER id into an `Interner`'s
+/// lookup table. Columns holding highly repetitive text (user agents, tags,
+/// hostnames, ...) shrink to a few bytes per row.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Interned(i64);
+
+impl Interned {
+    pub fn id(&self) -> i64 {
+        self.0
+    }
+}
+impl ToSql for Interned {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        self.0.to_sql()
+    }
+}
+column_type!("integer", Integer: Interned);
+impl FromSql for Interned {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        i64::column_result(value).map(Self)
+    }
+}
+
+/// Maps strings to `Interned` ids and back, through a lookup table and an
+/// in-memory cache. Ids are never reused or changed, so cached entries stay
+/// valid. Nothing is cached while a transaction is open, so a rollback can't
+/// leave dangling ids in the cache.
+pub struct Interner<'conn> {
+    conn: &'conn Connection,
+    /// The quoted table name.
+    table: String,
+    ids: RefCell<HashMap<Rc<str>, Interned>>,
+    strings: RefCell<HashMap<Interned, Rc<str>>>,
+}
+
+impl<'conn> Interner<'conn> {
+    pub const DEFAULT_TABLE: &'static str = "interned_strings";
+
+    pub fn new(conn: &'conn Connection) -> Self {
+        Self::with_table(conn, Self::DEFAULT_TABLE)
+    }
+    pub fn with_table(conn: &'conn Connection, table: &str) -> Self {
+        Self {
+            conn,
+            table: quote_ident(table),
+            ids: RefCell::new(HashMap::new()),
+            strings: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn create_sql(&self) -> String {
+        format!(
+            "create table if not exists {}(
+                id integer primary key,
+                value text unique not null
+            )",
+            self.table
+        )
+    }
+    /// Create the lookup table if it does not already exist.
+    pub fn create_table<E: Executor + ?Sized>(&self, exec: &E) -> rusqlite::Result<()> {
+        exec.run_batch(&self.create_sql())
+    }
+
+    /// The id for `s`, adding it to the lookup table if necessary.
+    pub fn intern(&self, s: &str) -> rusqlite::Result<Interned> {
+        if let Some(id) = self.ids.borrow().get(s) {
+            return Ok(*id);
+        }
+        let id = self.conn.query_row(
+            &format!(
+                "insert into {}(value) values (?) on conflict(value) do update set value = value
+                returning id",
+                self.table
+            ),
+            (s,),
+            |row| row.get(0),
+        )?;
+        self.cache(id, s);
+        Ok(id)
+    }
+    /// The id for `s`, if it has been interned.
+    pub fn lookup(&self, s: &str) -> rusqlite::Result<Option<Interned>> {
+        if let Some(id) = self.ids.borrow().get(s) {
+            return Ok(Some(*id));
+        }
+        let id = self
+            .conn
+            .query_row(
+                &format!("select id from {} where value = ?", self.table),
+                (s,),
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(id) = id {
+            self.cache(id, s);
+        }
+        Ok(id)
+    }
+    /// The string for `id`. Fails with `QueryReturnedNoRows` if `id` isn't in
+    /// the lookup table.
+    pub fn resolve(&self, id: Interned) -> rusqlite::Result<Rc<str>> {
+        if let Some(s) = self.strings.borrow().get(&id) {
+            return Ok(s.clone());
+        }
+        let s: String = self.conn.query_row(
+            &format!("select value from {} where id = ?", self.table),
+            (id,),
+            |row| row.get(0),
+        )?;
+        self.cache(id, &s);
+        Ok(s.into())
+    }
+
+    /// A SQL expression resolving the id in `column` to its string, for use
+    /// in queries and views.
+    pub fn resolve_sql(&self, column: &str) -> String {
+        format!("(select value from {} where id = {})", self.table, column)
+    }
+
+    /// Drop all cached mappings.
+    pub fn clear_cache(&self) {
+        self.ids.borrow_mut().clear();
+        self.strings.borrow_mut().clear();
+    }
+
+    fn cache(&self, id: Interned, s: &str) {
+        if !self.conn.is_autocommit() {
+            return;
+        }
+        let s: Rc<str> = s.into();
+        self.ids.borrow_mut().insert(s.clone(), id);
+        self.strings.borrow_mut().insert(id, s);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn setup() -> Connection {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        Interner::new(&db)
+            .create_table(&db)
+            .expect("Failed to create table");
+        db.execute("create table requests( agent integer not null )", ())
+            .expect("Failed to create table");
+        db
+    }
+
+    #[test]
+    fn intern_and_resolve() {
+        let db = setup();
+        let interner = Interner::new(&db);
+        let agents = ["curl", "firefox", "curl", "curl", "firefox"];
+        for agent in agents {
+            let res = interner.intern(agent);
+            assert!(res.is_ok(), "Failed to intern string: {:?}", res);
+            db.execute("insert into requests(agent) values (?)", (res.unwrap(),))
+                .expect("Failed to insert row");
+        }
+        let strings: i64 = db
+            .query_row("select count(*) from interned_strings", (), |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(strings, 2);
+
+        // A fresh interner reads through to the table.
+        let interner = Interner::new(&db);
+        let mut stmt = db.prepare("select agent from requests").unwrap();
+        let resolved = stmt
+            .query_map((), |row| row.get::<_, Interned>(0))
+            .unwrap()
+            .map(|id| interner.resolve(id.unwrap()).unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(resolved, agents);
+        assert_eq!(interner.lookup("wget").unwrap(), None);
+
+        let count: i64 = db
+            .query_row(
+                &format!(
+                    "select count(*) from requests where {} = 'curl'",
+                    interner.resolve_sql("agent")
+                ),
+                (),
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(count, 3);
+    }
+
+    #[test]
+    fn rolled_back_strings_are_not_cached() {
+        let mut db = setup();
+        {
+            let tx = db.transaction().unwrap();
+            let interner = Interner::new(&tx);
+            interner.intern("rolled back").unwrap();
+            assert!(interner.ids.borrow().is_empty());
+        }
+        let interner = Interner::new(&db);
+        assert_eq!(interner.lookup("rolled back").unwrap(), None);
+    }
+}
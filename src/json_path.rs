@@ -0,0 +1,167 @@
+use std::fmt::Display;
+
+use rusqlite::{types::ToSqlOutput, ToSql};
+use serde_json::Value;
+use thiserror::Error;
+
+/// Start building a JSON path at the root (`$`).
+pub fn path() -> JsonPath {
+    JsonPath::root()
+}
+
+/// A path into a JSON document, rendered in SQLite's JSON path syntax (eg
+/// `$.a[0]`) by `Display` and when bound as a parameter, so it can be passed
+/// directly to `json_extract(column, ?)`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct JsonPath(Vec<Segment>);
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Segment {
+    Field(String),
+    Index(usize),
+    FromEnd(usize),
+}
+
+impl JsonPath {
+    pub fn root() -> Self {
+        Self(vec![])
+    }
+    /// Descend into an object field. Panics if the name contains a `"`,
+    /// which SQLite's path syntax can't express; see `try_field`.
+    pub fn field(self, name: &str) -> Self {
+        self.try_field(name).expect("invalid JSON path field")
+    }
+    pub fn try_field(mut self, name: &str) -> Result<Self, Error> {
+        if name.contains('"') {
+            return Err(Error::InvalidField(name.to_string()));
+        }
+        self.0.push(Segment::Field(name.to_string()));
+        Ok(self)
+    }
+    /// Descend into an array element.
+    pub fn index(mut self, i: usize) -> Self {
+        self.0.push(Segment::Index(i));
+        self
+    }
+    /// Descend into the `n`th array element from the end; `from_end(1)` is
+    /// the last element.
+    pub fn from_end(mut self, n: usize) -> Self {
+        self.0.push(Segment::FromEnd(n));
+        self
+    }
+
+    /// The path as a SQL string literal, for embedding in DDL such as
+    /// generated columns and indexes, where parameters aren't allowed.
+    pub fn to_sql_literal(&self) -> String {
+        format!("'{}'", self.to_string().replace('\'', "''"))
+    }
+    /// A `json_extract` expression applying this path to `column`.
+    pub fn extract_sql(&self, column: &str) -> String {
+        format!("json_extract({}, {})", column, self.to_sql_literal())
+    }
+
+    /// Apply the path to a document in Rust, with the same semantics as
+    /// `json_extract`.
+    pub fn lookup<'v>(&self, value: &'v Value) -> Option<&'v Value> {
+        self.0.iter().try_fold(value, |v, segment| match segment {
+            Segment::Field(name) => v.as_object()?.get(name),
+            Segment::Index(i) => v.as_array()?.get(*i),
+            Segment::FromEnd(n) => {
+                let array = v.as_array()?;
+                array.get(array.len().checked_sub(*n)?)
+            }
+        })
+    }
+}
+
+impl Display for JsonPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("$")?;
+        for segment in &self.0 {
+            match segment {
+                Segment::Field(name)
+                    if !name.is_empty()
+                        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') =>
+                {
+                    write!(f, ".{}", name)?
+                }
+                Segment::Field(name) => write!(f, ".\"{}\"", name)?,
+                Segment::Index(i) => write!(f, "[{}]", i)?,
+                Segment::FromEnd(n) => write!(f, "[#-{}]", n)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ToSql for JsonPath {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.to_string()))
+    }
+}
+
+#[derive(Clone, Error, Debug, PartialEq, Eq)]
+pub enum Error {
+    #[error("JSON path field {0:?} contains a double quote")]
+    InvalidField(String),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use rusqlite::Connection;
+    use serde_json::json;
+
+    #[test]
+    fn render_paths() {
+        assert_eq!(path().to_string(), "$");
+        assert_eq!(path().field("a").index(0).to_string(), "$.a[0]");
+        assert_eq!(
+            path().field("a.b").field("it's").from_end(1).to_string(),
+            "$.\"a.b\".\"it's\"[#-1]"
+        );
+        assert_eq!(
+            path().field("it's").extract_sql("data"),
+            "json_extract(data, '$.\"it''s\"')"
+        );
+        assert_eq!(
+            path().try_field("a\"b"),
+            Err(Error::InvalidField("a\"b".to_string()))
+        );
+    }
+
+    #[test]
+    fn paths_agree_with_sqlite() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        let doc = json!({"a": [1, {"b c": "x"}, 3], "it's": true, "a.b": null});
+        let paths = [
+            path().field("a").index(0),
+            path().field("a").index(1).field("b c"),
+            path().field("a").from_end(1),
+            path().field("it's"),
+            path().field("missing"),
+            path().field("a").index(10),
+        ];
+
+        for p in paths {
+            let res: rusqlite::Result<Option<String>> =
+                db.query_row("select ? -> ?", (doc.to_string(), &p), |row| row.get(0));
+            assert!(res.is_ok(), "Failed to evaluate {}: {:?}", p, res);
+            assert_eq!(
+                res.unwrap()
+                    .map(|s| serde_json::from_str::<Value>(&s).unwrap()),
+                p.lookup(&doc).cloned(),
+                "Disagreement on {}",
+                p
+            );
+
+            let literal: rusqlite::Result<rusqlite::types::Value> = db.query_row(
+                &format!("select {}", p.extract_sql("?")),
+                (doc.to_string(),),
+                |row| row.get(0),
+            );
+            assert!(literal.is_ok(), "Failed to evaluate {}: {:?}", p, literal);
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use std::{cell::OnceCell, io::Read};
+
+use rusqlite::{
+    types::{FromSql, Type, ValueRef},
+    Connection, DatabaseName,
+};
+
+use crate::{
+    column_type::{SqliteColumnType, StorageClass},
+    util::quote_ident,
+};
+
+/// A column of a row read only when first needed, such as a large
+/// attachment or document of which a list view only needs the other
+/// columns. Usually a field marked `#[try_from_row(lazy = "table")]`, which
+/// `TryFromRow` reads from the row's rowid (the `rowid` column, or another
+/// given with `rowid = "..."`), so the column itself needn't be selected;
+/// `Columns` selects the rowid in its place.
+///
+/// The value is read with incremental blob I/O, as a BLOB or TEXT according
+/// to the type's `SqliteColumnType::STORAGE`, or with a `SELECT` if the
+/// column holds another type or NULL. It's then kept, so later accesses
+/// don't read it again.
+pub struct Lazy<T> {
+    table: &'static str,
+    column: &'static str,
+    rowid: i64,
+    value: OnceCell<T>,
+}
+
+impl<T> Lazy<T> {
+    pub fn new(table: &'static str, column: &'static str, rowid: i64) -> Self {
+        Self {
+            table,
+            column,
+            rowid,
+            value: OnceCell::new(),
+        }
+    }
+    pub fn rowid(&self) -> i64 {
+        self.rowid
+    }
+    /// The value, if it has been read.
+    pub fn loaded(&self) -> Option<&T> {
+        self.value.get()
+    }
+    pub fn into_inner(self) -> Option<T> {
+        self.value.into_inner()
+    }
+
+    /// The value, read from `conn` the first time.
+    pub fn get(&self, conn: &Connection) -> rusqlite::Result<&T>
+    where
+        T: FromSql + SqliteColumnType,
+    {
+        if let Some(value) = self.value.get() {
+            return Ok(value);
+        }
+        let value = self.read(conn)?;
+        Ok(self.value.get_or_init(|| value))
+    }
+
+    fn read(&self, conn: &Connection) -> rusqlite::Result<T>
+    where
+        T: FromSql + SqliteColumnType,
+    {
+        let blob = conn.blob_open(
+            DatabaseName::Main,
+            self.table,
+            self.column,
+            self.rowid,
+            true,
+        );
+        let mut blob = match blob {
+            Ok(blob) => blob,
+            // Only BLOBs and TEXT can be opened.
+            Err(_) => {
+                let sql = format!(
+                    "select {} from {} where rowid = ?",
+                    quote_ident(self.column),
+                    quote_ident(self.table)
+                );
+                return conn
+                    .prepare_cached(&sql)?
+                    .query_row([self.rowid], |row| row.get(0));
+            }
+        };
+        let (value_type, text) = match T::STORAGE {
+            Some(StorageClass::Text) => (Type::Text, true),
+            _ => (Type::Blob, false),
+        };
+        let failure = |e: Box<dyn std::error::Error + Send + Sync>| {
+            rusqlite::Error::FromSqlConversionFailure(0, value_type.clone(), e)
+        };
+        let mut bytes = Vec::with_capacity(blob.len());
+        blob.read_to_end(&mut bytes)
+            .map_err(|e| failure(Box::new(e)))?;
+        let value = match text {
+            true => ValueRef::Text(&bytes),
+            false => ValueRef::Blob(&bytes),
+        };
+        T::column_result(value).map_err(|e| failure(Box::new(e)))
+    }
+}
+
+impl<T: Clone> Clone for Lazy<T> {
+    fn clone(&self) -> Self {
+        Self {
+            table: self.table,
+            column: self.column,
+            rowid: self.rowid,
+            value: self.value.clone(),
+        }
+    }
+}
+impl<T: std::fmt::Debug> std::fmt::Debug for Lazy<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Lazy")
+            .field("table", &self.table)
+            .field("column", &self.column)
+            .field("rowid", &self.rowid)
+            .field("value", &self.value.get())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{object::JsonObject, row::Columns, TryFromRow};
+
+    #[derive(TryFromRow, Debug)]
+    struct Document {
+        #[try_from_row(column = "doc_id")]
+        id: i64,
+        title: String,
+        #[try_from_row(lazy = "document", rowid = "doc_id")]
+        attachment: Lazy<Vec<u8>>,
+        #[try_from_row(lazy = "document", rowid = "doc_id")]
+        meta: Lazy<JsonObject<serde_json::Value>>,
+        #[try_from_row(lazy = "document", rowid = "doc_id")]
+        note: Lazy<Option<String>>,
+    }
+
+    #[test]
+    fn read_on_access() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch(
+            "create table document(
+                doc_id integer primary key,
+                title text,
+                attachment blob,
+                meta json,
+                note text
+            );
+            insert into document values (1, 'report', zeroblob(100000), '{\"pages\": 3}', null);",
+        )
+        .expect("Failed to set up database");
+
+        assert_eq!(
+            Document::select_list(),
+            "\"doc_id\", \"title\", \"doc_id\", \"doc_id\", \"doc_id\""
+        );
+        let res = db.query_row(
+            &format!("select {} from document", Document::select_list()),
+            (),
+            |row| Document::try_from(row),
+        );
+        assert!(res.is_ok(), "Failed to read document: {:?}", res);
+        let doc = res.unwrap();
+        assert_eq!((doc.id, doc.title.as_str()), (1, "report"));
+        assert!(doc.attachment.loaded().is_none());
+
+        let res = doc.attachment.get(&db);
+        assert!(res.is_ok(), "Failed to read attachment: {:?}", res);
+        assert_eq!(res.unwrap().len(), 100000);
+        assert!(doc.attachment.loaded().is_some());
+        let meta = doc.meta.get(&db).expect("Failed to read meta");
+        assert_eq!(meta.clone().unwrap()["pages"], 3);
+        assert_eq!(doc.note.get(&db).unwrap(), &None);
+
+        // Once read, the value is kept.
+        db.execute_batch("delete from document").unwrap();
+        assert_eq!(doc.attachment.get(&db).unwrap().len(), 100000);
+        assert!(doc.note.clone().into_inner().is_some());
+        assert!(matches!(
+            Lazy::<String>::new("document", "title", 1).get(&db),
+            Err(rusqlite::Error::QueryReturnedNoRows)
+        ));
+    }
+}
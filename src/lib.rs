@@ -1,8 +1,85 @@
 #![allow(dead_code)]
 
-pub use rusqlite_utils_macros::TryFromRow;
+// Code generated by the derives names `::rusqlite_utils`, including here.
+extern crate self as rusqlite_utils;
 
+pub use rusqlite_utils_macros::{
+    Checksummed, Delete, EnumAsInteger, EnumAsText, Insert, Patch, Queryable, Relations,
+    SqlNewtype, Table, TenantScoped, ToParams, TryFromRow, Update, Upsert,
+};
+
+pub mod analyze;
+pub mod batch;
+#[cfg(feature = "bench")]
+pub mod bench;
+pub mod blob_stats;
+pub mod cancel;
+pub mod changelog;
+pub mod checksum;
+pub mod codegen;
+pub mod column_map;
+pub mod column_type;
+pub mod compat;
+pub mod connection;
+pub mod crud;
 pub mod date_time;
+pub mod diff;
+pub mod drift;
+pub mod encrypted;
+pub mod enums;
+pub mod error;
+pub mod execute;
+pub mod exists;
+pub mod feature_flags;
+pub mod health;
 pub mod id;
+pub mod import;
+pub mod interned;
+pub mod json_path;
+pub mod lazy;
+pub mod log_writer;
+#[cfg(feature = "maintenance")]
+pub mod maintenance;
+pub mod metrics;
+pub mod migration;
+pub mod mock_row;
+pub mod multi_column;
 pub mod object;
+pub mod params;
+pub mod pragma;
+pub mod provision;
+pub mod queries;
+pub mod query;
+pub mod query_cache;
+pub mod query_log;
+pub mod recover;
+pub mod relations;
+pub mod result_set;
+pub mod returning;
+pub mod row;
+pub mod scan;
+pub mod schema;
+pub mod scoped;
+pub mod scrub;
+pub mod seeds;
+pub mod sequence;
+#[cfg(feature = "simulation")]
+pub mod simulation;
+pub mod snapshot;
+pub mod stats;
+pub mod tag;
+pub mod temp_table;
+pub mod tenant;
+pub mod time_series;
+pub mod tree;
+pub mod trigger;
+pub mod unit_of_work;
+pub mod util;
+pub mod view;
+pub use error::Error;
 pub use id::integer::IntegerId;
+pub use schema::Table;
+
+// Used by `#[derive(Table)]` to build `CREATE_SQL`.
+#[doc(hidden)]
+pub use const_format;
@@ -0,0 +1,171 @@
+use std::time::{Duration, Instant};
+
+use rusqlite::Connection;
+
+use crate::schema::Table;
+
+/// Buffers rows for an append-only table and writes them in batches, which
+/// is far faster than a transaction per row.
+///
+/// The buffer is flushed when it reaches `max_rows`, when a row is pushed
+/// more than `max_age` after the oldest buffered row, on `flush`, and when
+/// the writer is dropped. Each flush is atomic; if it fails the rows stay
+/// buffered and are retried by the next flush.
+pub struct LogWriter<'conn, T: Table> {
+    conn: &'conn Connection,
+    buffer: Vec<T>,
+    max_rows: usize,
+    max_age: Option<Duration>,
+    oldest: Option<Instant>,
+}
+
+impl<'conn, T: Table> LogWriter<'conn, T> {
+    pub const DEFAULT_MAX_ROWS: usize = 1000;
+
+    pub fn new(conn: &'conn Connection) -> Self {
+        Self {
+            conn,
+            buffer: vec![],
+            max_rows: Self::DEFAULT_MAX_ROWS,
+            max_age: None,
+            oldest: None,
+        }
+    }
+    /// Flush once this many rows are buffered. Values below 1 are treated
+    /// as 1.
+    pub fn max_rows(mut self, n: usize) -> Self {
+        self.max_rows = n.max(1);
+        self
+    }
+    /// Flush on the next push once the oldest buffered row is this old.
+    pub fn max_age(mut self, age: Duration) -> Self {
+        self.max_age = Some(age);
+        self
+    }
+
+    /// Buffer `row`, flushing if a threshold is reached. Returns the number
+    /// of rows written.
+    pub fn push(&mut self, row: T) -> rusqlite::Result<usize> {
+        self.buffer.push(row);
+        let oldest = *self.oldest.get_or_insert_with(Instant::now);
+        let expired = self.max_age.is_some_and(|age| oldest.elapsed() >= age);
+        if self.buffer.len() >= self.max_rows || expired {
+            self.flush()
+        } else {
+            Ok(0)
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// Write all buffered rows in a single savepoint, returning how many were
+    /// written.
+    pub fn flush(&mut self) -> rusqlite::Result<usize> {
+        if self.buffer.is_empty() {
+            return Ok(0);
+        }
+        self.conn.execute_batch("savepoint log_writer")?;
+        let res = self
+            .conn
+            .prepare_cached(&T::schema().insert_sql())
+            .and_then(|mut stmt| {
+                self.buffer
+                    .iter()
+                    .try_for_each(|row| stmt.execute(&*row.params()).map(|_| ()))
+            });
+        if let Err(e) = res {
+            self.conn
+                .execute_batch("rollback to log_writer; release log_writer")?;
+            return Err(e);
+        }
+        self.conn.execute_batch("release log_writer")?;
+
+        let n = self.buffer.len();
+        self.buffer.clear();
+        self.oldest = None;
+        Ok(n)
+    }
+}
+
+impl<'conn, T: Table> Drop for LogWriter<'conn, T> {
+    fn drop(&mut self) {
+        // Errors can't be reported from drop; call `flush` first to handle
+        // them.
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::Table;
+
+    #[derive(Table, Debug)]
+    #[table = "events"]
+    struct Event {
+        kind: String,
+        value: i64,
+    }
+
+    fn setup() -> Connection {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        Event::create_table(&db).expect("Failed to create table");
+        db
+    }
+
+    fn count(db: &Connection) -> i64 {
+        db.query_row("select count(*) from events", (), |row| row.get(0))
+            .unwrap()
+    }
+
+    fn event(value: i64) -> Event {
+        Event {
+            kind: "click".into(),
+            value,
+        }
+    }
+
+    #[test]
+    fn flush_on_size_and_drop() {
+        let db = setup();
+        {
+            let mut writer = LogWriter::new(&db).max_rows(3);
+            for i in 0..5 {
+                let res = writer.push(event(i));
+                assert!(res.is_ok(), "Failed to push row: {:?}", res);
+            }
+            assert_eq!(count(&db), 3);
+            assert_eq!(writer.len(), 2);
+        }
+        assert_eq!(count(&db), 5, "Rows were not flushed on drop");
+    }
+
+    #[test]
+    fn flush_on_age() {
+        let db = setup();
+        let mut writer = LogWriter::new(&db).max_age(Duration::ZERO);
+        assert_eq!(writer.push(event(1)).unwrap(), 1);
+        assert!(writer.is_empty());
+        assert_eq!(count(&db), 1);
+    }
+
+    #[test]
+    fn failed_flush_keeps_rows() {
+        let db = setup();
+        let mut writer = LogWriter::new(&db);
+        writer.push(event(1)).unwrap();
+        db.execute("alter table events rename to old_events", ())
+            .unwrap();
+        assert!(writer.flush().is_err());
+        assert_eq!(writer.len(), 1);
+
+        db.execute("alter table old_events rename to events", ())
+            .unwrap();
+        assert_eq!(writer.flush().unwrap(), 1);
+    }
+}
@@ -0,0 +1,299 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex},
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use rusqlite::Connection;
+
+use crate::{
+    connection::{ConnectionBuilder, Error},
+    stats::{fragmentation, Suggestion},
+    util::quote_ident,
+};
+
+/// How thoroughly the WAL is checkpointed; see `PRAGMA wal_checkpoint`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Checkpoint {
+    /// Checkpoint as much as possible without waiting for readers or
+    /// writers.
+    Passive,
+    /// Wait for writers, then checkpoint everything.
+    Full,
+    /// As `Full`, then truncate the WAL file to zero bytes.
+    Truncate,
+}
+impl Checkpoint {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Checkpoint::Passive => "passive",
+            Checkpoint::Full => "full",
+            Checkpoint::Truncate => "truncate",
+        }
+    }
+}
+
+/// What one maintenance pass did.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Pass {
+    /// Frames in the WAL and frames checkpointed, if a checkpoint was run
+    /// and could complete. Both are -1 when the database isn't in WAL mode.
+    pub checkpointed: Option<(i64, i64)>,
+    /// Rows deleted by each pruning rule, in the order they were added.
+    pub pruned: Vec<usize>,
+    /// What the fragmentation report suggests, if one was run (and dbstat
+    /// is available). They're left to the application, as they rewrite
+    /// whole indexes or the whole file.
+    pub suggestions: Vec<Suggestion>,
+}
+
+type PassFn = Box<dyn FnMut(&rusqlite::Result<Pass>) + Send>;
+
+/// Periodic upkeep of a database: checkpointing the WAL, `PRAGMA optimize`,
+/// and deleting expired rows (eg from caches and queues). `spawn` runs it
+/// on a background thread with its own connection, so applications don't
+/// have to schedule it themselves.
+pub struct Maintenance {
+    path: PathBuf,
+    builder: ConnectionBuilder,
+    interval: Duration,
+    checkpoint: Option<Checkpoint>,
+    optimize: bool,
+    report_fragmentation: bool,
+    prune: Vec<(String, String)>,
+    on_pass: Option<PassFn>,
+}
+
+impl Maintenance {
+    /// Maintain the database at `path`, every 5 minutes by default, with a
+    /// passive checkpoint and `PRAGMA optimize`.
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            builder: ConnectionBuilder::new().busy_timeout(Duration::from_secs(5)),
+            interval: Duration::from_secs(300),
+            checkpoint: Some(Checkpoint::Passive),
+            optimize: true,
+            report_fragmentation: false,
+            prune: vec![],
+            on_pass: None,
+        }
+    }
+    /// Open the maintenance connection with `builder`, rather than with a 5
+    /// second busy timeout.
+    pub fn connection(mut self, builder: ConnectionBuilder) -> Self {
+        self.builder = builder;
+        self
+    }
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+    /// The kind of checkpoint to run, or `None` to leave checkpointing to
+    /// SQLite.
+    pub fn checkpoint(mut self, checkpoint: Option<Checkpoint>) -> Self {
+        self.checkpoint = checkpoint;
+        self
+    }
+    pub fn optimize(mut self, enabled: bool) -> Self {
+        self.optimize = enabled;
+        self
+    }
+    /// Run `stats::fragmentation` on every pass, filling in
+    /// `Pass::suggestions`. This reads every page of the database, so it's
+    /// off by default.
+    pub fn report_fragmentation(mut self, enabled: bool) -> Self {
+        self.report_fragmentation = enabled;
+        self
+    }
+    /// Delete the rows of `table` matching `condition`, eg
+    /// `"expires_at <= strftime('%s', 'now')"`. `condition` is interpolated
+    /// as is.
+    pub fn prune(mut self, table: &str, condition: &str) -> Self {
+        self.prune.push((table.to_string(), condition.to_string()));
+        self
+    }
+    /// Called with the outcome of every pass on the background thread, eg
+    /// to log errors, which are otherwise ignored.
+    pub fn on_pass<F: FnMut(&rusqlite::Result<Pass>) + Send + 'static>(mut self, f: F) -> Self {
+        self.on_pass = Some(Box::new(f));
+        self
+    }
+
+    /// Run one pass on `conn`.
+    pub fn run(&self, conn: &Connection) -> rusqlite::Result<Pass> {
+        let mut pass = Pass::default();
+        for (table, condition) in &self.prune {
+            let sql = format!("delete from {} where {}", quote_ident(table), condition);
+            pass.pruned.push(conn.execute(&sql, ())?);
+        }
+        if self.optimize {
+            conn.execute_batch("pragma optimize")?;
+        }
+        if let Some(checkpoint) = self.checkpoint {
+            let (busy, log, checkpointed): (i64, i64, i64) = conn.query_row(
+                &format!("pragma wal_checkpoint({})", checkpoint.as_str()),
+                (),
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )?;
+            if busy == 0 {
+                pass.checkpointed = Some((log, checkpointed));
+            }
+        }
+        if self.report_fragmentation {
+            if let Some(report) = fragmentation(conn)? {
+                pass.suggestions = report.suggestions();
+            }
+        }
+        Ok(pass)
+    }
+
+    /// Open a connection and run a pass on it every `interval`, starting
+    /// immediately, until the returned handle is shut down or dropped.
+    pub fn spawn(mut self) -> Result<MaintenanceHandle, Error> {
+        let conn = self.builder.open(&self.path)?;
+        let signal = Arc::new((Mutex::new(Signal::Idle), Condvar::new()));
+        let thread = {
+            let signal = signal.clone();
+            std::thread::spawn(move || {
+                let mut on_pass = self.on_pass.take();
+                loop {
+                    let res = self.run(&conn);
+                    if let Some(f) = &mut on_pass {
+                        f(&res);
+                    }
+                    let (lock, wake) = &*signal;
+                    let guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+                    let (mut guard, _) = wake
+                        .wait_timeout_while(guard, self.interval, |s| *s == Signal::Idle)
+                        .unwrap_or_else(|e| e.into_inner());
+                    match *guard {
+                        Signal::Stop => break,
+                        _ => *guard = Signal::Idle,
+                    }
+                }
+            })
+        };
+        Ok(MaintenanceHandle {
+            signal,
+            thread: Some(thread),
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Signal {
+    Idle,
+    RunNow,
+    Stop,
+}
+
+/// Controls a maintenance thread started by `Maintenance::spawn`. Dropping
+/// it shuts the thread down.
+pub struct MaintenanceHandle {
+    signal: Arc<(Mutex<Signal>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MaintenanceHandle {
+    /// Run a pass now rather than waiting for the interval.
+    pub fn run_now(&self) {
+        self.send(Signal::RunNow);
+    }
+    /// Stop the thread, waiting for a pass in progress to finish. The
+    /// connection is closed once it has stopped.
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn send(&self, signal: Signal) {
+        let (lock, wake) = &*self.signal;
+        let mut guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+        if *guard != Signal::Stop {
+            *guard = signal;
+        }
+        wake.notify_all();
+    }
+    fn stop(&mut self) {
+        self.send(Signal::Stop);
+        if let Some(thread) = self.thread.take() {
+            // A panic on the thread has already been reported.
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for MaintenanceHandle {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::mpsc;
+
+    fn setup(path: &Path) -> Connection {
+        let db = ConnectionBuilder::new()
+            .wal()
+            .open(path)
+            .expect("Failed to open connection");
+        db.execute_batch(
+            "create table cache( key text primary key, expires_at integer );
+            insert into cache values ('old', 0), ('new', 9999999999);",
+        )
+        .expect("Failed to create table");
+        db
+    }
+
+    #[test]
+    fn run_pass() {
+        let dir = tempfile::tempdir().expect("Failed to create directory");
+        let db = setup(&dir.path().join("db.sqlite"));
+        let maintenance = Maintenance::new(dir.path().join("db.sqlite"))
+            .checkpoint(Some(Checkpoint::Truncate))
+            .report_fragmentation(true)
+            .prune("cache", "expires_at <= strftime('%s', 'now')");
+
+        let res = maintenance.run(&db);
+        assert!(res.is_ok(), "Failed to run maintenance: {:?}", res);
+        let pass = res.unwrap();
+        assert_eq!(pass.pruned, vec![1]);
+        assert_eq!(pass.checkpointed, Some((0, 0)));
+        assert!(pass.suggestions.is_empty());
+        let count: i64 = db
+            .query_row("select count(*) from cache", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn background_thread() {
+        let dir = tempfile::tempdir().expect("Failed to create directory");
+        let db = setup(&dir.path().join("db.sqlite"));
+        let (tx, rx) = mpsc::channel();
+        let res = Maintenance::new(dir.path().join("db.sqlite"))
+            .interval(Duration::from_secs(3600))
+            .prune("cache", "expires_at <= strftime('%s', 'now')")
+            .on_pass(move |res| {
+                let _ = tx.send(res.as_ref().map(|p| p.pruned[0]).ok());
+            })
+            .spawn();
+        assert!(res.is_ok(), "Failed to spawn maintenance: {:?}", res.err());
+        let handle = res.unwrap();
+
+        // The first pass runs immediately; later ones on request.
+        let timeout = Duration::from_secs(10);
+        assert_eq!(rx.recv_timeout(timeout), Ok(Some(1)));
+        db.execute("insert into cache values ('stale', 1)", ())
+            .unwrap();
+        handle.run_now();
+        assert_eq!(rx.recv_timeout(timeout), Ok(Some(1)));
+
+        handle.shutdown();
+        assert!(rx.recv_timeout(timeout).is_err());
+    }
+}
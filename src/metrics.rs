@@ -0,0 +1,420 @@
+use std::ops::{Add, AddAssign};
+
+use rusqlite::{
+    functions::{Aggregate, Context, FunctionFlags},
+    types::{FromSql, FromSqlError, ToSqlOutput},
+    Connection, ToSql,
+};
+use thiserror::Error;
+
+use crate::column_type::{column_type, SqliteColumnType, StorageClass};
+
+/// A monotonically increasing count stored as a SQLite `INTEGER`. Merging
+/// counters adds them, saturating at `i64::MAX` (SQLite's largest integer).
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Counter(u64);
+impl Counter {
+    pub fn new(v: u64) -> Self {
+        Self(v.min(i64::MAX as u64))
+    }
+    pub fn unwrap(self) -> u64 {
+        self.0
+    }
+    pub fn increment(&mut self) {
+        *self += 1;
+    }
+    pub fn merge(&mut self, other: Counter) {
+        *self += other.0;
+    }
+}
+impl Add for Counter {
+    type Output = Counter;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Counter::new(self.0.saturating_add(rhs.0))
+    }
+}
+impl AddAssign<u64> for Counter {
+    fn add_assign(&mut self, rhs: u64) {
+        *self = Counter::new(self.0.saturating_add(rhs));
+    }
+}
+impl ToSql for Counter {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.0 as i64))
+    }
+}
+column_type!("integer", Integer: Counter);
+impl FromSql for Counter {
+    fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
+        let v = value.as_i64()?;
+        if v >= 0 {
+            Ok(Self(v as u64))
+        } else {
+            Err(FromSqlError::OutOfRange(v))
+        }
+    }
+}
+
+/// A histogram over fixed bucket boundaries, stored as a compact SQLite `BLOB`.
+///
+/// Each boundary is the inclusive upper bound of a bucket; values above the
+/// last boundary land in an overflow bucket. Histograms can only be merged
+/// with histograms sharing the same boundaries.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Histogram {
+    bounds: Vec<f64>,
+    counts: Vec<u64>,
+    sum: f64,
+}
+impl Histogram {
+    const VERSION: u8 = 1;
+
+    /// Create an empty histogram. Panics if the boundaries are not strictly
+    /// increasing.
+    pub fn new(bounds: Vec<f64>) -> Self {
+        assert!(
+            bounds.windows(2).all(|w| w[0] < w[1]),
+            "Histogram boundaries must be strictly increasing"
+        );
+        let counts = vec![0; bounds.len() + 1];
+        Self {
+            bounds,
+            counts,
+            sum: 0.,
+        }
+    }
+    /// Create an empty histogram with `n` boundaries, starting at `start`
+    /// and each `factor` times the last.
+    pub fn exponential(start: f64, factor: f64, n: usize) -> Self {
+        Self::new(
+            std::iter::successors(Some(start), |b| Some(b * factor))
+                .take(n)
+                .collect(),
+        )
+    }
+
+    pub fn record(&mut self, value: f64) {
+        let bucket = self.bounds.partition_point(|b| *b < value);
+        self.counts[bucket] = self.counts[bucket].saturating_add(1);
+        self.sum += value;
+    }
+    /// Add the counts of `other` to this histogram.
+    pub fn merge(&mut self, other: &Histogram) -> Result<(), Error> {
+        if self.bounds != other.bounds {
+            return Err(Error::BoundsMismatch);
+        }
+        for (a, b) in self.counts.iter_mut().zip(&other.counts) {
+            *a = a.saturating_add(*b);
+        }
+        self.sum += other.sum;
+        Ok(())
+    }
+
+    pub fn bounds(&self) -> &[f64] {
+        &self.bounds
+    }
+    /// The count of each bucket; the last is the overflow bucket.
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+    pub fn count(&self) -> u64 {
+        self.counts.iter().fold(0, |a, c| a.saturating_add(*c))
+    }
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+    pub fn mean(&self) -> Option<f64> {
+        let count = self.count();
+        (count > 0).then(|| self.sum / count as f64)
+    }
+    /// Upper bound of the bucket containing the `q`th quantile (0 - 1).
+    /// Returns infinity if it falls in the overflow bucket, and `None` for an
+    /// empty histogram.
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = ((q.clamp(0., 1.) * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, c) in self.counts.iter().enumerate() {
+            seen = c.saturating_add(seen);
+            if seen >= rank {
+                return Some(self.bounds.get(i).copied().unwrap_or(f64::INFINITY));
+            }
+        }
+        unreachable!("rank is at most count")
+    }
+
+    /// Encode as: version, boundary count (LEB128), boundaries (f64 LE),
+    /// sum (f64 LE), bucket counts (LEB128).
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(2 + 8 * (self.bounds.len() + 1) + self.counts.len());
+        buf.push(Self::VERSION);
+        write_varint(&mut buf, self.bounds.len() as u64);
+        for b in &self.bounds {
+            buf.extend_from_slice(&b.to_le_bytes());
+        }
+        buf.extend_from_slice(&self.sum.to_le_bytes());
+        for c in &self.counts {
+            write_varint(&mut buf, *c);
+        }
+        buf
+    }
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self, Error> {
+        let buf = &mut bytes;
+        if take(buf, 1)? != [Self::VERSION] {
+            return Err(Error::Encoding);
+        }
+        let n = read_varint(buf)?;
+        // Each boundary takes 8 bytes, then the sum 8 and each count at least
+        // one, so a larger count is corrupt (and mustn't be allocated).
+        if n.saturating_mul(9).saturating_add(9) > buf.len() as u64 {
+            return Err(Error::Encoding);
+        }
+        let n = n as usize;
+        let bounds: Vec<f64> = (0..n).map(|_| read_f64(buf)).collect::<Result<_, _>>()?;
+        if !bounds.windows(2).all(|w| w[0] < w[1]) {
+            return Err(Error::Encoding);
+        }
+        let sum = read_f64(buf)?;
+        let counts = (0..=n)
+            .map(|_| read_varint(buf))
+            .collect::<Result<_, _>>()?;
+        if !buf.is_empty() {
+            return Err(Error::Encoding);
+        }
+        Ok(Self {
+            bounds,
+            counts,
+            sum,
+        })
+    }
+}
+impl ToSql for Histogram {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.to_bytes()))
+    }
+}
+column_type!("blob", Blob: Histogram);
+impl FromSql for Histogram {
+    fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
+        Self::from_bytes(value.as_blob()?).map_err(|e| FromSqlError::Other(Box::new(e)))
+    }
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        buf.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+fn read_varint(buf: &mut &[u8]) -> Result<u64, Error> {
+    let mut v = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = take(buf, 1)?[0];
+        v |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(v);
+        }
+    }
+    Err(Error::Encoding)
+}
+fn read_f64(buf: &mut &[u8]) -> Result<f64, Error> {
+    let bytes = take(buf, 8)?;
+    Ok(f64::from_le_bytes(bytes.try_into().expect("took 8 bytes")))
+}
+fn take<'a>(buf: &mut &'a [u8], n: usize) -> Result<&'a [u8], Error> {
+    if buf.len() < n {
+        return Err(Error::Encoding);
+    }
+    let (head, tail) = buf.split_at(n);
+    *buf = tail;
+    Ok(head)
+}
+
+/// Register SQL functions operating on `Histogram` columns:
+///
+/// - `histogram_merge(h)`, an aggregate merging histograms (NULLs are skipped)
+/// - `histogram_count(h)`, the number of recorded values
+/// - `histogram_quantile(h, q)`, as `Histogram::quantile`
+pub fn register_functions(conn: &Connection) -> rusqlite::Result<()> {
+    let flags = FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC;
+    conn.create_aggregate_function("histogram_merge", 1, flags, HistogramMerge)?;
+    conn.create_scalar_function("histogram_count", 1, flags, |ctx| {
+        Ok(ctx
+            .get::<Option<Histogram>>(0)?
+            .map(|h| h.count().min(i64::MAX as u64) as i64))
+    })?;
+    conn.create_scalar_function("histogram_quantile", 2, flags, |ctx| {
+        let q: f64 = ctx.get(1)?;
+        Ok(ctx.get::<Option<Histogram>>(0)?.and_then(|h| h.quantile(q)))
+    })?;
+    Ok(())
+}
+
+struct HistogramMerge;
+impl Aggregate<Option<Histogram>, Option<Histogram>> for HistogramMerge {
+    fn init(&self, _: &mut Context<'_>) -> rusqlite::Result<Option<Histogram>> {
+        Ok(None)
+    }
+    fn step(&self, ctx: &mut Context<'_>, acc: &mut Option<Histogram>) -> rusqlite::Result<()> {
+        let h = match ctx.get::<Option<Histogram>>(0)? {
+            Some(h) => h,
+            None => return Ok(()),
+        };
+        match acc {
+            Some(acc) => acc
+                .merge(&h)
+                .map_err(|e| rusqlite::Error::UserFunctionError(Box::new(e))),
+            None => {
+                *acc = Some(h);
+                Ok(())
+            }
+        }
+    }
+    fn finalize(
+        &self,
+        _: &mut Context<'_>,
+        acc: Option<Option<Histogram>>,
+    ) -> rusqlite::Result<Option<Histogram>> {
+        Ok(acc.flatten())
+    }
+}
+
+#[derive(Clone, Copy, Error, Debug, PartialEq, Eq)]
+pub enum Error {
+    #[error("Histograms have different bucket boundaries")]
+    BoundsMismatch,
+    #[error("Invalid histogram encoding")]
+    Encoding,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn histogram_statistics() {
+        let mut h = Histogram::new(vec![1., 10., 100.]);
+        for v in [0.5, 1., 5., 50., 500.] {
+            h.record(v);
+        }
+        assert_eq!(h.counts(), &[2, 1, 1, 1]);
+        assert_eq!(h.count(), 5);
+        assert_eq!(h.mean(), Some(556.5 / 5.));
+        assert_eq!(h.quantile(0.4), Some(1.));
+        assert_eq!(h.quantile(0.5), Some(10.));
+        assert_eq!(h.quantile(1.), Some(f64::INFINITY));
+        assert_eq!(Histogram::new(vec![1.]).quantile(0.5), None);
+
+        let mut other = Histogram::exponential(1., 10., 3);
+        other.record(5.);
+        h.merge(&other).expect("Failed to merge histograms");
+        assert_eq!(h.counts(), &[2, 2, 1, 1]);
+        assert_eq!(
+            h.merge(&Histogram::new(vec![2.])),
+            Err(Error::BoundsMismatch)
+        );
+    }
+
+    #[test]
+    fn histogram_encoding() {
+        let mut h = Histogram::new(vec![1., 10.]);
+        for _ in 0..300 {
+            h.record(5.);
+        }
+        let bytes = h.to_bytes();
+        assert_eq!(bytes.len(), 1 + 1 + 16 + 8 + 1 + 2 + 1);
+        assert_eq!(Histogram::from_bytes(&bytes), Ok(h));
+        assert_eq!(
+            Histogram::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(Error::Encoding)
+        );
+
+        // A huge boundary count, and boundaries out of order.
+        let mut corrupt = bytes.clone();
+        corrupt[1] = 0x7f;
+        assert_eq!(Histogram::from_bytes(&corrupt), Err(Error::Encoding));
+        assert_eq!(
+            Histogram::from_bytes(&[1, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f]),
+            Err(Error::Encoding)
+        );
+        let mut corrupt = bytes;
+        corrupt[2..18].rotate_left(8);
+        assert_eq!(Histogram::from_bytes(&corrupt), Err(Error::Encoding));
+    }
+
+    #[test]
+    fn histogram_counts_saturate() {
+        let mut full = Histogram::new(vec![1.]);
+        full.counts = vec![u64::MAX, u64::MAX - 1];
+        let mut h = Histogram::from_bytes(&full.to_bytes()).unwrap();
+        h.record(5.);
+        assert!(h.merge(&full).is_ok());
+        assert_eq!(h.counts(), &[u64::MAX, u64::MAX]);
+        assert_eq!(h.count(), u64::MAX);
+        assert_eq!(h.quantile(0.5), Some(1.));
+    }
+
+    #[test]
+    fn insert_and_retrieve_metrics() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute("create table foo( h blob, c integer ) strict", ())
+            .expect("failed to create table");
+
+        let mut h = Histogram::new(vec![1., 10.]);
+        h.record(3.);
+        let mut c = Counter::default();
+        c.increment();
+        let res = db.query_row(
+            "insert into foo(h, c) values (?, ?) returning *",
+            (&h, c),
+            |row| Ok((row.get::<_, Histogram>("h")?, row.get::<_, Counter>("c")?)),
+        );
+        assert!(res.is_ok(), "Failed to retrieve metrics: {:?}", res);
+        assert_eq!(res.unwrap(), (h, c));
+    }
+
+    #[test]
+    fn merge_in_sql() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        register_functions(&db).expect("Failed to register functions");
+        db.execute("create table foo( host text, h blob ) strict", ())
+            .expect("failed to create table");
+        for (host, v) in [("a", 0.5), ("a", 5.), ("b", 50.)] {
+            let mut h = Histogram::new(vec![1., 10.]);
+            h.record(v);
+            db.execute("insert into foo(host, h) values (?, ?)", (host, h))
+                .expect("failed to insert histogram");
+        }
+        db.execute("insert into foo(host, h) values ('c', null)", ())
+            .expect("failed to insert null");
+
+        let res = db.query_row("select histogram_merge(h) from foo", (), |row| {
+            row.get::<_, Histogram>(0)
+        });
+        assert!(res.is_ok(), "Failed to merge histograms: {:?}", res);
+        assert_eq!(res.unwrap().counts(), &[1, 1, 1]);
+
+        let res: rusqlite::Result<Vec<(String, i64, f64)>> = db
+            .prepare(
+                "select host, histogram_count(histogram_merge(h)),
+                    histogram_quantile(histogram_merge(h), 1.0)
+                from foo where h is not null group by host order by host",
+            )
+            .unwrap()
+            .query_map((), |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect();
+        assert_eq!(
+            res.unwrap(),
+            vec![
+                ("a".to_string(), 2, 10.),
+                ("b".to_string(), 1, f64::INFINITY)
+            ]
+        );
+    }
+}
@@ -0,0 +1,479 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use rusqlite::{Connection, OpenFlags};
+use thiserror::Error;
+
+use crate::{
+    execute::{DryRun, Executor, Plan},
+    trigger::Trigger,
+};
+
+/// A single schema change. Applying it brings the database to `version`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Migration {
+    pub version: i64,
+    pub name: String,
+    pub sql: String,
+}
+
+/// An ordered set of migrations, tracked with `PRAGMA user_version`. The
+/// `n`th migration added has version `n`, and a fresh database has version 0.
+///
+/// Each migration runs in its own savepoint together with the version bump,
+/// so a failing migration leaves the database at the previous version.
+/// Migrations are applied inside a `BEGIN IMMEDIATE` transaction, in which
+/// the version is read, so when several processes migrate the same database
+/// at once only the first applies them. When called within a transaction,
+/// that transaction should be immediate for the same guarantee.
+#[derive(Clone, Debug, Default)]
+pub struct Migrations {
+    migrations: Vec<Migration>,
+}
+
+impl Migrations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn add(mut self, name: &str, sql: &str) -> Self {
+        self.migrations.push(Migration {
+            version: self.migrations.len() as i64 + 1,
+            name: name.to_string(),
+            sql: sql.to_string(),
+        });
+        self
+    }
+    /// Add a migration (re)creating `trigger`, so a changed trigger can be
+    /// shipped by adding it again.
+    pub fn add_trigger(self, trigger: &Trigger) -> Self {
+        let sql = format!("{}; {}", trigger.drop_sql(), trigger.create_sql());
+        self.add(&format!("create trigger {}", trigger.name()), &sql)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Migration> {
+        self.migrations.iter()
+    }
+    pub fn latest_version(&self) -> i64 {
+        self.migrations.len() as i64
+    }
+    pub fn current_version(conn: &Connection) -> rusqlite::Result<i64> {
+        conn.query_row("pragma user_version", (), |row| row.get(0))
+    }
+    /// Migrations not yet applied to the database.
+    pub fn pending(&self, conn: &Connection) -> Result<&[Migration], Error> {
+        let current = Self::current_version(conn)?;
+        let latest = self.latest_version();
+        if current > latest {
+            return Err(Error::NewerDatabase { current, latest });
+        }
+        Ok(&self.migrations[current.max(0) as usize..])
+    }
+
+    /// Apply pending migrations, returning how many were applied.
+    pub fn apply(&self, conn: &Connection) -> Result<usize, Error> {
+        self.run(conn)
+    }
+    /// The statements `apply` would execute, without executing them.
+    pub fn plan(&self, conn: &Connection) -> Result<Plan, Error> {
+        let dry_run = DryRun::new(conn);
+        self.run(&dry_run)?;
+        Ok(dry_run.into_plan())
+    }
+
+    /// Apply pending migrations through `exec`.
+    pub fn run<E: Executor + ?Sized>(&self, exec: &E) -> Result<usize, Error> {
+        // The version only grows, so nothing being pending holds; otherwise
+        // it's read again once other writers are locked out.
+        if self.pending(exec.connection())?.is_empty() {
+            return Ok(0);
+        }
+        let transaction = exec.connection().is_autocommit();
+        if transaction {
+            exec.run_batch("begin immediate")?;
+        }
+        let res = self.run_pending(exec);
+        if transaction {
+            match res {
+                // Keep the migrations applied before the failed one.
+                Ok(_) | Err(Error::Failed { .. }) => exec.run_batch("commit")?,
+                Err(_) => exec.run_batch("rollback")?,
+            }
+        }
+        res
+    }
+
+    fn run_pending<E: Executor + ?Sized>(&self, exec: &E) -> Result<usize, Error> {
+        let pending = self.pending(exec.connection())?;
+        for migration in pending {
+            exec.run_batch("savepoint migration")?;
+            let res = exec.run_batch(&migration.sql).and_then(|_| {
+                exec.run_batch(&format!("pragma user_version = {}", migration.version))
+            });
+            if let Err(e) = res {
+                exec.run_batch("rollback to migration; release migration")?;
+                return Err(Error::Failed {
+                    version: migration.version,
+                    name: migration.name.clone(),
+                    source: e,
+                });
+            }
+            exec.run_batch("release migration")?;
+        }
+        Ok(pending.len())
+    }
+}
+
+/// Migrates several database files, each with its own set of migrations
+/// and tracking its own version, eg a main database along with those
+/// attached to it, or every tenant's database with a shared set.
+///
+/// Each database is migrated on its own connection, so a failure only stops
+/// the migration of that database, and several can be migrated in parallel.
+/// The files must already exist.
+#[derive(Clone, Debug)]
+pub struct MultiMigrator {
+    targets: Vec<Target>,
+    parallelism: usize,
+}
+
+#[derive(Clone, Debug)]
+struct Target {
+    name: String,
+    path: PathBuf,
+    migrations: Arc<Migrations>,
+}
+
+impl Target {
+    fn migrate(&self) -> DatabaseReport {
+        let mut from_version = None;
+        let result = (|| {
+            let conn = Connection::open_with_flags(
+                &self.path,
+                OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+            )?;
+            from_version = Some(Migrations::current_version(&conn)?);
+            self.migrations.apply(&conn)
+        })();
+        DatabaseReport {
+            name: self.name.clone(),
+            path: self.path.clone(),
+            from_version,
+            result,
+        }
+    }
+}
+
+impl Default for MultiMigrator {
+    fn default() -> Self {
+        Self {
+            targets: vec![],
+            parallelism: 1,
+        }
+    }
+}
+
+impl MultiMigrator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Migrate the database at `path`, reported as `name`.
+    pub fn database<P: AsRef<Path>>(self, name: &str, path: P, migrations: Migrations) -> Self {
+        self.add(name, path.as_ref(), Arc::new(migrations))
+    }
+    pub(crate) fn add(mut self, name: &str, path: &Path, migrations: Arc<Migrations>) -> Self {
+        self.targets.push(Target {
+            name: name.to_string(),
+            path: path.to_path_buf(),
+            migrations,
+        });
+        self
+    }
+    /// Migrate each of `paths` with the same migrations, reported by path.
+    pub fn shared<I, P>(mut self, migrations: Migrations, paths: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        let migrations = Arc::new(migrations);
+        for path in paths {
+            let path = path.as_ref();
+            self = self.add(&path.display().to_string(), path, migrations.clone());
+        }
+        self
+    }
+    /// Migrate the file of the database attached to `conn` as `schema`,
+    /// reported by schema name. `conn` must not be in a transaction on it
+    /// while migrating.
+    pub fn attached(
+        self,
+        conn: &Connection,
+        schema: &str,
+        migrations: Migrations,
+    ) -> Result<Self, Error> {
+        let mut stmt = conn.prepare("pragma database_list")?;
+        let mut rows = stmt.query(())?;
+        while let Some(row) = rows.next()? {
+            let (name, file): (String, String) = (row.get(1)?, row.get(2)?);
+            if name.eq_ignore_ascii_case(schema) && !file.is_empty() {
+                return Ok(self.database(schema, file, migrations));
+            }
+        }
+        Err(Error::NoFile(schema.to_string()))
+    }
+    /// The number of databases migrated at once (1 by default).
+    pub fn parallelism(mut self, threads: usize) -> Self {
+        self.parallelism = threads.max(1);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.targets.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.targets.is_empty()
+    }
+
+    /// Migrate every database, reporting on each in the order they were
+    /// added.
+    pub fn run(&self) -> MigrationReport {
+        let next = AtomicUsize::new(0);
+        let reports: Mutex<Vec<Option<DatabaseReport>>> =
+            Mutex::new(self.targets.iter().map(|_| None).collect());
+        let workers = self.parallelism.min(self.targets.len());
+        std::thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let target = match self.targets.get(i) {
+                        Some(target) => target,
+                        None => break,
+                    };
+                    let report = target.migrate();
+                    reports.lock().expect("no worker panics")[i] = Some(report);
+                });
+            }
+        });
+        MigrationReport {
+            databases: reports
+                .into_inner()
+                .expect("no worker panics")
+                .into_iter()
+                .map(|r| r.expect("every database was migrated"))
+                .collect(),
+        }
+    }
+}
+
+/// The outcome of migrating one database with `MultiMigrator`.
+#[derive(Debug)]
+pub struct DatabaseReport {
+    pub name: String,
+    pub path: PathBuf,
+    /// The version before migrating, unless it couldn't be read.
+    pub from_version: Option<i64>,
+    /// The number of migrations applied, or why migrating failed.
+    pub result: Result<usize, Error>,
+}
+
+/// The outcome of `MultiMigrator::run`.
+#[derive(Debug)]
+pub struct MigrationReport {
+    pub databases: Vec<DatabaseReport>,
+}
+
+impl MigrationReport {
+    pub fn is_success(&self) -> bool {
+        self.databases.iter().all(|d| d.result.is_ok())
+    }
+    pub fn failed(&self) -> impl Iterator<Item = &DatabaseReport> {
+        self.databases.iter().filter(|d| d.result.is_err())
+    }
+    /// The total number of migrations applied.
+    pub fn applied(&self) -> usize {
+        self.databases
+            .iter()
+            .filter_map(|d| d.result.as_ref().ok())
+            .sum()
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Database version {current} is newer than the latest migration ({latest})")]
+    NewerDatabase { current: i64, latest: i64 },
+    #[error("Migration {version} ({name}) failed: {source}")]
+    Failed {
+        version: i64,
+        name: String,
+        source: rusqlite::Error,
+    },
+    #[error("Database {0} isn't attached, or has no file")]
+    NoFile(String),
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn migrations() -> Migrations {
+        Migrations::new()
+            .add("create foo", "create table foo( a integer );")
+            .add(
+                "add bar",
+                "alter table foo add column b integer; create index foo_b on foo(b);",
+            )
+    }
+
+    #[test]
+    fn apply_migrations() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+
+        let res = migrations().apply(&db);
+        assert!(res.is_ok(), "Failed to apply migrations: {:?}", res);
+        assert_eq!(res.unwrap(), 2);
+        assert_eq!(Migrations::current_version(&db).unwrap(), 2);
+        db.execute("insert into foo(a, b) values (1, 2)", ())
+            .expect("Migrated table is missing columns");
+
+        assert_eq!(migrations().apply(&db).unwrap(), 0);
+        assert!(matches!(
+            Migrations::new().apply(&db),
+            Err(Error::NewerDatabase {
+                current: 2,
+                latest: 0
+            })
+        ));
+    }
+
+    #[test]
+    fn failed_migration_is_rolled_back() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        let res = migrations()
+            .add("broken", "create table baz( a ); select * from missing;")
+            .apply(&db);
+        assert!(
+            matches!(res, Err(Error::Failed { version: 3, .. })),
+            "Expected migration 3 to fail: {:?}",
+            res
+        );
+        assert_eq!(Migrations::current_version(&db).unwrap(), 2);
+        let baz: i64 = db
+            .query_row(
+                "select count(*) from sqlite_schema where name = 'baz'",
+                (),
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(baz, 0, "Failed migration was not rolled back");
+    }
+
+    #[test]
+    fn concurrent_migrations_apply_once() {
+        let dir = tempfile::tempdir().expect("Failed to create directory");
+        let path = dir.path().join("db");
+        let open = || {
+            let conn = Connection::open(&path).expect("Failed to open database");
+            conn.busy_timeout(std::time::Duration::from_secs(10))
+                .unwrap();
+            conn
+        };
+        // Another process is applying the migrations, but hasn't committed.
+        let other = open();
+        other.execute_batch("begin immediate").unwrap();
+        assert_eq!(migrations().apply(&other).unwrap(), 2);
+
+        let conn = open();
+        let handle = std::thread::spawn(move || migrations().apply(&conn));
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        other.execute_batch("commit").unwrap();
+        let res = handle.join().unwrap();
+        assert!(res.is_ok(), "Failed to apply migrations: {:?}", res);
+        assert_eq!(res.unwrap(), 0, "Migrations were applied twice");
+    }
+
+    #[test]
+    fn plan_migrations() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        migrations().apply(&db).unwrap();
+
+        let plan = migrations()
+            .add("create baz", "create table baz( a )")
+            .plan(&db)
+            .expect("Failed to plan migrations");
+        let sql: Vec<_> = plan.iter().map(|s| s.sql.as_str()).collect();
+        assert_eq!(
+            sql,
+            vec![
+                "begin immediate",
+                "savepoint migration",
+                "create table baz( a )",
+                "pragma user_version = 3",
+                "release migration",
+                "commit"
+            ]
+        );
+        assert_eq!(Migrations::current_version(&db).unwrap(), 2);
+    }
+
+    #[test]
+    fn migrate_many_databases() {
+        let dir = tempfile::tempdir().expect("Failed to create directory");
+        let path = |name: &str| dir.path().join(name);
+        for name in ["main.db", "audit.db", "a.db", "b.db", "newer.db"] {
+            Connection::open(path(name)).expect("Failed to create database");
+        }
+        Connection::open(path("newer.db"))
+            .unwrap()
+            .execute_batch("pragma user_version = 5")
+            .unwrap();
+        let main = Connection::open(path("main.db")).unwrap();
+        main.execute("attach ?1 as audit", (path("audit.db").to_str(),))
+            .unwrap();
+
+        let audit = Migrations::new().add("create log", "create table log( entry text )");
+        let res = MultiMigrator::new()
+            .database("main", path("main.db"), migrations())
+            .attached(&main, "audit", audit);
+        assert!(res.is_ok(), "Failed to find attached database: {:?}", res);
+        let migrator = res
+            .unwrap()
+            .shared(migrations(), ["a.db", "b.db", "newer.db"].map(path))
+            .parallelism(3);
+        assert_eq!(migrator.len(), 5);
+
+        let report = migrator.run();
+        let names: Vec<_> = report.databases.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(names[..2], ["main", "audit"]);
+        assert!(!report.is_success());
+        assert_eq!(report.applied(), 2 + 1 + 2 + 2);
+        let failed: Vec<_> = report.failed().collect();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].path, path("newer.db"));
+        assert_eq!(failed[0].from_version, Some(5));
+        assert!(matches!(
+            failed[0].result,
+            Err(Error::NewerDatabase { current: 5, .. })
+        ));
+
+        // Each database tracks its own version.
+        assert_eq!(Migrations::current_version(&main).unwrap(), 2);
+        let audit_version: i64 = main
+            .query_row("pragma audit.user_version", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(audit_version, 1);
+        main.execute("insert into audit.log values ('migrated')", ())
+            .expect("Attached database was not migrated");
+
+        assert!(matches!(
+            MultiMigrator::new().attached(&main, "missing", Migrations::new()),
+            Err(Error::NoFile(_))
+        ));
+    }
+}
@@ -0,0 +1,154 @@
+use rusqlite::{
+    params_from_iter,
+    types::{FromSql, ToSqlOutput, Value, ValueRef},
+    Connection, Row, ToSql,
+};
+
+use crate::util::quote_ident;
+
+thread_local! {
+    static CONN: Connection =
+        Connection::open_in_memory().expect("in-memory databases can always be opened");
+}
+
+/// A synthetic row of named values, for unit testing `TryFrom<&Row>`
+/// implementations (such as those from `#[derive(TryFromRow)]`) and `FromSql`
+/// types without setting up tables.
+///
+/// `rusqlite::Row` can only come from a statement, so the row is produced by
+/// selecting the values on a private in-memory connection. Its columns have
+/// no declared type.
+#[derive(Debug, Default)]
+pub struct MockRow {
+    columns: Vec<(String, Value)>,
+    /// The first value which failed to convert, returned when the row is
+    /// used.
+    error: Option<rusqlite::Error>,
+}
+
+impl MockRow {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Add a column holding `value` as it would be stored.
+    pub fn column<V: ToSql>(mut self, name: &str, value: V) -> Self {
+        let value = match value.to_sql() {
+            Ok(ToSqlOutput::Borrowed(v)) => v.into(),
+            Ok(ToSqlOutput::Owned(v)) => v,
+            // Zeroblobs and the like aren't enabled.
+            Ok(_) => Value::Null,
+            Err(e) => {
+                self.error.get_or_insert(e);
+                Value::Null
+            }
+        };
+        self.columns.push((name.to_string(), value));
+        self
+    }
+
+    /// Run `f` on the row.
+    pub fn with<T, F>(&self, f: F) -> rusqlite::Result<T>
+    where
+        F: FnOnce(&Row<'_>) -> rusqlite::Result<T>,
+    {
+        if let Some(e) = &self.error {
+            return Err(rusqlite::Error::ToSqlConversionFailure(
+                e.to_string().into(),
+            ));
+        }
+        let columns: Vec<_> = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, (name, _))| format!("?{} as {}", i + 1, quote_ident(name)))
+            .collect();
+        let sql = if columns.is_empty() {
+            // A row must have a column.
+            "select null".to_string()
+        } else {
+            format!("select {}", columns.join(", "))
+        };
+        CONN.with(|conn| {
+            let mut stmt = conn.prepare_cached(&sql)?;
+            let mut rows = stmt.query(params_from_iter(self.columns.iter().map(|(_, v)| v)))?;
+            let row = rows
+                .next()?
+                .expect("a select without a table returns one row");
+            f(row)
+        })
+    }
+    /// Convert the row, as a query would.
+    pub fn convert<T>(&self) -> rusqlite::Result<T>
+    where
+        for<'r, 'stmt> T: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error>,
+    {
+        self.with(|row| T::try_from(row))
+    }
+    /// Read one column, as `Row::get` would.
+    pub fn get<T: FromSql>(&self, name: &str) -> rusqlite::Result<T> {
+        self.with(|row| row.get(name))
+    }
+}
+
+/// Convert a single value as `FromSql` would when reading it from a column.
+pub fn from_value<T: FromSql>(value: &Value) -> rusqlite::types::FromSqlResult<T> {
+    T::column_result(ValueRef::from(value))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::{date_time::UnixEpoch, TryFromRow};
+
+    #[derive(TryFromRow, Debug, PartialEq)]
+    struct User {
+        id: i64,
+        #[try_from_row(column = "user_name")]
+        name: String,
+        email: Option<String>,
+    }
+
+    #[test]
+    fn convert_mock_rows() {
+        let row = MockRow::new()
+            .column("id", 1)
+            .column("user_name", "ada")
+            .column("email", None::<String>);
+        let res = row.convert::<User>();
+        assert!(res.is_ok(), "Failed to convert row: {:?}", res);
+        assert_eq!(
+            res.unwrap(),
+            User {
+                id: 1,
+                name: "ada".into(),
+                email: None
+            }
+        );
+        assert_eq!(row.get::<String>("user_name").unwrap(), "ada");
+
+        let res = MockRow::new().column("id", 1).convert::<User>();
+        assert!(matches!(res, Err(rusqlite::Error::InvalidColumnName(ref c)) if c == "user_name"));
+        let res = MockRow::new()
+            .column("id", "one")
+            .column("user_name", "ada")
+            .column("email", "ada@a")
+            .convert::<User>();
+        assert!(matches!(
+            res,
+            Err(rusqlite::Error::FromSqlConversionFailure(
+                0,
+                rusqlite::types::Type::Text,
+                _
+            ))
+        ));
+    }
+
+    #[test]
+    fn convert_values() {
+        let res = from_value::<UnixEpoch>(&Value::Integer(86400));
+        assert!(res.is_ok(), "Failed to convert value: {:?}", res);
+        assert_eq!(res.unwrap().unwrap().timestamp(), 86400);
+        assert!(from_value::<UnixEpoch>(&Value::Text("x".into())).is_err());
+    }
+}
@@ -0,0 +1,124 @@
+use rusqlite::{
+    types::{Null, ValueRef},
+    Row, ToSql,
+};
+
+use crate::row::prefixed;
+
+/// A value stored across several columns, such as a point as its latitude
+/// and longitude, or an amount and its currency. Each column is named by a
+/// suffix of the field's prefix, so a `location` field of a type with
+/// columns `lat` and `lon` is stored in `location_lat` and `location_lon`.
+///
+/// Fields are marked `#[try_from_row(multi_column)]` (with `prefix = "..."`
+/// to override the prefix, which defaults to the field name and `_`) to be
+/// read by `#[derive(TryFromRow)]` and written by `#[derive(Insert)]` and
+/// `#[derive(Update)]`.
+pub trait MultiColumn: Sized {
+    /// The suffixes of the column names.
+    const COLUMNS: &'static [&'static str];
+
+    /// Read the value from the columns of `row` starting with `prefix`.
+    fn from_columns(row: &Row<'_>, prefix: &str) -> rusqlite::Result<Self>;
+
+    /// The value of each column, in the order of `COLUMNS`.
+    fn to_columns(&self) -> Vec<&dyn ToSql>;
+}
+
+/// `None` when every column is NULL.
+impl<T: MultiColumn> MultiColumn for Option<T> {
+    const COLUMNS: &'static [&'static str] = T::COLUMNS;
+
+    fn from_columns(row: &Row<'_>, prefix: &str) -> rusqlite::Result<Self> {
+        for column in T::COLUMNS {
+            if row.get_ref(&*prefixed(prefix, column))? != ValueRef::Null {
+                return T::from_columns(row, prefix).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    fn to_columns(&self) -> Vec<&dyn ToSql> {
+        match self {
+            Some(value) => value.to_columns(),
+            None => vec![&Null; T::COLUMNS.len()],
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use rusqlite::Connection;
+
+    use crate::{crud::Insert, Insert, TryFromRow};
+
+    #[derive(Debug, PartialEq)]
+    struct GeoPoint {
+        lat: f64,
+        lon: f64,
+    }
+    impl MultiColumn for GeoPoint {
+        const COLUMNS: &'static [&'static str] = &["lat", "lon"];
+
+        fn from_columns(row: &Row<'_>, prefix: &str) -> rusqlite::Result<Self> {
+            Ok(Self {
+                lat: row.get(&*prefixed(prefix, "lat"))?,
+                lon: row.get(&*prefixed(prefix, "lon"))?,
+            })
+        }
+        fn to_columns(&self) -> Vec<&dyn ToSql> {
+            vec![&self.lat, &self.lon]
+        }
+    }
+
+    #[derive(Insert, TryFromRow, Debug, PartialEq)]
+    struct Place {
+        name: String,
+        #[try_from_row(multi_column)]
+        location: GeoPoint,
+        #[try_from_row(multi_column, prefix = "entrance_")]
+        entrance: Option<GeoPoint>,
+    }
+
+    #[test]
+    fn write_and_read_columns() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch(
+            "create table place( name text, location_lat real, location_lon real,
+                entrance_lat real, entrance_lon real );",
+        )
+        .expect("Failed to create table");
+        assert_eq!(
+            Place::insert_sql(),
+            r#"insert into "place"("name", "location_lat", "location_lon", "entrance_lat", "entrance_lon") values (?1, ?2, ?3, ?4, ?5)"#
+        );
+
+        let places = [
+            Place {
+                name: "harbour".into(),
+                location: GeoPoint {
+                    lat: 1.5,
+                    lon: -2.5,
+                },
+                entrance: None,
+            },
+            Place {
+                name: "museum".into(),
+                location: GeoPoint { lat: 3.0, lon: 4.0 },
+                entrance: Some(GeoPoint { lat: 3.1, lon: 4.1 }),
+            },
+        ];
+        for place in &places {
+            let res = place.insert(&db);
+            assert!(res.is_ok(), "Failed to insert row: {:?}", res);
+        }
+
+        let mut stmt = db.prepare("select * from place").unwrap();
+        let res: rusqlite::Result<Vec<Place>> =
+            stmt.query_map((), |row| row.try_into()).unwrap().collect();
+        assert!(res.is_ok(), "Failed to read rows: {:?}", res);
+        assert_eq!(res.unwrap(), places);
+    }
+}
@@ -4,14 +4,29 @@ use rusqlite::{
 };
 use serde::{de::DeserializeOwned, Serialize};
 
+use crate::{
+    column_type::{SqliteColumnType, StorageClass},
+    json_path::JsonPath,
+};
+
 /// Represents a BSON-encoded column value stored as a SQLite `BLOB`. T should implement
 /// serde Serialize & DeserializeOwned.
+///
+/// Fields marked `#[try_from_row(bson)]` are read and written as if wrapped
+/// in a `BsonObject`, so the struct can keep the plain type.
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(transparent)]
 pub struct BsonObject<T>(T);
 impl<T> BsonObject<T> {
     pub fn new(v: T) -> Self {
         Self(v)
     }
+    /// View a borrowed value as a `BsonObject`, to bind it without moving it.
+    pub fn from_ref(v: &T) -> &Self {
+        // SAFETY: `BsonObject` is a transparent wrapper of `T`, so the two
+        // have the same layout.
+        unsafe { &*(v as *const T as *const Self) }
+    }
     pub fn unwrap(self) -> T {
         self.0
     }
@@ -38,19 +53,48 @@ impl<T: DeserializeOwned> FromSql for BsonObject<T> {
         }
     }
 }
+impl<T> SqliteColumnType for BsonObject<T> {
+    const DECL_TYPE: Option<&'static str> = Some("blob");
+    const STORAGE: Option<StorageClass> = Some(StorageClass::Blob);
+}
 
 /// Represents a JSON-encoded column value stored as a SQLite `TEXT`. T should implement
 /// serde Serialize & DeserializeOwned.
+///
+/// Fields marked `#[try_from_row(json)]` are read and written as if wrapped
+/// in a `JsonObject`, so the struct can keep the plain type.
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(transparent)]
 pub struct JsonObject<T>(T);
 impl<T> JsonObject<T> {
     pub fn new(v: T) -> Self {
         Self(v)
     }
+    /// View a borrowed value as a `JsonObject`, to bind it without moving it.
+    pub fn from_ref(v: &T) -> &Self {
+        // SAFETY: `JsonObject` is a transparent wrapper of `T`, so the two
+        // have the same layout.
+        unsafe { &*(v as *const T as *const Self) }
+    }
     pub fn unwrap(self) -> T {
         self.0
     }
 }
+impl<T: Serialize> JsonObject<T> {
+    /// Look up `path` in the JSON representation of the value, as
+    /// `json_extract` would on the stored column.
+    pub fn extract(&self, path: &JsonPath) -> serde_json::Result<Option<serde_json::Value>> {
+        let value = serde_json::to_value(&self.0)?;
+        Ok(path.lookup(&value).cloned())
+    }
+    /// Look up `path` and deserialize the result into `U`.
+    pub fn extract_as<U: DeserializeOwned>(
+        &self,
+        path: &JsonPath,
+    ) -> serde_json::Result<Option<U>> {
+        self.extract(path)?.map(serde_json::from_value).transpose()
+    }
+}
 impl<T: Serialize> ToSql for JsonObject<T> {
     fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
         let conversion_res = serde_json::to_string(&self.0);
@@ -73,6 +117,10 @@ impl<T: DeserializeOwned> FromSql for JsonObject<T> {
         }
     }
 }
+impl<T> SqliteColumnType for JsonObject<T> {
+    const DECL_TYPE: Option<&'static str> = Some("json");
+    const STORAGE: Option<StorageClass> = Some(StorageClass::Text);
+}
 
 #[cfg(test)]
 mod test {
@@ -138,4 +186,126 @@ mod test {
         let value = res.unwrap();
         assert_eq!(value.bar.unwrap(), Bar { a: 10 });
     }
+
+    #[test]
+    fn extract_json_path() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+        struct Bar {
+            a: Vec<i64>,
+        }
+        let bar = JsonObject::new(Bar { a: vec![1, 2, 3] });
+        let path = crate::json_path::path().field("a").from_end(1);
+        assert_eq!(bar.extract_as::<i64>(&path).unwrap(), Some(3));
+
+        db.execute("create table foo( bar text ) strict", ())
+            .expect("failed to create table");
+        db.execute("insert into foo(bar) values (?)", (&bar,))
+            .expect("failed to insert JsonObject");
+        let res = db.query_row("select json_extract(bar, ?) from foo", (&path,), |row| {
+            row.get::<_, i64>(0)
+        });
+        assert!(res.is_ok(), "Failed to extract from JsonObject: {:?}", res);
+        assert_eq!(res.unwrap(), 3);
+    }
+
+    #[test]
+    fn derive_json_field() {
+        use crate::{crud::Insert, Insert, Table, TryFromRow};
+
+        #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+        struct Settings {
+            theme: String,
+        }
+        #[derive(Debug, PartialEq, Eq, Insert, Table, TryFromRow)]
+        struct Profile {
+            name: String,
+            #[try_from_row(json)]
+            settings: Settings,
+            #[try_from_row(json, nullable)]
+            tags: Option<Vec<String>>,
+            #[try_from_row(json)]
+            avatar: Option<Vec<u8>>,
+        }
+
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch(Profile::CREATE_SQL)
+            .expect("failed to create table");
+        let profile = Profile {
+            name: "ada".into(),
+            settings: Settings {
+                theme: "dark".into(),
+            },
+            tags: None,
+            avatar: Some(vec![1, 2]),
+        };
+        let res = Insert::insert(&profile, &db);
+        assert!(res.is_ok(), "Failed to insert row: {:?}", res);
+        let decl_type: String = db
+            .query_row(
+                "select type from pragma_table_info('profile') where name = 'settings'",
+                (),
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(decl_type, "json");
+
+        let res: rusqlite::Result<Profile> =
+            db.query_row("select * from profile", (), |row| row.try_into());
+        assert!(res.is_ok(), "Failed to retrieve row: {:?}", res);
+        assert_eq!(res.unwrap(), profile);
+        let settings: String = db
+            .query_row("select settings from profile", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(settings, r#"{"theme":"dark"}"#);
+        assert_eq!(
+            <Profile as crate::schema::Table>::schema().create_sql(),
+            Profile::CREATE_SQL
+        );
+    }
+
+    #[test]
+    fn derive_bson_field() {
+        use crate::{crud::Insert, Insert, Table, TryFromRow};
+
+        #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+        struct Document {
+            title: String,
+            pages: i32,
+        }
+        #[derive(Debug, PartialEq, Eq, Insert, Table, TryFromRow)]
+        #[strict]
+        struct Upload {
+            #[try_from_row(bson)]
+            document: Document,
+            #[try_from_row(bson)]
+            thumbnail: Option<Document>,
+        }
+
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch(Upload::CREATE_SQL)
+            .expect("failed to create table");
+        let upload = Upload {
+            document: Document {
+                title: "report".into(),
+                pages: 3,
+            },
+            thumbnail: None,
+        };
+        let res = Insert::insert(&upload, &db);
+        assert!(res.is_ok(), "Failed to insert row: {:?}", res);
+        let types: (String, String) = db
+            .query_row(
+                "select typeof(document), typeof(thumbnail) from upload",
+                (),
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(types, ("blob".to_string(), "null".to_string()));
+
+        let res: rusqlite::Result<Upload> =
+            db.query_row("select * from upload", (), |row| row.try_into());
+        assert!(res.is_ok(), "Failed to retrieve row: {:?}", res);
+        assert_eq!(res.unwrap(), upload);
+    }
 }
@@ -0,0 +1,15 @@
+use rusqlite::ToSql;
+
+/// A struct bound as named parameters, `:field` to the field's value.
+/// Usually derived with `#[derive(ToParams)]`; fields can be renamed with
+/// `#[to_params(name = "...")]` or left out with `#[to_params(skip)]`.
+///
+/// The parameters can be passed to any query, as `&*value.to_params()`.
+/// Every parameter must be used by the statement, or SQLite fails with
+/// `InvalidParameterName`.
+pub trait ToParams {
+    /// The parameter names, including the leading `:`.
+    const NAMES: &'static [&'static str];
+
+    fn to_params(&self) -> Vec<(&'static str, &dyn ToSql)>;
+}
@@ -0,0 +1,205 @@
+//! Typed results of the pragmas describing the schema and the connection,
+//! read through their table-valued functions.
+
+use rusqlite::{Connection, Params};
+
+use crate::TryFromRow;
+
+/// A column of a table or view, from `pragma_table_info`.
+#[derive(TryFromRow, Clone, Debug, PartialEq, Eq)]
+pub struct TableColumn {
+    pub cid: i64,
+    pub name: String,
+    /// The declared type, as written (empty if there is none).
+    #[try_from_row(column = "type")]
+    pub decl_type: String,
+    #[try_from_row(column = "notnull")]
+    pub not_null: bool,
+    /// The `DEFAULT` expression, as written.
+    #[try_from_row(column = "dflt_value")]
+    pub default: Option<String>,
+    /// The position of the column in the primary key, from 1, or 0 if it
+    /// isn't part of it.
+    pub pk: i64,
+}
+
+/// A column of an index, from `pragma_index_info`.
+#[derive(TryFromRow, Clone, Debug, PartialEq, Eq)]
+pub struct IndexColumn {
+    /// The position of the column in the index.
+    pub seqno: i64,
+    /// The position of the column in the table, -1 for the rowid and -2
+    /// for an expression.
+    pub cid: i64,
+    /// The column's name, or `None` for an expression.
+    pub name: Option<String>,
+}
+
+/// A column of a foreign key, from `pragma_foreign_key_list`. Keys over
+/// several columns have a row for each, sharing an `id`.
+#[derive(TryFromRow, Clone, Debug, PartialEq, Eq)]
+pub struct ForeignKey {
+    pub id: i64,
+    /// The position of the column in the key.
+    pub seq: i64,
+    /// The parent table.
+    pub table: String,
+    /// The column of the child table.
+    pub from: String,
+    /// The column of the parent table, or `None` for its primary key.
+    pub to: Option<String>,
+    pub on_update: String,
+    pub on_delete: String,
+    #[try_from_row(column = "match")]
+    pub match_clause: String,
+}
+
+/// An SQL function, from `pragma_function_list`. Overloads have a row
+/// each.
+#[derive(TryFromRow, Clone, Debug, PartialEq, Eq)]
+pub struct Function {
+    pub name: String,
+    pub builtin: bool,
+    /// `s` for a scalar function, `a` for an aggregate and `w` for a window
+    /// function.
+    #[try_from_row(column = "type")]
+    pub kind: String,
+    /// The text encoding, eg `utf8`.
+    pub enc: String,
+    /// The number of arguments, or -1 if variadic.
+    pub narg: i64,
+    pub flags: i64,
+}
+
+fn query_pragma<T, P>(conn: &Connection, sql: &str, params: P) -> rusqlite::Result<Vec<T>>
+where
+    T: for<'a, 'stmt> TryFrom<&'a rusqlite::Row<'stmt>, Error = rusqlite::Error>,
+    P: Params,
+{
+    let mut stmt = conn.prepare(sql)?;
+    let rows = stmt.query_map(params, |row| T::try_from(row))?;
+    rows.collect()
+}
+
+/// The columns of `table` in order, or nothing if it doesn't exist.
+pub fn table_info(conn: &Connection, table: &str) -> rusqlite::Result<Vec<TableColumn>> {
+    query_pragma(
+        conn,
+        "select * from pragma_table_info(?) order by cid",
+        (table,),
+    )
+}
+
+/// The columns of `index` in order, or nothing if it doesn't exist.
+pub fn index_info(conn: &Connection, index: &str) -> rusqlite::Result<Vec<IndexColumn>> {
+    query_pragma(
+        conn,
+        "select * from pragma_index_info(?) order by seqno",
+        (index,),
+    )
+}
+
+/// The foreign keys of `table`, ordered by key and then column.
+pub fn foreign_key_list(conn: &Connection, table: &str) -> rusqlite::Result<Vec<ForeignKey>> {
+    query_pragma(
+        conn,
+        "select * from pragma_foreign_key_list(?) order by id, seq",
+        (table,),
+    )
+}
+
+/// The functions available on the connection, including application
+/// defined ones, ordered by name.
+pub fn function_list(conn: &Connection) -> rusqlite::Result<Vec<Function>> {
+    query_pragma(
+        conn,
+        "select * from pragma_function_list order by name, narg",
+        (),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn schema_pragmas() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch(
+            "create table author( id integer primary key, name text not null default 'anon' );
+            create table book(
+                id integer primary key,
+                author_id integer references author on delete cascade,
+                title
+            );
+            create index book_title on book(title, lower(title));",
+        )
+        .expect("Failed to create tables");
+
+        let res = table_info(&db, "author");
+        assert!(res.is_ok(), "Failed to read table info: {:?}", res);
+        assert_eq!(
+            res.unwrap(),
+            vec![
+                TableColumn {
+                    cid: 0,
+                    name: "id".into(),
+                    decl_type: "INTEGER".into(),
+                    not_null: false,
+                    default: None,
+                    pk: 1
+                },
+                TableColumn {
+                    cid: 1,
+                    name: "name".into(),
+                    decl_type: "TEXT".into(),
+                    not_null: true,
+                    default: Some("'anon'".into()),
+                    pk: 0
+                }
+            ]
+        );
+        assert_eq!(table_info(&db, "book").unwrap()[2].decl_type, "");
+        assert!(table_info(&db, "missing").unwrap().is_empty());
+
+        let index = index_info(&db, "book_title").unwrap();
+        assert_eq!(index.len(), 2);
+        assert_eq!(index[0].name.as_deref(), Some("title"));
+        assert_eq!((index[1].cid, index[1].name.as_deref()), (-2, None));
+
+        let keys = foreign_key_list(&db, "book").unwrap();
+        assert_eq!(
+            keys,
+            vec![ForeignKey {
+                id: 0,
+                seq: 0,
+                table: "author".into(),
+                from: "author_id".into(),
+                to: None,
+                on_update: "NO ACTION".into(),
+                on_delete: "CASCADE".into(),
+                match_clause: "NONE".into()
+            }]
+        );
+    }
+
+    #[test]
+    fn functions() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.create_scalar_function(
+            "double",
+            1,
+            rusqlite::functions::FunctionFlags::SQLITE_UTF8,
+            |ctx| Ok(ctx.get::<i64>(0)? * 2),
+        )
+        .expect("Failed to create function");
+
+        let res = function_list(&db);
+        assert!(res.is_ok(), "Failed to list functions: {:?}", res);
+        let functions = res.unwrap();
+        let double = functions.iter().find(|f| f.name == "double").unwrap();
+        assert!(!double.builtin);
+        assert_eq!((double.kind.as_str(), double.narg), ("s", 1));
+        assert!(functions.iter().any(|f| f.name == "upper" && f.builtin));
+    }
+}
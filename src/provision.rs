@@ -0,0 +1,251 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rusqlite::{Connection, OpenFlags};
+use thiserror::Error;
+
+type SetupFn = Box<dyn Fn(&Connection) -> rusqlite::Result<()>>;
+
+/// Whether `Provisioner::provision` created the database.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Provisioned {
+    Created,
+    /// The file already existed (possibly created concurrently), and was
+    /// left as is.
+    Existing,
+}
+
+/// Creates a database file on first run, fully set up before any other
+/// process can see it.
+///
+/// The database is built in a temporary file next to the target, with the
+/// page size, application id and user version set before the first write
+/// and any `setup` (eg creating the schema) applied, then linked into place.
+/// Linking fails rather than replacing a file created in the meantime, so
+/// concurrent first runs can't clobber each other. Missing directories are
+/// created, and on Unix the file is only accessible by its owner (and new
+/// directories only by theirs) unless `permissions` says otherwise.
+pub struct Provisioner {
+    page_size: Option<u32>,
+    application_id: Option<i32>,
+    user_version: Option<i32>,
+    #[cfg_attr(not(unix), allow(dead_code))]
+    mode: u32,
+    create_dirs: bool,
+    setup: Option<SetupFn>,
+}
+
+impl Default for Provisioner {
+    fn default() -> Self {
+        Self {
+            page_size: None,
+            application_id: None,
+            user_version: None,
+            mode: 0o600,
+            create_dirs: true,
+            setup: None,
+        }
+    }
+}
+
+impl Provisioner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// The page size, which can only be changed before the first write (or
+    /// by a `VACUUM`).
+    pub fn page_size(mut self, bytes: u32) -> Self {
+        self.page_size = Some(bytes);
+        self
+    }
+    /// The application id, which identifies the file format. Existing files
+    /// with another id are rejected.
+    pub fn application_id(mut self, id: i32) -> Self {
+        self.application_id = Some(id);
+        self
+    }
+    pub fn user_version(mut self, version: i32) -> Self {
+        self.user_version = Some(version);
+        self
+    }
+    /// The Unix permissions of the file (`0o600` by default).
+    pub fn permissions(mut self, mode: u32) -> Self {
+        self.mode = mode;
+        self
+    }
+    pub fn create_dirs(mut self, create: bool) -> Self {
+        self.create_dirs = create;
+        self
+    }
+    /// Prepare the new database, eg applying migrations, before it's linked
+    /// into place.
+    pub fn setup<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Connection) -> rusqlite::Result<()> + 'static,
+    {
+        self.setup = Some(Box::new(f));
+        self
+    }
+
+    /// Create the database at `path` unless it exists. An existing file is
+    /// checked against the application id, if one is set.
+    pub fn provision<P: AsRef<Path>>(&self, path: P) -> Result<Provisioned, Error> {
+        let path = path.as_ref();
+        if path.exists() {
+            self.check_existing(path)?;
+            return Ok(Provisioned::Existing);
+        }
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            if self.create_dirs {
+                create_dirs(dir)?;
+            }
+        }
+
+        let temp = temp_path(path);
+        let res = self.build(&temp).and_then(|_| {
+            // Unlike a rename, linking fails if the target exists.
+            match fs::hard_link(&temp, path) {
+                Ok(()) => Ok(Provisioned::Created),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(Provisioned::Existing),
+                Err(e) => Err(e.into()),
+            }
+        });
+        let _ = fs::remove_file(&temp);
+        if res.as_ref().ok() == Some(&Provisioned::Existing) {
+            self.check_existing(path)?;
+        }
+        res
+    }
+
+    fn build(&self, path: &Path) -> Result<(), Error> {
+        let conn = Connection::open(path)?;
+        if let Some(page_size) = self.page_size {
+            conn.execute_batch(&format!("pragma page_size = {}", page_size))?;
+        }
+        // Writing the header makes the file a database even if nothing else
+        // is set.
+        conn.execute_batch(&format!(
+            "pragma application_id = {}; pragma user_version = {}",
+            self.application_id.unwrap_or(0),
+            self.user_version.unwrap_or(0)
+        ))?;
+        if let Some(setup) = &self.setup {
+            setup(&conn)?;
+        }
+        conn.close().map_err(|(_, e)| e)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(path, fs::Permissions::from_mode(self.mode))?;
+        }
+        Ok(())
+    }
+
+    fn check_existing(&self, path: &Path) -> Result<(), Error> {
+        let expected = match self.application_id {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        let found: i32 = conn.query_row("pragma application_id", (), |row| row.get(0))?;
+        if found != expected {
+            return Err(Error::ApplicationId { expected, found });
+        }
+        Ok(())
+    }
+}
+
+/// A unique path in the same directory as `path`, so it can be linked to it.
+fn temp_path(path: &Path) -> PathBuf {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or_default();
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.{}-{}.tmp", name, std::process::id(), nanos))
+}
+
+fn create_dirs(dir: &Path) -> io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(dir)
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("the database has application id {found}, not {expected}")]
+    ApplicationId { expected: i32, found: i32 },
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn provision_database() {
+        let dir = tempfile::tempdir().expect("Failed to create directory");
+        let path = dir.path().join("data/app/app.db");
+        let provisioner = Provisioner::new()
+            .page_size(8192)
+            .application_id(0x1234)
+            .user_version(3)
+            .setup(|conn| conn.execute_batch("create table settings( k text, v text )"));
+
+        let res = provisioner.provision(&path);
+        assert!(res.is_ok(), "Failed to provision database: {:?}", res);
+        assert_eq!(res.unwrap(), Provisioned::Created);
+        assert_eq!(
+            fs::read_dir(path.parent().unwrap()).unwrap().count(),
+            1,
+            "Temporary file left behind"
+        );
+
+        let conn = Connection::open(&path).expect("Failed to open connection");
+        let pragma = |name: &str| -> i64 {
+            conn.query_row(&format!("pragma {}", name), (), |row| row.get(0))
+                .unwrap()
+        };
+        assert_eq!(pragma("page_size"), 8192);
+        assert_eq!(pragma("application_id"), 0x1234);
+        assert_eq!(pragma("user_version"), 3);
+        conn.execute("insert into settings values ('theme', 'dark')", ())
+            .expect("Failed to insert row");
+        drop(conn);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        assert_eq!(provisioner.provision(&path).unwrap(), Provisioned::Existing);
+        let res = Provisioner::new().application_id(1).provision(&path);
+        assert!(
+            matches!(
+                res,
+                Err(Error::ApplicationId {
+                    expected: 1,
+                    found: 0x1234
+                })
+            ),
+            "Accepted another application's database: {:?}",
+            res
+        );
+    }
+}
@@ -0,0 +1,241 @@
+use rusqlite::{Connection, OptionalExtension, Params, Row};
+use thiserror::Error;
+
+use crate::util::split_queries;
+
+/// Named SQL queries, loaded from SQL source (usually embedded with
+/// `include_str!`) in which each query is preceded by a `-- name: <name>`
+/// comment:
+///
+/// ```sql
+/// -- name: get_user
+/// select * from users where id = ?;
+/// ```
+///
+/// Call `validate` at startup (after migrations) to check that every query
+/// compiles against the schema.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Queries {
+    queries: Vec<(String, String)>,
+}
+
+impl Queries {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn parse(source: &str) -> Result<Self, Error> {
+        Self::new().add_source(source)
+    }
+    /// Add the queries in `source`; names must be unique across all sources.
+    pub fn add_source(mut self, source: &str) -> Result<Self, Error> {
+        let mut current: Option<(String, String)> = None;
+        for (i, line) in source.lines().enumerate() {
+            let name = line
+                .trim()
+                .strip_prefix("--")
+                .and_then(|c| c.trim_start().strip_prefix("name:"))
+                .map(|n| n.trim());
+            match (name, &mut current) {
+                (Some(name), _) => {
+                    if let Some(query) = current.take() {
+                        self.push(query)?;
+                    }
+                    current = Some((name.to_string(), String::new()));
+                }
+                (None, Some((_, sql))) => {
+                    sql.push_str(line);
+                    sql.push('\n');
+                }
+                (None, None) if split_queries(line).next().is_none() => {}
+                (None, None) => return Err(Error::Unnamed { line: i + 1 }),
+            }
+        }
+        if let Some(query) = current {
+            self.push(query)?;
+        }
+        Ok(self)
+    }
+    fn push(&mut self, (name, sql): (String, String)) -> Result<(), Error> {
+        if self.get(&name).is_some() {
+            return Err(Error::Duplicate(name));
+        }
+        let statements: Vec<_> = split_queries(&sql).collect();
+        match statements[..] {
+            [statement] => {
+                let statement = statement.to_string();
+                self.queries.push((name, statement));
+                Ok(())
+            }
+            _ => Err(Error::NotSingleStatement(name)),
+        }
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.queries.iter().map(|(name, _)| name.as_str())
+    }
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.queries
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, sql)| sql.as_str())
+    }
+    /// The SQL for `name`, or `Error::Unknown`.
+    pub fn sql(&self, name: &str) -> Result<&str, Error> {
+        self.get(name)
+            .ok_or_else(|| Error::Unknown(name.to_string()))
+    }
+
+    /// Prepare every query, failing on the first that doesn't compile.
+    pub fn validate(&self, conn: &Connection) -> Result<(), Error> {
+        for (name, sql) in &self.queries {
+            conn.prepare_cached(sql).map_err(|e| Error::Invalid {
+                name: name.clone(),
+                source: e,
+            })?;
+        }
+        Ok(())
+    }
+
+    pub fn execute<P: Params>(
+        &self,
+        conn: &Connection,
+        name: &str,
+        params: P,
+    ) -> Result<usize, Error> {
+        let mut stmt = conn.prepare_cached(self.sql(name)?)?;
+        Ok(stmt.execute(params)?)
+    }
+    pub fn query_all<T, P>(&self, conn: &Connection, name: &str, params: P) -> Result<Vec<T>, Error>
+    where
+        P: Params,
+        for<'r, 'stmt> T: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error>,
+    {
+        let mut stmt = conn.prepare_cached(self.sql(name)?)?;
+        let rows = stmt
+            .query_map(params, |row| T::try_from(row))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(rows)
+    }
+    pub fn query_optional<T, P>(
+        &self,
+        conn: &Connection,
+        name: &str,
+        params: P,
+    ) -> Result<Option<T>, Error>
+    where
+        P: Params,
+        for<'r, 'stmt> T: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error>,
+    {
+        let mut stmt = conn.prepare_cached(self.sql(name)?)?;
+        Ok(stmt.query_row(params, |row| T::try_from(row)).optional()?)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Query on line {line} has no `-- name:` annotation")]
+    Unnamed { line: usize },
+    #[error("Query {0} is defined more than once")]
+    Duplicate(String),
+    #[error("Query {0} must contain exactly one statement")]
+    NotSingleStatement(String),
+    #[error("No query named {0}")]
+    Unknown(String),
+    #[error("Query {name} is invalid: {source}")]
+    Invalid {
+        name: String,
+        source: rusqlite::Error,
+    },
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::TryFromRow;
+
+    const SOURCE: &str = "
+        -- Queries for the users table.
+
+        -- name: create_user
+        insert into users(name) values (:name);
+
+        -- name: get_user
+        -- Look up a user by id.
+        select id, name
+        from users
+        where id = ?;
+
+        -- name: list_users
+        select id, name from users order by id
+    ";
+
+    #[derive(TryFromRow, Debug, PartialEq)]
+    struct User {
+        id: i64,
+        name: String,
+    }
+
+    #[test]
+    fn load_and_run_queries() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute(
+            "create table users( id integer primary key, name text )",
+            (),
+        )
+        .unwrap();
+
+        let res = Queries::parse(SOURCE);
+        assert!(res.is_ok(), "Failed to parse queries: {:?}", res);
+        let queries = res.unwrap();
+        assert_eq!(
+            queries.names().collect::<Vec<_>>(),
+            vec!["create_user", "get_user", "list_users"]
+        );
+        let res = queries.validate(&db);
+        assert!(res.is_ok(), "Failed to validate queries: {:?}", res);
+
+        queries
+            .execute(&db, "create_user", &[(":name", &"ada")])
+            .unwrap();
+        let user: Option<User> = queries.query_optional(&db, "get_user", (1,)).unwrap();
+        assert_eq!(
+            user,
+            Some(User {
+                id: 1,
+                name: "ada".into()
+            })
+        );
+        let users: Vec<User> = queries.query_all(&db, "list_users", ()).unwrap();
+        assert_eq!(users.len(), 1);
+        assert!(matches!(
+            queries.execute(&db, "missing", ()),
+            Err(Error::Unknown(_))
+        ));
+    }
+
+    #[test]
+    fn reject_invalid_sources() {
+        assert!(matches!(
+            Queries::parse("select 1;"),
+            Err(Error::Unnamed { line: 1 })
+        ));
+        assert!(matches!(
+            Queries::parse("-- name: a\nselect 1;\n-- name: a\nselect 2;"),
+            Err(Error::Duplicate(_))
+        ));
+        assert!(matches!(
+            Queries::parse("-- name: a\nselect 1; select 2;"),
+            Err(Error::NotSingleStatement(_))
+        ));
+
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        let queries = Queries::parse("-- name: broken\nselect * from missing").unwrap();
+        assert!(matches!(
+            queries.validate(&db),
+            Err(Error::Invalid { name, .. }) if name == "broken"
+        ));
+    }
+}
@@ -0,0 +1,255 @@
+use std::marker::PhantomData;
+
+use rusqlite::{types::FromSql, Connection, Row, ToSql};
+
+use crate::util::quote_ident;
+
+/// The name of the column holding each node's distance from the starting
+/// node, as read by `Node`.
+pub const DEPTH_COLUMN: &str = "depth";
+
+/// A row read into `T`, along with its distance from the node a traversal
+/// started at: 1 for a parent or child, 2 for a grandparent or grandchild...
+#[derive(Clone, Debug, PartialEq)]
+pub struct Node<T> {
+    pub row: T,
+    pub depth: i64,
+}
+
+impl<'stmt, T> TryFrom<&Row<'stmt>> for Node<T>
+where
+    for<'r> T: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error>,
+{
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row<'stmt>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            row: T::try_from(row)?,
+            depth: row.get(DEPTH_COLUMN)?,
+        })
+    }
+}
+
+/// Traverses a tree stored as an adjacency list, where each row of `table`
+/// refers to its parent through a nullable column, using recursive CTEs.
+/// Rows are identified by the id type `I`. Unlike a `ClosureTable`, no extra
+/// bookkeeping is needed on writes, at the cost of a recursive query on reads.
+#[derive(Clone, Debug)]
+pub struct Hierarchy<I> {
+    table: String,
+    id_column: String,
+    parent_column: String,
+    max_depth: u32,
+    _id: PhantomData<I>,
+}
+
+impl<I: FromSql + ToSql> Hierarchy<I> {
+    /// Traverse `table`, whose rows refer to their parent's `id` through
+    /// `parent_column`.
+    pub fn new(table: &str, parent_column: &str) -> Self {
+        Self {
+            table: table.to_string(),
+            id_column: "id".to_string(),
+            parent_column: parent_column.to_string(),
+            max_depth: 1000,
+            _id: PhantomData,
+        }
+    }
+    /// Identify rows by `column` rather than `id`.
+    pub fn id_column(mut self, column: &str) -> Self {
+        self.id_column = column.to_string();
+        self
+    }
+    /// Stop traversing after `depth` levels (1000 by default), so a cycle in
+    /// the data can't make a query run forever.
+    pub fn max_depth(mut self, depth: u32) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    /// Ancestors of `node`, nearest first, excluding the node itself. The rows
+    /// are selected with `*` and a `depth` column, which `T` must not also
+    /// read from the table.
+    pub fn ancestors<T>(&self, conn: &Connection, node: &I) -> rusqlite::Result<Vec<Node<T>>>
+    where
+        for<'r, 'stmt> T: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error>,
+    {
+        self.query_nodes(conn, &self.ancestors_sql(), node)
+    }
+    /// Every node below `node`, breadth first. See `ancestors`.
+    pub fn descendants<T>(&self, conn: &Connection, node: &I) -> rusqlite::Result<Vec<Node<T>>>
+    where
+        for<'r, 'stmt> T: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error>,
+    {
+        self.query_nodes(conn, &self.descendants_sql(), node)
+    }
+    /// The ids on the path from `from` down to `to`, inclusive, or `None` if
+    /// `from` is not an ancestor of `to` (or `to` itself).
+    pub fn path(&self, conn: &Connection, from: &I, to: &I) -> rusqlite::Result<Option<Vec<I>>> {
+        let mut stmt = conn.prepare(&format!(
+            "with recursive {chain}(id, depth) as (
+                select ?2, 0
+                union all
+                select t.{parent}, c.depth + 1 from {table} t
+                join {chain} c on t.{id} = c.id
+                where t.{parent} is not null and c.depth < {max_depth}
+            )
+            select id from {chain}
+            where depth <= (select min(depth) from {chain} where id = ?1)
+            order by depth desc",
+            chain = quote_ident("__hierarchy_path"),
+            table = quote_ident(&self.table),
+            id = quote_ident(&self.id_column),
+            parent = quote_ident(&self.parent_column),
+            max_depth = self.max_depth,
+        ))?;
+        let path = stmt
+            .query_map((from, to), |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<I>>>()?;
+        Ok(Some(path).filter(|p| !p.is_empty()))
+    }
+
+    fn ancestors_sql(&self) -> String {
+        format!(
+            "with recursive {cte}(id, depth) as (
+                select {parent}, 1 from {table} where {id} = ?1 and {parent} is not null
+                union all
+                select t.{parent}, a.depth + 1 from {table} t
+                join {cte} a on t.{id} = a.id
+                where t.{parent} is not null and a.depth < {max_depth}
+            )
+            select t.*, a.depth as {depth} from {cte} a
+            join {table} t on t.{id} = a.id
+            order by a.depth",
+            cte = quote_ident("__hierarchy_ancestors"),
+            table = quote_ident(&self.table),
+            id = quote_ident(&self.id_column),
+            parent = quote_ident(&self.parent_column),
+            max_depth = self.max_depth,
+            depth = quote_ident(DEPTH_COLUMN),
+        )
+    }
+    fn descendants_sql(&self) -> String {
+        format!(
+            "with recursive {cte}(id, depth) as (
+                select {id}, 1 from {table} where {parent} = ?1
+                union all
+                select t.{id}, d.depth + 1 from {table} t
+                join {cte} d on t.{parent} = d.id
+                where d.depth < {max_depth}
+            )
+            select t.*, d.depth as {depth} from {cte} d
+            join {table} t on t.{id} = d.id
+            order by d.depth, t.{id}",
+            cte = quote_ident("__hierarchy_descendants"),
+            table = quote_ident(&self.table),
+            id = quote_ident(&self.id_column),
+            parent = quote_ident(&self.parent_column),
+            max_depth = self.max_depth,
+            depth = quote_ident(DEPTH_COLUMN),
+        )
+    }
+    fn query_nodes<T>(
+        &self,
+        conn: &Connection,
+        sql: &str,
+        node: &I,
+    ) -> rusqlite::Result<Vec<Node<T>>>
+    where
+        for<'r, 'stmt> T: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error>,
+    {
+        let mut stmt = conn.prepare(sql)?;
+        let nodes = stmt
+            .query_map((node,), |row| Node::try_from(row))?
+            .collect();
+        nodes
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::{IntegerId, TryFromRow};
+
+    #[derive(TryFromRow, Debug, PartialEq)]
+    struct Category {
+        id: CategoryId,
+        name: String,
+    }
+    type CategoryId = IntegerId<Category>;
+
+    fn id(n: i64) -> CategoryId {
+        CategoryId::column_result(rusqlite::types::ValueRef::Integer(n)).unwrap()
+    }
+
+    fn setup() -> Connection {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch(
+            "create table category( id integer primary key, parent integer, name text );
+            insert into category values
+                (1, null, 'root'), (2, 1, 'books'), (3, 1, 'music'),
+                (4, 2, 'fiction'), (5, 4, 'fantasy'), (6, null, 'other');",
+        )
+        .expect("Failed to set up database");
+        db
+    }
+
+    fn names(nodes: Vec<Node<Category>>) -> Vec<(String, i64)> {
+        nodes.into_iter().map(|n| (n.row.name, n.depth)).collect()
+    }
+
+    #[test]
+    fn traverse_hierarchy() {
+        let db = setup();
+        let categories = Hierarchy::<CategoryId>::new("category", "parent");
+
+        let res = categories.ancestors(&db, &id(5));
+        assert!(res.is_ok(), "Failed to query ancestors: {:?}", res);
+        assert_eq!(
+            names(res.unwrap()),
+            vec![
+                ("fiction".into(), 1),
+                ("books".into(), 2),
+                ("root".into(), 3)
+            ]
+        );
+
+        let res = categories.descendants(&db, &id(1));
+        assert!(res.is_ok(), "Failed to query descendants: {:?}", res);
+        assert_eq!(
+            names(res.unwrap()),
+            vec![
+                ("books".into(), 1),
+                ("music".into(), 1),
+                ("fiction".into(), 2),
+                ("fantasy".into(), 3)
+            ]
+        );
+        assert!(categories
+            .descendants::<Category>(&db, &id(6))
+            .unwrap()
+            .is_empty());
+
+        let res = categories.path(&db, &id(1), &id(5));
+        assert!(res.is_ok(), "Failed to query path: {:?}", res);
+        assert_eq!(res.unwrap(), Some(vec![id(1), id(2), id(4), id(5)]));
+        assert_eq!(categories.path(&db, &id(3), &id(5)).unwrap(), None);
+        assert_eq!(
+            categories.path(&db, &id(5), &id(5)).unwrap(),
+            Some(vec![id(5)])
+        );
+    }
+
+    #[test]
+    fn cycles_are_bounded() {
+        let db = setup();
+        db.execute("update category set parent = 5 where id = 1", ())
+            .unwrap();
+        let res = Hierarchy::<CategoryId>::new("category", "parent")
+            .max_depth(10)
+            .ancestors::<Category>(&db, &id(5));
+        assert!(res.is_ok(), "Failed to query ancestors: {:?}", res);
+        assert_eq!(res.unwrap().len(), 10);
+    }
+}
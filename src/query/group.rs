@@ -0,0 +1,132 @@
+use std::collections::BTreeMap;
+
+use rusqlite::{types::FromSql, Connection, Params, Row};
+
+/// Run a query and group its rows by the value of the first column, for
+/// turning a flattened aggregate query (eg one row per customer and month)
+/// into a nested report. Every row, key column included, is read into a `V`.
+/// Rows keep their query order within each group.
+pub fn query_grouped<K, V, P>(
+    conn: &Connection,
+    sql: &str,
+    params: P,
+) -> rusqlite::Result<BTreeMap<K, Vec<V>>>
+where
+    K: FromSql + Ord,
+    P: Params,
+    for<'r, 'stmt> V: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error>,
+{
+    group_by(conn, sql, params, |row| row.get(0))
+}
+
+/// Like `query_grouped`, but with keys read from the whole row, eg by a
+/// struct deriving `TryFromRow` over the grouping columns.
+pub fn query_grouped_rows<K, V, P>(
+    conn: &Connection,
+    sql: &str,
+    params: P,
+) -> rusqlite::Result<BTreeMap<K, Vec<V>>>
+where
+    P: Params,
+    for<'r, 'stmt> K: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error> + Ord,
+    for<'r, 'stmt> V: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error>,
+{
+    group_by(conn, sql, params, |row| K::try_from(row))
+}
+
+fn group_by<K, V, P, F>(
+    conn: &Connection,
+    sql: &str,
+    params: P,
+    key: F,
+) -> rusqlite::Result<BTreeMap<K, Vec<V>>>
+where
+    K: Ord,
+    P: Params,
+    F: Fn(&Row<'_>) -> rusqlite::Result<K>,
+    for<'r, 'stmt> V: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error>,
+{
+    let mut stmt = conn.prepare(sql)?;
+    let mut rows = stmt.query(params)?;
+    let mut groups: BTreeMap<K, Vec<V>> = BTreeMap::new();
+    while let Some(row) = rows.next()? {
+        groups.entry(key(row)?).or_default().push(V::try_from(row)?);
+    }
+    Ok(groups)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::TryFromRow;
+
+    #[derive(TryFromRow, Debug, PartialEq)]
+    struct MonthlyTotal {
+        month: String,
+        total: i64,
+    }
+
+    #[derive(TryFromRow, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    struct Customer {
+        customer: String,
+        region: String,
+    }
+
+    fn setup() -> Connection {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch(
+            "create table orders( customer text, region text, month text, amount integer );
+            insert into orders values
+                ('bob', 'west', '2024-02', 5), ('alice', 'east', '2024-01', 10),
+                ('alice', 'east', '2024-01', 2), ('alice', 'east', '2024-03', 7);",
+        )
+        .expect("Failed to set up database");
+        db
+    }
+
+    const SQL: &str = "select customer, region, month, sum(amount) as total from orders
+        group by customer, region, month order by month";
+
+    #[test]
+    fn group_by_column() {
+        let db = setup();
+        let res = query_grouped::<String, MonthlyTotal, _>(&db, SQL, ());
+        assert!(res.is_ok(), "Failed to query groups: {:?}", res);
+        let groups = res.unwrap();
+        assert_eq!(groups.keys().collect::<Vec<_>>(), vec!["alice", "bob"]);
+        assert_eq!(
+            groups["alice"],
+            vec![
+                MonthlyTotal {
+                    month: "2024-01".into(),
+                    total: 12
+                },
+                MonthlyTotal {
+                    month: "2024-03".into(),
+                    total: 7
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn group_by_row() {
+        let db = setup();
+        let res = query_grouped_rows::<Customer, MonthlyTotal, _>(&db, SQL, ());
+        assert!(res.is_ok(), "Failed to query groups: {:?}", res);
+        let groups = res.unwrap();
+        let bob = Customer {
+            customer: "bob".into(),
+            region: "west".into(),
+        };
+        assert_eq!(groups.len(), 2);
+        assert_eq!(
+            groups[&bob],
+            vec![MonthlyTotal {
+                month: "2024-02".into(),
+                total: 5
+            }]
+        );
+    }
+}
@@ -0,0 +1,435 @@
+use rusqlite::{types::Value, Connection, Row, ToSql};
+
+use crate::{execute::to_value, util::quote_ident};
+
+pub mod cte;
+pub mod group;
+pub mod order;
+pub mod window;
+
+pub use cte::{Hierarchy, Node};
+pub use group::{query_grouped, query_grouped_rows};
+pub use order::{OrderBy, SortKeys};
+pub use window::{Computed, Window};
+
+/// A piece of SQL together with the values of its positional (`?`)
+/// parameters, so fragments can be combined without counting placeholders.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fragment {
+    sql: String,
+    params: Vec<Value>,
+}
+
+impl Fragment {
+    /// The most parameters a single fragment expands to. SQLite builds
+    /// before 3.32 limit statements to 999 parameters.
+    pub const MAX_PARAMS: usize = 999;
+
+    pub fn new(sql: &str, params: Vec<Value>) -> Self {
+        Self {
+            sql: sql.to_string(),
+            params,
+        }
+    }
+    /// A fragment without parameters.
+    pub fn raw(sql: &str) -> Self {
+        Self::new(sql, vec![])
+    }
+
+    pub fn sql(&self) -> &str {
+        &self.sql
+    }
+    /// The parameters, for passing to `execute`, `query_all` and the like.
+    pub fn params(&self) -> Vec<&dyn ToSql> {
+        self.params.iter().map(|p| p as &dyn ToSql).collect()
+    }
+    pub fn into_parts(self) -> (String, Vec<Value>) {
+        (self.sql, self.params)
+    }
+
+    /// Both conditions.
+    pub fn and(self, other: Fragment) -> Self {
+        self.join("and", other)
+    }
+    /// Either condition.
+    pub fn or(self, other: Fragment) -> Self {
+        self.join("or", other)
+    }
+    fn join(mut self, op: &str, other: Fragment) -> Self {
+        self.sql = format!("({}) {} ({})", self.sql, op, other.sql);
+        self.params.extend(other.params);
+        self
+    }
+}
+
+/// `column in (?, ?, ...)` with a parameter per value. Lists longer than
+/// `Fragment::MAX_PARAMS` are instead passed as a single JSON array read
+/// with `json_each`, which can't hold BLOBs; use `in_list_chunks` for those.
+/// `column` is a SQL expression.
+pub fn in_list<T: ToSql>(column: &str, values: &[T]) -> rusqlite::Result<Fragment> {
+    let params = values
+        .iter()
+        .map(|v| v.to_sql().map(to_value))
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    if params.len() <= Fragment::MAX_PARAMS {
+        return Ok(Fragment::new(
+            &format!("{} in ({})", column, vec!["?"; params.len()].join(", ")),
+            params,
+        ));
+    }
+
+    let json = params
+        .into_iter()
+        .map(|v| match v {
+            Value::Null => Ok(serde_json::Value::Null),
+            Value::Integer(i) => Ok(i.into()),
+            Value::Real(f) => Ok(f.into()),
+            Value::Text(s) => Ok(s.into()),
+            Value::Blob(_) => Err(rusqlite::Error::ToSqlConversionFailure(
+                "BLOBs can't be passed in a JSON array".into(),
+            )),
+        })
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(Fragment::new(
+        &format!("{} in (select value from json_each(?))", column),
+        vec![Value::Text(serde_json::Value::Array(json).to_string())],
+    ))
+}
+/// `in_list` conditions for consecutive chunks of at most `chunk_size`
+/// values, for running a statement once per chunk.
+pub fn in_list_chunks<T: ToSql>(
+    column: &str,
+    values: &[T],
+    chunk_size: usize,
+) -> rusqlite::Result<Vec<Fragment>> {
+    values
+        .chunks(chunk_size.clamp(1, Fragment::MAX_PARAMS))
+        .map(|chunk| in_list(column, chunk))
+        .collect()
+}
+
+/// The escape character used by the LIKE helpers.
+pub const LIKE_ESCAPE: char = '\\';
+
+/// Escape `s` for use in a LIKE pattern with `ESCAPE '\'`, so `%` and `_`
+/// match literally.
+pub fn escape_like(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '%' | '_' | LIKE_ESCAPE) {
+            escaped.push(LIKE_ESCAPE);
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+/// Escape `s` for use in a GLOB pattern, so `*`, `?` and `[` match
+/// literally. GLOB has no `ESCAPE` clause, so they're wrapped in brackets.
+pub fn escape_glob(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '*' | '?' | '[' => {
+                escaped.push('[');
+                escaped.push(c);
+                escaped.push(']');
+            }
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn like(column: &str, pattern: String) -> Fragment {
+    Fragment::new(
+        &format!("{} like ? escape '{}'", column, LIKE_ESCAPE),
+        vec![Value::Text(pattern)],
+    )
+}
+/// `column` contains `needle`, matched literally. Like all LIKE matches,
+/// this ignores the case of ASCII letters.
+pub fn contains(column: &str, needle: &str) -> Fragment {
+    like(column, format!("%{}%", escape_like(needle)))
+}
+/// `column` starts with `prefix`, matched literally and ignoring ASCII case.
+pub fn starts_with(column: &str, prefix: &str) -> Fragment {
+    like(column, format!("{}%", escape_like(prefix)))
+}
+/// `column` ends with `suffix`, matched literally and ignoring ASCII case.
+pub fn ends_with(column: &str, suffix: &str) -> Fragment {
+    like(column, format!("%{}", escape_like(suffix)))
+}
+
+/// A builder for `SELECT` statements over one table. Column and condition
+/// arguments are SQL expressions; the table name is quoted.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Select {
+    table: String,
+    columns: Vec<String>,
+    filters: Vec<Fragment>,
+    order_by: Vec<String>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+impl Select {
+    pub fn from(table: &str) -> Self {
+        Self {
+            table: table.to_string(),
+            columns: vec![],
+            filters: vec![],
+            order_by: vec![],
+            limit: None,
+            offset: None,
+        }
+    }
+    /// Select these columns rather than `*`.
+    pub fn columns(mut self, columns: &[&str]) -> Self {
+        self.columns.extend(columns.iter().map(|c| c.to_string()));
+        self
+    }
+    /// Also select `expr` (eg a window function) into the column read by
+    /// `Computed`, after all columns if no others were chosen.
+    pub fn computed(mut self, expr: &str) -> Self {
+        if self.columns.is_empty() {
+            self.columns.push("*".to_string());
+        }
+        self.columns.push(format!(
+            "{} as {}",
+            expr,
+            quote_ident(window::COMPUTED_COLUMN)
+        ));
+        self
+    }
+    /// Only rows matching `condition`, in addition to any other filters.
+    pub fn filter(mut self, condition: Fragment) -> Self {
+        self.filters.push(condition);
+        self
+    }
+    /// Only rows where `column` is one of `values`; see `in_list`.
+    pub fn where_in<T: ToSql>(self, column: &str, values: &[T]) -> rusqlite::Result<Self> {
+        Ok(self.filter(in_list(column, values)?))
+    }
+    /// Only rows where `column` contains `needle`; see `contains`.
+    pub fn contains(self, column: &str, needle: &str) -> Self {
+        self.filter(contains(column, needle))
+    }
+    /// Only rows where `column` starts with `prefix`; see `starts_with`.
+    pub fn starts_with(self, column: &str, prefix: &str) -> Self {
+        self.filter(starts_with(column, prefix))
+    }
+    /// Only rows where `column` ends with `suffix`; see `ends_with`.
+    pub fn ends_with(self, column: &str, suffix: &str) -> Self {
+        self.filter(ends_with(column, suffix))
+    }
+    pub fn order_by(mut self, expr: &str) -> Self {
+        self.order_by.push(expr.to_string());
+        self
+    }
+    /// Sort by a validated `OrderBy`, after any previous sort terms.
+    pub fn order(mut self, order: &OrderBy) -> Self {
+        if !order.terms().is_empty() {
+            self.order_by.push(order.sql());
+        }
+        self
+    }
+    pub fn limit(mut self, limit: i64) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+    pub fn offset(mut self, offset: i64) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    /// The statement and its parameters.
+    pub fn build(&self) -> Fragment {
+        let mut sql = format!(
+            "select {} from {}",
+            if self.columns.is_empty() {
+                "*".to_string()
+            } else {
+                self.columns.join(", ")
+            },
+            quote_ident(&self.table)
+        );
+        let mut params = vec![];
+        for (i, filter) in self.filters.iter().enumerate() {
+            sql.push_str(if i == 0 { " where (" } else { " and (" });
+            sql.push_str(&filter.sql);
+            sql.push(')');
+            params.extend(filter.params.iter().cloned());
+        }
+        if !self.order_by.is_empty() {
+            sql.push_str(" order by ");
+            sql.push_str(&self.order_by.join(", "));
+        }
+        match (self.limit, self.offset) {
+            (Some(limit), Some(offset)) => {
+                sql.push_str(&format!(" limit {} offset {}", limit, offset))
+            }
+            (Some(limit), None) => sql.push_str(&format!(" limit {}", limit)),
+            (None, Some(offset)) => sql.push_str(&format!(" limit -1 offset {}", offset)),
+            (None, None) => {}
+        }
+        Fragment { sql, params }
+    }
+
+    pub fn query_all<T>(&self, conn: &Connection) -> rusqlite::Result<Vec<T>>
+    where
+        for<'r, 'stmt> T: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error>,
+    {
+        let query = self.build();
+        let mut stmt = conn.prepare(&query.sql)?;
+        let rows = stmt
+            .query_map(&*query.params(), |row| T::try_from(row))?
+            .collect();
+        rows
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::TryFromRow;
+
+    #[derive(TryFromRow, Debug, PartialEq)]
+    struct Item {
+        id: i64,
+        name: String,
+    }
+
+    fn setup() -> Connection {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute(
+            "create table items( id integer primary key, name text )",
+            (),
+        )
+        .expect("Failed to create table");
+        let mut stmt = db.prepare("insert into items(name) values (?)").unwrap();
+        for i in 1..=2000 {
+            stmt.execute((format!("item {}", i),)).unwrap();
+        }
+        drop(stmt);
+        db
+    }
+
+    #[test]
+    fn expand_in_list() {
+        let db = setup();
+        let res = in_list("id", &[3, 1, 2]);
+        assert!(res.is_ok(), "Failed to build list: {:?}", res);
+        let fragment = res.unwrap().and(Fragment::new(
+            "name <> ?",
+            vec![Value::from("item 2".to_string())],
+        ));
+        assert_eq!(fragment.sql(), "(id in (?, ?, ?)) and (name <> ?)");
+
+        let res: rusqlite::Result<Vec<Item>> = Select::from("items")
+            .filter(fragment)
+            .order_by("id")
+            .query_all(&db);
+        assert!(res.is_ok(), "Failed to query rows: {:?}", res);
+        let ids: Vec<_> = res.unwrap().iter().map(|i| i.id).collect();
+        assert_eq!(ids, vec![1, 3]);
+    }
+
+    #[test]
+    fn long_lists() {
+        let db = setup();
+        let ids: Vec<i64> = (1..=1500).collect();
+        let select = Select::from("items")
+            .columns(&["count(*)"])
+            .where_in("id", &ids)
+            .unwrap();
+        assert_eq!(
+            select.build().params().len(),
+            1,
+            "List was not passed as JSON"
+        );
+        let query = select.build();
+        let count: i64 = db
+            .query_row(query.sql(), &*query.params(), |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 1500);
+
+        let chunks = in_list_chunks("id", &ids, 1000).unwrap();
+        assert_eq!(chunks.len(), 2);
+        let mut deleted = 0;
+        for chunk in chunks {
+            deleted += db
+                .execute(
+                    &format!("delete from items where {}", chunk.sql()),
+                    &*chunk.params(),
+                )
+                .unwrap();
+        }
+        assert_eq!(deleted, 1500);
+        assert!(in_list("id", &vec![vec![0u8]; 1000]).is_err());
+    }
+
+    #[test]
+    fn build_select() {
+        let query = Select::from("my items")
+            .columns(&["id", "name"])
+            .filter(Fragment::raw("id > 1"))
+            .filter(Fragment::new(
+                "name = ?",
+                vec![Value::from("a".to_string())],
+            ))
+            .order_by("name desc")
+            .limit(10)
+            .offset(20)
+            .build();
+        assert_eq!(
+            query.sql(),
+            "select id, name from \"my items\" where (id > 1) and (name = ?) \
+            order by name desc limit 10 offset 20"
+        );
+        assert_eq!(query.params().len(), 1);
+        assert_eq!(
+            Select::from("items").offset(5).build().sql(),
+            "select * from \"items\" limit -1 offset 5"
+        );
+    }
+
+    #[test]
+    fn match_patterns_literally() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch(
+            "create table files( id integer primary key, name text );
+            insert into files(name) values
+                ('100% done'), ('100 done'), ('a_b'), ('axb'), ('C:\\tmp'), ('f*o?'), ('fxoy');",
+        )
+        .expect("Failed to set up database");
+        let ids = |select: Select| -> Vec<i64> {
+            let query = select.columns(&["id"]).order_by("id").build();
+            let mut stmt = db.prepare(query.sql()).unwrap();
+            let ids = stmt
+                .query_map(&*query.params(), |row| row.get(0))
+                .unwrap()
+                .collect::<rusqlite::Result<_>>()
+                .unwrap();
+            ids
+        };
+
+        assert_eq!(ids(Select::from("files").contains("name", "0%")), vec![1]);
+        assert_eq!(
+            ids(Select::from("files").starts_with("name", "A_")),
+            vec![3]
+        );
+        assert_eq!(
+            ids(Select::from("files").ends_with("name", ":\\TMP")),
+            vec![5]
+        );
+
+        let glob = Fragment::new(
+            "name glob ?",
+            vec![Value::Text(format!("{}*", escape_glob("f*o?")))],
+        );
+        assert_eq!(ids(Select::from("files").filter(glob)), vec![6]);
+        assert_eq!(escape_like("50%_\\"), "50\\%\\_\\\\");
+        assert_eq!(escape_glob("[a]*"), "[[]a][*]");
+    }
+}
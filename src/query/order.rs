@@ -0,0 +1,151 @@
+use thiserror::Error;
+
+use crate::{row::Columns, util::quote_ident};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Asc,
+    Desc,
+}
+impl Direction {
+    pub fn sql(&self) -> &'static str {
+        match self {
+            Direction::Asc => "asc",
+            Direction::Desc => "desc",
+        }
+    }
+}
+
+/// The sort keys a caller may request, each mapped to the column it sorts
+/// by. Anything else is rejected, so sort orders taken from eg a query string
+/// can't inject SQL.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SortKeys {
+    keys: Vec<(String, String)>,
+}
+
+impl SortKeys {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Allow sorting by every column `T` reads, under its own name.
+    pub fn of<T: Columns>() -> Self {
+        T::COLUMNS
+            .iter()
+            .fold(Self::new(), |keys, column| keys.allow(column, column))
+    }
+    /// Allow sorting by `column` when `key` is requested.
+    pub fn allow(mut self, key: &str, column: &str) -> Self {
+        self.keys.push((key.to_string(), column.to_string()));
+        self
+    }
+
+    /// Parse a comma separated list of sort keys, each optionally prefixed
+    /// with `-` or followed by `:asc` or `:desc` to choose the direction, eg
+    /// `-created_at,name`.
+    pub fn parse(&self, spec: &str) -> Result<OrderBy, Error> {
+        let mut order = OrderBy::default();
+        for term in spec.split(',').map(str::trim) {
+            let (key, direction) = match term.split_once(':') {
+                Some((key, "asc")) => (key, Direction::Asc),
+                Some((key, "desc")) => (key, Direction::Desc),
+                Some(_) => return Err(Error::InvalidDirection(term.to_string())),
+                None => match term.strip_prefix('-') {
+                    Some(key) => (key, Direction::Desc),
+                    None => (term, Direction::Asc),
+                },
+            };
+            let column = self
+                .keys
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, c)| c)
+                .ok_or_else(|| Error::UnknownKey(key.to_string()))?;
+            order.terms.push((column.clone(), direction));
+        }
+        Ok(order)
+    }
+}
+
+/// A validated sort order, made by `SortKeys::parse`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OrderBy {
+    terms: Vec<(String, Direction)>,
+}
+
+impl OrderBy {
+    /// The columns and directions, in order of precedence.
+    pub fn terms(&self) -> &[(String, Direction)] {
+        &self.terms
+    }
+    /// The terms of an `ORDER BY` clause, eg `"created_at" desc, "name" asc`.
+    pub fn sql(&self) -> String {
+        self.terms
+            .iter()
+            .map(|(column, direction)| format!("{} {}", quote_ident(column), direction.sql()))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    #[error("Unknown sort key {0:?}")]
+    UnknownKey(String),
+    #[error("Invalid sort direction in {0:?}")]
+    InvalidDirection(String),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use rusqlite::Connection;
+
+    use crate::{query::Select, TryFromRow};
+
+    #[derive(TryFromRow, Debug, PartialEq)]
+    struct User {
+        name: String,
+        age: i64,
+    }
+
+    #[test]
+    fn parse_sort_orders() {
+        let keys = SortKeys::of::<User>().allow("oldest", "age");
+        let res = keys.parse("-age, name");
+        assert!(res.is_ok(), "Failed to parse sort order: {:?}", res);
+        assert_eq!(res.unwrap().sql(), "\"age\" desc, \"name\" asc");
+        assert_eq!(
+            keys.parse("oldest:desc").unwrap().terms(),
+            &[("age".to_string(), Direction::Desc)]
+        );
+
+        for (spec, err) in [
+            ("password", Error::UnknownKey("password".into())),
+            (
+                "name; drop table users",
+                Error::UnknownKey("name; drop table users".into()),
+            ),
+            (
+                "name:sideways",
+                Error::InvalidDirection("name:sideways".into()),
+            ),
+            ("", Error::UnknownKey("".into())),
+        ] {
+            assert_eq!(keys.parse(spec), Err(err));
+        }
+
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch(
+            "create table users( name text, age integer );
+            insert into users values ('b', 30), ('a', 30), ('c', 20);",
+        )
+        .unwrap();
+        let res: rusqlite::Result<Vec<User>> = Select::from("users")
+            .order(&keys.parse("-age,name").unwrap())
+            .query_all(&db);
+        let names: Vec<_> = res.unwrap().into_iter().map(|u| u.name).collect();
+        assert_eq!(names, vec!["a", "b", "c"]);
+    }
+}
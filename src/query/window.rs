@@ -0,0 +1,193 @@
+use rusqlite::{types::FromSql, Row};
+
+use super::order::{Direction, OrderBy};
+use crate::util::quote_ident;
+
+/// The name of the column holding the value computed alongside each row, as
+/// read by `Computed`.
+pub const COMPUTED_COLUMN: &str = "computed";
+
+/// The `OVER` clause of a window function: the rows are split into
+/// partitions, and ordered within each partition. Columns are quoted.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Window {
+    partition_by: Vec<String>,
+    order_by: Vec<String>,
+}
+
+impl Window {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn partition_by(mut self, column: &str) -> Self {
+        self.partition_by.push(quote_ident(column));
+        self
+    }
+    pub fn order_by(mut self, column: &str, direction: Direction) -> Self {
+        self.order_by
+            .push(format!("{} {}", quote_ident(column), direction.sql()));
+        self
+    }
+    /// Order by a validated `OrderBy`, after any previous terms.
+    pub fn order(mut self, order: &OrderBy) -> Self {
+        if !order.terms().is_empty() {
+            self.order_by.push(order.sql());
+        }
+        self
+    }
+
+    /// The `over (...)` clause, with an optional frame specification.
+    pub fn over_sql(&self, frame: Option<&str>) -> String {
+        let mut clauses = vec![];
+        if !self.partition_by.is_empty() {
+            clauses.push(format!("partition by {}", self.partition_by.join(", ")));
+        }
+        if !self.order_by.is_empty() {
+            clauses.push(format!("order by {}", self.order_by.join(", ")));
+        }
+        clauses.extend(frame.map(|f| f.to_string()));
+        format!("over ({})", clauses.join(" "))
+    }
+}
+
+/// The position of each row in its partition, starting from 1.
+pub fn row_number(window: &Window) -> String {
+    format!("row_number() {}", window.over_sql(None))
+}
+/// The rank of each row in its partition, with gaps after ties.
+pub fn rank(window: &Window) -> String {
+    format!("rank() {}", window.over_sql(None))
+}
+/// The value of `column` `offset` rows earlier in the partition, or NULL.
+pub fn lag(column: &str, offset: u32, window: &Window) -> String {
+    format!(
+        "lag({}, {}) {}",
+        quote_ident(column),
+        offset,
+        window.over_sql(None)
+    )
+}
+/// The value of `column` `offset` rows later in the partition, or NULL.
+pub fn lead(column: &str, offset: u32, window: &Window) -> String {
+    format!(
+        "lead({}, {}) {}",
+        quote_ident(column),
+        offset,
+        window.over_sql(None)
+    )
+}
+/// The sum of `column` over the partition up to and including each row.
+/// Rows with equal sort keys are still summed one at a time.
+pub fn running_total(column: &str, window: &Window) -> String {
+    format!(
+        "sum({}) {}",
+        quote_ident(column),
+        window.over_sql(Some("rows between unbounded preceding and current row"))
+    )
+}
+
+/// A row read into `T`, along with a value `V` computed for it (eg by a
+/// window function) in the column named `COMPUTED_COLUMN`. See
+/// `Select::computed`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Computed<T, V> {
+    pub row: T,
+    pub value: V,
+}
+
+impl<'stmt, T, V> TryFrom<&Row<'stmt>> for Computed<T, V>
+where
+    for<'r> T: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error>,
+    V: FromSql,
+{
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row<'stmt>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            row: T::try_from(row)?,
+            value: row.get(COMPUTED_COLUMN)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use rusqlite::Connection;
+
+    use crate::{query::Select, TryFromRow};
+
+    #[derive(TryFromRow, Debug, PartialEq)]
+    struct Sale {
+        region: String,
+        day: i64,
+        amount: i64,
+    }
+
+    fn setup() -> Connection {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch(
+            "create table sales( region text, day integer, amount integer );
+            insert into sales values
+                ('east', 1, 10), ('east', 2, 5), ('east', 2, 7), ('west', 1, 3), ('west', 3, 4);",
+        )
+        .expect("Failed to set up database");
+        db
+    }
+
+    fn query<V: FromSql>(db: &Connection, expr: &str) -> Vec<(i64, V)> {
+        let res: rusqlite::Result<Vec<Computed<Sale, V>>> = Select::from("sales")
+            .computed(expr)
+            .order_by("region, day, amount")
+            .query_all(db);
+        assert!(res.is_ok(), "Failed to query rows: {:?}", res.err());
+        res.unwrap()
+            .into_iter()
+            .map(|c| (c.row.amount, c.value))
+            .collect()
+    }
+
+    #[test]
+    fn window_functions() {
+        let db = setup();
+        let by_region = Window::new()
+            .partition_by("region")
+            .order_by("day", Direction::Asc)
+            .order_by("amount", Direction::Asc);
+
+        assert_eq!(
+            query::<i64>(&db, &row_number(&by_region)),
+            vec![(10, 1), (5, 2), (7, 3), (3, 1), (4, 2)]
+        );
+        assert_eq!(
+            query::<i64>(&db, &running_total("amount", &by_region)),
+            vec![(10, 10), (5, 15), (7, 22), (3, 3), (4, 7)]
+        );
+        assert_eq!(
+            query::<Option<i64>>(&db, &lag("amount", 1, &by_region)),
+            vec![
+                (10, None),
+                (5, Some(10)),
+                (7, Some(5)),
+                (3, None),
+                (4, Some(3))
+            ]
+        );
+        assert_eq!(
+            query::<Option<i64>>(&db, &lead("amount", 1, &by_region)),
+            vec![
+                (10, Some(5)),
+                (5, Some(7)),
+                (7, None),
+                (3, Some(4)),
+                (4, None)
+            ]
+        );
+        let by_day = Window::new().order_by("day", Direction::Asc);
+        assert_eq!(
+            query::<i64>(&db, &rank(&by_day)),
+            vec![(10, 1), (5, 3), (7, 3), (3, 1), (4, 5)]
+        );
+    }
+}
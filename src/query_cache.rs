@@ -0,0 +1,366 @@
+use std::{
+    any::{Any, TypeId},
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+    sync::{Arc, Mutex},
+};
+
+use rusqlite::{
+    hooks::Action,
+    types::{ToSqlOutput, Value, ValueRef},
+    Connection, Row, ToSql,
+};
+
+use crate::util::quote_ident;
+
+/// A table, as its database (eg `main`) and name.
+type TableName = (String, String);
+
+/// A read-through cache of query results, for read-mostly applications
+/// which run the same queries again and again (eg on every UI refresh).
+///
+/// Results are keyed by the query and its parameters, and invalidated when
+/// a table the query reads is changed: through the connection's update hook
+/// for changes made on it, which the cache takes over, and by clearing
+/// everything when another connection commits (detected through `PRAGMA
+/// data_version`) or the schema changes. The tables a query reads are found
+/// once per query, from the root pages it opens in its `EXPLAIN` output.
+///
+/// The text of each query is interned, so every result of a query shares
+/// it. Nothing is cached or served while a transaction is open, since the
+/// update hook doesn't report changes undone by a rollback.
+pub struct QueryCache<'conn> {
+    conn: &'conn Connection,
+    /// The tables changed since the cache was last used, from the update
+    /// hook.
+    changed: Arc<Mutex<HashSet<TableName>>>,
+    state: RefCell<State>,
+}
+
+#[derive(Default)]
+struct State {
+    data_version: i64,
+    schema_version: i64,
+    /// The interned text of each query and the tables it reads, or `None` if
+    /// those couldn't all be found (eg for virtual tables).
+    queries: HashMap<Rc<str>, Option<Rc<HashSet<TableName>>>>,
+    results: HashMap<Key, Entry>,
+}
+
+#[derive(PartialEq, Eq, Hash)]
+struct Key {
+    sql: Rc<str>,
+    params: Vec<Param>,
+    /// The type the rows were read as.
+    rows: TypeId,
+}
+
+/// A parameter value, in a form which can be hashed.
+#[derive(PartialEq, Eq, Hash)]
+enum Param {
+    Null,
+    Integer(i64),
+    Real(u64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+impl Param {
+    fn new(param: &dyn ToSql) -> rusqlite::Result<Self> {
+        let output = param.to_sql()?;
+        let value = match &output {
+            ToSqlOutput::Borrowed(value) => *value,
+            ToSqlOutput::Owned(value) => ValueRef::from(value),
+            _ => {
+                return Err(rusqlite::Error::ToSqlConversionFailure(
+                    "unsupported parameter for a cached query".into(),
+                ))
+            }
+        };
+        Ok(match value {
+            ValueRef::Null => Param::Null,
+            ValueRef::Integer(i) => Param::Integer(i),
+            ValueRef::Real(r) => Param::Real(r.to_bits()),
+            ValueRef::Text(t) => Param::Text(String::from_utf8_lossy(t).into_owned()),
+            ValueRef::Blob(b) => Param::Blob(b.to_vec()),
+        })
+    }
+}
+
+struct Entry {
+    tables: Option<Rc<HashSet<TableName>>>,
+    /// An `Rc<Vec<T>>` of the rows.
+    rows: Rc<dyn Any>,
+}
+
+impl<'conn> QueryCache<'conn> {
+    /// Cache the results of queries on `conn`, replacing its update hook.
+    pub fn new(conn: &'conn Connection) -> Self {
+        let changed = Arc::new(Mutex::new(HashSet::new()));
+        let hook_changed = Arc::clone(&changed);
+        conn.update_hook(Some(move |_: Action, db: &str, table: &str, _| {
+            if let Ok(mut changed) = hook_changed.lock() {
+                changed.insert((db.to_string(), table.to_string()));
+            }
+        }));
+        Self {
+            conn,
+            changed,
+            state: RefCell::new(State::default()),
+        }
+    }
+
+    /// The rows of a query, from the cache if it's still valid.
+    pub fn query_all<T>(&self, sql: &str, params: &[&dyn ToSql]) -> rusqlite::Result<Vec<T>>
+    where
+        T: Clone + 'static,
+        for<'r, 'stmt> T: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error>,
+    {
+        if !self.conn.is_autocommit() {
+            return self.read(sql, params);
+        }
+        self.invalidate_changed()?;
+        let mut state = self.state.borrow_mut();
+        let (sql, tables) = match state.queries.get_key_value(sql) {
+            Some((sql, tables)) => (Rc::clone(sql), tables.clone()),
+            None => {
+                let sql: Rc<str> = sql.into();
+                let tables = self.tables_read(&sql)?.map(Rc::new);
+                state.queries.insert(Rc::clone(&sql), tables.clone());
+                (sql, tables)
+            }
+        };
+        let key = Key {
+            sql,
+            params: params
+                .iter()
+                .map(|p| Param::new(*p))
+                .collect::<rusqlite::Result<_>>()?,
+            rows: TypeId::of::<T>(),
+        };
+        if let Some(rows) = state
+            .results
+            .get(&key)
+            .and_then(|entry| entry.rows.downcast_ref::<Vec<T>>())
+        {
+            return Ok(rows.clone());
+        }
+
+        let rows = self.read(&key.sql, params)?;
+        state.results.insert(
+            key,
+            Entry {
+                tables,
+                rows: Rc::new(rows.clone()),
+            },
+        );
+        Ok(rows)
+    }
+    /// The first row of a query, failing with `QueryReturnedNoRows` if
+    /// there is none. Every row is read and cached, as for `query_all`.
+    pub fn query_one<T>(&self, sql: &str, params: &[&dyn ToSql]) -> rusqlite::Result<T>
+    where
+        T: Clone + 'static,
+        for<'r, 'stmt> T: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error>,
+    {
+        self.query_optional(sql, params)?
+            .ok_or(rusqlite::Error::QueryReturnedNoRows)
+    }
+    pub fn query_optional<T>(&self, sql: &str, params: &[&dyn ToSql]) -> rusqlite::Result<Option<T>>
+    where
+        T: Clone + 'static,
+        for<'r, 'stmt> T: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error>,
+    {
+        Ok(self.query_all(sql, params)?.into_iter().next())
+    }
+
+    /// Drop every cached result, eg after changes the cache can't detect.
+    pub fn invalidate(&self) {
+        self.state.borrow_mut().results.clear();
+    }
+    /// The number of cached results.
+    pub fn len(&self) -> usize {
+        self.state.borrow().results.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn read<T>(&self, sql: &str, params: &[&dyn ToSql]) -> rusqlite::Result<Vec<T>>
+    where
+        for<'r, 'stmt> T: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error>,
+    {
+        let mut stmt = self.conn.prepare_cached(sql)?;
+        let rows = stmt.query_map(params, |row| T::try_from(row))?.collect();
+        rows
+    }
+
+    /// Drop the results which may have changed since the cache was last
+    /// used.
+    fn invalidate_changed(&self) -> rusqlite::Result<()> {
+        let pragma = |name: &str| -> rusqlite::Result<i64> {
+            self.conn
+                .query_row(&format!("pragma {}", name), (), |row| row.get(0))
+        };
+        let data_version = pragma("data_version")?;
+        let schema_version = pragma("schema_version")?;
+        let changed = std::mem::take(
+            &mut *self
+                .changed
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner()),
+        );
+
+        let mut state = self.state.borrow_mut();
+        if state.schema_version != schema_version {
+            // Tables may have been replaced, so their root pages reused.
+            state.queries.clear();
+            state.results.clear();
+        } else if state.data_version != data_version {
+            state.results.clear();
+        } else if !changed.is_empty() {
+            state.results.retain(|_, entry| match &entry.tables {
+                Some(tables) => tables.is_disjoint(&changed),
+                None => false,
+            });
+        }
+        state.data_version = data_version;
+        state.schema_version = schema_version;
+        Ok(())
+    }
+
+    /// The tables `sql` reads, from the root pages its `EXPLAIN` output
+    /// opens, or `None` if it reads anything else (eg a virtual table).
+    fn tables_read(&self, sql: &str) -> rusqlite::Result<Option<HashSet<TableName>>> {
+        let mut databases = HashMap::new();
+        let mut stmt = self.conn.prepare("pragma database_list")?;
+        let mut rows = stmt.query(())?;
+        while let Some(row) = rows.next()? {
+            databases.insert(row.get::<_, i64>(0)?, row.get::<_, String>(1)?);
+        }
+
+        // Parameters are left unbound, as NULL.
+        let mut stmt = self.conn.prepare(&format!("explain {}", sql))?;
+        let mut rows = stmt.raw_query();
+        let mut tables = HashSet::new();
+        while let Some(row) = rows.next()? {
+            let opcode: String = row.get("opcode")?;
+            match opcode.as_str() {
+                "OpenRead" | "ReopenIdx" => {}
+                "VOpen" => return Ok(None),
+                _ => continue,
+            }
+            let root_page: i64 = row.get("p2")?;
+            let database = match databases.get(&row.get::<_, i64>("p3")?) {
+                Some(database) => database,
+                None => return Ok(None),
+            };
+            let table: Option<Value> = self
+                .conn
+                .query_row(
+                    &format!(
+                        "select tbl_name from {}.sqlite_master where rootpage = ?",
+                        quote_ident(database)
+                    ),
+                    (root_page,),
+                    |row| row.get(0),
+                )
+                .ok();
+            match table {
+                Some(Value::Text(table)) => tables.insert((database.clone(), table)),
+                // The schema table itself, whose changes are caught by the
+                // schema version.
+                None if root_page == 1 => continue,
+                _ => return Ok(None),
+            };
+        }
+        Ok(Some(tables))
+    }
+}
+
+impl Drop for QueryCache<'_> {
+    fn drop(&mut self) {
+        self.conn.update_hook(None::<fn(Action, &str, &str, i64)>);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::TryFromRow;
+
+    #[derive(TryFromRow, Clone, Debug, PartialEq)]
+    struct Item {
+        name: String,
+        price: i64,
+    }
+
+    #[test]
+    fn invalidate_by_table() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch(
+            "create table item( name text, price integer );
+            create table audit( message text );
+            insert into item values ('pen', 2), ('book', 12);",
+        )
+        .expect("Failed to create tables");
+        let cache = QueryCache::new(&db);
+        let sql = "select name, price from item where price > ? order by name";
+
+        let res = cache.query_all::<Item>(sql, &[&1]);
+        assert!(res.is_ok(), "Failed to query rows: {:?}", res);
+        assert_eq!(res.unwrap().len(), 2);
+        assert_eq!(cache.query_all::<Item>(sql, &[&10]).unwrap().len(), 1);
+        assert_eq!(cache.len(), 2);
+
+        // Writes to other tables keep the results.
+        db.execute("insert into audit values ('read')", ()).unwrap();
+        assert_eq!(cache.query_all::<Item>(sql, &[&1]).unwrap().len(), 2);
+        assert_eq!(cache.len(), 2);
+
+        db.execute("update item set price = 20 where name = 'pen'", ())
+            .unwrap();
+        assert_eq!(
+            cache.query_one::<Item>(sql, &[&10]).unwrap(),
+            Item {
+                name: "book".into(),
+                price: 12
+            }
+        );
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.query_all::<Item>(sql, &[&10]).unwrap().len(), 2);
+
+        db.execute("delete from item", ()).unwrap();
+        assert_eq!(cache.query_optional::<Item>(sql, &[&1]).unwrap(), None);
+    }
+
+    #[test]
+    fn invalidate_on_other_connections_and_transactions() {
+        let dir = tempfile::tempdir().expect("Failed to create directory");
+        let path = dir.path().join("test.db");
+        let db = Connection::open(&path).expect("Failed to open connection");
+        db.execute_batch("create table item( name text, price integer )")
+            .unwrap();
+        let cache = QueryCache::new(&db);
+        let count = || {
+            cache
+                .query_all::<Item>("select * from item", &[])
+                .unwrap()
+                .len()
+        };
+        assert_eq!(count(), 0);
+
+        let other = Connection::open(&path).expect("Failed to open connection");
+        other
+            .execute("insert into item values ('pen', 2)", ())
+            .unwrap();
+        assert_eq!(count(), 1);
+
+        db.execute_batch("begin; insert into item values ('book', 12);")
+            .unwrap();
+        assert_eq!(count(), 2);
+        db.execute_batch("rollback").unwrap();
+        assert_eq!(count(), 1);
+    }
+}
@@ -0,0 +1,326 @@
+use std::{
+    collections::VecDeque,
+    fmt::{Debug, Display},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use rusqlite::{
+    types::{FromSql, FromSqlResult, ToSqlOutput, ValueRef},
+    Connection, Row, ToSql,
+};
+
+/// A parameter which can be recorded by a `QueryLog`. Parameters are logged
+/// through their `Debug` representation, so types holding secrets should
+/// mask it, as `Secret` does.
+pub trait Param: ToSql + Debug {}
+impl<T: ToSql + Debug + ?Sized> Param for T {}
+
+/// A value which is bound like `T`, but never shown by `Debug` or in a
+/// `QueryLog`.
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    pub fn new(v: T) -> Self {
+        Self(v)
+    }
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+impl<T> Debug for Secret<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Secret(***)")
+    }
+}
+impl<T: ToSql> ToSql for Secret<T> {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        self.0.to_sql()
+    }
+}
+impl<T: FromSql> FromSql for Secret<T> {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        T::column_result(value).map(Self)
+    }
+}
+
+/// Which logged parameter values are masked. Named parameters whose name
+/// contains one of the configured fragments (case-insensitively) are
+/// masked, and long values are truncated.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RedactionPolicy {
+    names: Vec<String>,
+    max_len: usize,
+}
+
+impl Default for RedactionPolicy {
+    fn default() -> Self {
+        Self {
+            names: ["password", "secret", "token", "key"]
+                .iter()
+                .map(|n| n.to_string())
+                .collect(),
+            max_len: 64,
+        }
+    }
+}
+
+impl RedactionPolicy {
+    pub const MASK: &'static str = "***";
+
+    /// A policy which masks nothing but `Secret`s.
+    pub fn none() -> Self {
+        Self {
+            names: vec![],
+            max_len: usize::MAX,
+        }
+    }
+    /// Also mask named parameters containing `fragment`.
+    pub fn redact_name(mut self, fragment: &str) -> Self {
+        self.names.push(fragment.to_lowercase());
+        self
+    }
+    /// Truncate rendered values to `max_len` characters.
+    pub fn max_len(mut self, max_len: usize) -> Self {
+        self.max_len = max_len;
+        self
+    }
+
+    /// Render a parameter for the log.
+    pub fn render(&self, name: Option<&str>, param: &dyn Param) -> String {
+        if let Some(name) = name {
+            let name = name.to_lowercase();
+            if self.names.iter().any(|n| name.contains(n.as_str())) {
+                return Self::MASK.to_string();
+            }
+        }
+        let rendered = format!("{:?}", param);
+        match rendered.char_indices().nth(self.max_len) {
+            Some((i, _)) => format!("{}...", &rendered[..i]),
+            None => rendered,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogEntry {
+    pub sql: String,
+    /// Rendered parameters, prefixed with their name if they have one.
+    pub params: Vec<String>,
+    pub elapsed: Duration,
+    pub error: Option<String>,
+}
+impl Display for LogEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}; -- [{}] {:?}",
+            self.sql,
+            self.params.join(", "),
+            self.elapsed
+        )?;
+        if let Some(error) = &self.error {
+            write!(f, " failed: {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+/// An opt-in record of the most recent statements executed through it, with
+/// parameters rendered through a `RedactionPolicy`, for attaching to bug
+/// reports. Statements executed directly on the connection are not
+/// recorded.
+#[derive(Debug)]
+pub struct QueryLog {
+    capacity: usize,
+    policy: RedactionPolicy,
+    entries: Mutex<VecDeque<LogEntry>>,
+}
+
+impl QueryLog {
+    /// A log keeping the last `capacity` statements.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            policy: RedactionPolicy::default(),
+            entries: Mutex::new(VecDeque::new()),
+        }
+    }
+    pub fn policy(mut self, policy: RedactionPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn execute(
+        &self,
+        conn: &Connection,
+        sql: &str,
+        params: &[&dyn Param],
+    ) -> rusqlite::Result<usize> {
+        let bound: Vec<&dyn ToSql> = params.iter().map(|p| *p as &dyn ToSql).collect();
+        let rendered = params
+            .iter()
+            .map(|p| self.policy.render(None, *p))
+            .collect();
+        self.record(sql, rendered, || conn.execute(sql, &*bound))
+    }
+    pub fn execute_named(
+        &self,
+        conn: &Connection,
+        sql: &str,
+        params: &[(&str, &dyn Param)],
+    ) -> rusqlite::Result<usize> {
+        let bound: Vec<(&str, &dyn ToSql)> = params
+            .iter()
+            .map(|(name, p)| (*name, *p as &dyn ToSql))
+            .collect();
+        let rendered = params
+            .iter()
+            .map(|(name, p)| format!("{}={}", name, self.policy.render(Some(name), *p)))
+            .collect();
+        self.record(sql, rendered, || conn.execute(sql, &*bound))
+    }
+    pub fn query_all<T>(
+        &self,
+        conn: &Connection,
+        sql: &str,
+        params: &[&dyn Param],
+    ) -> rusqlite::Result<Vec<T>>
+    where
+        for<'r, 'stmt> T: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error>,
+    {
+        let bound: Vec<&dyn ToSql> = params.iter().map(|p| *p as &dyn ToSql).collect();
+        let rendered = params
+            .iter()
+            .map(|p| self.policy.render(None, *p))
+            .collect();
+        self.record(sql, rendered, || {
+            let mut stmt = conn.prepare_cached(sql)?;
+            let rows = stmt.query_map(&*bound, |row| T::try_from(row))?.collect();
+            rows
+        })
+    }
+
+    /// The recorded statements, oldest first.
+    pub fn entries(&self) -> Vec<LogEntry> {
+        self.lock().iter().cloned().collect()
+    }
+    pub fn clear(&self) {
+        self.lock().clear()
+    }
+
+    fn record<T>(
+        &self,
+        sql: &str,
+        params: Vec<String>,
+        f: impl FnOnce() -> rusqlite::Result<T>,
+    ) -> rusqlite::Result<T> {
+        let start = Instant::now();
+        let res = f();
+        let entry = LogEntry {
+            sql: sql.trim().to_string(),
+            params,
+            elapsed: start.elapsed(),
+            error: res.as_ref().err().map(|e| e.to_string()),
+        };
+        let mut entries = self.lock();
+        if entries.len() >= self.capacity {
+            entries.pop_front();
+        }
+        if self.capacity > 0 {
+            entries.push_back(entry);
+        }
+        res
+    }
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<LogEntry>> {
+        // A panic while holding the lock can't leave the entries invalid.
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+impl Display for QueryLog {
+    /// Renders the log for a bug report, one statement per line.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for entry in self.lock().iter() {
+            writeln!(f, "{}", entry)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::TryFromRow;
+
+    #[derive(TryFromRow, Debug)]
+    struct User {
+        name: String,
+        password: Secret<String>,
+    }
+
+    #[test]
+    fn log_with_redaction() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute(
+            "create table users( name text, password text, api_key text )",
+            (),
+        )
+        .unwrap();
+        let log = QueryLog::new(2);
+
+        let res = log.execute(
+            &db,
+            "insert into users(name, password) values (?, ?)",
+            &[&"ada", &Secret::new("hunter2")],
+        );
+        assert!(res.is_ok(), "Failed to execute statement: {:?}", res);
+        let res = log.execute_named(
+            &db,
+            "update users set api_key = :api_key where name = :name",
+            &[(":api_key", &"abc123"), (":name", &"ada")],
+        );
+        assert!(res.is_ok(), "Failed to execute statement: {:?}", res);
+        let res: rusqlite::Result<Vec<User>> = log.query_all(
+            &db,
+            "select name, password from users where name = ?",
+            &[&"ada"],
+        );
+        assert_eq!(res.unwrap()[0].password.expose(), "hunter2");
+        assert!(log.execute(&db, "select * from missing", &[]).is_err());
+
+        let entries = log.entries();
+        assert_eq!(entries.len(), 2, "Log is not bounded");
+        assert_eq!(entries[0].params, vec!["\"ada\""]);
+        assert!(entries[1].error.is_some());
+
+        log.clear();
+        log.execute(
+            &db,
+            "insert into users(name, password) values (?, ?)",
+            &[&"bob", &Secret::new("hunter2")],
+        )
+        .unwrap();
+        log.execute_named(
+            &db,
+            "update users set api_key = :api_key where name = :name",
+            &[(":api_key", &"abc123"), (":name", &"bob")],
+        )
+        .unwrap();
+        let report = log.to_string();
+        assert!(!report.contains("hunter2"), "Secret was logged: {}", report);
+        assert!(!report.contains("abc123"), "Key was logged: {}", report);
+        assert!(report.contains(":name=\"bob\""), "{}", report);
+    }
+
+    #[test]
+    fn truncate_long_values() {
+        let policy = RedactionPolicy::none().max_len(5);
+        assert_eq!(policy.render(None, &"abcdefgh"), "\"abcd...");
+        assert_eq!(policy.render(Some(":password"), &1), "1");
+    }
+}
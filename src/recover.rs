@@ -0,0 +1,426 @@
+//! Salvaging what can still be read from a corrupt database.
+//!
+//! SQLite's recovery extension (`sqlite3_recover`) isn't part of the
+//! amalgamation rusqlite builds, so this copies the database table by table
+//! instead, skipping the rows it can't read. That recovers less than the
+//! extension would from a badly damaged file (eg rows on pages no longer
+//! linked from their table), but everything which can be read through
+//! SQLite itself.
+
+use std::path::{Path, PathBuf};
+
+use rusqlite::{params_from_iter, types::Value, Connection, OpenFlags, Statement};
+use thiserror::Error;
+
+use crate::{crud::insert_sql, util::quote_ident};
+
+/// What was salvaged of a table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TableRecovery {
+    pub name: String,
+    /// The number of rows copied to the new database.
+    pub recovered: u64,
+    /// The number of rows which couldn't be copied, if the table could still
+    /// be counted.
+    pub lost: Option<u64>,
+    /// The first error reading the table, or writing it to the new database.
+    pub error: Option<String>,
+}
+
+impl TableRecovery {
+    /// Whether every row was copied.
+    pub fn is_complete(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// What `recover` salvaged.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RecoveryReport {
+    pub tables: Vec<TableRecovery>,
+    /// The indexes, triggers and views which couldn't be recreated, with
+    /// their errors. A unique index fails if the rows salvaged from a damaged
+    /// table violate it.
+    pub schema_errors: Vec<(String, String)>,
+}
+
+impl RecoveryReport {
+    /// Whether everything was recovered.
+    pub fn is_complete(&self) -> bool {
+        self.schema_errors.is_empty() && self.tables.iter().all(TableRecovery::is_complete)
+    }
+    pub fn table(&self, name: &str) -> Option<&TableRecovery> {
+        self.tables.iter().find(|t| t.name == name)
+    }
+}
+
+/// Copy what can be read of the database at `source` into a new database at
+/// `dest`, reporting what was lost from each table.
+///
+/// The schema is recreated first, then each table's rows are copied in
+/// rowid order. When a row can't be read, the copy skips ahead to the next
+/// row that can be (or gives up on a `WITHOUT ROWID` table). Indexes,
+/// triggers and views are created once the rows are in place, so triggers
+/// don't fire for the copied rows. The page size, application id and user
+/// version are kept.
+///
+/// `source` is only read. `dest` must not exist; if writing it fails, it's
+/// left as far as it got.
+pub fn recover<P: AsRef<Path>, Q: AsRef<Path>>(
+    source: P,
+    dest: Q,
+) -> Result<RecoveryReport, Error> {
+    let dest = dest.as_ref();
+    if dest.exists() {
+        return Err(Error::Exists(dest.to_owned()));
+    }
+    let source = Connection::open_with_flags(source, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    // Tolerates schema entries which don't parse, rather than failing every
+    // statement.
+    let _ = source.execute_batch("pragma writable_schema = on");
+    let schema = read_schema(&source).map_err(Error::Schema)?;
+
+    let new = Connection::open(dest)?;
+    let header = |name: &str| -> i64 {
+        source
+            .query_row(&format!("pragma {}", name), (), |row| row.get(0))
+            .unwrap_or_default()
+    };
+    new.execute_batch(&format!(
+        "pragma page_size = {}; pragma application_id = {}; pragma user_version = {}",
+        header("page_size"),
+        header("application_id"),
+        header("user_version")
+    ))?;
+
+    let mut report = RecoveryReport::default();
+    new.execute_batch("begin")?;
+    for entry in schema.iter().filter(|e| e.kind == "table") {
+        // `sqlite_sequence` is created along with the first table using
+        // `AUTOINCREMENT`.
+        if entry.name != "sqlite_sequence" {
+            if let Err(e) = new.execute_batch(&entry.sql) {
+                report.tables.push(TableRecovery {
+                    name: entry.name.clone(),
+                    recovered: 0,
+                    lost: count(&source, &entry.name),
+                    error: Some(e.to_string()),
+                });
+                continue;
+            }
+        }
+        report.tables.push(copy_table(&source, &new, &entry.name)?);
+    }
+    for entry in schema.iter().filter(|e| e.kind != "table") {
+        if let Err(e) = new.execute_batch(&entry.sql) {
+            report
+                .schema_errors
+                .push((entry.name.clone(), e.to_string()));
+        }
+    }
+    new.execute_batch("commit")?;
+    Ok(report)
+}
+
+struct SchemaEntry {
+    kind: String,
+    name: String,
+    sql: String,
+}
+
+fn read_schema(conn: &Connection) -> rusqlite::Result<Vec<SchemaEntry>> {
+    let mut stmt = conn.prepare(
+        "select type, name, sql from sqlite_master
+        where sql is not null
+            and (name not like 'sqlite\\_%' escape '\\' or name = 'sqlite_sequence')",
+    )?;
+    let entries = stmt.query_map((), |row| {
+        Ok(SchemaEntry {
+            kind: row.get(0)?,
+            name: row.get(1)?,
+            sql: row.get(2)?,
+        })
+    })?;
+    entries.collect()
+}
+
+/// The number of rows in `table`, unless it can't be read.
+fn count(conn: &Connection, table: &str) -> Option<u64> {
+    conn.query_row(
+        &format!("select count(*) from {}", quote_ident(table)),
+        (),
+        |row| row.get(0),
+    )
+    .ok()
+}
+
+fn copy_table(
+    source: &Connection,
+    dest: &Connection,
+    table: &str,
+) -> rusqlite::Result<TableRecovery> {
+    // Generated columns aren't listed, as they can't be inserted.
+    let columns: Vec<String> = dest
+        .prepare(&format!("pragma table_info({})", quote_ident(table)))?
+        .query_map((), |row| row.get(1))?
+        .collect::<rusqlite::Result<_>>()?;
+    let mut recovery = TableRecovery {
+        name: table.to_string(),
+        recovered: 0,
+        lost: None,
+        error: None,
+    };
+
+    if let Err(e) = copy_rows(source, dest, table, &columns, &mut recovery) {
+        recovery.error.get_or_insert_with(|| e.to_string());
+    }
+    recovery.lost = match recovery.error {
+        None => Some(0),
+        Some(_) => count(source, table).map(|n| n.saturating_sub(recovery.recovered)),
+    };
+    Ok(recovery)
+}
+
+fn copy_rows(
+    source: &Connection,
+    dest: &Connection,
+    table: &str,
+    columns: &[String],
+    recovery: &mut TableRecovery,
+) -> rusqlite::Result<()> {
+    let select = columns
+        .iter()
+        .map(|c| quote_ident(c))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let by_rowid = source.prepare(&format!(
+        "select rowid, {} from {} where rowid >= ?1 order by rowid",
+        select,
+        quote_ident(table)
+    ));
+    match by_rowid {
+        Ok(mut select) => {
+            let with_rowid: Vec<_> = std::iter::once("rowid".to_string())
+                .chain(columns.iter().cloned())
+                .collect();
+            let mut copy = Copy {
+                insert: dest.prepare(&insert_sql(table, &with_rowid))?,
+                recovery,
+            };
+            let probe = format!(
+                "select rowid from {} where rowid >= ?1 order by rowid limit 1",
+                quote_ident(table)
+            );
+            let mut probe = source.prepare(&probe)?;
+            let mut start = Some(i64::MIN);
+            while let Some(from) = start {
+                start = match copy.rows(&mut select, Some(from)) {
+                    Ok(_) => None,
+                    Err(last) => resume_after(&mut probe, last.unwrap_or(from)),
+                };
+            }
+        }
+        Err(_) => {
+            // Without a rowid there's no way to skip past an unreadable row.
+            let mut select =
+                source.prepare(&format!("select {} from {}", select, quote_ident(table)))?;
+            let mut copy = Copy {
+                insert: dest.prepare(&insert_sql(table, columns))?,
+                recovery,
+            };
+            let _ = copy.rows(&mut select, None);
+        }
+    }
+    Ok(())
+}
+
+struct Copy<'a, 'conn> {
+    insert: Statement<'conn>,
+    recovery: &'a mut TableRecovery,
+}
+
+impl Copy<'_, '_> {
+    /// Copy the rows of `select`, from rowid `from` if it selects by rowid,
+    /// until one can't be read. Rows which can't be inserted are skipped. On
+    /// failure, returns the rowid of the last row read.
+    fn rows(&mut self, select: &mut Statement, from: Option<i64>) -> Result<(), Option<i64>> {
+        let columns = select.column_count();
+        let rows = match from {
+            Some(from) => select.query([from]),
+            None => select.query(()),
+        };
+        let mut last = None;
+        let mut rows = match rows {
+            Ok(rows) => rows,
+            Err(e) => return Err(self.fail(e, last)),
+        };
+        loop {
+            let row = match rows.next() {
+                Ok(Some(row)) => row,
+                Ok(None) => return Ok(()),
+                Err(e) => return Err(self.fail(e, last)),
+            };
+            let values = (0..columns)
+                .map(|i| row.get::<_, Value>(i))
+                .collect::<rusqlite::Result<Vec<_>>>();
+            let values = match values {
+                Ok(values) => values,
+                Err(e) => return Err(self.fail(e, last)),
+            };
+            if from.is_some() {
+                if let Value::Integer(rowid) = values[0] {
+                    last = Some(rowid);
+                }
+            }
+            match self.insert.execute(params_from_iter(values)) {
+                Ok(_) => self.recovery.recovered += 1,
+                Err(e) => {
+                    self.recovery.error.get_or_insert_with(|| e.to_string());
+                }
+            }
+        }
+    }
+
+    fn fail(&mut self, e: rusqlite::Error, last: Option<i64>) -> Option<i64> {
+        self.recovery.error.get_or_insert_with(|| e.to_string());
+        last
+    }
+}
+
+/// The lowest rowid after `after` from which rows can be read again, or
+/// `None` if there isn't one. The gap is widened until `probe` succeeds,
+/// then narrowed again to skip as few rows as possible.
+fn resume_after(probe: &mut Statement, after: i64) -> Option<i64> {
+    let mut readable = |from: i64| -> Option<bool> {
+        match probe.query_row([from], |_| Ok(())) {
+            Ok(()) => Some(true),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Some(false),
+            Err(_) => None,
+        }
+    };
+    let (mut bad, mut good) = (after, None);
+    let mut gap: i64 = 1;
+    while good.is_none() {
+        let from = after.saturating_add(gap);
+        match readable(from) {
+            Some(_) => good = Some(from),
+            None if from == i64::MAX => return None,
+            None => {
+                bad = from;
+                gap = gap.saturating_mul(2);
+            }
+        }
+    }
+    let mut good = good.expect("found a readable rowid");
+    while good - bad > 1 {
+        let mid = bad + (good - bad) / 2;
+        match readable(mid) {
+            Some(_) => good = mid,
+            None => bad = mid,
+        }
+    }
+    // Nothing left to read is the end of the table.
+    readable(good).filter(|&more| more).map(|_| good)
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{0} already exists")]
+    Exists(PathBuf),
+    #[error("the schema can't be read: {0}")]
+    Schema(rusqlite::Error),
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::{
+        fs::OpenOptions,
+        io::{Seek, SeekFrom, Write},
+    };
+
+    #[test]
+    fn recover_corrupt_database() {
+        let dir = tempfile::tempdir().expect("Failed to create directory");
+        let path = dir.path().join("corrupt.db");
+        let conn = Connection::open(&path).expect("Failed to open connection");
+        conn.execute_batch(
+            "pragma page_size = 4096;
+            pragma user_version = 7;
+            create table note( id integer primary key, body text );
+            with recursive n(i) as (select 1 union all select i + 1 from n where i < 1000)
+            insert into note select i, printf('%.200c', 'x') from n;
+            create table tag( name text primary key ) without rowid;
+            insert into tag values ('a'), ('b');
+            create index note_body on note(body);
+            create view short_note as select id from note where length(body) < 10;",
+        )
+        .expect("Failed to create database");
+        let page: i64 = conn
+            .query_row(
+                "select rootpage from sqlite_master where name = 'note'",
+                (),
+                |row| row.get(0),
+            )
+            .unwrap();
+        drop(conn);
+
+        // Overwrite a leaf page in the middle of `note`.
+        let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(SeekFrom::Start((page + 20) as u64 * 4096))
+            .unwrap();
+        file.write_all(&[0xff; 4096]).unwrap();
+        drop(file);
+
+        let recovered = dir.path().join("recovered.db");
+        let res = recover(&path, &recovered);
+        assert!(res.is_ok(), "Failed to recover database: {:?}", res);
+        let report = res.unwrap();
+        assert!(!report.is_complete());
+        assert!(
+            report.schema_errors.is_empty(),
+            "{:?}",
+            report.schema_errors
+        );
+
+        let note = report.table("note").unwrap();
+        assert!(note.error.is_some());
+        assert!(
+            note.recovered > 900 && note.recovered < 1000,
+            "Recovered {} notes",
+            note.recovered
+        );
+        assert_eq!(
+            report.table("tag"),
+            Some(&TableRecovery {
+                name: "tag".into(),
+                recovered: 2,
+                lost: Some(0),
+                error: None,
+            })
+        );
+
+        let conn = Connection::open(&recovered).expect("Failed to open connection");
+        let notes: u64 = conn
+            .query_row("select count(*) from note", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(notes, note.recovered);
+        let last: i64 = conn
+            .query_row("select max(id) from note", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(last, 1000, "Didn't resume after the damaged page");
+        let version: i64 = conn
+            .query_row("pragma user_version", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, 7);
+        let check: String = conn
+            .query_row("pragma integrity_check", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(check, "ok");
+
+        assert!(matches!(recover(&path, &recovered), Err(Error::Exists(_))));
+    }
+}
@@ -0,0 +1,197 @@
+//! Loading related rows: the children referencing a row through a foreign
+//! key, and the parent a foreign key references. Used by the methods
+//! `#[derive(Relations)]` generates, and usable directly.
+//!
+//! The related struct names its table and key by implementing `Delete`
+//! (usually derived), and is read through `Columns` and `TryFrom<&Row>`.
+//! The batch loaders read the related rows of many rows with a single
+//! query, rather than one per row.
+
+use std::{collections::HashMap, hash::Hash};
+
+use rusqlite::{types::FromSql, Connection, OptionalExtension, Row, ToSql};
+
+use crate::{crud::Delete, query::in_list, row::Columns, util::quote_ident};
+
+fn select<T: Delete + Columns>(key: &str, condition: &str) -> String {
+    format!(
+        "select {}, {} from {} where {}",
+        T::select_list(),
+        quote_ident(key),
+        quote_ident(T::TABLE),
+        condition
+    )
+}
+
+/// The rows of `C` whose column `fk` references `parent`.
+pub fn children<C>(conn: &Connection, fk: &str, parent: &dyn ToSql) -> rusqlite::Result<Vec<C>>
+where
+    for<'r, 'stmt> C: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error> + Delete + Columns,
+{
+    let sql = select::<C>(fk, &format!("{} = ?", quote_ident(fk)));
+    let mut stmt = conn.prepare_cached(&sql)?;
+    let rows = stmt.query_map([parent], |row| C::try_from(row))?.collect();
+    rows
+}
+
+/// The rows of `C` whose column `fk` references each of `parents`, with
+/// a single query. Every parent has an entry, empty if it has no
+/// children.
+pub fn children_of<C, K>(
+    conn: &Connection,
+    fk: &str,
+    parents: &[K],
+) -> rusqlite::Result<HashMap<K, Vec<C>>>
+where
+    for<'r, 'stmt> C: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error> + Delete + Columns,
+    K: ToSql + FromSql + Eq + Hash + Clone,
+{
+    let mut children: HashMap<K, Vec<C>> = parents.iter().map(|p| (p.clone(), vec![])).collect();
+    if parents.is_empty() {
+        return Ok(children);
+    }
+    let condition = in_list(&quote_ident(fk), parents)?;
+    let mut stmt = conn.prepare(&select::<C>(fk, condition.sql()))?;
+    let mut rows = stmt.query(&*condition.params())?;
+    while let Some(row) = rows.next()? {
+        let parent: K = row.get(C::COLUMNS.len())?;
+        children.entry(parent).or_default().push(C::try_from(row)?);
+    }
+    Ok(children)
+}
+
+/// The row of `P` with key `id`, or `None` if it doesn't exist.
+pub fn parent<P>(conn: &Connection, id: &P::Id) -> rusqlite::Result<Option<P>>
+where
+    for<'r, 'stmt> P: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error> + Delete + Columns,
+{
+    let sql = select::<P>(P::ID_COLUMN, &format!("{} = ?", quote_ident(P::ID_COLUMN)));
+    conn.prepare_cached(&sql)?
+        .query_row([id], |row| P::try_from(row))
+        .optional()
+}
+
+/// The rows of `P` with each of the keys `ids` which exist, by key, with a
+/// single query.
+pub fn parents_of<P>(conn: &Connection, ids: &[P::Id]) -> rusqlite::Result<HashMap<P::Id, P>>
+where
+    for<'r, 'stmt> P: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error> + Delete + Columns,
+    P::Id: FromSql + Eq + Hash,
+{
+    if ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let condition = in_list(&quote_ident(P::ID_COLUMN), ids)?;
+    let mut stmt = conn.prepare(&select::<P>(P::ID_COLUMN, condition.sql()))?;
+    let mut rows = stmt.query(&*condition.params())?;
+    let mut parents = HashMap::new();
+    while let Some(row) = rows.next()? {
+        parents.insert(row.get(P::COLUMNS.len())?, P::try_from(row)?);
+    }
+    Ok(parents)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Delete, IntegerId, Relations, TryFromRow};
+
+    #[derive(TryFromRow, Delete, Relations, Debug, PartialEq)]
+    #[has_many(Book, fk = "author_id")]
+    struct Author {
+        #[id]
+        id: IntegerId<Author>,
+        name: String,
+    }
+
+    #[derive(TryFromRow, Delete, Relations, Debug, PartialEq)]
+    #[has_many(Review, fk = "book_id", name = "reviewed")]
+    struct Book {
+        #[id]
+        id: i64,
+        #[belongs_to(Author)]
+        author_id: IntegerId<Author>,
+        #[belongs_to(Author, name = "editor")]
+        editor_id: Option<IntegerId<Author>>,
+        title: String,
+    }
+
+    #[derive(TryFromRow, Delete, Debug, PartialEq)]
+    struct Review {
+        #[id]
+        id: i64,
+        book_id: i64,
+        stars: i64,
+    }
+
+    fn setup() -> Connection {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch(
+            "create table author( id integer primary key, name text );
+            create table book(
+                id integer primary key,
+                author_id integer references author,
+                editor_id integer references author,
+                title text
+            );
+            create table review( id integer primary key, book_id integer, stars integer );
+            insert into review values (1, 2, 5), (2, 2, 4);
+            insert into author values (1, 'Le Guin'), (2, 'Pratchett'), (3, 'Nobody');
+            insert into book values
+                (1, 1, null, 'The Dispossessed'),
+                (2, 2, 1, 'Mort'),
+                (3, 2, null, 'Small Gods');",
+        )
+        .expect("Failed to set up database");
+        db
+    }
+
+    fn authors(db: &Connection) -> Vec<Author> {
+        let mut stmt = db.prepare("select * from author order by id").unwrap();
+        let rows = stmt.query_map((), |row| Author::try_from(row)).unwrap();
+        rows.collect::<rusqlite::Result<_>>().unwrap()
+    }
+
+    #[test]
+    fn load_children() {
+        let db = setup();
+        let authors = authors(&db);
+        let res = authors[1].books(&db);
+        assert!(res.is_ok(), "Failed to load books: {:?}", res);
+        let titles: Vec<_> = res.unwrap().into_iter().map(|b| b.title).collect();
+        assert_eq!(titles, vec!["Mort", "Small Gods"]);
+
+        let res = Author::load_books(&db, &authors);
+        assert!(res.is_ok(), "Failed to load books: {:?}", res);
+        let books = res.unwrap();
+        assert_eq!(books.len(), 3);
+        assert_eq!(books[&authors[0].id].len(), 1);
+        assert_eq!(books[&authors[1].id].len(), 2);
+        assert!(books[&authors[2].id].is_empty());
+    }
+
+    #[test]
+    fn load_parents() {
+        let db = setup();
+        let books: Vec<Book> = children(&db, "author_id", &2).unwrap();
+        let res = books[0].author(&db);
+        assert!(res.is_ok(), "Failed to load author: {:?}", res);
+        assert_eq!(res.unwrap().unwrap().name, "Pratchett");
+        assert_eq!(books[0].editor(&db).unwrap().unwrap().name, "Le Guin");
+        assert_eq!(books[1].editor(&db).unwrap(), None);
+
+        let res = Book::load_editor(&db, &books);
+        assert!(res.is_ok(), "Failed to load editors: {:?}", res);
+        let editors = res.unwrap();
+        assert_eq!(editors.len(), 1);
+        assert_eq!(
+            editors[books[0].editor_id.as_ref().unwrap()].name,
+            "Le Guin"
+        );
+        assert_eq!(Book::load_author(&db, &books).unwrap().len(), 1);
+
+        let reviews = books[0].reviewed(&db).unwrap();
+        assert_eq!(reviews.iter().map(|r| r.stars).sum::<i64>(), 9);
+        assert!(books[1].reviewed(&db).unwrap().is_empty());
+    }
+}
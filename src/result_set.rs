@@ -0,0 +1,226 @@
+use std::{ops::Index, sync::Arc};
+
+use rusqlite::{types::Value, Connection, Params, Row};
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ColumnInfo {
+    pub name: String,
+    /// The declared type of the column the value came from, if any.
+    pub decl_type: Option<String>,
+}
+
+fn column_info(row: &Row<'_>) -> Arc<[ColumnInfo]> {
+    row.as_ref()
+        .columns()
+        .into_iter()
+        .map(|c| ColumnInfo {
+            name: c.name().to_string(),
+            decl_type: c.decl_type().map(|t| t.to_string()),
+        })
+        .collect()
+}
+
+/// A row whose shape isn't known at compile time. Values can be accessed by
+/// position or (case-insensitively) by column name.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DynamicRow {
+    columns: Arc<[ColumnInfo]>,
+    values: Vec<Value>,
+}
+
+impl DynamicRow {
+    pub fn columns(&self) -> &[ColumnInfo] {
+        &self.columns
+    }
+    pub fn values(&self) -> &[Value] {
+        &self.values
+    }
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+    pub fn get(&self, index: usize) -> Option<&Value> {
+        self.values.get(index)
+    }
+    /// The value of the first column called `name`.
+    pub fn get_by_name(&self, name: &str) -> Option<&Value> {
+        let i = self
+            .columns
+            .iter()
+            .position(|c| c.name.eq_ignore_ascii_case(name))?;
+        self.values.get(i)
+    }
+    /// (column name, value) pairs, in column order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.columns
+            .iter()
+            .map(|c| c.name.as_str())
+            .zip(self.values.iter())
+    }
+
+    fn read(row: &Row<'_>, columns: Arc<[ColumnInfo]>) -> rusqlite::Result<Self> {
+        let values = (0..columns.len())
+            .map(|i| row.get(i))
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(Self { columns, values })
+    }
+}
+
+impl<'stmt> TryFrom<&Row<'stmt>> for DynamicRow {
+    type Error = rusqlite::Error;
+    fn try_from(row: &Row<'stmt>) -> Result<Self, Self::Error> {
+        Self::read(row, column_info(row))
+    }
+}
+
+impl Index<usize> for DynamicRow {
+    type Output = Value;
+    fn index(&self, index: usize) -> &Value {
+        &self.values[index]
+    }
+}
+impl Index<&str> for DynamicRow {
+    type Output = Value;
+    /// Panics if there is no such column.
+    fn index(&self, name: &str) -> &Value {
+        self.get_by_name(name)
+            .unwrap_or_else(|| panic!("no column named {:?}", name))
+    }
+}
+
+/// All rows of an arbitrary query, along with its columns, for tools such
+/// as admin UIs and REPLs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResultSet {
+    columns: Arc<[ColumnInfo]>,
+    rows: Vec<DynamicRow>,
+}
+
+impl ResultSet {
+    pub fn query<P: Params>(conn: &Connection, sql: &str, params: P) -> rusqlite::Result<Self> {
+        let mut stmt = conn.prepare(sql)?;
+        let columns: Arc<[ColumnInfo]> = stmt
+            .columns()
+            .into_iter()
+            .map(|c| ColumnInfo {
+                name: c.name().to_string(),
+                decl_type: c.decl_type().map(|t| t.to_string()),
+            })
+            .collect();
+        let rows = stmt
+            .query_map(params, |row| DynamicRow::read(row, columns.clone()))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(Self { columns, rows })
+    }
+
+    pub fn columns(&self) -> &[ColumnInfo] {
+        &self.columns
+    }
+    pub fn column_names(&self) -> impl Iterator<Item = &str> {
+        self.columns.iter().map(|c| c.name.as_str())
+    }
+    pub fn rows(&self) -> &[DynamicRow] {
+        &self.rows
+    }
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+    pub fn iter(&self) -> std::slice::Iter<'_, DynamicRow> {
+        self.rows.iter()
+    }
+}
+
+impl Index<usize> for ResultSet {
+    type Output = DynamicRow;
+    fn index(&self, index: usize) -> &DynamicRow {
+        &self.rows[index]
+    }
+}
+impl IntoIterator for ResultSet {
+    type Item = DynamicRow;
+    type IntoIter = std::vec::IntoIter<DynamicRow>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.rows.into_iter()
+    }
+}
+impl<'a> IntoIterator for &'a ResultSet {
+    type Item = &'a DynamicRow;
+    type IntoIter = std::slice::Iter<'a, DynamicRow>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.rows.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn setup() -> Connection {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch(
+            "create table items( id integer, name text );
+            insert into items values (1, 'a'), (2, null);",
+        )
+        .expect("Failed to set up database");
+        db
+    }
+
+    #[test]
+    fn query_result_set() {
+        let db = setup();
+        let res = ResultSet::query(
+            &db,
+            "select id, name, id * 2 as twice from items order by id",
+            (),
+        );
+        assert!(res.is_ok(), "Failed to query rows: {:?}", res);
+        let results = res.unwrap();
+
+        assert_eq!(
+            results.column_names().collect::<Vec<_>>(),
+            vec!["id", "name", "twice"]
+        );
+        assert!(results.columns()[0]
+            .decl_type
+            .as_deref()
+            .is_some_and(|t| t.eq_ignore_ascii_case("integer")));
+        assert_eq!(results.columns()[2].decl_type, None);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0]["NAME"], Value::Text("a".into()));
+        assert_eq!(results[1][1], Value::Null);
+        assert_eq!(
+            results[1].iter().collect::<Vec<_>>(),
+            vec![
+                ("id", &Value::Integer(2)),
+                ("name", &Value::Null),
+                ("twice", &Value::Integer(4))
+            ]
+        );
+        assert_eq!(
+            results
+                .iter()
+                .filter(|r| r.get_by_name("missing").is_none())
+                .count(),
+            2
+        );
+
+        let empty = ResultSet::query(&db, "select * from items where id > 10", ()).unwrap();
+        assert!(empty.is_empty());
+        assert_eq!(empty.columns().len(), 2);
+    }
+
+    #[test]
+    fn dynamic_row_from_row() {
+        let db = setup();
+        let res = db.query_row("select * from items where id = 1", (), |row| {
+            DynamicRow::try_from(row)
+        });
+        assert!(res.is_ok(), "Failed to read row: {:?}", res);
+        assert_eq!(res.unwrap()["id"], Value::Integer(1));
+    }
+}
@@ -0,0 +1,228 @@
+use rusqlite::{
+    types::{ToSqlOutput, Value, ValueRef},
+    Connection, Row, ToSql,
+};
+
+use crate::{pragma::table_info, schema::Table, util::quote_ident};
+
+/// Whether the linked SQLite supports `RETURNING` (added in 3.35.0).
+pub fn supports_returning() -> bool {
+    rusqlite::version_number() >= 3_035_000
+}
+
+/// Insert `value` and read back the row as stored, including column
+/// defaults, generated columns and the assigned rowid. Fields which are NULL
+/// are left out of the insert, so that column defaults apply to them.
+///
+/// Uses `RETURNING *` when available, and otherwise reads the row back by
+/// `last_insert_rowid`, which requires a rowid table.
+pub fn insert_returning<T>(conn: &Connection, value: &T) -> rusqlite::Result<T>
+where
+    T: Table,
+    for<'r, 'stmt> T: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error>,
+{
+    insert(conn, value, supports_returning())
+}
+
+/// Update the row with `rowid` to `value` and read it back as stored.
+/// Returns `None` if there is no such row.
+pub fn update_returning<T>(conn: &Connection, rowid: i64, value: &T) -> rusqlite::Result<Option<T>>
+where
+    T: Table,
+    for<'r, 'stmt> T: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error>,
+{
+    update(conn, rowid, value, supports_returning())
+}
+
+fn insert<T>(conn: &Connection, value: &T, returning: bool) -> rusqlite::Result<T>
+where
+    T: Table,
+    for<'r, 'stmt> T: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error>,
+{
+    let schema = T::schema();
+    let table = quote_ident(&schema.name);
+    let mut columns = vec![];
+    let mut params = vec![];
+    for (column, param) in schema.writable_columns().zip(value.params()) {
+        if !is_null(param)? {
+            columns.push(quote_ident(&column.name));
+            params.push(param);
+        }
+    }
+    let sql = if columns.is_empty() {
+        format!("insert into {} default values", table)
+    } else {
+        format!(
+            "insert into {}({}) values ({})",
+            table,
+            columns.join(", "),
+            vec!["?"; columns.len()].join(", ")
+        )
+    };
+
+    if returning {
+        conn.query_row(&format!("{} returning *", sql), &*params, |row| {
+            T::try_from(row)
+        })
+    } else {
+        conn.execute(&sql, &*params)?;
+        conn.query_row(
+            &format!("select * from {} where rowid = ?", table),
+            (conn.last_insert_rowid(),),
+            |row| T::try_from(row),
+        )
+    }
+}
+
+fn update<T>(
+    conn: &Connection,
+    rowid: i64,
+    value: &T,
+    returning: bool,
+) -> rusqlite::Result<Option<T>>
+where
+    T: Table,
+    for<'r, 'stmt> T: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error>,
+{
+    let schema = T::schema();
+    let table = quote_ident(&schema.name);
+    let assignments: Vec<_> = schema
+        .writable_columns()
+        .map(|c| format!("{} = ?", quote_ident(&c.name)))
+        .collect();
+    let sql = format!(
+        "update {} set {} where rowid = ?",
+        table,
+        assignments.join(", ")
+    );
+    let mut params = value.params();
+    params.push(&rowid);
+
+    let res = if returning {
+        conn.query_row(&format!("{} returning *", sql), &*params, |row| {
+            T::try_from(row)
+        })
+    } else {
+        if conn.execute(&sql, &*params)? == 0 {
+            return Ok(None);
+        }
+        // The update may have changed the rowid through its alias.
+        let alias = rowid_alias(conn, &schema.name)?;
+        let mut new_rowid: &dyn ToSql = &rowid;
+        for (column, param) in schema.writable_columns().zip(value.params()) {
+            let is_alias = alias
+                .as_ref()
+                .is_some_and(|a| a.eq_ignore_ascii_case(&column.name));
+            if is_alias && !is_null(param)? {
+                new_rowid = param;
+            }
+        }
+        conn.query_row(
+            &format!("select * from {} where rowid = ?", table),
+            [new_rowid],
+            |row| T::try_from(row),
+        )
+    };
+    match res {
+        Ok(value) => Ok(Some(value)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// The `INTEGER PRIMARY KEY` column of `table`, which aliases the rowid.
+fn rowid_alias(conn: &Connection, table: &str) -> rusqlite::Result<Option<String>> {
+    let key: Vec<_> = table_info(conn, table)?
+        .into_iter()
+        .filter(|c| c.pk > 0)
+        .collect();
+    Ok(match &key[..] {
+        [column] if column.decl_type.eq_ignore_ascii_case("integer") => Some(column.name.clone()),
+        _ => None,
+    })
+}
+
+fn is_null(param: &dyn ToSql) -> rusqlite::Result<bool> {
+    Ok(matches!(
+        param.to_sql()?,
+        ToSqlOutput::Owned(Value::Null) | ToSqlOutput::Borrowed(ValueRef::Null)
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::{Table, TryFromRow};
+
+    #[derive(Table, TryFromRow, Debug, PartialEq)]
+    #[table = "notes"]
+    struct Note {
+        id: Option<i64>,
+        body: String,
+        status: Option<String>,
+        #[generated("upper(body)")]
+        shout: Option<String>,
+    }
+
+    fn setup() -> Connection {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch(
+            "create table notes(
+                id integer primary key,
+                body text not null,
+                status text default 'new',
+                shout text generated always as (upper(body))
+            )",
+        )
+        .expect("Failed to create table");
+        db
+    }
+
+    fn note(id: Option<i64>, body: &str) -> Note {
+        Note {
+            id,
+            body: body.into(),
+            status: None,
+            shout: None,
+        }
+    }
+
+    #[test]
+    fn insert_and_update_returning() {
+        assert!(supports_returning());
+        for returning in [true, false] {
+            let db = setup();
+            let res = insert(&db, &note(None, "hello"), returning);
+            assert!(res.is_ok(), "Failed to insert row: {:?}", res);
+            let inserted = res.unwrap();
+            assert_eq!(
+                inserted,
+                Note {
+                    id: Some(1),
+                    body: "hello".into(),
+                    status: Some("new".into()),
+                    shout: Some("HELLO".into())
+                }
+            );
+
+            let res = update(&db, 1, &note(Some(1), "bye"), returning);
+            assert!(res.is_ok(), "Failed to update row: {:?}", res);
+            let updated = res.unwrap().expect("Row was not found");
+            assert_eq!(updated.id, Some(1));
+            assert_eq!(updated.status, None);
+            assert_eq!(updated.shout.as_deref(), Some("BYE"));
+
+            assert_eq!(
+                update(&db, 7, &note(Some(7), "missing"), returning).unwrap(),
+                None
+            );
+
+            // Changing the key moves the row to another rowid.
+            let res = update(&db, 1, &note(Some(5), "moved"), returning);
+            assert!(res.is_ok(), "Failed to update row: {:?}", res);
+            let moved = res.unwrap().expect("Moved row was not found");
+            assert_eq!((moved.id, moved.body.as_str()), (Some(5), "moved"));
+        }
+    }
+}
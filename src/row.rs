@@ -0,0 +1,249 @@
+use std::borrow::Cow;
+
+use rusqlite::{
+    types::{FromSqlError, FromSqlResult, Type, ValueRef},
+    Connection, Params, Row, RowIndex,
+};
+use serde_json::{Map, Number, Value};
+use thiserror::Error;
+
+use crate::util::quote_ident;
+
+/// The columns a struct reads from a row, in field order. Implemented by
+/// `#[derive(TryFromRow)]`, except for structs with flattened fields.
+pub trait Columns {
+    const COLUMNS: &'static [&'static str];
+
+    /// The columns, quoted and separated by commas, to select exactly what
+    /// the struct reads in its order, eg
+    /// `format!("select {} from foo", Foo::select_list())`.
+    fn select_list() -> String {
+        let columns: Vec<_> = Self::COLUMNS.iter().map(|c| quote_ident(c)).collect();
+        columns.join(", ")
+    }
+    /// As `select_list`, with each column qualified by `table` (a table name
+    /// or alias), for joins.
+    fn select_list_from(table: &str) -> String {
+        let table = quote_ident(table);
+        let columns: Vec<_> = Self::COLUMNS
+            .iter()
+            .map(|c| format!("{}.{}", table, quote_ident(c)))
+            .collect();
+        columns.join(", ")
+    }
+}
+
+/// A struct read from the columns of a row whose names start with a prefix,
+/// so that it can be nested in another with `#[try_from_row(flatten)]`.
+/// Implemented by `#[derive(TryFromRow)]`.
+pub trait FromPrefixedRow: Sized {
+    fn from_prefixed_row(row: &Row<'_>, prefix: &str) -> rusqlite::Result<Self>;
+}
+
+/// `name` with `prefix` prepended, allocating only if there is a prefix.
+pub fn prefixed<'a>(prefix: &str, name: &'a str) -> Cow<'a, str> {
+    if prefix.is_empty() {
+        Cow::Borrowed(name)
+    } else {
+        Cow::Owned(format!("{}{}", prefix, name))
+    }
+}
+
+/// A NULL read into a field marked `#[try_from_row(not_null)]`.
+#[derive(Error, Debug)]
+#[error("column {column:?} is NULL, but field `{field}` isn't nullable")]
+pub struct UnexpectedNull {
+    pub column: String,
+    pub field: &'static str,
+}
+
+/// The error for a NULL in column `index` of `row`, read into `field`. Used
+/// by `#[derive(TryFromRow)]`.
+pub fn unexpected_null(row: &Row<'_>, index: usize, field: &'static str) -> rusqlite::Error {
+    let column = row
+        .as_ref()
+        .column_name(index)
+        .map_or_else(|_| index.to_string(), String::from);
+    rusqlite::Error::FromSqlConversionFailure(
+        index,
+        Type::Null,
+        Box::new(UnexpectedNull { column, field }),
+    )
+}
+
+/// The failure to read a column into a field, naming the struct and field
+/// as well as the column. The index of the column is in the
+/// `FromSqlConversionFailure` wrapping it.
+#[derive(Error, Debug)]
+#[error("can't read column {column:?} into `{ty}::{field}`: {source}")]
+pub struct FieldError {
+    pub ty: &'static str,
+    pub field: &'static str,
+    pub column: String,
+    /// The conversion error, or the original error if it wasn't one.
+    pub source: Box<dyn std::error::Error + Send + Sync>,
+}
+
+/// `error`, from reading `column` into `field` of `ty`, wrapped in a
+/// `FieldError`. Conversion errors (including type mismatches and integers
+/// out of range) become a `FromSqlConversionFailure` of the same column and
+/// type, and others are returned as is, as they already name the column or
+/// aren't about it. Used by `#[derive(TryFromRow)]`.
+pub fn field_error(
+    ty: &'static str,
+    field: &'static str,
+    column: &str,
+    error: rusqlite::Error,
+) -> rusqlite::Error {
+    let (index, value_type, source): (_, _, Box<dyn std::error::Error + Send + Sync>) = match error
+    {
+        rusqlite::Error::FromSqlConversionFailure(index, value_type, source)
+            if !source.is::<FieldError>() =>
+        {
+            (index, value_type, source)
+        }
+        rusqlite::Error::InvalidColumnType(index, _, ref value_type) => {
+            let value_type = value_type.clone();
+            (index, value_type, Box::new(error))
+        }
+        rusqlite::Error::IntegralValueOutOfRange(index, _) => {
+            (index, Type::Integer, Box::new(error))
+        }
+        _ => return error,
+    };
+    rusqlite::Error::FromSqlConversionFailure(
+        index,
+        value_type,
+        Box::new(FieldError {
+            ty,
+            field,
+            column: column.to_string(),
+            source,
+        }),
+    )
+}
+
+/// A type borrowing a value of a row rather than copying it, for reading
+/// TEXT and BLOB columns into `&str` and `&[u8]` fields without allocating.
+/// Fields of reference types (or `Option`s of them) are read through it by
+/// `#[derive(TryFromRow)]` on a struct with a lifetime, which then borrows
+/// the row for that lifetime.
+pub trait BorrowFromSql<'a>: Sized {
+    fn borrow_from(value: ValueRef<'a>) -> FromSqlResult<Self>;
+}
+impl<'a> BorrowFromSql<'a> for &'a str {
+    fn borrow_from(value: ValueRef<'a>) -> FromSqlResult<Self> {
+        value.as_str()
+    }
+}
+impl<'a> BorrowFromSql<'a> for &'a [u8] {
+    fn borrow_from(value: ValueRef<'a>) -> FromSqlResult<Self> {
+        value.as_blob()
+    }
+}
+impl<'a, T: BorrowFromSql<'a>> BorrowFromSql<'a> for Option<T> {
+    fn borrow_from(value: ValueRef<'a>) -> FromSqlResult<Self> {
+        match value {
+            ValueRef::Null => Ok(None),
+            value => T::borrow_from(value).map(Some),
+        }
+    }
+}
+
+/// Column `index` of `row`, borrowed for as long as the row is, with the
+/// errors of `Row::get`. Used by `#[derive(TryFromRow)]`.
+pub fn get_borrowed<'a, I, T>(row: &'a Row<'_>, index: I) -> rusqlite::Result<T>
+where
+    I: RowIndex,
+    T: BorrowFromSql<'a>,
+{
+    let index = index.idx(row.as_ref())?;
+    let value = row.get_ref(index)?;
+    T::borrow_from(value).map_err(|e| match e {
+        FromSqlError::InvalidType => rusqlite::Error::InvalidColumnType(
+            index,
+            row.as_ref()
+                .column_name(index)
+                .unwrap_or_default()
+                .to_string(),
+            value.data_type(),
+        ),
+        e => rusqlite::Error::FromSqlConversionFailure(index, value.data_type(), Box::new(e)),
+    })
+}
+
+/// Convert a row to a JSON object keyed by column name, for ad-hoc export
+/// of queries without a struct to read them into.
+///
+/// Values map to their natural JSON type, guided by the declared type of
+/// the column where there is one: integers in `BOOLEAN` columns become
+/// booleans, and text in `JSON` columns is parsed (falling back to a string
+/// if it isn't valid JSON). Non-finite reals become `null`, and blobs become
+/// hex strings.
+pub fn row_to_json(row: &Row<'_>) -> rusqlite::Result<Value> {
+    let columns = row.as_ref().columns();
+    let mut object = Map::new();
+    for (i, column) in columns.iter().enumerate() {
+        let decl_type = column.decl_type().unwrap_or_default().to_ascii_uppercase();
+        let value = match row.get_ref(i)? {
+            ValueRef::Null => Value::Null,
+            ValueRef::Integer(i) if decl_type.contains("BOOL") => Value::Bool(i != 0),
+            ValueRef::Integer(i) => Value::Number(i.into()),
+            ValueRef::Real(f) => Number::from_f64(f).map_or(Value::Null, Value::Number),
+            ValueRef::Text(t) => {
+                let text = String::from_utf8_lossy(t);
+                let parsed = if decl_type.contains("JSON") {
+                    serde_json::from_str(&text).ok()
+                } else {
+                    None
+                };
+                parsed.unwrap_or_else(|| Value::String(text.into_owned()))
+            }
+            ValueRef::Blob(b) => Value::String(b.iter().map(|b| format!("{:02x}", b)).collect()),
+        };
+        object.insert(column.name().to_string(), value);
+    }
+    Ok(Value::Object(object))
+}
+
+/// Run a query and convert every row with `row_to_json`.
+pub fn query_to_json<P: Params>(
+    conn: &Connection,
+    sql: &str,
+    params: P,
+) -> rusqlite::Result<Vec<Value>> {
+    let mut stmt = conn.prepare(sql)?;
+    let rows = stmt.query_map(params, row_to_json)?.collect();
+    rows
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn export_rows() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch(
+            "create table items( id integer, name text, active boolean, data json, price real, raw blob );
+            insert into items values (1, 'a', 1, '{\"tags\": [\"x\"]}', 1.5, x'00ff');
+            insert into items values (2, null, 0, 'not json', null, null);",
+        )
+        .expect("Failed to set up database");
+
+        let res = query_to_json(&db, "select * from items order by id", ());
+        assert!(res.is_ok(), "Failed to export rows: {:?}", res);
+        assert_eq!(
+            res.unwrap(),
+            vec![
+                json!({"id": 1, "name": "a", "active": true, "data": {"tags": ["x"]}, "price": 1.5, "raw": "00ff"}),
+                json!({"id": 2, "name": null, "active": false, "data": "not json", "price": null, "raw": null}),
+            ]
+        );
+
+        let res = query_to_json(&db, "select count(*) as n from items where id > ?", (0,));
+        assert_eq!(res.unwrap(), vec![json!({"n": 2})]);
+    }
+}
@@ -0,0 +1,172 @@
+use std::marker::PhantomData;
+
+use rusqlite::{types::FromSql, Connection, Row, ToSql};
+
+use crate::util::quote_ident;
+
+/// Iterates over every row of a table in batches ordered by id, for jobs
+/// such as exports or reindexing which touch a whole table. Each batch is a
+/// separate query continuing after the last id seen (keyset pagination), so
+/// no read transaction is held open between batches and writers aren't
+/// starved. Rows inserted or updated during the scan may or may not be seen.
+pub struct TableScanner<'c, T, I> {
+    conn: &'c Connection,
+    sql: String,
+    batch_size: usize,
+    last: Option<I>,
+    done: bool,
+    _row: PhantomData<T>,
+}
+
+impl<'c, T, I> TableScanner<'c, T, I>
+where
+    for<'r, 'stmt> T: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error>,
+    I: FromSql + ToSql,
+{
+    /// Scan `table`, whose rows are identified by an `id` column of type `I`,
+    /// 1000 rows at a time.
+    pub fn new(conn: &'c Connection, table: &str) -> Self {
+        Self::with_id_column(conn, table, "id")
+    }
+    /// Scan `table`, ordering and continuing by `id_column`, which must be
+    /// unique.
+    pub fn with_id_column(conn: &'c Connection, table: &str, id_column: &str) -> Self {
+        Self {
+            conn,
+            // The id is selected last, so it doesn't shift columns `T` reads
+            // by index.
+            sql: format!(
+                "select *, {id} from {table} where ?1 is null or {id} > ?1 order by {id} limit ?2",
+                id = quote_ident(id_column),
+                table = quote_ident(table),
+            ),
+            batch_size: 1000,
+            last: None,
+            done: false,
+            _row: PhantomData,
+        }
+    }
+    pub fn batch_size(mut self, size: usize) -> Self {
+        self.batch_size = size.max(1);
+        self
+    }
+    /// Start after `id`, eg to resume an interrupted scan from the id of the
+    /// last row processed.
+    pub fn resume_after(mut self, id: I) -> Self {
+        self.last = Some(id);
+        self
+    }
+    /// The id of the last row returned, for resuming later.
+    pub fn last_id(&self) -> Option<&I> {
+        self.last.as_ref()
+    }
+
+    fn next_batch(&mut self) -> rusqlite::Result<Vec<T>> {
+        let mut stmt = self.conn.prepare_cached(&self.sql)?;
+        let mut rows = stmt.query((&self.last, self.batch_size as i64))?;
+        let mut batch = Vec::with_capacity(self.batch_size);
+        let mut last = None;
+        while let Some(row) = rows.next()? {
+            batch.push(T::try_from(row)?);
+            last = Some(row.get(row.as_ref().column_count() - 1)?);
+        }
+        if batch.len() < self.batch_size {
+            self.done = true;
+        }
+        if last.is_some() {
+            self.last = last;
+        }
+        Ok(batch)
+    }
+}
+
+impl<'c, T, I> Iterator for TableScanner<'c, T, I>
+where
+    for<'r, 'stmt> T: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error>,
+    I: FromSql + ToSql,
+{
+    type Item = rusqlite::Result<Vec<T>>;
+
+    /// The next non-empty batch. Iteration stops after an error.
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.next_batch() {
+            Ok(batch) if batch.is_empty() => None,
+            Ok(batch) => Some(Ok(batch)),
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::{IntegerId, TryFromRow};
+
+    #[derive(TryFromRow, Debug, PartialEq)]
+    struct Item {
+        id: ItemId,
+        name: String,
+    }
+    type ItemId = IntegerId<Item>;
+
+    fn setup() -> Connection {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch(
+            "create table item( id integer primary key, name text );
+            with recursive n(v) as (select 1 union all select v + 1 from n where v < 10)
+            insert into item(id, name) select v * 2, 'item' || v from n;",
+        )
+        .expect("Failed to set up database");
+        db
+    }
+
+    #[test]
+    fn scan_in_batches() {
+        let db = setup();
+        let mut scanner = TableScanner::<Item, ItemId>::new(&db, "item").batch_size(4);
+        let mut sizes = vec![];
+        let mut names = vec![];
+        for batch in &mut scanner {
+            assert!(batch.is_ok(), "Failed to scan batch: {:?}", batch);
+            let batch = batch.unwrap();
+            sizes.push(batch.len());
+            names.extend(batch.into_iter().map(|item| item.name));
+        }
+        assert_eq!(sizes, vec![4, 4, 2]);
+        assert_eq!(names.len(), 10);
+        assert_eq!(names[0], "item1");
+        assert_eq!(names[9], "item10");
+        assert_eq!(
+            scanner.last_id().map(|id| id.to_string()),
+            Some("20".into())
+        );
+    }
+
+    #[test]
+    fn resume_scan() {
+        let db = setup();
+        let mut scanner = TableScanner::<Item, ItemId>::new(&db, "item").batch_size(5);
+        let first = scanner.next().unwrap().unwrap();
+        assert_eq!(first.len(), 5);
+
+        let last = scanner.last_id().copied().unwrap();
+        assert_eq!(last, first[4].id);
+        let rest: Vec<Item> = TableScanner::<Item, ItemId>::new(&db, "item")
+            .resume_after(last)
+            .batch_size(5)
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .unwrap()
+            .into_iter()
+            .flatten()
+            .collect();
+        assert_eq!(rest.len(), 5);
+        assert_eq!(rest[0].name, "item6");
+    }
+}
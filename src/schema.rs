@@ -0,0 +1,591 @@
+use rusqlite::{Connection, ToSql};
+
+use crate::{
+    column_type::{SqliteColumnType, StorageClass},
+    date_time::clock::Clock,
+    execute::Executor,
+    scrub::Scrub,
+    util::quote_ident,
+};
+
+/// A Rust type stored as a table row. Usually derived with
+/// `#[derive(Table)]`, which reads the table name from `#[table = "..."]`
+/// (defaulting to the struct name in snake case) and maps each field to a
+/// column, typed by the field type's `SqliteColumnType`. Fields can be
+/// marked `#[primary_key]` (on several fields for a composite key),
+/// `#[unique]` or `#[not_null]`, and the struct `#[strict]`. The derive also
+/// adds a `CREATE_SQL` constant, the same as `schema().create_sql()`.
+///
+/// Columns are named as `TryFromRow` reads them, so `#[try_from_row(column =
+/// "...")]` renames one, and fields it skips or reads lazily have none.
+/// Flattened and multi-column fields aren't supported.
+///
+/// Fields marked `#[auto_now_add]` are set by `touch` when the row is
+/// inserted, and fields marked `#[auto_now]` whenever it is written. Fields
+/// marked `#[pii]` are scrubbed by `scrub::Scrubber`.
+///
+/// `#[projection(Name, fields(a, b), derive(Debug))]` on the struct
+/// declares a struct `Name` of just those fields (which must be the
+/// table's), read with `#[derive(TryFromRow)]` and selected with
+/// `Name::select_sql()`, so that list views can leave out large columns.
+pub trait Table {
+    fn schema() -> TableSchema;
+    /// Parameters for the writable (non-generated) columns, in the order of
+    /// `TableSchema::insert_sql`.
+    fn params(&self) -> Vec<&dyn ToSql>;
+
+    /// Set the automatically stamped fields from `clock`, before inserting
+    /// (`inserting`) or updating the row.
+    fn touch(&mut self, _clock: &dyn Clock, _inserting: bool) {}
+
+    fn create_table<E: Executor + ?Sized>(exec: &E) -> rusqlite::Result<()> {
+        Self::schema().create_table(exec)
+    }
+    /// Stamp the row for insertion, then insert it.
+    fn insert<E: Executor + ?Sized>(
+        &mut self,
+        exec: &E,
+        clock: &dyn Clock,
+    ) -> rusqlite::Result<usize>
+    where
+        Self: Sized,
+    {
+        self.touch(clock, true);
+        exec.run(&Self::schema().insert_sql(), &self.params())
+    }
+}
+
+/// How a generated column is computed. Virtual columns are computed when
+/// read, stored columns when the row is written.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Generated {
+    pub expr: String,
+    pub stored: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Column {
+    pub name: String,
+    /// The declared type, if any (eg `integer`).
+    pub decl_type: Option<String>,
+    pub not_null: bool,
+    pub primary_key: bool,
+    pub unique: bool,
+    /// The storage class of the column's values, if known.
+    pub storage: Option<StorageClass>,
+    /// The `DEFAULT` expression.
+    pub default: Option<String>,
+    pub generated: Option<Generated>,
+    /// How the column is scrubbed from shared copies of the database, if it
+    /// holds personal data.
+    pub pii: Option<Scrub>,
+}
+
+impl Column {
+    pub fn new(name: &str, decl_type: Option<&str>) -> Self {
+        Self {
+            name: name.to_string(),
+            decl_type: decl_type.map(|t| t.to_string()),
+            not_null: false,
+            primary_key: false,
+            unique: false,
+            storage: None,
+            default: None,
+            generated: None,
+            pii: None,
+        }
+    }
+    /// A column holding values of `T`, as declared by its
+    /// `SqliteColumnType`.
+    pub fn of<T: SqliteColumnType + ?Sized>(name: &str) -> Self {
+        Self {
+            not_null: !T::NULLABLE,
+            storage: T::STORAGE,
+            default: T::DEFAULT.map(|d| d.to_string()),
+            ..Self::new(name, T::DECL_TYPE)
+        }
+    }
+    pub fn not_null(mut self) -> Self {
+        self.not_null = true;
+        self
+    }
+    /// Make the column (part of) the primary key. An `integer` primary key
+    /// that may be NULL is an alias for the rowid, assigned on insert.
+    pub fn primary_key(mut self) -> Self {
+        self.primary_key = true;
+        self
+    }
+    pub fn unique(mut self) -> Self {
+        self.unique = true;
+        self
+    }
+    /// Set the `DEFAULT` expression.
+    pub fn default(mut self, expr: &str) -> Self {
+        self.default = Some(expr.to_string());
+        self
+    }
+    /// Compute the column from `expr` (eg `json_extract(data, '$.name')`)
+    /// rather than storing a written value. Generated columns are read like
+    /// any other, but can't be inserted or updated.
+    pub fn generated(mut self, expr: &str, stored: bool) -> Self {
+        self.generated = Some(Generated {
+            expr: expr.to_string(),
+            stored,
+        });
+        self
+    }
+    /// Mark the column as holding personal data; see `scrub::Scrubber`.
+    pub fn pii(mut self, scrub: Scrub) -> Self {
+        self.pii = Some(scrub);
+        self
+    }
+    pub fn is_generated(&self) -> bool {
+        self.generated.is_some()
+    }
+
+    /// The column's definition, as in `CREATE TABLE`. A primary key spanning
+    /// several columns is declared by the table instead.
+    pub fn definition_sql(&self) -> String {
+        self.definition(true)
+    }
+    fn definition(&self, primary_key: bool) -> String {
+        let mut sql = quote_ident(&self.name);
+        if let Some(decl_type) = &self.decl_type {
+            sql.push(' ');
+            sql.push_str(decl_type);
+        }
+        if self.primary_key && primary_key {
+            sql.push_str(" primary key");
+        }
+        if self.unique {
+            sql.push_str(" unique");
+        }
+        if self.not_null {
+            sql.push_str(" not null");
+        }
+        if let Some(default) = &self.default {
+            sql.push_str(&format!(" default ({})", default));
+        }
+        if let Some(generated) = &self.generated {
+            sql.push_str(&format!(
+                " generated always as ({}) {}",
+                generated.expr,
+                if generated.stored {
+                    "stored"
+                } else {
+                    "virtual"
+                }
+            ));
+        }
+        sql
+    }
+}
+
+/// The definition of a table, built at runtime or derived with
+/// `#[derive(Table)]`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TableSchema {
+    pub name: String,
+    pub columns: Vec<Column>,
+    pub strict: bool,
+}
+
+impl TableSchema {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            columns: vec![],
+            strict: false,
+        }
+    }
+    pub fn column(mut self, column: Column) -> Self {
+        self.columns.push(column);
+        self
+    }
+    /// Create the table as `STRICT` (from SQLite 3.37), rejecting values
+    /// which can't be converted to the column's type. Strict tables only
+    /// allow the storage class names as declared types, so the columns
+    /// already added are declared with their storage class (or `any`),
+    /// rather than eg `json`.
+    pub fn strict(mut self) -> Self {
+        for column in &mut self.columns {
+            column.decl_type = Some(strict_type(column.storage).to_string());
+        }
+        self.strict = true;
+        self
+    }
+
+    /// Columns which take a value on insert or update, ie all but the
+    /// generated columns.
+    pub fn writable_columns(&self) -> impl Iterator<Item = &Column> {
+        self.columns.iter().filter(|c| !c.is_generated())
+    }
+
+    pub fn create_sql(&self) -> String {
+        format!(
+            "create table if not exists {}( {} ){}",
+            quote_ident(&self.name),
+            self.columns_sql(),
+            if self.strict { " strict" } else { "" }
+        )
+    }
+    /// `CREATE TEMP TABLE`, for a table dropped when the connection closes.
+    pub fn create_temp_sql(&self) -> String {
+        format!(
+            "create temp table {}( {} ){}",
+            quote_ident(&self.name),
+            self.columns_sql(),
+            if self.strict { " strict" } else { "" }
+        )
+    }
+    fn columns_sql(&self) -> String {
+        let key: Vec<_> = self
+            .columns
+            .iter()
+            .filter(|c| c.primary_key)
+            .map(|c| quote_ident(&c.name))
+            .collect();
+        let mut definitions: Vec<_> = self
+            .columns
+            .iter()
+            .map(|c| c.definition(key.len() == 1))
+            .collect();
+        if key.len() > 1 {
+            definitions.push(format!("primary key ({})", key.join(", ")));
+        }
+        definitions.join(", ")
+    }
+    /// Create the table if it does not already exist.
+    pub fn create_table<E: Executor + ?Sized>(&self, exec: &E) -> rusqlite::Result<()> {
+        exec.run_batch(&self.create_sql())
+    }
+    /// Compare the schema with the table in `conn`: the table and every
+    /// column must exist with the same declared type, and hold only values
+    /// of the column's storage class (or NULL). The storage classes are
+    /// checked by scanning the table.
+    pub fn verify(&self, conn: &Connection) -> rusqlite::Result<Vec<Mismatch>> {
+        let mut stmt = conn.prepare("select name, type from pragma_table_info(?)")?;
+        let existing = stmt
+            .query_map((&self.name,), |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        if existing.is_empty() {
+            return Ok(vec![Mismatch::MissingTable]);
+        }
+        let mut mismatches = vec![];
+        for column in &self.columns {
+            let found = match existing.iter().find(|(name, _)| *name == column.name) {
+                Some((_, decl_type)) => decl_type,
+                None => {
+                    mismatches.push(Mismatch::MissingColumn(column.name.clone()));
+                    continue;
+                }
+            };
+            let expected = column.decl_type.as_deref().unwrap_or_default();
+            if !found.eq_ignore_ascii_case(expected) {
+                mismatches.push(Mismatch::DeclType {
+                    column: column.name.clone(),
+                    expected: column.decl_type.clone(),
+                    found: Some(found.clone()).filter(|t| !t.is_empty()),
+                });
+            }
+            if let Some(storage) = column.storage {
+                let rows: i64 = conn.query_row(
+                    &format!(
+                        "select count(*) from {} where typeof({}) not in ('null', ?)",
+                        quote_ident(&self.name),
+                        quote_ident(&column.name)
+                    ),
+                    (storage.as_str(),),
+                    |row| row.get(0),
+                )?;
+                if rows > 0 {
+                    mismatches.push(Mismatch::Storage {
+                        column: column.name.clone(),
+                        expected: storage,
+                        rows: rows as usize,
+                    });
+                }
+            }
+        }
+        Ok(mismatches)
+    }
+    /// An `INSERT` of the writable columns, with positional parameters.
+    pub fn insert_sql(&self) -> String {
+        let columns: Vec<_> = self
+            .writable_columns()
+            .map(|c| quote_ident(&c.name))
+            .collect();
+        format!(
+            "insert into {}({}) values ({})",
+            quote_ident(&self.name),
+            columns.join(", "),
+            vec!["?"; columns.len()].join(", ")
+        )
+    }
+}
+
+/// The declared type of a column of `storage` in a strict table.
+pub const fn strict_type(storage: Option<StorageClass>) -> &'static str {
+    match storage {
+        Some(storage) => storage.as_str(),
+        None => "any",
+    }
+}
+
+/// A difference found by `TableSchema::verify`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Mismatch {
+    MissingTable,
+    MissingColumn(String),
+    DeclType {
+        column: String,
+        expected: Option<String>,
+        found: Option<String>,
+    },
+    /// Rows hold values of another storage class than the column's type.
+    Storage {
+        column: String,
+        expected: StorageClass,
+        rows: usize,
+    },
+}
+
+#[cfg(test)]
+mod test {
+    use rusqlite::Connection;
+
+    use super::*;
+    use crate::{json_path::path, object::JsonObject, Table, TryFromRow};
+
+    #[derive(Table, TryFromRow, Debug, PartialEq)]
+    #[table = "people"]
+    #[projection(PersonName, fields(id, name), derive(Debug, PartialEq))]
+    struct Person {
+        id: i64,
+        data: JsonObject<serde_json::Value>,
+        #[generated("json_extract(data, '$.name')", stored)]
+        name: Option<String>,
+        #[generated("json_extract(data, '$.age')")]
+        age: Option<i64>,
+    }
+
+    #[test]
+    fn derived_schema() {
+        let schema = Person::schema();
+        assert_eq!(
+            schema.create_sql(),
+            "create table if not exists \"people\"( \"id\" integer not null, \"data\" json not null, \
+            \"name\" text generated always as (json_extract(data, '$.name')) stored, \
+            \"age\" integer generated always as (json_extract(data, '$.age')) virtual )"
+        );
+        assert_eq!(
+            schema.insert_sql(),
+            "insert into \"people\"(\"id\", \"data\") values (?, ?)"
+        );
+        assert_eq!(Person::CREATE_SQL, schema.create_sql());
+    }
+
+    #[test]
+    fn generated_columns_are_read_and_indexable() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        let res = Person::create_table(&db);
+        assert!(res.is_ok(), "Failed to create table: {:?}", res);
+        db.execute(
+            &format!(
+                "create index people_name on people({})",
+                path().field("name").extract_sql("data")
+            ),
+            (),
+        )
+        .expect("Failed to create index");
+
+        let person = Person {
+            id: 1,
+            data: JsonObject::new(serde_json::json!({"name": "Ada", "age": 36})),
+            name: None,
+            age: None,
+        };
+        let res = db.execute(&Person::schema().insert_sql(), &*person.params());
+        assert!(res.is_ok(), "Failed to insert row: {:?}", res);
+
+        let res = db.query_row("select * from people where name = 'Ada'", (), |row| {
+            Person::try_from(row)
+        });
+        assert!(res.is_ok(), "Failed to retrieve row: {:?}", res);
+        let person = res.unwrap();
+        assert_eq!(person.name.as_deref(), Some("Ada"));
+        assert_eq!(person.age, Some(36));
+    }
+
+    #[test]
+    fn projection_leaves_out_columns() {
+        assert_eq!(
+            PersonName::select_sql(),
+            "select \"id\", \"name\" from \"people\""
+        );
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        Person::create_table(&db).expect("Failed to create table");
+        db.execute(
+            "insert into people(id, data) values (1, '{\"name\": \"Ada\"}')",
+            (),
+        )
+        .expect("Failed to insert row");
+
+        let res = db.query_row(
+            &format!("{} where id = ?", PersonName::select_sql()),
+            (1,),
+            |row| PersonName::try_from(row),
+        );
+        assert!(res.is_ok(), "Failed to retrieve projection: {:?}", res);
+        assert_eq!(
+            res.unwrap(),
+            PersonName {
+                id: 1,
+                name: Some("Ada".to_string())
+            }
+        );
+    }
+
+    #[derive(Table, TryFromRow, Debug)]
+    #[table = "documents"]
+    struct Document {
+        #[try_from_row(column = "doc_id")]
+        #[primary_key]
+        id: i64,
+        title: String,
+        #[try_from_row(skip)]
+        cached: Option<String>,
+        #[try_from_row(lazy = "documents", rowid = "doc_id")]
+        body: crate::lazy::Lazy<String>,
+    }
+
+    #[test]
+    fn columns_follow_try_from_row() {
+        assert_eq!(
+            Document::CREATE_SQL,
+            "create table if not exists \"documents\"( \"doc_id\" integer primary key not null, \
+            \"title\" text not null )"
+        );
+        assert_eq!(Document::CREATE_SQL, Document::schema().create_sql());
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        Document::create_table(&db).expect("Failed to create table");
+        db.execute_batch("alter table documents add column body text")
+            .unwrap();
+
+        let mut doc = Document {
+            id: 3,
+            title: "notes".into(),
+            cached: Some("ignored".into()),
+            body: crate::lazy::Lazy::new("documents", "body", 3),
+        };
+        let res = doc.insert(&db, &crate::date_time::SystemClock);
+        assert!(res.is_ok(), "Failed to insert row: {:?}", res);
+        let res = db.query_row("select doc_id, title from documents", (), |row| {
+            Document::try_from(row)
+        });
+        assert!(res.is_ok(), "Failed to retrieve row: {:?}", res);
+        let doc = res.unwrap();
+        assert_eq!((doc.id, doc.title.as_str(), doc.cached), (3, "notes", None));
+    }
+
+    #[derive(Table)]
+    struct Setting {
+        key: String,
+        value: Option<f64>,
+        enabled: bool,
+    }
+
+    #[test]
+    fn verify_schema() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        assert_eq!(
+            Setting::schema().verify(&db).unwrap(),
+            vec![Mismatch::MissingTable]
+        );
+        Setting::create_table(&db).expect("Failed to create table");
+        let res = Setting::schema().verify(&db);
+        assert!(res.is_ok(), "Failed to verify schema: {:?}", res);
+        assert!(res.unwrap().is_empty());
+
+        db.execute_batch(
+            "insert into setting values ('a', 1.5, 1), ('b', null, 0), ('c', 'high', 1);
+            alter table setting rename column enabled to active;",
+        )
+        .unwrap();
+        assert_eq!(
+            Setting::schema().verify(&db).unwrap(),
+            vec![
+                Mismatch::Storage {
+                    column: "value".into(),
+                    expected: StorageClass::Real,
+                    rows: 1
+                },
+                Mismatch::MissingColumn("enabled".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn column_defaults() {
+        let column = Column::of::<Option<i64>>("n").default("0");
+        assert_eq!(column.definition_sql(), "\"n\" integer default (0)");
+        assert_eq!(
+            Column::of::<String>("s").definition_sql(),
+            "\"s\" text not null"
+        );
+    }
+
+    #[derive(Table)]
+    #[strict]
+    struct Account {
+        #[primary_key]
+        id: Option<i64>,
+        #[unique]
+        email: String,
+        profile: Option<JsonObject<serde_json::Value>>,
+        #[not_null]
+        verified: Option<bool>,
+    }
+
+    #[derive(Table)]
+    struct Membership {
+        #[primary_key]
+        account: i64,
+        #[primary_key]
+        team: i64,
+    }
+
+    #[test]
+    fn table_constraints() {
+        assert_eq!(
+            Account::CREATE_SQL,
+            "create table if not exists \"account\"( \"id\" integer primary key, \
+            \"email\" text unique not null, \"profile\" text, \"verified\" integer not null ) strict"
+        );
+        assert_eq!(Account::CREATE_SQL, Account::schema().create_sql());
+        assert_eq!(
+            Membership::CREATE_SQL,
+            "create table if not exists \"membership\"( \"account\" integer not null, \
+            \"team\" integer not null, primary key (\"account\", \"team\") )"
+        );
+        assert_eq!(Membership::CREATE_SQL, Membership::schema().create_sql());
+
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        let res = Account::create_table(&db);
+        assert!(res.is_ok(), "Failed to create table: {:?}", res);
+        Membership::create_table(&db).expect("Failed to create table");
+        let insert = "insert into account(email, verified) values (?, ?)";
+        assert!(db.execute(insert, ("a@example.com", true)).is_ok());
+        assert!(db.execute(insert, ("a@example.com", false)).is_err());
+        assert!(db.execute(insert, ("b@example.com", "yes")).is_err());
+        let res = Account::schema().verify(&db);
+        assert_eq!(res.unwrap(), vec![]);
+        db.execute_batch("insert into membership values (1, 1), (1, 2)")
+            .unwrap();
+        assert!(db
+            .execute_batch("insert into membership values (1, 2)")
+            .is_err());
+    }
+}
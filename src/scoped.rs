@@ -0,0 +1,218 @@
+use std::marker::PhantomData;
+
+use rusqlite::{Connection, Row, ToSql};
+use thiserror::Error;
+
+use crate::{
+    crud::{Delete, Insert, Update},
+    date_time::SystemClock,
+    row::Columns,
+    util::quote_ident,
+    SqlNewtype,
+};
+
+/// The tenant owning a row of a table shared by several tenants.
+#[derive(SqlNewtype, Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TenantId(pub i64);
+
+impl std::fmt::Display for TenantId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// A struct stored in a table shared by several tenants, each row owned by
+/// the tenant in one of its columns. Usually derived with
+/// `#[derive(TenantScoped)]`, which reads the table name as the CRUD
+/// derives do and takes the tenant from the field marked `#[tenant]`.
+pub trait TenantScoped {
+    const TABLE: &'static str;
+    const TENANT_COLUMN: &'static str;
+
+    /// The tenant owning the row.
+    fn tenant(&self) -> TenantId;
+}
+
+/// The rows of `T` owned by one tenant. Every statement is restricted to
+/// the tenant's rows, and rows of other tenants are rejected before being
+/// written, so that code handling a request for one tenant can't read or
+/// change another's rows by mistake.
+pub struct Scoped<'conn, T> {
+    conn: &'conn Connection,
+    tenant: TenantId,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<'conn, T: TenantScoped> Scoped<'conn, T> {
+    pub fn new(conn: &'conn Connection, tenant: TenantId) -> Self {
+        Self {
+            conn,
+            tenant,
+            _marker: PhantomData,
+        }
+    }
+    pub fn tenant(&self) -> TenantId {
+        self.tenant
+    }
+
+    /// `sql`, whose conditions end the statement, also restricted to the
+    /// tenant bound to parameter `param`.
+    fn restrict(sql: &str, param: usize) -> String {
+        format!("{} and {} = ?{}", sql, quote_ident(T::TENANT_COLUMN), param)
+    }
+
+    fn check(&self, row: &T) -> Result<(), Error> {
+        match row.tenant() {
+            found if found == self.tenant => Ok(()),
+            found => Err(Error::WrongTenant {
+                expected: self.tenant,
+                found,
+            }),
+        }
+    }
+
+    /// The tenant's rows.
+    pub fn all(&self) -> rusqlite::Result<Vec<T>>
+    where
+        for<'r, 'stmt> T: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error> + Columns,
+    {
+        self.filter("true", &[])
+    }
+
+    /// The tenant's rows matching `condition`, with parameters `params`
+    /// (anonymous, or numbered from `?1`).
+    pub fn filter(&self, condition: &str, params: &[&dyn ToSql]) -> rusqlite::Result<Vec<T>>
+    where
+        for<'r, 'stmt> T: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error> + Columns,
+    {
+        let sql = Self::restrict(
+            &format!(
+                "select {} from {} where ({})",
+                T::select_list(),
+                quote_ident(T::TABLE),
+                condition
+            ),
+            params.len() + 1,
+        );
+        let mut params = params.to_vec();
+        params.push(&self.tenant);
+        let mut stmt = self.conn.prepare_cached(&sql)?;
+        let rows = stmt.query_map(&*params, |row| T::try_from(row))?.collect();
+        rows
+    }
+
+    /// Insert `row`, which must belong to the tenant, returning its rowid.
+    pub fn insert(&self, row: &T) -> Result<i64, Error>
+    where
+        T: Insert,
+    {
+        self.check(row)?;
+        Ok(row.insert(self.conn)?)
+    }
+
+    /// Update `row`, which must belong to the tenant, returning the number
+    /// of rows changed (0 if the tenant has no row with its key).
+    pub fn update(&self, row: &T) -> Result<usize, Error>
+    where
+        T: Update,
+    {
+        self.check(row)?;
+        row.with_update_params(&SystemClock, |params| {
+            let sql = Self::restrict(&T::update_sql(), params.len() + 1);
+            let mut params = params.to_vec();
+            params.push(&self.tenant);
+            Ok(self.conn.prepare_cached(&sql)?.execute(&*params)?)
+        })
+    }
+
+    /// Delete the tenant's row with key `id`, returning whether there was
+    /// one.
+    pub fn delete(&self, id: &T::Id) -> rusqlite::Result<bool>
+    where
+        T: Delete,
+    {
+        let sql = Self::restrict(&T::delete_sql(), 2);
+        let deleted = self
+            .conn
+            .prepare_cached(&sql)?
+            .execute([id as &dyn ToSql, &self.tenant])?;
+        Ok(deleted > 0)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("the row belongs to tenant {found}, not {expected}")]
+    WrongTenant { expected: TenantId, found: TenantId },
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::{Delete, Insert, TenantScoped, TryFromRow, Update};
+
+    #[derive(TryFromRow, Insert, Update, Delete, TenantScoped, Debug, PartialEq)]
+    struct Note {
+        #[id]
+        id: i64,
+        #[tenant]
+        tenant_id: TenantId,
+        body: String,
+    }
+
+    fn note(id: i64, tenant: i64, body: &str) -> Note {
+        Note {
+            id,
+            tenant_id: TenantId(tenant),
+            body: body.to_string(),
+        }
+    }
+
+    #[test]
+    fn scoped_to_tenant() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch(
+            "create table note( id integer primary key, tenant_id integer, body text )",
+        )
+        .expect("Failed to create table");
+        let acme = Scoped::<Note>::new(&db, TenantId(1));
+        let globex = Scoped::<Note>::new(&db, TenantId(2));
+
+        let res = acme.insert(&note(1, 1, "acme's"));
+        assert!(res.is_ok(), "Failed to insert note: {:?}", res);
+        globex.insert(&note(2, 2, "globex's")).unwrap();
+        globex.insert(&note(3, 2, "globex's too")).unwrap();
+        let res = acme.insert(&note(4, 2, "planted"));
+        assert!(
+            matches!(
+                res,
+                Err(Error::WrongTenant {
+                    expected: TenantId(1),
+                    found: TenantId(2)
+                })
+            ),
+            "Inserted another tenant's row: {:?}",
+            res
+        );
+
+        assert_eq!(acme.all().unwrap(), vec![note(1, 1, "acme's")]);
+        assert_eq!(globex.all().unwrap().len(), 2);
+        let res = globex.filter("body like ?", &[&"%too"]);
+        assert!(res.is_ok(), "Failed to filter notes: {:?}", res);
+        assert_eq!(res.unwrap(), vec![note(3, 2, "globex's too")]);
+        assert!(acme.filter("id = ?1", &[&2]).unwrap().is_empty());
+
+        // Acme can't change Globex's rows, even knowing their ids.
+        assert_eq!(acme.update(&note(2, 1, "edited")).unwrap(), 0);
+        assert!(!acme.delete(&2).unwrap());
+        assert_eq!(globex.all().unwrap()[0], note(2, 2, "globex's"));
+
+        assert_eq!(acme.update(&note(1, 1, "edited")).unwrap(), 1);
+        assert!(globex.delete(&2).unwrap());
+        assert_eq!(acme.all().unwrap(), vec![note(1, 1, "edited")]);
+        assert_eq!(globex.all().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,231 @@
+use std::path::Path;
+
+use rusqlite::{functions::FunctionFlags, types::ValueRef, Connection};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    schema::{Table, TableSchema},
+    util::quote_ident,
+};
+
+/// The name of the SQL function `Scrub::Hash` columns are passed through.
+pub const HASH_FUNCTION: &str = "scrub_hash";
+
+/// How a column holding personal data is replaced in a scrubbed copy.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Scrub {
+    /// A salted hash of the value, as TEXT. Equal values hash equally, so
+    /// joins and duplicates are preserved. NULLs stay NULL.
+    Hash,
+    /// NULL, for nullable columns.
+    Null,
+    /// A SQL expression evaluated for each row, eg
+    /// `'user' || rowid || '@example.com'`.
+    Replace(String),
+}
+
+impl Scrub {
+    /// The expression replacing `column`.
+    pub fn expr_sql(&self, column: &str) -> String {
+        match self {
+            Scrub::Hash => format!("{}({})", HASH_FUNCTION, quote_ident(column)),
+            Scrub::Null => "null".to_string(),
+            Scrub::Replace(expr) => expr.clone(),
+        }
+    }
+}
+
+/// Produces copies of a database with the columns marked as personal data
+/// (`#[pii]` in `#[derive(Table)]`, or `Column::pii`) replaced, so databases
+/// reproducing a bug can be shared without leaking user data.
+#[derive(Clone, Debug, Default)]
+pub struct Scrubber {
+    tables: Vec<TableSchema>,
+    salt: String,
+}
+
+impl Scrubber {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Scrub the columns of `T` marked `#[pii]`.
+    pub fn table<T: Table>(self) -> Self {
+        self.schema(T::schema())
+    }
+    pub fn schema(mut self, schema: TableSchema) -> Self {
+        self.tables.push(schema);
+        self
+    }
+    /// Salt the hashes, so short values can't be recovered by hashing
+    /// guesses. Keep the salt private to whoever produces copies.
+    pub fn salt(mut self, salt: &str) -> Self {
+        self.salt = salt.to_string();
+        self
+    }
+
+    /// The `UPDATE` statements scrubbing each table with PII columns.
+    pub fn updates_sql(&self) -> Vec<String> {
+        self.tables
+            .iter()
+            .filter_map(|table| {
+                let assignments: Vec<_> = table
+                    .columns
+                    .iter()
+                    .filter_map(|c| {
+                        let scrub = c.pii.as_ref()?;
+                        Some(format!(
+                            "{} = {}",
+                            quote_ident(&c.name),
+                            scrub.expr_sql(&c.name)
+                        ))
+                    })
+                    .collect();
+                if assignments.is_empty() {
+                    return None;
+                }
+                Some(format!(
+                    "update {} set {}",
+                    quote_ident(&table.name),
+                    assignments.join(", ")
+                ))
+            })
+            .collect()
+    }
+
+    /// Scrub the database of `conn` in place, in a single savepoint.
+    /// `secure_delete` is enabled so that the original values are
+    /// overwritten rather than left in free pages.
+    pub fn scrub(&self, conn: &Connection) -> rusqlite::Result<()> {
+        self.register_hash(conn)?;
+        conn.execute_batch("pragma secure_delete = on")?;
+        conn.execute_batch("savepoint scrub")?;
+        for update in self.updates_sql() {
+            if let Err(e) = conn.execute_batch(&update) {
+                conn.execute_batch("rollback to scrub; release scrub")?;
+                return Err(e);
+            }
+        }
+        conn.execute_batch("release scrub")
+    }
+    /// Write a scrubbed copy of the database of `conn` to `path`, which must
+    /// not exist. The original database is not modified.
+    pub fn scrubbed_copy<P: AsRef<Path>>(
+        &self,
+        conn: &Connection,
+        path: P,
+    ) -> rusqlite::Result<()> {
+        conn.execute(
+            "vacuum into ?",
+            (path.as_ref().to_string_lossy().into_owned(),),
+        )?;
+        let copy = Connection::open(path)?;
+        self.scrub(&copy)?;
+        // Rebuild the file, so nothing of the original pages survives.
+        copy.execute_batch("vacuum")
+    }
+
+    fn register_hash(&self, conn: &Connection) -> rusqlite::Result<()> {
+        let salt = self.salt.clone();
+        let flags = FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC;
+        conn.create_scalar_function(HASH_FUNCTION, 1, flags, move |ctx| {
+            let text;
+            let bytes = match ctx.get_raw(0) {
+                ValueRef::Null => return Ok(None),
+                ValueRef::Integer(i) => {
+                    text = i.to_string();
+                    text.as_bytes()
+                }
+                ValueRef::Real(f) => {
+                    text = f.to_string();
+                    text.as_bytes()
+                }
+                ValueRef::Text(t) | ValueRef::Blob(t) => t,
+            };
+            let digest = Sha256::new()
+                .chain_update(salt.as_bytes())
+                .chain_update(bytes)
+                .finalize();
+            Ok(Some(
+                digest[..16]
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect::<String>(),
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::{Table, TryFromRow};
+
+    #[derive(Table, TryFromRow, Debug, PartialEq)]
+    struct Account {
+        id: i64,
+        #[pii(replace = "'user' || id || '@example.com'")]
+        email: String,
+        #[pii]
+        name: String,
+        #[pii(null)]
+        phone: Option<String>,
+        plan: String,
+    }
+
+    #[test]
+    fn derived_updates() {
+        assert_eq!(
+            Scrubber::new().table::<Account>().updates_sql(),
+            vec![
+                "update \"account\" set \"email\" = 'user' || id || '@example.com', \
+                \"name\" = scrub_hash(\"name\"), \"phone\" = null"
+            ]
+        );
+    }
+
+    #[test]
+    fn scrubbed_copy() {
+        let dir = tempfile::tempdir().expect("Failed to create directory");
+        let db =
+            Connection::open(dir.path().join("original.db")).expect("Failed to open connection");
+        Account::create_table(&db).expect("Failed to create table");
+        for (id, name) in [(1, "Ada"), (2, "Grace"), (3, "Ada")] {
+            db.execute(
+                "insert into account values (?, ?, ?, '555-1234', 'pro')",
+                (id, format!("{}@mail.com", name.to_lowercase()), name),
+            )
+            .expect("Failed to insert row");
+        }
+
+        let path = dir.path().join("scrubbed.db");
+        let res = Scrubber::new()
+            .table::<Account>()
+            .salt("pepper")
+            .scrubbed_copy(&db, &path);
+        assert!(res.is_ok(), "Failed to scrub copy: {:?}", res);
+
+        let copy = Connection::open(&path).expect("Failed to open copy");
+        let mut stmt = copy.prepare("select * from account order by id").unwrap();
+        let accounts: Vec<Account> = stmt
+            .query_map((), |row| Account::try_from(row))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(accounts[0].email, "user1@example.com");
+        assert_eq!(accounts[0].phone, None);
+        assert_eq!(accounts[0].plan, "pro");
+        assert_ne!(accounts[0].name, "Ada");
+        assert_eq!(accounts[0].name, accounts[2].name);
+        assert_ne!(accounts[0].name, accounts[1].name);
+
+        let original: String = db
+            .query_row("select name from account where id = 1", (), |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(original, "Ada");
+        let raw = std::fs::read(&path).unwrap();
+        assert!(!raw.windows(5).any(|w| w == b"Grace"));
+    }
+}
@@ -0,0 +1,239 @@
+use rusqlite::{Connection, ToSql};
+use thiserror::Error;
+
+use crate::{crud::Upsert, util::quote_ident};
+
+/// What `Seeds::sync` did to a table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SeedReport {
+    pub table: &'static str,
+    /// The number of rows inserted or updated.
+    pub written: usize,
+    /// The number of rows deleted because they're no longer seeded.
+    pub pruned: usize,
+}
+
+type SyncFn = Box<dyn Fn(&Connection) -> rusqlite::Result<SeedReport>>;
+
+/// Reference data, such as lookup tables and default settings, declared as
+/// rows and synced into the database, eg on startup.
+///
+/// Rows are written with `Upsert`, so they're identified by their conflict
+/// target (their natural key), and syncing is idempotent: a changed row is
+/// updated in place, keeping its rowid. Unlike migrations, seeds aren't
+/// versioned, so they can be edited freely; the tables they're written to
+/// must already exist.
+#[derive(Default)]
+pub struct Seeds {
+    tables: Vec<(&'static str, SyncFn)>,
+}
+
+impl Seeds {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Seed `rows`, leaving any other rows of their table alone.
+    pub fn seed<T: Upsert + 'static>(self, rows: Vec<T>) -> Self {
+        self.push(rows, false)
+    }
+    /// Seed `rows`, deleting the other rows of their table, so that entries
+    /// removed from the seeds are removed from the database.
+    pub fn seed_pruned<T: Upsert + 'static>(self, rows: Vec<T>) -> Self {
+        self.push(rows, true)
+    }
+    fn push<T: Upsert + 'static>(mut self, rows: Vec<T>, prune: bool) -> Self {
+        self.tables.push((
+            T::TABLE,
+            Box::new(move |conn| sync_rows(conn, &rows, prune)),
+        ));
+        self
+    }
+
+    /// Write the seeds, in the order they were added, in a savepoint. If a
+    /// table fails to sync, none are changed.
+    pub fn sync(&self, conn: &Connection) -> Result<Vec<SeedReport>, Error> {
+        conn.execute_batch("savepoint seeds")?;
+        let mut reports = Vec::with_capacity(self.tables.len());
+        for (table, sync) in &self.tables {
+            match sync(conn) {
+                Ok(report) => reports.push(report),
+                Err(source) => {
+                    conn.execute_batch("rollback to seeds; release seeds")?;
+                    return Err(Error::Failed { table, source });
+                }
+            }
+        }
+        conn.execute_batch("release seeds")?;
+        Ok(reports)
+    }
+}
+
+fn sync_rows<T: Upsert>(
+    conn: &Connection,
+    rows: &[T],
+    prune: bool,
+) -> rusqlite::Result<SeedReport> {
+    let mut report = SeedReport {
+        table: T::TABLE,
+        written: 0,
+        pruned: 0,
+    };
+    for row in rows {
+        report.written += row.upsert(conn)?;
+    }
+    if !prune {
+        return Ok(report);
+    }
+
+    let key = T::conflict_target();
+    let sql = if rows.is_empty() {
+        format!("delete from {}", quote_ident(T::TABLE))
+    } else {
+        let mut params = 1..;
+        let mut tuple = || {
+            let params: Vec<_> = key
+                .iter()
+                .map(|_| format!("?{}", params.next().unwrap()))
+                .collect();
+            format!("({})", params.join(", "))
+        };
+        let seeded: Vec<_> = rows.iter().map(|_| tuple()).collect();
+        format!(
+            "delete from {} where ({}) not in (values {})",
+            quote_ident(T::TABLE),
+            key.iter()
+                .map(|c| quote_ident(c))
+                .collect::<Vec<_>>()
+                .join(", "),
+            seeded.join(", ")
+        )
+    };
+    let params: Vec<&dyn ToSql> = rows.iter().flat_map(|r| r.conflict_params()).collect();
+    report.pruned = conn.execute(&sql, &*params)?;
+    Ok(report)
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Seeding {table} failed: {source}")]
+    Failed {
+        table: &'static str,
+        source: rusqlite::Error,
+    },
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::Upsert;
+
+    #[derive(Upsert)]
+    struct Currency {
+        #[id]
+        code: &'static str,
+        name: &'static str,
+    }
+
+    #[derive(Upsert)]
+    #[conflict_target(key)]
+    struct Setting {
+        key: &'static str,
+        value: i64,
+    }
+
+    fn currencies(db: &Connection) -> Vec<(String, String)> {
+        db.prepare("select code, name from currency order by code")
+            .unwrap()
+            .query_map((), |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap()
+    }
+
+    fn seeds() -> Seeds {
+        Seeds::new()
+            .seed_pruned(vec![
+                Currency {
+                    code: "EUR",
+                    name: "Euro",
+                },
+                Currency {
+                    code: "USD",
+                    name: "US dollar",
+                },
+            ])
+            .seed(vec![Setting {
+                key: "page_size",
+                value: 50,
+            }])
+    }
+
+    #[test]
+    fn sync_seeds() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch(
+            "create table currency( code text primary key, name text );
+            create table setting( key text unique, value integer );
+            insert into currency values ('USD', 'Dollar'), ('DEM', 'Deutsche Mark');
+            insert into setting values ('theme', 1);",
+        )
+        .expect("Failed to create tables");
+
+        let res = seeds().sync(&db);
+        assert!(res.is_ok(), "Failed to sync seeds: {:?}", res);
+        assert_eq!(
+            res.unwrap(),
+            vec![
+                SeedReport {
+                    table: "currency",
+                    written: 2,
+                    pruned: 1
+                },
+                SeedReport {
+                    table: "setting",
+                    written: 1,
+                    pruned: 0
+                }
+            ]
+        );
+        assert_eq!(
+            currencies(&db),
+            vec![
+                ("EUR".to_string(), "Euro".to_string()),
+                ("USD".to_string(), "US dollar".to_string())
+            ]
+        );
+        let settings: i64 = db
+            .query_row("select count(*) from setting", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(settings, 2, "Unpruned table was pruned");
+
+        let res = seeds().sync(&db);
+        assert!(res.is_ok(), "Failed to sync seeds again: {:?}", res);
+        assert_eq!(res.unwrap()[0].pruned, 0);
+
+        db.execute_batch("drop table setting").unwrap();
+        let res = Seeds::new()
+            .seed_pruned(Vec::<Currency>::new())
+            .seed(vec![Setting {
+                key: "page_size",
+                value: 20,
+            }])
+            .sync(&db);
+        assert!(
+            matches!(
+                res,
+                Err(Error::Failed {
+                    table: "setting",
+                    ..
+                })
+            ),
+            "Seeding a missing table didn't fail: {:?}",
+            res
+        );
+        assert_eq!(currencies(&db).len(), 2, "Failed sync was not rolled back");
+    }
+}
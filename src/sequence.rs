@@ -0,0 +1,183 @@
+use std::{cell::RefCell, collections::HashMap, ops::Range};
+
+use rusqlite::{Connection, OptionalExtension};
+
+use crate::{execute::Executor, util::quote_ident};
+
+/// Named counters stored in a table, for numbering that `AUTOINCREMENT` can't
+/// provide (per-tenant or per-year sequences, invoice numbers, ...).
+///
+/// Values are unique and increasing but may contain gaps. With a batch size
+/// greater than one, blocks of values are reserved from the database at once
+/// and handed out from memory; any values left in a block when the `Sequence`
+/// is dropped are skipped. Blocks are never cached while a transaction is
+/// open, as rolling it back would release the block to other writers.
+pub struct Sequence<'conn> {
+    conn: &'conn Connection,
+    /// The quoted table name.
+    table: String,
+    batch_size: i64,
+    reserved: RefCell<HashMap<String, Range<i64>>>,
+}
+
+impl<'conn> Sequence<'conn> {
+    pub const DEFAULT_TABLE: &'static str = "sequences";
+
+    pub fn new(conn: &'conn Connection) -> Self {
+        Self::with_table(conn, Self::DEFAULT_TABLE)
+    }
+    pub fn with_table(conn: &'conn Connection, table: &str) -> Self {
+        Self {
+            conn,
+            table: quote_ident(table),
+            batch_size: 1,
+            reserved: RefCell::new(HashMap::new()),
+        }
+    }
+    /// Reserve `n` values from the database at a time. Values below 1 are
+    /// treated as 1.
+    pub fn batch_size(mut self, n: i64) -> Self {
+        self.batch_size = n.max(1);
+        self
+    }
+
+    pub fn create_sql(&self) -> String {
+        format!(
+            "create table if not exists {}(
+                name text primary key not null,
+                next_value integer not null
+            )",
+            self.table
+        )
+    }
+    /// Create the sequence table if it does not already exist.
+    pub fn create_table<E: Executor + ?Sized>(&self, exec: &E) -> rusqlite::Result<()> {
+        exec.run_batch(&self.create_sql())
+    }
+
+    /// Retrieve the next value of the named sequence. New sequences start at 1.
+    pub fn next_value(&self, name: &str) -> rusqlite::Result<i64> {
+        if let Some(range) = self.reserved.borrow_mut().get_mut(name) {
+            if let Some(v) = range.next() {
+                return Ok(v);
+            }
+        }
+
+        if self.batch_size == 1 || !self.conn.is_autocommit() {
+            return Ok(self.reserve(name, 1)?.start);
+        }
+        let mut range = self.reserve(name, self.batch_size)?;
+        let v = range.next().expect("batch size is at least 1");
+        self.reserved.borrow_mut().insert(name.to_string(), range);
+        Ok(v)
+    }
+
+    /// Atomically reserve `n` consecutive values of the named sequence,
+    /// bypassing the in-memory batch.
+    pub fn reserve(&self, name: &str, n: i64) -> rusqlite::Result<Range<i64>> {
+        let end: i64 = self.conn.query_row(
+            &format!(
+                "insert into {}(name, next_value) values (?1, 1 + ?2)
+                on conflict(name) do update set next_value = next_value + ?2
+                returning next_value",
+                self.table
+            ),
+            (name, n),
+            |row| row.get(0),
+        )?;
+        Ok(end - n..end)
+    }
+
+    /// The value the database will hand out next, if the sequence exists.
+    /// Values reserved in memory by any `Sequence` are already excluded.
+    pub fn peek(&self, name: &str) -> rusqlite::Result<Option<i64>> {
+        self.conn
+            .query_row(
+                &format!("select next_value from {} where name = ?", self.table),
+                (name,),
+                |row| row.get(0),
+            )
+            .optional()
+    }
+
+    /// Restart the named sequence so that the next value is `next`. Values
+    /// reserved in memory by this instance are discarded.
+    pub fn restart(&self, name: &str, next: i64) -> rusqlite::Result<()> {
+        self.conn.execute(
+            &format!(
+                "insert into {}(name, next_value) values (?1, ?2)
+                on conflict(name) do update set next_value = excluded.next_value",
+                self.table
+            ),
+            (name, next),
+        )?;
+        self.reserved.borrow_mut().remove(name);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn setup() -> Connection {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        Sequence::new(&db)
+            .create_table(&db)
+            .expect("Failed to create table");
+        db
+    }
+
+    #[test]
+    fn independent_sequences() {
+        let db = setup();
+        let seq = Sequence::new(&db);
+
+        let invoices: Vec<_> = (0..3).map(|_| seq.next_value("invoice").unwrap()).collect();
+        assert_eq!(invoices, vec![1, 2, 3]);
+        assert_eq!(seq.next_value("tenant-2/invoice").unwrap(), 1);
+        assert_eq!(seq.peek("invoice").unwrap(), Some(4));
+        assert_eq!(seq.peek("missing").unwrap(), None);
+
+        seq.restart("invoice", 100).unwrap();
+        assert_eq!(seq.next_value("invoice").unwrap(), 100);
+    }
+
+    #[test]
+    fn batched_reservation() {
+        let db = setup();
+        let a = Sequence::new(&db).batch_size(10);
+        let b = Sequence::new(&db).batch_size(10);
+
+        let mut values = vec![];
+        for _ in 0..15 {
+            values.push(a.next_value("order").unwrap());
+            values.push(b.next_value("order").unwrap());
+        }
+        values.sort_unstable();
+        values.dedup();
+        assert_eq!(values.len(), 30, "Batched sequences handed out duplicates");
+        assert_eq!(
+            Sequence::new(&db).peek("order").unwrap(),
+            Some(41),
+            "Expected four blocks of ten to be reserved"
+        );
+    }
+
+    #[test]
+    fn rolled_back_values_are_not_cached() {
+        let mut db = setup();
+        {
+            let tx = db.transaction().expect("Failed to begin transaction");
+            {
+                let seq = Sequence::new(&tx).batch_size(10);
+                assert_eq!(seq.next_value("order").unwrap(), 1);
+                assert_eq!(seq.next_value("order").unwrap(), 2);
+                assert_eq!(seq.peek("order").unwrap(), Some(3));
+            }
+            tx.rollback().expect("Failed to roll back");
+        }
+        let seq = Sequence::new(&db);
+        assert_eq!(seq.next_value("order").unwrap(), 1);
+    }
+}
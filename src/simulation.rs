@@ -0,0 +1,333 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Barrier},
+    time::Duration,
+};
+
+use rusqlite::{Connection, ErrorCode, OpenFlags};
+
+use crate::connection::{ConnectionBuilder, Error};
+
+type Workload = Arc<dyn Fn(&Connection, &mut Step) -> rusqlite::Result<()> + Send + Sync>;
+type Invariant = Box<dyn Fn(&Connection) -> rusqlite::Result<bool>>;
+
+/// The context of one iteration of a workload.
+#[derive(Debug)]
+pub struct Step {
+    /// The thread running the workload, counting from 0 across all workers.
+    pub thread: usize,
+    pub iteration: usize,
+    rng: u64,
+}
+impl Step {
+    /// A pseudo-random number, determined by the simulation's seed and the
+    /// thread, for randomizing workloads reproducibly.
+    pub fn random(&mut self) -> u64 {
+        // xorshift64*
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        self.rng.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+}
+
+/// A workload failing in one iteration.
+#[derive(Debug)]
+pub struct WorkloadFailure {
+    pub thread: usize,
+    pub iteration: usize,
+    pub error: rusqlite::Error,
+}
+
+/// The outcome of a `Simulation`.
+#[derive(Debug, Default)]
+pub struct SimulationReport {
+    /// Iterations which completed.
+    pub completed: usize,
+    /// Iterations which failed with `SQLITE_BUSY` or `SQLITE_LOCKED`.
+    pub busy: usize,
+    pub failures: Vec<WorkloadFailure>,
+    /// The names of the invariants which didn't hold afterwards.
+    pub violations: Vec<String>,
+}
+impl SimulationReport {
+    /// Whether every iteration completed and every invariant held.
+    pub fn is_clean(&self) -> bool {
+        self.busy == 0 && self.failures.is_empty() && self.violations.is_empty()
+    }
+}
+
+/// Runs reader and writer workloads concurrently against one database, each
+/// on its own thread and connection, then checks invariants. Workloads are
+/// started together, and sleep a random delay before each iteration to
+/// shake out different interleavings.
+///
+/// Meant for tests of code relying on SQLite's locking, eg that concurrent
+/// transfers between accounts preserve the total balance.
+pub struct Simulation {
+    path: PathBuf,
+    builder: ConnectionBuilder,
+    workers: Vec<(bool, usize, Workload)>,
+    invariants: Vec<(String, Invariant)>,
+    iterations: usize,
+    max_delay: Duration,
+    seed: u64,
+}
+
+impl Simulation {
+    /// Simulate access to the database at `path`, which should already have
+    /// its schema.
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            builder: ConnectionBuilder::new().busy_timeout(Duration::from_secs(5)),
+            workers: vec![],
+            invariants: vec![],
+            iterations: 100,
+            max_delay: Duration::from_millis(1),
+            seed: 0x5eed,
+        }
+    }
+    /// Open the workers' connections with `builder`, rather than with a 5
+    /// second busy timeout. Readers are opened read only, replacing the
+    /// builder's flags.
+    pub fn connection(mut self, builder: ConnectionBuilder) -> Self {
+        self.builder = builder;
+        self
+    }
+    /// Shorthand to set the busy timeout of the workers' connections.
+    pub fn busy_timeout(mut self, timeout: Duration) -> Self {
+        self.builder = self.builder.busy_timeout(timeout);
+        self
+    }
+    /// Run `workload` on `threads` read only connections.
+    pub fn readers<F>(mut self, threads: usize, workload: F) -> Self
+    where
+        F: Fn(&Connection, &mut Step) -> rusqlite::Result<()> + Send + Sync + 'static,
+    {
+        self.workers.push((true, threads, Arc::new(workload)));
+        self
+    }
+    /// Run `workload` on `threads` read-write connections.
+    pub fn writers<F>(mut self, threads: usize, workload: F) -> Self
+    where
+        F: Fn(&Connection, &mut Step) -> rusqlite::Result<()> + Send + Sync + 'static,
+    {
+        self.workers.push((false, threads, Arc::new(workload)));
+        self
+    }
+    /// Check that `invariant` holds once every workload has finished.
+    pub fn invariant<F>(mut self, name: &str, invariant: F) -> Self
+    where
+        F: Fn(&Connection) -> rusqlite::Result<bool> + 'static,
+    {
+        self.invariants
+            .push((name.to_string(), Box::new(invariant)));
+        self
+    }
+    /// Iterations run by each thread (100 by default).
+    pub fn iterations(mut self, n: usize) -> Self {
+        self.iterations = n;
+        self
+    }
+    /// The longest delay before each iteration (1ms by default).
+    pub fn max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn run(&self) -> Result<SimulationReport, Error> {
+        let mut connections = vec![];
+        for (read_only, threads, workload) in &self.workers {
+            for _ in 0..*threads {
+                let builder = if *read_only {
+                    self.builder.clone().flags(
+                        (OpenFlags::default()
+                            - OpenFlags::SQLITE_OPEN_READ_WRITE
+                            - OpenFlags::SQLITE_OPEN_CREATE)
+                            | OpenFlags::SQLITE_OPEN_READ_ONLY,
+                    )
+                } else {
+                    self.builder.clone()
+                };
+                connections.push((builder.open(&self.path)?, workload.clone()));
+            }
+        }
+
+        let barrier = Arc::new(Barrier::new(connections.len()));
+        let threads: Vec<_> = connections
+            .into_iter()
+            .enumerate()
+            .map(|(thread, (conn, workload))| {
+                let barrier = barrier.clone();
+                let iterations = self.iterations;
+                let max_delay = self.max_delay.as_micros() as u64;
+                // The seed must be non-zero for xorshift.
+                let mut rng =
+                    (self.seed ^ (thread as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15)).max(1);
+                std::thread::spawn(move || {
+                    let mut report = SimulationReport::default();
+                    barrier.wait();
+                    for iteration in 0..iterations {
+                        let mut step = Step {
+                            thread,
+                            iteration,
+                            rng,
+                        };
+                        if max_delay > 0 {
+                            let delay = step.random() % (max_delay + 1);
+                            std::thread::sleep(Duration::from_micros(delay));
+                        }
+                        let res = workload(&conn, &mut step);
+                        rng = step.rng;
+                        match res {
+                            Ok(()) => report.completed += 1,
+                            Err(e) if is_busy(&e) => report.busy += 1,
+                            Err(error) => report.failures.push(WorkloadFailure {
+                                thread,
+                                iteration,
+                                error,
+                            }),
+                        }
+                    }
+                    report
+                })
+            })
+            .collect();
+
+        let mut report = SimulationReport::default();
+        for thread in threads {
+            let res = thread
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+            report.completed += res.completed;
+            report.busy += res.busy;
+            report.failures.extend(res.failures);
+        }
+        report.failures.sort_by_key(|f| (f.thread, f.iteration));
+
+        let conn = self.builder.open(&self.path)?;
+        for (name, invariant) in &self.invariants {
+            if !invariant(&conn)? {
+                report.violations.push(name.clone());
+            }
+        }
+        Ok(report)
+    }
+}
+
+fn is_busy(e: &rusqlite::Error) -> bool {
+    matches!(
+        e,
+        rusqlite::Error::SqliteFailure(f, _)
+            if f.code == ErrorCode::DatabaseBusy || f.code == ErrorCode::DatabaseLocked
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn bank(path: &Path) {
+        let db = ConnectionBuilder::new()
+            .wal()
+            .open(path)
+            .expect("Failed to open connection");
+        db.execute_batch(
+            "create table account( id integer primary key, balance integer not null );
+            insert into account values (0, 100), (1, 100), (2, 100), (3, 100);",
+        )
+        .expect("Failed to create table");
+    }
+
+    fn transfer(conn: &Connection, step: &mut Step) -> rusqlite::Result<()> {
+        let from = (step.random() % 4) as i64;
+        let to = (from + 1) % 4;
+        // Take the write lock up front, so the busy timeout applies rather
+        // than failing on upgrading a read transaction.
+        conn.execute_batch("begin immediate")?;
+        let res = conn
+            .execute(
+                "update account set balance = balance - 1 where id = ?",
+                (from,),
+            )
+            .and_then(|_| {
+                conn.execute(
+                    "update account set balance = balance + 1 where id = ?",
+                    (to,),
+                )
+            });
+        match res {
+            Ok(_) => conn.execute_batch("commit"),
+            Err(e) => {
+                conn.execute_batch("rollback")?;
+                Err(e)
+            }
+        }
+    }
+
+    #[test]
+    fn preserve_invariants() {
+        let dir = tempfile::tempdir().expect("Failed to create directory");
+        let path = dir.path().join("bank.db");
+        bank(&path);
+
+        let res = Simulation::new(&path)
+            .iterations(50)
+            .writers(4, transfer)
+            .readers(2, |conn, _| {
+                let total: i64 =
+                    conn.query_row("select sum(balance) from account", (), |row| row.get(0))?;
+                assert_eq!(total, 400, "Read a partial transfer");
+                Ok(())
+            })
+            .invariant("total balance", |conn| {
+                conn.query_row("select sum(balance) = 400 from account", (), |row| {
+                    row.get(0)
+                })
+            })
+            .run();
+        assert!(res.is_ok(), "Failed to run simulation: {:?}", res);
+        let report = res.unwrap();
+        assert!(report.is_clean(), "Simulation failed: {:?}", report);
+        assert_eq!(report.completed, 300);
+    }
+
+    #[test]
+    fn report_violations_and_busy() {
+        let dir = tempfile::tempdir().expect("Failed to create directory");
+        let path = dir.path().join("bank.db");
+        bank(&path);
+
+        let res = Simulation::new(&path)
+            .busy_timeout(Duration::ZERO)
+            .iterations(20)
+            .max_delay(Duration::ZERO)
+            .writers(4, |conn, step| {
+                // Holds the write lock while sleeping, so other writers are
+                // kept waiting, and loses money.
+                conn.execute_batch("begin immediate")?;
+                std::thread::sleep(Duration::from_millis(1));
+                conn.execute(
+                    "update account set balance = balance - 1 where id = ?",
+                    ((step.random() % 4) as i64,),
+                )?;
+                conn.execute_batch("commit")
+            })
+            .invariant("total balance", |conn| {
+                conn.query_row("select sum(balance) = 400 from account", (), |row| {
+                    row.get(0)
+                })
+            })
+            .run();
+        assert!(res.is_ok(), "Failed to run simulation: {:?}", res);
+        let report = res.unwrap();
+        assert!(report.busy > 0);
+        assert_eq!(report.completed + report.busy, 80);
+        assert_eq!(report.violations, vec!["total balance".to_string()]);
+    }
+}
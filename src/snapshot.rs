@@ -0,0 +1,185 @@
+use std::path::Path;
+
+use rusqlite::{Connection, OpenFlags, Params, Row};
+
+/// Open `path` read-only, with `query_only` set so that writes fail even
+/// through attached databases.
+pub fn open_read_only<P: AsRef<Path>>(path: P) -> rusqlite::Result<Connection> {
+    let conn = Connection::open_with_flags(
+        path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY
+            | OpenFlags::SQLITE_OPEN_URI
+            | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )?;
+    conn.execute_batch("pragma query_only = 1")?;
+    Ok(conn)
+}
+
+/// Open `path` as an immutable database, which skips all locking. This is
+/// only safe for files no other connection will modify while it is open, eg
+/// backups and exported reports.
+///
+/// Immutable connections ignore the write-ahead log, so if `path` has a
+/// non-empty WAL the database is opened with `open_read_only` instead.
+pub fn open_immutable<P: AsRef<Path>>(path: P) -> rusqlite::Result<Connection> {
+    let path = path.as_ref();
+    let mut wal = path.as_os_str().to_owned();
+    wal.push("-wal");
+    if std::fs::metadata(wal).is_ok_and(|m| m.len() > 0) {
+        return open_read_only(path);
+    }
+
+    let mut uri = String::from("file:");
+    for c in path.to_string_lossy().chars() {
+        match c {
+            '?' => uri.push_str("%3f"),
+            '#' => uri.push_str("%23"),
+            '%' => uri.push_str("%25"),
+            c => uri.push(c),
+        }
+    }
+    uri.push_str("?immutable=1");
+    open_read_only(uri)
+}
+
+/// A read-only, point-in-time view of a database. All queries see the
+/// database as it was when the snapshot was taken (or last refreshed).
+///
+/// `Snapshot` doesn't expose its connection, so it can't be passed to the
+/// crate's write helpers, and `query_only` rejects any write that is
+/// attempted through a query.
+///
+/// The snapshot is a read transaction held open until it's refreshed or
+/// dropped. The database should be in WAL mode (`pragma journal_mode =
+/// wal`): in the default rollback journal mode the transaction's shared lock
+/// keeps every other connection from committing a write for as long as the
+/// snapshot is open.
+pub struct Snapshot {
+    conn: Connection,
+}
+
+impl Snapshot {
+    pub fn open<P: AsRef<Path>>(path: P) -> rusqlite::Result<Self> {
+        Self::from_connection(open_read_only(path)?)
+    }
+    /// Take a snapshot on an existing connection, which is made
+    /// `query_only`.
+    pub fn from_connection(conn: Connection) -> rusqlite::Result<Self> {
+        conn.execute_batch("pragma query_only = 1")?;
+        let snapshot = Self { conn };
+        snapshot.begin()?;
+        Ok(snapshot)
+    }
+
+    /// Move the snapshot forward to the current state of the database.
+    pub fn refresh(&self) -> rusqlite::Result<()> {
+        self.conn.execute_batch("commit")?;
+        self.begin()
+    }
+    /// Start a read transaction; reading the schema makes it take its
+    /// snapshot immediately rather than at the first query.
+    fn begin(&self) -> rusqlite::Result<()> {
+        self.conn.execute_batch("begin")?;
+        self.conn
+            .query_row("select count(*) from sqlite_schema", (), |_| Ok(()))
+    }
+
+    pub fn query_row<T, P, F>(&self, sql: &str, params: P, f: F) -> rusqlite::Result<T>
+    where
+        P: Params,
+        F: FnOnce(&Row<'_>) -> rusqlite::Result<T>,
+    {
+        self.conn.query_row(sql, params, f)
+    }
+    pub fn query_map<T, P, F>(&self, sql: &str, params: P, f: F) -> rusqlite::Result<Vec<T>>
+    where
+        P: Params,
+        F: FnMut(&Row<'_>) -> rusqlite::Result<T>,
+    {
+        let mut stmt = self.conn.prepare_cached(sql)?;
+        let rows = stmt.query_map(params, f)?.collect();
+        rows
+    }
+    /// All rows of a query, converted with `TryFrom<&Row>` (eg a derived
+    /// `TryFromRow`).
+    pub fn query_all<T, P>(&self, sql: &str, params: P) -> rusqlite::Result<Vec<T>>
+    where
+        P: Params,
+        for<'r, 'stmt> T: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error>,
+    {
+        self.query_map(sql, params, |row| T::try_from(row))
+    }
+
+    /// Close the snapshot, returning the underlying connection. It remains
+    /// `query_only`.
+    pub fn into_connection(self) -> Connection {
+        let _ = self.conn.execute_batch("commit");
+        self.conn
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::TryFromRow;
+
+    #[derive(TryFromRow, Debug, PartialEq)]
+    struct Item {
+        name: String,
+    }
+
+    fn setup(path: &Path) -> Connection {
+        let db = Connection::open(path).expect("Failed to open connection");
+        db.execute_batch(
+            "pragma journal_mode = wal;
+            create table items( name text );
+            insert into items values ('a');",
+        )
+        .expect("Failed to set up database");
+        db
+    }
+
+    #[test]
+    fn snapshot_isolation() {
+        let dir = tempfile::tempdir().expect("Failed to create directory");
+        let path = dir.path().join("test.db");
+        let writer = setup(&path);
+
+        let res = Snapshot::open(&path);
+        assert!(res.is_ok(), "Failed to open snapshot: {:?}", res.err());
+        let snapshot = res.unwrap();
+        writer
+            .execute("insert into items values ('b')", ())
+            .expect("Snapshot blocked the writer");
+
+        let items: Vec<Item> = snapshot.query_all("select name from items", ()).unwrap();
+        assert_eq!(items.len(), 1, "Snapshot saw a later write");
+        snapshot.refresh().unwrap();
+        let items: Vec<Item> = snapshot.query_all("select name from items", ()).unwrap();
+        assert_eq!(items.len(), 2);
+
+        let res = snapshot.query_row("insert into items values ('c') returning name", (), |row| {
+            row.get::<_, String>(0)
+        });
+        assert!(res.is_err(), "Snapshot allowed a write");
+    }
+
+    #[test]
+    fn open_immutable_database() {
+        let dir = tempfile::tempdir().expect("Failed to create directory");
+        let path = dir.path().join("test?.db");
+        setup(&path)
+            .execute_batch("pragma journal_mode = delete")
+            .unwrap();
+
+        let res = open_immutable(&path);
+        assert!(res.is_ok(), "Failed to open database: {:?}", res);
+        let conn = res.unwrap();
+        let count: i64 = conn
+            .query_row("select count(*) from items", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 1);
+        assert!(conn.execute("delete from items", ()).is_err());
+    }
+}
@@ -0,0 +1,440 @@
+use std::collections::HashMap;
+
+use rusqlite::Connection;
+
+use crate::util::quote_ident;
+
+/// Size of a table or index. Page counts are only available when SQLite was
+/// compiled with the `dbstat` virtual table (as the bundled build is).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndexStats {
+    pub name: String,
+    pub pages: Option<i64>,
+    pub bytes: Option<i64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TableStats {
+    pub name: String,
+    pub rows: i64,
+    pub pages: Option<i64>,
+    pub bytes: Option<i64>,
+    pub indexes: Vec<IndexStats>,
+}
+impl TableStats {
+    /// Bytes used by the table and all of its indexes.
+    pub fn total_bytes(&self) -> Option<i64> {
+        self.indexes
+            .iter()
+            .try_fold(self.bytes?, |acc, i| Some(acc + i.bytes?))
+    }
+}
+
+/// Database-level size breakdown, from the page counters in the header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DatabaseStats {
+    pub page_size: i64,
+    pub page_count: i64,
+    pub freelist_count: i64,
+}
+impl DatabaseStats {
+    pub fn total_bytes(&self) -> i64 {
+        self.page_size * self.page_count
+    }
+    /// Bytes in free pages, which `VACUUM` would return to the filesystem.
+    pub fn free_bytes(&self) -> i64 {
+        self.page_size * self.freelist_count
+    }
+}
+
+pub fn database_stats(conn: &Connection) -> rusqlite::Result<DatabaseStats> {
+    let pragma = |name: &str| conn.query_row(&format!("pragma {}", name), (), |row| row.get(0));
+    Ok(DatabaseStats {
+        page_size: pragma("page_size")?,
+        page_count: pragma("page_count")?,
+        freelist_count: pragma("freelist_count")?,
+    })
+}
+
+/// Whether the `dbstat` virtual table is available on this connection.
+pub fn dbstat_available(conn: &Connection) -> bool {
+    conn.prepare("select 1 from dbstat limit 0").is_ok()
+}
+
+/// The number of rows in `table`. This is a full scan of the table (or its
+/// smallest index).
+pub fn row_count(conn: &Connection, table: &str) -> rusqlite::Result<i64> {
+    conn.query_row(
+        &format!("select count(*) from {}", quote_ident(table)),
+        (),
+        |row| row.get(0),
+    )
+}
+
+/// Statistics for every table in the main schema, largest first when page
+/// counts are available, and by name otherwise.
+pub fn table_stats(conn: &Connection) -> rusqlite::Result<Vec<TableStats>> {
+    let sizes = object_sizes(conn)?;
+    let size = |name: &str| match &sizes {
+        Some(sizes) => {
+            let (pages, bytes) = sizes.get(name).copied().unwrap_or((0, 0));
+            (Some(pages), Some(bytes))
+        }
+        None => (None, None),
+    };
+
+    let mut stmt = conn.prepare(
+        "select name from sqlite_schema
+        where type = 'table' and name not like 'sqlite_%' and sql not like 'create virtual%'
+        order by name",
+    )?;
+    let tables = stmt
+        .query_map((), |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let mut index_stmt = conn.prepare(
+        "select name from sqlite_schema where type = 'index' and tbl_name = ? order by name",
+    )?;
+
+    let mut stats = vec![];
+    for name in tables {
+        let indexes = index_stmt
+            .query_map((&name,), |row| row.get::<_, String>(0))?
+            .map(|index| {
+                index.map(|index| {
+                    let (pages, bytes) = size(&index);
+                    IndexStats {
+                        name: index,
+                        pages,
+                        bytes,
+                    }
+                })
+            })
+            .collect::<rusqlite::Result<_>>()?;
+        let (pages, bytes) = size(&name);
+        stats.push(TableStats {
+            rows: row_count(conn, &name)?,
+            name,
+            pages,
+            bytes,
+            indexes,
+        });
+    }
+    stats.sort_by_key(|s| std::cmp::Reverse(s.total_bytes()));
+    Ok(stats)
+}
+
+/// (pages, bytes) per table and index, if dbstat is available.
+fn object_sizes(conn: &Connection) -> rusqlite::Result<Option<HashMap<String, (i64, i64)>>> {
+    if !dbstat_available(conn) {
+        return Ok(None);
+    }
+    let mut stmt = conn.prepare(
+        "select name, count(*), sum(pgsize) from dbstat where schema = 'main' group by name",
+    )?;
+    let sizes = stmt
+        .query_map((), |row| Ok((row.get(0)?, (row.get(1)?, row.get(2)?))))?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(Some(sizes))
+}
+
+/// A page of a table or index, as reported by the `dbstat` virtual table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PageStat {
+    /// The table or index the page belongs to.
+    pub name: String,
+    /// The path to the page from the root of its b-tree, eg `/01a/`.
+    pub path: String,
+    pub pageno: i64,
+    /// `internal`, `leaf` or `overflow`.
+    pub pagetype: String,
+    pub ncell: i64,
+    pub payload: i64,
+    pub unused: i64,
+    pub mx_payload: i64,
+    pub pgoffset: i64,
+    pub pgsize: i64,
+}
+
+/// Every page of the main schema in b-tree order, if dbstat is available.
+pub fn dbstat_pages(conn: &Connection) -> rusqlite::Result<Option<Vec<PageStat>>> {
+    if !dbstat_available(conn) {
+        return Ok(None);
+    }
+    let mut stmt = conn.prepare(
+        "select name, path, pageno, pagetype, ncell, payload, unused, mx_payload, pgoffset, pgsize
+        from dbstat where schema = 'main'",
+    )?;
+    let pages = stmt
+        .query_map((), |row| {
+            Ok(PageStat {
+                name: row.get(0)?,
+                path: row.get(1)?,
+                pageno: row.get(2)?,
+                pagetype: row.get(3)?,
+                ncell: row.get(4)?,
+                payload: row.get(5)?,
+                unused: row.get(6)?,
+                mx_payload: row.get(7)?,
+                pgoffset: row.get(8)?,
+                pgsize: row.get(9)?,
+            })
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(Some(pages))
+}
+
+/// How well packed and ordered the pages of a table or index are.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fragmentation {
+    pub name: String,
+    /// The table, or for an index the table it's on.
+    pub table: String,
+    pub is_index: bool,
+    pub pages: i64,
+    pub leaf_pages: i64,
+    pub overflow_pages: i64,
+    pub bytes: i64,
+    /// Bytes of the pages holding neither content nor headers.
+    pub unused_bytes: i64,
+    /// Leaf pages which come before the previous leaf in the file, so that
+    /// scanning in key order seeks backwards.
+    pub out_of_order: i64,
+}
+
+impl Fragmentation {
+    /// The fraction of the pages' bytes in use.
+    pub fn fill_factor(&self) -> f64 {
+        if self.bytes == 0 {
+            return 1.0;
+        }
+        (self.bytes - self.unused_bytes) as f64 / self.bytes as f64
+    }
+    /// The fraction of consecutive leaf pages which are out of order in the
+    /// file.
+    pub fn fragmentation(&self) -> f64 {
+        if self.leaf_pages < 2 {
+            return 0.0;
+        }
+        self.out_of_order as f64 / (self.leaf_pages - 1) as f64
+    }
+}
+
+/// A fix suggested by a `FragmentationReport`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Suggestion {
+    /// Rebuild the database with `VACUUM`, returning free pages to the
+    /// filesystem and repacking the tables.
+    Vacuum,
+    /// Rebuild the index with `REINDEX`.
+    Reindex(String),
+}
+
+/// The fragmentation of every table and index in the main schema, from
+/// `dbstat`, with suggestions of what would fix it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FragmentationReport {
+    pub database: DatabaseStats,
+    /// Tables and indexes, largest first.
+    pub objects: Vec<Fragmentation>,
+}
+
+impl FragmentationReport {
+    /// Objects smaller than this many pages are never worth rebuilding.
+    pub const MIN_PAGES: i64 = 8;
+    /// The fill factor below which an object is considered sparse.
+    pub const MIN_FILL_FACTOR: f64 = 0.5;
+    /// The fragmentation above which an object is considered fragmented.
+    pub const MAX_FRAGMENTATION: f64 = 0.1;
+    /// The fraction of the file in free pages above which it's worth
+    /// vacuuming.
+    pub const MAX_FREE: f64 = 0.25;
+
+    pub fn object(&self, name: &str) -> Option<&Fragmentation> {
+        self.objects.iter().find(|o| o.name == name)
+    }
+
+    /// `VACUUM` if much of the file is free or a large table is sparse or
+    /// fragmented, and otherwise `REINDEX` for each such index (which
+    /// `VACUUM` rebuilds anyway).
+    pub fn suggestions(&self) -> Vec<Suggestion> {
+        let needs_rebuild = |o: &&Fragmentation| {
+            o.pages >= Self::MIN_PAGES
+                && (o.fill_factor() < Self::MIN_FILL_FACTOR
+                    || o.fragmentation() > Self::MAX_FRAGMENTATION)
+        };
+        let free = match self.database.page_count {
+            0 => 0.0,
+            n => self.database.freelist_count as f64 / n as f64,
+        };
+        if free > Self::MAX_FREE
+            || self
+                .objects
+                .iter()
+                .filter(needs_rebuild)
+                .any(|o| !o.is_index)
+        {
+            return vec![Suggestion::Vacuum];
+        }
+        self.objects
+            .iter()
+            .filter(needs_rebuild)
+            .map(|o| Suggestion::Reindex(o.name.clone()))
+            .collect()
+    }
+}
+
+/// Report the fragmentation of the main schema, if dbstat is available.
+/// This reads every page of the database.
+pub fn fragmentation(conn: &Connection) -> rusqlite::Result<Option<FragmentationReport>> {
+    let pages = match dbstat_pages(conn)? {
+        Some(pages) => pages,
+        None => return Ok(None),
+    };
+    let mut stmt = conn.prepare(
+        "select name, tbl_name, type = 'index' from sqlite_schema
+        where type in ('table', 'index') and rootpage > 0",
+    )?;
+    let owners: HashMap<String, (String, bool)> = stmt
+        .query_map((), |row| Ok((row.get(0)?, (row.get(1)?, row.get(2)?))))?
+        .collect::<rusqlite::Result<_>>()?;
+
+    let mut objects: Vec<Fragmentation> = vec![];
+    let mut positions = HashMap::new();
+    let mut last_leaf = HashMap::new();
+    for page in pages {
+        let index = match positions.get(&page.name) {
+            Some(&i) => i,
+            None => {
+                positions.insert(page.name.clone(), objects.len());
+                let (table, is_index) = owners
+                    .get(&page.name)
+                    .cloned()
+                    .unwrap_or_else(|| (page.name.clone(), false));
+                objects.push(Fragmentation {
+                    name: page.name.clone(),
+                    table,
+                    is_index,
+                    pages: 0,
+                    leaf_pages: 0,
+                    overflow_pages: 0,
+                    bytes: 0,
+                    unused_bytes: 0,
+                    out_of_order: 0,
+                });
+                objects.len() - 1
+            }
+        };
+        let object = &mut objects[index];
+        object.pages += 1;
+        object.bytes += page.pgsize;
+        object.unused_bytes += page.unused;
+        match page.pagetype.as_str() {
+            "leaf" => {
+                object.leaf_pages += 1;
+                if let Some(last) = last_leaf.insert(page.name, page.pageno) {
+                    if page.pageno < last {
+                        object.out_of_order += 1;
+                    }
+                }
+            }
+            "overflow" => object.overflow_pages += 1,
+            _ => {}
+        }
+    }
+    objects.sort_by_key(|o| std::cmp::Reverse(o.bytes));
+    Ok(Some(FragmentationReport {
+        database: database_stats(conn)?,
+        objects,
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn setup() -> Connection {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch(
+            "create table small( a integer );
+            create table large( a integer, b text );
+            create index large_b on large(b);
+            insert into small(a) values (1);
+            with recursive n(i) as (select 1 union all select i + 1 from n where i < 2000)
+            insert into large(a, b) select i, hex(randomblob(32)) from n;",
+        )
+        .expect("Failed to set up database");
+        db
+    }
+
+    #[test]
+    fn per_table_stats() {
+        let db = setup();
+        assert!(dbstat_available(&db));
+
+        let res = table_stats(&db);
+        assert!(res.is_ok(), "Failed to retrieve stats: {:?}", res);
+        let stats = res.unwrap();
+        assert_eq!(
+            stats.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(),
+            vec!["large", "small"]
+        );
+        assert_eq!(stats[0].rows, 2000);
+        assert_eq!(stats[1].rows, 1);
+        assert_eq!(stats[0].indexes.len(), 1);
+        assert!(stats[0].indexes[0].pages.unwrap() > 1);
+        assert!(stats[0].total_bytes() > stats[0].bytes);
+    }
+
+    #[test]
+    fn database_level_stats() {
+        let db = setup();
+        let before = database_stats(&db).expect("Failed to retrieve stats");
+        assert!(before.total_bytes() > 0);
+
+        db.execute("delete from large", ()).unwrap();
+        let after = database_stats(&db).expect("Failed to retrieve stats");
+        assert!(after.free_bytes() > 0, "Deleting rows should free pages");
+        assert_eq!(after.page_count, before.page_count);
+    }
+
+    #[test]
+    fn fragmentation_report() {
+        // Rows are appended to the table, but random keys are inserted all
+        // over the index, splitting its pages.
+        let db = setup();
+        let res = fragmentation(&db);
+        assert!(res.is_ok(), "Failed to report fragmentation: {:?}", res);
+        let report = res.unwrap().expect("dbstat is available");
+        let large = report.object("large").unwrap();
+        assert!(!large.is_index);
+        assert!(large.leaf_pages > 1);
+        assert!(
+            large.fill_factor() > 0.8,
+            "Fill factor {}",
+            large.fill_factor()
+        );
+        let index = report.object("large_b").unwrap();
+        assert!(index.is_index);
+        assert_eq!(index.table, "large");
+        assert!(
+            index.fragmentation() > FragmentationReport::MAX_FRAGMENTATION,
+            "Fragmentation {}",
+            index.fragmentation()
+        );
+        assert_eq!(
+            report.suggestions(),
+            vec![Suggestion::Reindex("large_b".to_string())]
+        );
+
+        db.execute_batch("reindex large_b").unwrap();
+        let report = fragmentation(&db).unwrap().unwrap();
+        assert_eq!(report.object("large_b").unwrap().fragmentation(), 0.0);
+        assert!(report.suggestions().is_empty());
+
+        // Most of the pages are freed.
+        db.execute("delete from large where a % 10 != 0", ())
+            .unwrap();
+        let report = fragmentation(&db).unwrap().unwrap();
+        assert_eq!(report.suggestions(), vec![Suggestion::Vacuum]);
+    }
+}
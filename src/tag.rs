@@ -0,0 +1,252 @@
+use std::marker::PhantomData;
+
+use rusqlite::{types::FromSql, Connection, ToSql};
+
+use crate::{execute::Executor, util::quote_ident};
+
+/// Many-to-many tagging of the rows of an entity table, identified by the id
+/// type `I` (typically an `IntegerId<T>`).
+///
+/// Tag names are stored once in a shared tag table, and linked to entities
+/// through a join table named `<entity>_tags` by default.
+pub struct Taggable<I> {
+    entity_table: String,
+    join_table: String,
+    tag_table: String,
+    _id: PhantomData<I>,
+}
+
+impl<I: FromSql + ToSql> Taggable<I> {
+    pub const DEFAULT_TAG_TABLE: &'static str = "tags";
+
+    /// Tag the rows of `entity_table`, which must have an `id` primary key.
+    pub fn new(entity_table: &str) -> Self {
+        Self {
+            entity_table: entity_table.to_string(),
+            join_table: format!("{}_tags", entity_table),
+            tag_table: Self::DEFAULT_TAG_TABLE.to_string(),
+            _id: PhantomData,
+        }
+    }
+    pub fn join_table(mut self, name: &str) -> Self {
+        self.join_table = name.to_string();
+        self
+    }
+    pub fn tag_table(mut self, name: &str) -> Self {
+        self.tag_table = name.to_string();
+        self
+    }
+
+    /// DDL for the tag and join tables. Links are removed along with either
+    /// the entity or the tag.
+    pub fn create_sql(&self) -> String {
+        format!(
+            "create table if not exists {tags}(
+                id integer primary key,
+                name text not null unique
+            );
+            create table if not exists {join}(
+                entity_id not null references {entity}(id) on delete cascade,
+                tag_id integer not null references {tags}(id) on delete cascade,
+                primary key (entity_id, tag_id)
+            ) without rowid;
+            create index if not exists {index} on {join}(tag_id);",
+            tags = quote_ident(&self.tag_table),
+            join = quote_ident(&self.join_table),
+            index = quote_ident(&format!("{}_tag_id", self.join_table)),
+            entity = quote_ident(&self.entity_table),
+        )
+    }
+    pub fn create_tables<E: Executor + ?Sized>(&self, exec: &E) -> rusqlite::Result<()> {
+        exec.run_batch(&self.create_sql())
+    }
+
+    /// Tag an entity. Returns false if it already had the tag.
+    pub fn add_tag(&self, conn: &Connection, id: &I, tag: &str) -> rusqlite::Result<bool> {
+        conn.execute(
+            &format!(
+                "insert or ignore into {}(name) values (?)",
+                quote_ident(&self.tag_table)
+            ),
+            (tag,),
+        )?;
+        let inserted = conn.execute(
+            &format!(
+                "insert or ignore into {join}(entity_id, tag_id)
+                select ?1, id from {tags} where name = ?2",
+                join = quote_ident(&self.join_table),
+                tags = quote_ident(&self.tag_table)
+            ),
+            (id, tag),
+        )?;
+        Ok(inserted > 0)
+    }
+    /// Remove a tag from an entity. Returns false if it didn't have the tag.
+    pub fn remove_tag(&self, conn: &Connection, id: &I, tag: &str) -> rusqlite::Result<bool> {
+        let deleted = conn.execute(
+            &format!(
+                "delete from {join} where entity_id = ?1
+                and tag_id = (select id from {tags} where name = ?2)",
+                join = quote_ident(&self.join_table),
+                tags = quote_ident(&self.tag_table)
+            ),
+            (id, tag),
+        )?;
+        Ok(deleted > 0)
+    }
+
+    /// The tags of an entity, in alphabetical order.
+    pub fn tags(&self, conn: &Connection, id: &I) -> rusqlite::Result<Vec<String>> {
+        let mut stmt = conn.prepare(&format!(
+            "select t.name from {join} j join {tags} t on t.id = j.tag_id
+            where j.entity_id = ? order by t.name",
+            join = quote_ident(&self.join_table),
+            tags = quote_ident(&self.tag_table)
+        ))?;
+        let tags = stmt.query_map((id,), |row| row.get(0))?.collect();
+        tags
+    }
+
+    /// Ids of the entities having every one of `tags`.
+    pub fn entities_with_all_tags(
+        &self,
+        conn: &Connection,
+        tags: &[&str],
+    ) -> rusqlite::Result<Vec<I>> {
+        self.entities_matching(
+            conn,
+            tags,
+            "(select count(distinct value) from json_each(?1))",
+        )
+    }
+    /// Ids of the entities having at least one of `tags`.
+    pub fn entities_with_any_tags(
+        &self,
+        conn: &Connection,
+        tags: &[&str],
+    ) -> rusqlite::Result<Vec<I>> {
+        self.entities_matching(conn, tags, "1")
+    }
+
+    /// The number of entities carrying each tag, most used first. Tags
+    /// without any entities are omitted.
+    pub fn tag_counts(&self, conn: &Connection) -> rusqlite::Result<Vec<(String, i64)>> {
+        let mut stmt = conn.prepare(&format!(
+            "select t.name, count(*) as n from {join} j join {tags} t on t.id = j.tag_id
+            group by t.id order by n desc, t.name",
+            join = quote_ident(&self.join_table),
+            tags = quote_ident(&self.tag_table)
+        ))?;
+        let counts = stmt
+            .query_map((), |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect();
+        counts
+    }
+
+    fn entities_matching(
+        &self,
+        conn: &Connection,
+        tags: &[&str],
+        min_matches: &str,
+    ) -> rusqlite::Result<Vec<I>> {
+        let tags = serde_json::to_string(tags)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        let mut stmt = conn.prepare(&format!(
+            "select j.entity_id from {join} j join {tags} t on t.id = j.tag_id
+            where t.name in (select value from json_each(?1))
+            group by j.entity_id having count(*) >= {min_matches}
+            order by j.entity_id",
+            join = quote_ident(&self.join_table),
+            tags = quote_ident(&self.tag_table),
+        ))?;
+        let ids = stmt.query_map((tags,), |row| row.get(0))?.collect();
+        ids
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::IntegerId;
+
+    struct Post;
+    type PostId = IntegerId<Post>;
+
+    fn setup() -> (Connection, Vec<PostId>) {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch(
+            "pragma foreign_keys = on;
+            create table post( id integer primary key autoincrement );",
+        )
+        .expect("Failed to create table");
+        Taggable::<PostId>::new("post")
+            .create_tables(&db)
+            .expect("Failed to create tag tables");
+        let ids = (0..3)
+            .map(|_| {
+                db.query_row("insert into post default values returning id", (), |row| {
+                    row.get(0)
+                })
+                .expect("Failed to insert post")
+            })
+            .collect();
+        (db, ids)
+    }
+
+    #[test]
+    fn add_and_remove_tags() {
+        let (db, ids) = setup();
+        let tags = Taggable::<PostId>::new("post");
+
+        assert!(tags.add_tag(&db, &ids[0], "rust").unwrap());
+        assert!(!tags.add_tag(&db, &ids[0], "rust").unwrap());
+        assert!(tags.add_tag(&db, &ids[0], "sqlite").unwrap());
+        assert_eq!(tags.tags(&db, &ids[0]).unwrap(), vec!["rust", "sqlite"]);
+
+        assert!(tags.remove_tag(&db, &ids[0], "rust").unwrap());
+        assert!(!tags.remove_tag(&db, &ids[0], "rust").unwrap());
+        assert_eq!(tags.tags(&db, &ids[0]).unwrap(), vec!["sqlite"]);
+    }
+
+    #[test]
+    fn query_by_tags() {
+        let (db, ids) = setup();
+        let tags = Taggable::<PostId>::new("post");
+        for (id, tag) in [
+            (ids[0], "rust"),
+            (ids[0], "sqlite"),
+            (ids[1], "rust"),
+            (ids[2], "sqlite"),
+        ] {
+            tags.add_tag(&db, &id, tag).unwrap();
+        }
+
+        let res = tags.entities_with_all_tags(&db, &["rust", "sqlite"]);
+        assert!(res.is_ok(), "Failed to query tagged entities: {:?}", res);
+        assert_eq!(res.unwrap(), vec![ids[0]]);
+        assert_eq!(
+            tags.entities_with_all_tags(&db, &["rust", "rust"]).unwrap(),
+            vec![ids[0], ids[1]]
+        );
+        assert_eq!(
+            tags.entities_with_any_tags(&db, &["rust", "sqlite"])
+                .unwrap(),
+            ids
+        );
+        assert_eq!(
+            tags.tag_counts(&db).unwrap(),
+            vec![("rust".to_string(), 2), ("sqlite".to_string(), 2)]
+        );
+    }
+
+    #[test]
+    fn cascade_on_entity_delete() {
+        let (db, ids) = setup();
+        let tags = Taggable::<PostId>::new("post");
+        tags.add_tag(&db, &ids[0], "rust").unwrap();
+
+        db.execute("delete from post where id = ?", (ids[0],))
+            .expect("Failed to delete post");
+        assert!(tags.tag_counts(&db).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,132 @@
+use std::marker::PhantomData;
+
+use rusqlite::Connection;
+
+use crate::{schema::Table, util::quote_ident};
+
+/// A temp table with the columns of `T`, dropped when this value goes out of
+/// scope. Temp tables are private to the connection and can be joined by
+/// name, which makes them a good replacement for very large `IN` lists and a
+/// staging area for imports.
+pub struct TempTable<'conn, T> {
+    conn: &'conn Connection,
+    name: String,
+    _marker: PhantomData<T>,
+}
+
+impl<'conn, T: Table> TempTable<'conn, T> {
+    /// Create an empty temp table called `name`. Fails if a temp table of
+    /// that name already exists.
+    pub fn create(conn: &'conn Connection, name: &str) -> rusqlite::Result<Self> {
+        let mut schema = T::schema();
+        schema.name = name.to_string();
+        conn.execute_batch(&schema.create_temp_sql())?;
+        Ok(Self {
+            conn,
+            name: name.to_string(),
+            _marker: PhantomData,
+        })
+    }
+    /// Create a temp table called `name` holding `rows`.
+    pub fn with_rows(conn: &'conn Connection, name: &str, rows: &[T]) -> rusqlite::Result<Self> {
+        let table = Self::create(conn, name)?;
+        table.insert_all(rows)?;
+        Ok(table)
+    }
+
+    /// The table's name, to use in queries.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Insert `rows` in a single savepoint, returning how many were
+    /// inserted.
+    pub fn insert_all(&self, rows: &[T]) -> rusqlite::Result<usize> {
+        let mut schema = T::schema();
+        schema.name = self.name.clone();
+        self.conn.execute_batch("savepoint temp_table_insert")?;
+        let res = self
+            .conn
+            .prepare_cached(&schema.insert_sql())
+            .and_then(|mut stmt| {
+                rows.iter()
+                    .try_fold(0, |n, row| Ok(n + stmt.execute(&*row.params())?))
+            });
+        match res {
+            Ok(_) => self.conn.execute_batch("release temp_table_insert")?,
+            Err(_) => self
+                .conn
+                .execute_batch("rollback to temp_table_insert; release temp_table_insert")?,
+        }
+        res
+    }
+    /// Delete all rows, keeping the table.
+    pub fn clear(&self) -> rusqlite::Result<usize> {
+        self.conn
+            .execute(&format!("delete from temp.{}", quote_ident(&self.name)), ())
+    }
+}
+
+impl<'conn, T> Drop for TempTable<'conn, T> {
+    fn drop(&mut self) {
+        // Errors can't be reported from drop; the table is dropped with the
+        // connection regardless.
+        let _ = self.conn.execute_batch(&format!(
+            "drop table if exists temp.{}",
+            quote_ident(&self.name)
+        ));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::Table;
+
+    #[derive(Table, Debug)]
+    struct Wanted {
+        id: i64,
+    }
+
+    #[test]
+    fn join_against_temp_table() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch(
+            "create table items( id integer primary key, name text );
+            insert into items values (1, 'a'), (2, 'b'), (3, 'c'), (4, 'd');",
+        )
+        .expect("Failed to set up database");
+
+        {
+            let wanted = [Wanted { id: 2 }, Wanted { id: 4 }, Wanted { id: 9 }];
+            let res = TempTable::with_rows(&db, "wanted", &wanted);
+            assert!(res.is_ok(), "Failed to create temp table: {:?}", res.err());
+            let table = res.unwrap();
+
+            let mut stmt = db
+                .prepare(&format!(
+                    "select name from items join {} using (id) order by id",
+                    table.name()
+                ))
+                .unwrap();
+            let names = stmt
+                .query_map((), |row| row.get::<_, String>(0))
+                .unwrap()
+                .collect::<rusqlite::Result<Vec<_>>>()
+                .unwrap();
+            assert_eq!(names, vec!["b", "d"]);
+
+            assert_eq!(table.clear().unwrap(), 3);
+        }
+
+        let tables: i64 = db
+            .query_row(
+                "select count(*) from temp.sqlite_schema where name = 'wanted'",
+                (),
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(tables, 0, "Temp table was not dropped");
+    }
+}
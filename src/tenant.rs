@@ -0,0 +1,221 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use rusqlite::Connection;
+use thiserror::Error;
+
+use crate::{
+    connection::{self, ConnectionBuilder},
+    migration::{Migrations, MultiMigrator},
+};
+
+/// Manages one database file per tenant in a directory. Connections are
+/// opened on first use with a shared `ConnectionBuilder`, so pending
+/// migrations are applied as each tenant is opened. At most `capacity` are
+/// kept open, closing the least recently used when another is needed.
+pub struct TenantManager {
+    dir: PathBuf,
+    builder: ConnectionBuilder,
+    capacity: usize,
+    /// Open connections, least recently used first.
+    open: Vec<(String, Connection)>,
+}
+
+impl TenantManager {
+    pub const DEFAULT_CAPACITY: usize = 16;
+    pub const EXTENSION: &'static str = "sqlite3";
+
+    pub fn new<P: AsRef<Path>>(dir: P, builder: ConnectionBuilder) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            builder,
+            capacity: Self::DEFAULT_CAPACITY,
+            open: vec![],
+        }
+    }
+    /// The maximum number of connections kept open. Values below 1 are
+    /// treated as 1.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// The database file for `tenant`. Tenant keys may only contain ASCII
+    /// letters, digits, `-` and `_`, so they can't escape the directory.
+    pub fn path(&self, tenant: &str) -> Result<PathBuf, Error> {
+        let valid = !tenant.is_empty()
+            && tenant
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(Error::InvalidTenant(tenant.to_string()));
+        }
+        Ok(self.dir.join(format!("{}.{}", tenant, Self::EXTENSION)))
+    }
+    /// Whether the tenant's database file exists.
+    pub fn exists(&self, tenant: &str) -> Result<bool, Error> {
+        Ok(self.path(tenant)?.exists())
+    }
+
+    /// The tenants with a database file, in no particular order.
+    pub fn tenants(&self) -> Result<Vec<String>, Error> {
+        let mut tenants = vec![];
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension() != Some(Self::EXTENSION.as_ref()) {
+                continue;
+            }
+            let tenant = match path.file_stem().and_then(|s| s.to_str()) {
+                Some(tenant) => tenant,
+                None => continue,
+            };
+            if self.path(tenant).is_ok() {
+                tenants.push(tenant.to_string());
+            }
+        }
+        Ok(tenants)
+    }
+    /// A migrator applying `migrations` to every tenant's database, reported
+    /// by tenant key. Tenants are otherwise only migrated as they're opened.
+    pub fn migrator(&self, migrations: Migrations) -> Result<MultiMigrator, Error> {
+        let mut tenants = self.tenants()?;
+        tenants.sort();
+        let paths = tenants
+            .iter()
+            .map(|t| self.path(t))
+            .collect::<Result<Vec<_>, _>>()?;
+        let migrations = Arc::new(migrations);
+        let mut migrator = MultiMigrator::new();
+        for (tenant, path) in tenants.iter().zip(paths) {
+            migrator = migrator.add(tenant, &path, migrations.clone());
+        }
+        Ok(migrator)
+    }
+
+    /// The connection for `tenant`, opening (and creating) its database if
+    /// needed.
+    pub fn get(&mut self, tenant: &str) -> Result<&Connection, Error> {
+        if let Some(i) = self.open.iter().position(|(t, _)| t == tenant) {
+            let entry = self.open.remove(i);
+            self.open.push(entry);
+        } else {
+            let conn = self.builder.open(self.path(tenant)?)?;
+            if self.open.len() >= self.capacity {
+                self.open.remove(0);
+            }
+            self.open.push((tenant.to_string(), conn));
+        }
+        Ok(&self.open.last().expect("connection was just added").1)
+    }
+
+    pub fn is_open(&self, tenant: &str) -> bool {
+        self.open.iter().any(|(t, _)| t == tenant)
+    }
+    pub fn open_count(&self) -> usize {
+        self.open.len()
+    }
+    /// Close the tenant's connection, if open.
+    pub fn close(&mut self, tenant: &str) -> Result<(), Error> {
+        if let Some(i) = self.open.iter().position(|(t, _)| t == tenant) {
+            let (_, conn) = self.open.remove(i);
+            conn.close().map_err(|(_, e)| e)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Invalid tenant key {0:?}")]
+    InvalidTenant(String),
+    #[error(transparent)]
+    Connection(#[from] connection::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn manager(dir: &Path) -> TenantManager {
+        let builder = ConnectionBuilder::new()
+            .migrations(Migrations::new().add("create notes", "create table notes( body text )"));
+        TenantManager::new(dir, builder).capacity(2)
+    }
+
+    #[test]
+    fn open_tenants_with_eviction() {
+        let dir = tempfile::tempdir().expect("Failed to create directory");
+        let mut tenants = manager(dir.path());
+
+        for tenant in ["a", "b", "a", "c"] {
+            let res = tenants.get(tenant);
+            assert!(res.is_ok(), "Failed to open tenant: {:?}", res.err());
+            res.unwrap()
+                .execute("insert into notes(body) values (?)", (tenant,))
+                .expect("Tenant was not migrated");
+        }
+        assert_eq!(tenants.open_count(), 2);
+        assert!(tenants.is_open("a") && tenants.is_open("c"));
+        assert!(!tenants.is_open("b"), "Least recently used tenant was kept");
+
+        let notes: i64 = tenants
+            .get("a")
+            .unwrap()
+            .query_row("select count(*) from notes", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(notes, 2, "Tenant databases are not separate");
+        assert!(tenants.exists("b").unwrap());
+        assert!(!tenants.exists("d").unwrap());
+    }
+
+    #[test]
+    fn reject_invalid_tenants() {
+        let dir = tempfile::tempdir().expect("Failed to create directory");
+        let mut tenants = manager(dir.path());
+        for tenant in ["", "../escape", "a/b", "a.b"] {
+            assert!(
+                matches!(tenants.get(tenant), Err(Error::InvalidTenant(_))),
+                "Accepted tenant {:?}",
+                tenant
+            );
+        }
+    }
+
+    #[test]
+    fn migrate_every_tenant() {
+        let dir = tempfile::tempdir().expect("Failed to create directory");
+        let mut tenants = manager(dir.path());
+        for tenant in ["b", "a"] {
+            tenants.get(tenant).unwrap();
+        }
+        std::fs::write(dir.path().join("notes.txt"), "").unwrap();
+
+        let migrations = Migrations::new()
+            .add("create notes", "create table notes( body text )")
+            .add("add tags", "alter table notes add column tags text");
+        let res = tenants.migrator(migrations);
+        assert!(res.is_ok(), "Failed to list tenants: {:?}", res.err());
+        let report = res.unwrap().parallelism(2).run();
+        assert!(
+            report.is_success(),
+            "Failed to migrate tenants: {:?}",
+            report
+        );
+        let names: Vec<_> = report.databases.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "b"]);
+        assert!(report.databases.iter().all(|d| d.from_version == Some(1)));
+        assert_eq!(report.applied(), 2);
+
+        tenants
+            .get("a")
+            .unwrap()
+            .execute("insert into notes(body, tags) values ('x', 'y')", ())
+            .expect("Tenant was not migrated");
+    }
+}
@@ -0,0 +1,287 @@
+use std::marker::PhantomData;
+
+use rusqlite::{types::FromSql, Connection, Row, ToSql};
+
+use crate::{
+    date_time::{time_bucket_sql, Duration, Scale, Timestamp},
+    execute::Executor,
+    util::quote_ident,
+};
+
+/// Aggregates of the samples falling into one time bucket.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bucket<S> {
+    pub start: Timestamp<S>,
+    pub count: i64,
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+}
+impl<'stmt, S> TryFrom<&Row<'stmt>> for Bucket<S>
+where
+    Timestamp<S>: FromSql,
+{
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row<'stmt>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            start: row.get("start")?,
+            count: row.get("count")?,
+            min: row.get("min")?,
+            max: row.get("max")?,
+            avg: row.get("avg")?,
+        })
+    }
+}
+
+/// A table of `(series, ts, value)` samples, with timestamps stored at `S`
+/// scale. Several named series can share one table.
+pub struct TimeSeries<S> {
+    table: String,
+    _scale: PhantomData<S>,
+}
+
+impl<S: Scale> TimeSeries<S>
+where
+    Timestamp<S>: ToSql + FromSql,
+{
+    pub fn new(table: &str) -> Self {
+        Self {
+            table: table.to_string(),
+            _scale: PhantomData,
+        }
+    }
+
+    pub fn create_sql(&self) -> String {
+        format!(
+            "create table if not exists {table}(
+                series text not null,
+                ts integer not null,
+                value real not null
+            );
+            create index if not exists {index} on {table}(series, ts);",
+            table = quote_ident(&self.table),
+            index = quote_ident(&format!("{}_series_ts", self.table))
+        )
+    }
+    pub fn create_table<E: Executor + ?Sized>(&self, exec: &E) -> rusqlite::Result<()> {
+        exec.run_batch(&self.create_sql())
+    }
+
+    pub fn append(
+        &self,
+        conn: &Connection,
+        series: &str,
+        ts: Timestamp<S>,
+        value: f64,
+    ) -> rusqlite::Result<()> {
+        conn.execute(
+            &format!(
+                "insert into {}(series, ts, value) values (?, ?, ?)",
+                quote_ident(&self.table)
+            ),
+            (series, ts, value),
+        )?;
+        Ok(())
+    }
+    /// Append many samples using a single prepared statement.
+    pub fn append_many<I>(
+        &self,
+        conn: &Connection,
+        series: &str,
+        samples: I,
+    ) -> rusqlite::Result<()>
+    where
+        I: IntoIterator<Item = (Timestamp<S>, f64)>,
+    {
+        let mut stmt = conn.prepare_cached(&format!(
+            "insert into {}(series, ts, value) values (?, ?, ?)",
+            quote_ident(&self.table)
+        ))?;
+        for (ts, value) in samples {
+            stmt.execute((series, ts, value))?;
+        }
+        Ok(())
+    }
+
+    /// Aggregate the samples of `series` in `[from, to)` into buckets of
+    /// `width`. Empty buckets are omitted.
+    pub fn buckets(
+        &self,
+        conn: &Connection,
+        series: &str,
+        from: Timestamp<S>,
+        to: Timestamp<S>,
+        width: chrono::Duration,
+    ) -> rusqlite::Result<Vec<Bucket<S>>> {
+        let mut stmt = conn.prepare(&format!(
+            "select {bucket} as start, count(*) as count,
+                min(value) as min, max(value) as max, avg(value) as avg
+            from {table} where series = ? and ts >= ? and ts < ?
+            group by start order by start",
+            bucket = bucket_sql::<S>("ts", width)?,
+            table = quote_ident(&self.table)
+        ))?;
+        let buckets = stmt
+            .query_map((series, from, to), |row| Bucket::try_from(row))?
+            .collect();
+        buckets
+    }
+
+    /// Replace the samples of every series older than `before` with one
+    /// averaged sample per `width` bucket, written to `target`. `before` is
+    /// rounded down to a bucket boundary so that no bucket is split between
+    /// two runs. Returns the number of samples removed.
+    pub fn downsample_into(
+        &self,
+        conn: &Connection,
+        target: &TimeSeries<S>,
+        width: chrono::Duration,
+        before: Timestamp<S>,
+    ) -> rusqlite::Result<usize> {
+        let bucket = bucket_sql::<S>("ts", width)?;
+        let cutoff = bucket_sql::<S>("?1", width)?;
+
+        conn.execute_batch("savepoint downsample")?;
+        let res = conn
+            .execute(
+                &format!(
+                    "insert into {target}(series, ts, value)
+                    select series, {bucket} as start, avg(value) from {table}
+                    where ts < {cutoff} group by series, start",
+                    target = quote_ident(&target.table),
+                    table = quote_ident(&self.table)
+                ),
+                (&before,),
+            )
+            .and_then(|_| {
+                conn.execute(
+                    &format!(
+                        "delete from {} where ts < {}",
+                        quote_ident(&self.table),
+                        cutoff
+                    ),
+                    (&before,),
+                )
+            });
+        match res {
+            Ok(_) => conn.execute_batch("release downsample")?,
+            Err(_) => conn.execute_batch("rollback to downsample; release downsample")?,
+        }
+        res
+    }
+
+    /// Delete samples with timestamps before `cutoff`.
+    pub fn delete_before<E: Executor + ?Sized>(
+        &self,
+        exec: &E,
+        cutoff: Timestamp<S>,
+    ) -> rusqlite::Result<usize> {
+        exec.run(
+            &format!("delete from {} where ts < ?", quote_ident(&self.table)),
+            &[&cutoff],
+        )
+    }
+    /// Delete samples older than `age`, relative to the current time.
+    pub fn delete_older_than<A, E: Executor + ?Sized>(
+        &self,
+        exec: &E,
+        age: Duration<A>,
+    ) -> rusqlite::Result<usize> {
+        let cutoff: Timestamp<S> = (chrono::Utc::now() - age.unwrap()).into();
+        self.delete_before(exec, cutoff)
+    }
+}
+
+fn bucket_sql<S: Scale>(column: &str, width: chrono::Duration) -> rusqlite::Result<String> {
+    time_bucket_sql::<S>(column, width)
+        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::{
+        date_time::{DurationSeconds, Seconds, UnixEpoch},
+        execute::DryRun,
+    };
+
+    fn at(secs: i64) -> UnixEpoch {
+        chrono::DateTime::<chrono::Utc>::from_utc(
+            chrono::NaiveDateTime::from_timestamp_opt(secs, 0).unwrap(),
+            chrono::Utc,
+        )
+        .into()
+    }
+
+    fn setup() -> (Connection, TimeSeries<Seconds>) {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        let ts = TimeSeries::<Seconds>::new("samples");
+        ts.create_table(&db).expect("Failed to create table");
+        ts.append_many(&db, "cpu", (0..180).map(|s| (at(s), s as f64)))
+            .expect("Failed to append samples");
+        ts.append(&db, "mem", at(0), 1.0)
+            .expect("Failed to append sample");
+        (db, ts)
+    }
+
+    #[test]
+    fn bucketed_aggregates() {
+        let (db, ts) = setup();
+
+        let res = ts.buckets(&db, "cpu", at(30), at(150), chrono::Duration::minutes(1));
+        assert!(res.is_ok(), "Failed to query buckets: {:?}", res);
+        let buckets = res.unwrap();
+        assert_eq!(buckets.len(), 3);
+        assert_eq!(buckets[0].start, at(0));
+        assert_eq!(buckets[0].count, 30);
+        assert_eq!((buckets[0].min, buckets[0].max), (30.0, 59.0));
+        assert_eq!(buckets[1].avg, 89.5);
+        assert_eq!(buckets[2].start, at(120));
+        assert_eq!(buckets[2].count, 30);
+
+        let res = ts.buckets(
+            &db,
+            "cpu",
+            at(30),
+            at(150),
+            chrono::Duration::milliseconds(10),
+        );
+        assert!(
+            matches!(res, Err(rusqlite::Error::ToSqlConversionFailure(_))),
+            "Sub-second width was accepted: {:?}",
+            res
+        );
+    }
+
+    #[test]
+    fn downsample_and_prune() {
+        let (db, ts) = setup();
+        let rollup = TimeSeries::<Seconds>::new("samples_1m");
+        rollup.create_table(&db).expect("Failed to create table");
+
+        // Rounded down to 120, leaving the last minute untouched
+        let removed = ts
+            .downsample_into(&db, &rollup, chrono::Duration::minutes(1), at(150))
+            .expect("Failed to downsample");
+        assert_eq!(removed, 121);
+        let rolled = rollup
+            .buckets(&db, "cpu", at(0), at(180), chrono::Duration::minutes(1))
+            .unwrap();
+        assert_eq!(
+            rolled.iter().map(|b| b.avg).collect::<Vec<_>>(),
+            vec![29.5, 89.5]
+        );
+
+        let dry_run = DryRun::new(&db);
+        ts.delete_before(&dry_run, at(150)).unwrap();
+        assert_eq!(dry_run.into_plan().len(), 1);
+        assert_eq!(ts.delete_before(&db, at(150)).unwrap(), 30);
+        assert_eq!(
+            ts.delete_older_than(&db, DurationSeconds::from(chrono::Duration::days(1)))
+                .unwrap(),
+            30
+        );
+    }
+}
@@ -0,0 +1,404 @@
+use std::{fmt::Display, marker::PhantomData, str::FromStr};
+
+use rusqlite::{
+    types::{FromSql, FromSqlError, ToSqlOutput},
+    Connection, OptionalExtension, ToSql,
+};
+
+use crate::{
+    column_type::{column_type, SqliteColumnType, StorageClass},
+    execute::Executor,
+    util::quote_ident,
+};
+
+/// The path from the root of a tree to a node, stored as a SQLite `TEXT` of
+/// the form `/1/4/9/`. Every path starts and ends with a `/`, so a node and
+/// its descendants can be selected with `path LIKE '/1/4/%'` (see
+/// `subtree_pattern`) without accidentally matching `/1/40/`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MaterializedPath(Vec<i64>);
+impl MaterializedPath {
+    /// The empty path, which is the parent of root nodes.
+    pub fn root() -> Self {
+        Self(vec![])
+    }
+    /// The path of a child of this node.
+    pub fn child(&self, id: i64) -> Self {
+        let mut ids = self.0.clone();
+        ids.push(id);
+        Self(ids)
+    }
+    /// The path of this node's parent, or `None` for the empty path.
+    pub fn parent(&self) -> Option<Self> {
+        self.0.split_last().map(|(_, init)| Self(init.to_vec()))
+    }
+    /// The id of the node this path leads to.
+    pub fn last(&self) -> Option<i64> {
+        self.0.last().copied()
+    }
+    /// The ids along the path, root first.
+    pub fn ids(&self) -> &[i64] {
+        &self.0
+    }
+    /// Number of nodes in the path; root nodes have a depth of 1.
+    pub fn depth(&self) -> usize {
+        self.0.len()
+    }
+    /// Whether this path is a strict prefix of `other`.
+    pub fn is_ancestor_of(&self, other: &Self) -> bool {
+        self.0.len() < other.0.len() && other.0.starts_with(&self.0)
+    }
+    /// A `LIKE` pattern matching the paths of this node and its descendants.
+    /// Pair it with `path != ?` (this path) to select only the descendants.
+    pub fn subtree_pattern(&self) -> String {
+        format!("{}%", self)
+    }
+}
+impl Display for MaterializedPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("/")?;
+        for id in &self.0 {
+            write!(f, "{}/", id)?;
+        }
+        Ok(())
+    }
+}
+impl FromStr for MaterializedPath {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split('/')
+            .filter(|s| !s.is_empty())
+            .map(|s| s.parse())
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+impl From<Vec<i64>> for MaterializedPath {
+    fn from(v: Vec<i64>) -> Self {
+        Self(v)
+    }
+}
+impl ToSql for MaterializedPath {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.to_string()))
+    }
+}
+column_type!("text", Text: MaterializedPath);
+impl FromSql for MaterializedPath {
+    fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
+        value
+            .as_str()?
+            .parse()
+            .map_err(|e| FromSqlError::Other(Box::new(e)))
+    }
+}
+
+/// A closure table recording every (ancestor, descendant) pair of a tree
+/// stored in `node_table`, whose rows are identified by the id type `I`.
+/// Each node is also its own ancestor at depth 0.
+pub struct ClosureTable<I> {
+    node_table: String,
+    table: String,
+    _id: PhantomData<I>,
+}
+
+impl<I: FromSql + ToSql> ClosureTable<I> {
+    /// Track the hierarchy of `node_table`, which must have an `id` primary
+    /// key. The closure table is named `<node_table>_closure` by default.
+    pub fn new(node_table: &str) -> Self {
+        Self {
+            node_table: node_table.to_string(),
+            table: format!("{}_closure", node_table),
+            _id: PhantomData,
+        }
+    }
+    pub fn table(mut self, name: &str) -> Self {
+        self.table = name.to_string();
+        self
+    }
+
+    pub fn create_sql(&self) -> String {
+        format!(
+            "create table if not exists {table}(
+                ancestor not null references {nodes}(id) on delete cascade,
+                descendant not null references {nodes}(id) on delete cascade,
+                depth integer not null,
+                primary key (ancestor, descendant)
+            ) without rowid;
+            create index if not exists {index} on {table}(descendant, depth);",
+            table = quote_ident(&self.table),
+            index = quote_ident(&format!("{}_descendant", self.table)),
+            nodes = quote_ident(&self.node_table),
+        )
+    }
+    pub fn create_table<E: Executor + ?Sized>(&self, exec: &E) -> rusqlite::Result<()> {
+        exec.run_batch(&self.create_sql())
+    }
+
+    /// Record `node` as a child of `parent`, or as a root node.
+    pub fn insert(&self, conn: &Connection, node: &I, parent: Option<&I>) -> rusqlite::Result<()> {
+        conn.execute(
+            &format!(
+                "insert into {}(ancestor, descendant, depth) values (?1, ?1, 0)",
+                quote_ident(&self.table)
+            ),
+            (node,),
+        )?;
+        if let Some(parent) = parent {
+            conn.execute(
+                &format!(
+                    "insert into {table}(ancestor, descendant, depth)
+                    select ancestor, ?1, depth + 1 from {table} where descendant = ?2",
+                    table = quote_ident(&self.table)
+                ),
+                (node, parent),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Move `node` and its subtree below `new_parent`, or make it a root.
+    pub fn move_subtree(
+        &self,
+        conn: &Connection,
+        node: &I,
+        new_parent: Option<&I>,
+    ) -> rusqlite::Result<()> {
+        // Detach the subtree from its current ancestors
+        conn.execute(
+            &format!(
+                "delete from {table}
+                where descendant in (select descendant from {table} where ancestor = ?1)
+                and ancestor not in (select descendant from {table} where ancestor = ?1)",
+                table = quote_ident(&self.table)
+            ),
+            (node,),
+        )?;
+        if let Some(parent) = new_parent {
+            conn.execute(
+                &format!(
+                    "insert into {table}(ancestor, descendant, depth)
+                    select super.ancestor, sub.descendant, super.depth + sub.depth + 1
+                    from {table} super cross join {table} sub
+                    where super.descendant = ?2 and sub.ancestor = ?1",
+                    table = quote_ident(&self.table)
+                ),
+                (node, parent),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Forget `node` and its subtree. The node rows themselves are untouched.
+    pub fn remove_subtree(&self, conn: &Connection, node: &I) -> rusqlite::Result<usize> {
+        conn.execute(
+            &format!(
+                "delete from {table}
+                where descendant in (select descendant from {table} where ancestor = ?1)",
+                table = quote_ident(&self.table)
+            ),
+            (node,),
+        )
+    }
+
+    pub fn parent(&self, conn: &Connection, node: &I) -> rusqlite::Result<Option<I>> {
+        conn.query_row(
+            &format!(
+                "select ancestor from {} where descendant = ? and depth = 1",
+                quote_ident(&self.table)
+            ),
+            (node,),
+            |row| row.get(0),
+        )
+        .optional()
+    }
+    /// Ancestors of `node`, root first, excluding the node itself.
+    pub fn ancestors(&self, conn: &Connection, node: &I) -> rusqlite::Result<Vec<I>> {
+        self.query_ids(
+            &format!(
+                "select ancestor from {} where descendant = ? and depth > 0
+                order by depth desc",
+                quote_ident(&self.table)
+            ),
+            conn,
+            node,
+        )
+    }
+    pub fn children(&self, conn: &Connection, node: &I) -> rusqlite::Result<Vec<I>> {
+        self.query_ids(
+            &format!(
+                "select descendant from {} where ancestor = ? and depth = 1
+                order by descendant",
+                quote_ident(&self.table)
+            ),
+            conn,
+            node,
+        )
+    }
+    /// Every node below `node` with its depth relative to it, breadth first.
+    pub fn descendants(&self, conn: &Connection, node: &I) -> rusqlite::Result<Vec<(I, i64)>> {
+        let mut stmt = conn.prepare(&format!(
+            "select descendant, depth from {} where ancestor = ? and depth > 0
+            order by depth, descendant",
+            quote_ident(&self.table)
+        ))?;
+        let descendants = stmt
+            .query_map((node,), |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect();
+        descendants
+    }
+    /// Ids of the nodes without a parent.
+    pub fn roots(&self, conn: &Connection) -> rusqlite::Result<Vec<I>> {
+        let mut stmt = conn.prepare(&format!(
+            "select descendant from {table} group by descendant
+            having max(depth) = 0 order by descendant",
+            table = quote_ident(&self.table)
+        ))?;
+        let roots = stmt.query_map((), |row| row.get(0))?.collect();
+        roots
+    }
+
+    fn query_ids(&self, sql: &str, conn: &Connection, node: &I) -> rusqlite::Result<Vec<I>> {
+        let mut stmt = conn.prepare(sql)?;
+        let ids = stmt.query_map((node,), |row| row.get(0))?.collect();
+        ids
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::IntegerId;
+
+    struct Category;
+    type CategoryId = IntegerId<Category>;
+
+    #[test]
+    fn materialized_path_roundtrip() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute("create table foo( path text ) strict", ())
+            .expect("failed to create table");
+
+        let path = MaterializedPath::root().child(1).child(4).child(9);
+        assert_eq!(path.to_string(), "/1/4/9/");
+        assert_eq!(path.parent(), Some(MaterializedPath::from(vec![1, 4])));
+        assert!(path.parent().unwrap().is_ancestor_of(&path));
+        assert!(!path.is_ancestor_of(&path));
+
+        let res = db.query_row(
+            "insert into foo(path) values (?) returning *",
+            (&path,),
+            |row| row.get::<_, MaterializedPath>("path"),
+        );
+        assert!(res.is_ok(), "Failed to retrieve path: {:?}", res);
+        assert_eq!(res.unwrap(), path);
+    }
+
+    #[test]
+    fn materialized_path_subtree_query() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute("create table foo( path text ) strict", ())
+            .expect("failed to create table");
+        for path in ["/1/", "/1/4/", "/1/4/9/", "/1/40/"] {
+            db.execute("insert into foo(path) values (?)", (path,))
+                .expect("failed to insert path");
+        }
+
+        let parent: MaterializedPath = "/1/4/".parse().unwrap();
+        let count: i64 = db
+            .query_row(
+                "select count(*) from foo where path like ?",
+                (parent.subtree_pattern(),),
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(count, 2, "Subtree should contain /1/4/ and /1/4/9/ only");
+        let descendants: i64 = db
+            .query_row(
+                "select count(*) from foo where path like ?1 and path != ?2",
+                (parent.subtree_pattern(), parent.to_string()),
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(descendants, 1, "Descendants should be /1/4/9/ only");
+    }
+
+    fn setup_closure() -> (Connection, ClosureTable<CategoryId>, Vec<CategoryId>) {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute("create table category( id integer primary key )", ())
+            .expect("failed to create table");
+        let closure = ClosureTable::<CategoryId>::new("category");
+        closure.create_table(&db).expect("failed to create closure");
+
+        // 1 -> 2 -> 3, 1 -> 4, 5
+        let ids: Vec<CategoryId> = (0..5)
+            .map(|_| {
+                db.query_row(
+                    "insert into category default values returning id",
+                    (),
+                    |row| row.get(0),
+                )
+                .unwrap()
+            })
+            .collect();
+        for (node, parent) in [
+            (0, None),
+            (1, Some(0)),
+            (2, Some(1)),
+            (3, Some(0)),
+            (4, None),
+        ] {
+            closure
+                .insert(&db, &ids[node], parent.map(|p| &ids[p]))
+                .expect("failed to insert node");
+        }
+        (db, closure, ids)
+    }
+
+    #[test]
+    fn closure_table_queries() {
+        let (db, closure, ids) = setup_closure();
+
+        assert_eq!(
+            closure.ancestors(&db, &ids[2]).unwrap(),
+            vec![ids[0], ids[1]]
+        );
+        assert_eq!(closure.parent(&db, &ids[2]).unwrap(), Some(ids[1]));
+        assert_eq!(closure.parent(&db, &ids[0]).unwrap(), None);
+        assert_eq!(
+            closure.children(&db, &ids[0]).unwrap(),
+            vec![ids[1], ids[3]]
+        );
+        assert_eq!(
+            closure.descendants(&db, &ids[0]).unwrap(),
+            vec![(ids[1], 1), (ids[3], 1), (ids[2], 2)]
+        );
+        assert_eq!(closure.roots(&db).unwrap(), vec![ids[0], ids[4]]);
+    }
+
+    #[test]
+    fn closure_table_move_and_remove() {
+        let (db, closure, ids) = setup_closure();
+
+        closure
+            .move_subtree(&db, &ids[1], Some(&ids[4]))
+            .expect("failed to move subtree");
+        assert_eq!(
+            closure.ancestors(&db, &ids[2]).unwrap(),
+            vec![ids[4], ids[1]]
+        );
+        assert_eq!(closure.children(&db, &ids[0]).unwrap(), vec![ids[3]]);
+
+        closure
+            .move_subtree(&db, &ids[1], None)
+            .expect("failed to move subtree");
+        assert_eq!(closure.roots(&db).unwrap(), vec![ids[0], ids[1], ids[4]]);
+
+        closure
+            .remove_subtree(&db, &ids[1])
+            .expect("failed to remove subtree");
+        assert!(closure.ancestors(&db, &ids[2]).unwrap().is_empty());
+        assert_eq!(closure.roots(&db).unwrap(), vec![ids[0], ids[4]]);
+    }
+}
@@ -0,0 +1,216 @@
+use crate::{
+    execute::Executor,
+    util::{quote_ident, split_queries},
+};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Timing {
+    Before,
+    After,
+    /// Only valid for triggers on views.
+    InsteadOf,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Event {
+    Insert,
+    Delete,
+    /// An update of any column, or of only the listed columns.
+    Update(Vec<String>),
+}
+
+/// A builder for `CREATE TRIGGER` statements. Names and columns are quoted;
+/// the `WHEN` condition and body statements are SQL, and may refer to the
+/// `new` and `old` rows.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Trigger {
+    name: String,
+    table: String,
+    timing: Timing,
+    event: Event,
+    temporary: bool,
+    when: Option<String>,
+    body: Vec<String>,
+}
+
+impl Trigger {
+    /// A trigger running after inserts into `table`; use the builder
+    /// methods to change the timing and event, and `then` to add the body.
+    pub fn new(name: &str, table: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            table: table.to_string(),
+            timing: Timing::After,
+            event: Event::Insert,
+            temporary: false,
+            when: None,
+            body: vec![],
+        }
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn before(mut self) -> Self {
+        self.timing = Timing::Before;
+        self
+    }
+    pub fn after(mut self) -> Self {
+        self.timing = Timing::After;
+        self
+    }
+    pub fn instead_of(mut self) -> Self {
+        self.timing = Timing::InsteadOf;
+        self
+    }
+    pub fn on_insert(mut self) -> Self {
+        self.event = Event::Insert;
+        self
+    }
+    pub fn on_delete(mut self) -> Self {
+        self.event = Event::Delete;
+        self
+    }
+    pub fn on_update(mut self) -> Self {
+        self.event = Event::Update(vec![]);
+        self
+    }
+    pub fn on_update_of(mut self, columns: &[&str]) -> Self {
+        self.event = Event::Update(columns.iter().map(|c| c.to_string()).collect());
+        self
+    }
+    /// Create the trigger in the temp schema, so it is dropped when the
+    /// connection closes.
+    pub fn temporary(mut self) -> Self {
+        self.temporary = true;
+        self
+    }
+    /// Only run the trigger for rows where `condition` holds.
+    pub fn when(mut self, condition: &str) -> Self {
+        self.when = Some(condition.to_string());
+        self
+    }
+    /// Append statements to the body. `sql` may contain several statements;
+    /// trailing semicolons are optional.
+    pub fn then(mut self, sql: &str) -> Self {
+        self.body.extend(split_queries(sql).map(|s| s.to_string()));
+        self
+    }
+
+    pub fn create_sql(&self) -> String {
+        let event = match &self.event {
+            Event::Insert => "insert".to_string(),
+            Event::Delete => "delete".to_string(),
+            Event::Update(columns) if columns.is_empty() => "update".to_string(),
+            Event::Update(columns) => format!(
+                "update of {}",
+                columns
+                    .iter()
+                    .map(|c| quote_ident(c))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        };
+        let timing = match self.timing {
+            Timing::Before => "before",
+            Timing::After => "after",
+            Timing::InsteadOf => "instead of",
+        };
+        let mut sql = format!(
+            "create {}trigger if not exists {} {} {} on {} for each row",
+            if self.temporary { "temp " } else { "" },
+            quote_ident(&self.name),
+            timing,
+            event,
+            quote_ident(&self.table)
+        );
+        if let Some(condition) = &self.when {
+            sql.push_str(&format!(" when {}", condition));
+        }
+        sql.push_str(" begin ");
+        for stmt in &self.body {
+            sql.push_str(stmt);
+            sql.push_str("; ");
+        }
+        sql.push_str("end");
+        sql
+    }
+    pub fn drop_sql(&self) -> String {
+        format!("drop trigger if exists {}", quote_ident(&self.name))
+    }
+
+    /// Create the trigger if it does not already exist.
+    pub fn create<E: Executor + ?Sized>(&self, exec: &E) -> rusqlite::Result<()> {
+        exec.run_batch(&self.create_sql())
+    }
+    /// Drop and recreate the trigger, replacing any existing definition.
+    pub fn replace<E: Executor + ?Sized>(&self, exec: &E) -> rusqlite::Result<()> {
+        exec.run_batch(&self.drop_sql())?;
+        self.create(exec)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use rusqlite::Connection;
+
+    use crate::migration::Migrations;
+
+    fn audited() -> Trigger {
+        Trigger::new("audit;name", "foo")
+            .after()
+            .on_update_of(&["a"])
+            .when("new.a <> old.a")
+            .then(
+                "insert into audit(msg) values ('a changed; was ' || old.a);
+                insert into audit(msg) values ('now ' || new.a);",
+            )
+    }
+
+    #[test]
+    fn render_trigger() {
+        assert_eq!(
+            audited().create_sql(),
+            "create trigger if not exists \"audit;name\" after update of \"a\" on \"foo\" \
+            for each row when new.a <> old.a begin \
+            insert into audit(msg) values ('a changed; was ' || old.a); \
+            insert into audit(msg) values ('now ' || new.a); end"
+        );
+    }
+
+    #[test]
+    fn trigger_migration() {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        let migrations = Migrations::new()
+            .add(
+                "create tables",
+                "create table foo( a integer ); create table audit( msg text );",
+            )
+            .add_trigger(&audited());
+
+        let plan = migrations.plan(&db).expect("Failed to plan migrations");
+        assert!(
+            plan.iter().any(|s| s.sql == audited().create_sql()),
+            "Trigger was split: {}",
+            plan
+        );
+
+        let res = migrations.apply(&db);
+        assert!(res.is_ok(), "Failed to apply migrations: {:?}", res);
+        db.execute_batch(
+            "insert into foo(a) values (1);
+            update foo set a = 1;
+            update foo set a = 2;",
+        )
+        .unwrap();
+        let mut stmt = db.prepare("select msg from audit").unwrap();
+        let messages = stmt
+            .query_map((), |row| row.get::<_, String>(0))
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(messages, vec!["a changed; was 1", "now 2"]);
+    }
+}
@@ -0,0 +1,160 @@
+use rusqlite::{Connection, ToSql};
+
+use crate::execute::Executor;
+
+/// A unit of work on a connection, in a savepoint which is released by
+/// `commit` and rolled back by `rollback` or when dropped. Units nest: a
+/// nested unit rolls back on its own, without undoing its parent's work,
+/// and its changes are only committed once every unit around it is.
+///
+/// A unit holds the only borrow of the connection (and a nested unit the
+/// only borrow of its parent), so the connection can't be used directly,
+/// nor a parent used under a nested unit, while the unit is active.
+#[derive(Debug)]
+pub struct UnitOfWork<'conn> {
+    conn: &'conn Connection,
+    depth: usize,
+    finished: bool,
+}
+
+impl<'conn> UnitOfWork<'conn> {
+    /// Begin a unit of work, in a transaction unless `conn` is already in
+    /// one.
+    pub fn begin(conn: &'conn mut Connection) -> rusqlite::Result<Self> {
+        Self::start(conn, 0)
+    }
+    fn start(conn: &'conn Connection, depth: usize) -> rusqlite::Result<Self> {
+        conn.execute_batch(&format!("savepoint {}", Self::savepoint(depth)))?;
+        Ok(Self {
+            conn,
+            depth,
+            finished: false,
+        })
+    }
+    fn savepoint(depth: usize) -> String {
+        format!("unit_of_work_{}", depth)
+    }
+
+    /// Begin a unit of work nested in this one.
+    pub fn nested(&mut self) -> rusqlite::Result<UnitOfWork<'_>> {
+        UnitOfWork::start(self.conn, self.depth + 1)
+    }
+    /// Run `f` in a nested unit of work, which is committed if it succeeds
+    /// and rolled back if it fails.
+    pub fn run_nested<T, E, F>(&mut self, f: F) -> Result<T, E>
+    where
+        E: From<rusqlite::Error>,
+        F: FnOnce(&mut UnitOfWork<'_>) -> Result<T, E>,
+    {
+        let mut unit = self.nested()?;
+        match f(&mut unit) {
+            Ok(value) => {
+                unit.commit()?;
+                Ok(value)
+            }
+            Err(e) => {
+                unit.rollback()?;
+                Err(e)
+            }
+        }
+    }
+
+    /// The number of units around this one.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn commit(mut self) -> rusqlite::Result<()> {
+        self.finished = true;
+        self.conn
+            .execute_batch(&format!("release {}", Self::savepoint(self.depth)))
+    }
+    pub fn rollback(mut self) -> rusqlite::Result<()> {
+        self.finished = true;
+        self.rollback_savepoint()
+    }
+    fn rollback_savepoint(&self) -> rusqlite::Result<()> {
+        self.conn.execute_batch(&format!(
+            "rollback to {name}; release {name}",
+            name = Self::savepoint(self.depth)
+        ))
+    }
+}
+
+impl Executor for UnitOfWork<'_> {
+    fn connection(&self) -> &Connection {
+        self.conn
+    }
+    fn run(&self, sql: &str, params: &[&dyn ToSql]) -> rusqlite::Result<usize> {
+        self.conn.execute(sql, params)
+    }
+    fn run_batch(&self, sql: &str) -> rusqlite::Result<()> {
+        self.conn.execute_batch(sql)
+    }
+}
+
+impl Drop for UnitOfWork<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            // Errors can't be reported here; a failed rollback leaves the
+            // savepoint to be rolled back with its parent.
+            let _ = self.rollback_savepoint();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn names(conn: &Connection) -> Vec<String> {
+        conn.prepare("select name from item order by name")
+            .unwrap()
+            .query_map((), |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn nested_units() {
+        let mut db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch("create table item( name text )")
+            .expect("Failed to create table");
+
+        let res = UnitOfWork::begin(&mut db);
+        assert!(res.is_ok(), "Failed to begin unit of work: {:?}", res);
+        let mut unit = res.unwrap();
+        unit.run_batch("insert into item values ('a')").unwrap();
+
+        let mut nested = unit.nested().unwrap();
+        assert_eq!(nested.depth(), 1);
+        nested.run_batch("insert into item values ('b')").unwrap();
+        nested
+            .run_nested(|inner| inner.run_batch("insert into item values ('c')"))
+            .unwrap();
+        nested.rollback().unwrap();
+
+        let res: rusqlite::Result<()> = unit.run_nested(|nested| {
+            nested.run_batch("insert into item values ('d')")?;
+            nested.run_batch("insert into missing values ('e')")
+        });
+        assert!(res.is_err());
+        unit.run_nested(|nested| nested.run_batch("insert into item values ('f')"))
+            .unwrap();
+        {
+            let dropped = unit.nested().unwrap();
+            dropped.run_batch("insert into item values ('g')").unwrap();
+        }
+        assert_eq!(names(unit.connection()), vec!["a", "f"]);
+        unit.commit().unwrap();
+
+        assert!(db.is_autocommit());
+        assert_eq!(names(&db), vec!["a", "f"]);
+
+        let unit = UnitOfWork::begin(&mut db).unwrap();
+        unit.run_batch("insert into item values ('h')").unwrap();
+        drop(unit);
+        assert_eq!(names(&db), vec!["a", "f"]);
+    }
+}
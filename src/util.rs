@@ -1,6 +1,314 @@
+use thiserror::Error;
+
 /// Split a string containing many SQL queries seperated by ';' into individual queries.
+///
+/// Semicolons inside string literals, quoted identifiers, comments and the
+/// body of a `CREATE TRIGGER` statement don't end a query. The body ends at
+/// the `END` matching its `BEGIN`, so `CASE ... END` expressions inside it
+/// don't end it early.
 pub fn split_queries(s: &str) -> impl Iterator<Item = &str> {
-    s.split(";").map(|s| s.trim()).filter(|s| s.len() > 0)
+    let bytes = s.as_bytes();
+    let mut queries = vec![];
+    let mut start = 0;
+    let mut i = 0;
+    // The first words of the current query, to detect `CREATE TRIGGER`.
+    let mut words: Vec<&str> = vec![];
+    // The number of `BEGIN` and `CASE` not yet closed by an `END`.
+    let mut depth = 0usize;
+
+    let skip_to = |from: usize, end: &[u8]| match s[from..].find(end_str(end)) {
+        Some(n) => from + n + end.len(),
+        None => bytes.len(),
+    };
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\'' | b'"' | b'`' => {
+                i = skip_to(i + 1, &bytes[i..i + 1]);
+            }
+            b'[' => {
+                i = skip_to(i + 1, b"]");
+            }
+            b'-' if bytes.get(i + 1) == Some(&b'-') => i = skip_to(i + 2, b"\n"),
+            b'/' if bytes.get(i + 1) == Some(&b'*') => i = skip_to(i + 2, b"*/"),
+            b';' => {
+                if !is_trigger(&words) || depth == 0 {
+                    queries.push(&s[start..i]);
+                    start = i + 1;
+                    words.clear();
+                    depth = 0;
+                }
+                i += 1;
+            }
+            c if c.is_ascii_alphanumeric() || c == b'_' => {
+                let end = bytes[i..]
+                    .iter()
+                    .position(|c| !(c.is_ascii_alphanumeric() || *c == b'_'))
+                    .map_or(bytes.len(), |n| i + n);
+                let word = &s[i..end];
+                if words.len() < 3 {
+                    words.push(word);
+                }
+                if word.eq_ignore_ascii_case("begin") || word.eq_ignore_ascii_case("case") {
+                    depth += 1;
+                } else if word.eq_ignore_ascii_case("end") {
+                    depth = depth.saturating_sub(1);
+                }
+                i = end;
+            }
+            _ => i += 1,
+        }
+    }
+    queries.push(&s[start..]);
+
+    queries
+        .into_iter()
+        .map(|s| s.trim())
+        .filter(|s| !is_blank(s))
+}
+
+fn end_str(end: &[u8]) -> &str {
+    std::str::from_utf8(end).expect("delimiters are ASCII")
+}
+
+/// Whether a query begins `CREATE [TEMP|TEMPORARY] TRIGGER`.
+fn is_trigger(words: &[&str]) -> bool {
+    match words {
+        [create, trigger, ..] if create.eq_ignore_ascii_case("create") => {
+            trigger.eq_ignore_ascii_case("trigger")
+                || ((trigger.eq_ignore_ascii_case("temp")
+                    || trigger.eq_ignore_ascii_case("temporary"))
+                    && words
+                        .get(2)
+                        .is_some_and(|w| w.eq_ignore_ascii_case("trigger")))
+        }
+        _ => false,
+    }
+}
+
+/// Whether a query contains only whitespace and comments.
+fn is_blank(s: &str) -> bool {
+    let mut rest = s.trim_start();
+    loop {
+        if let Some(comment) = rest.strip_prefix("--") {
+            rest = comment
+                .find('\n')
+                .map_or("", |n| &comment[n..])
+                .trim_start();
+        } else if let Some(comment) = rest.strip_prefix("/*") {
+            rest = comment
+                .find("*/")
+                .map_or("", |n| &comment[n + 2..])
+                .trim_start();
+        } else {
+            return rest.is_empty();
+        }
+    }
+}
+
+/// SQLite's keywords, which can only be used as identifiers when quoted.
+pub const KEYWORDS: [&str; 147] = [
+    "ABORT",
+    "ACTION",
+    "ADD",
+    "AFTER",
+    "ALL",
+    "ALTER",
+    "ALWAYS",
+    "ANALYZE",
+    "AND",
+    "AS",
+    "ASC",
+    "ATTACH",
+    "AUTOINCREMENT",
+    "BEFORE",
+    "BEGIN",
+    "BETWEEN",
+    "BY",
+    "CASCADE",
+    "CASE",
+    "CAST",
+    "CHECK",
+    "COLLATE",
+    "COLUMN",
+    "COMMIT",
+    "CONFLICT",
+    "CONSTRAINT",
+    "CREATE",
+    "CROSS",
+    "CURRENT",
+    "CURRENT_DATE",
+    "CURRENT_TIME",
+    "CURRENT_TIMESTAMP",
+    "DATABASE",
+    "DEFAULT",
+    "DEFERRABLE",
+    "DEFERRED",
+    "DELETE",
+    "DESC",
+    "DETACH",
+    "DISTINCT",
+    "DO",
+    "DROP",
+    "EACH",
+    "ELSE",
+    "END",
+    "ESCAPE",
+    "EXCEPT",
+    "EXCLUDE",
+    "EXCLUSIVE",
+    "EXISTS",
+    "EXPLAIN",
+    "FAIL",
+    "FILTER",
+    "FIRST",
+    "FOLLOWING",
+    "FOR",
+    "FOREIGN",
+    "FROM",
+    "FULL",
+    "GENERATED",
+    "GLOB",
+    "GROUP",
+    "GROUPS",
+    "HAVING",
+    "IF",
+    "IGNORE",
+    "IMMEDIATE",
+    "IN",
+    "INDEX",
+    "INDEXED",
+    "INITIALLY",
+    "INNER",
+    "INSERT",
+    "INSTEAD",
+    "INTERSECT",
+    "INTO",
+    "IS",
+    "ISNULL",
+    "JOIN",
+    "KEY",
+    "LAST",
+    "LEFT",
+    "LIKE",
+    "LIMIT",
+    "MATCH",
+    "MATERIALIZED",
+    "NATURAL",
+    "NO",
+    "NOT",
+    "NOTHING",
+    "NOTNULL",
+    "NULL",
+    "NULLS",
+    "OF",
+    "OFFSET",
+    "ON",
+    "OR",
+    "ORDER",
+    "OTHERS",
+    "OUTER",
+    "OVER",
+    "PARTITION",
+    "PLAN",
+    "PRAGMA",
+    "PRECEDING",
+    "PRIMARY",
+    "QUERY",
+    "RAISE",
+    "RANGE",
+    "RECURSIVE",
+    "REFERENCES",
+    "REGEXP",
+    "REINDEX",
+    "RELEASE",
+    "RENAME",
+    "REPLACE",
+    "RESTRICT",
+    "RETURNING",
+    "RIGHT",
+    "ROLLBACK",
+    "ROW",
+    "ROWS",
+    "SAVEPOINT",
+    "SELECT",
+    "SET",
+    "TABLE",
+    "TEMP",
+    "TEMPORARY",
+    "THEN",
+    "TIES",
+    "TO",
+    "TRANSACTION",
+    "TRIGGER",
+    "UNBOUNDED",
+    "UNION",
+    "UNIQUE",
+    "UPDATE",
+    "USING",
+    "VACUUM",
+    "VALUES",
+    "VIEW",
+    "VIRTUAL",
+    "WHEN",
+    "WHERE",
+    "WINDOW",
+    "WITH",
+    "WITHOUT",
+];
+
+/// Whether `word` is a SQLite keyword, ignoring case.
+pub fn is_keyword(word: &str) -> bool {
+    KEYWORDS.iter().any(|k| k.eq_ignore_ascii_case(word))
+}
+
+/// Whether `ident` must be quoted to be used as an identifier: it is a
+/// keyword, or contains anything but ASCII letters, digits and `_`, or starts
+/// with a digit.
+pub fn needs_quoting(ident: &str) -> bool {
+    let plain = ident
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && ident.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    !plain || is_keyword(ident)
+}
+
+/// Quote an identifier (eg a table name read from `sqlite_schema`) for use in
+/// SQL. Any identifier is safe once quoted, including keywords and names
+/// containing spaces or quotes.
+pub fn quote_ident(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+/// Check that `ident` can name a table, column, index, trigger or view.
+/// Quoting makes any other name safe to interpolate, so this only rejects
+/// names SQLite refuses even when quoted, and those likely to be mistakes.
+pub fn validate_ident(ident: &str) -> Result<(), InvalidIdentifier> {
+    let reason = if ident.is_empty() {
+        "is empty"
+    } else if ident.contains('\0') {
+        "contains a NUL character"
+    } else if ident
+        .get(..7)
+        .is_some_and(|p| p.eq_ignore_ascii_case("sqlite_"))
+    {
+        "uses the prefix reserved for SQLite's internal objects"
+    } else if ident.trim() != ident {
+        "has leading or trailing whitespace"
+    } else {
+        return Ok(());
+    };
+    Err(InvalidIdentifier {
+        ident: ident.to_string(),
+        reason,
+    })
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("Invalid identifier {ident:?}: {reason}")]
+pub struct InvalidIdentifier {
+    pub ident: String,
+    pub reason: &'static str,
 }
 
 #[cfg(test)]
@@ -10,6 +318,79 @@ mod test {
     #[test]
     fn split() {
         let foo = "hello; world;";
-        assert_eq!(split_queries(foo).collect::<Vec<_>>(), vec!["hello", "world"]);
+        assert_eq!(
+            split_queries(foo).collect::<Vec<_>>(),
+            vec!["hello", "world"]
+        );
+    }
+
+    #[test]
+    fn split_respects_quotes_comments_and_triggers() {
+        let script = "insert into foo(a) values ('a;b'); -- trailing; comment
+            select \"x;y\" from [a;b];
+            /* a ; comment */
+            create temp trigger t after insert on foo begin
+                insert into bar(a) values (new.a);
+                update baz set end_ = 1;
+            end;
+            select 1";
+        assert_eq!(
+            split_queries(script).collect::<Vec<_>>(),
+            vec![
+                "insert into foo(a) values ('a;b')",
+                "-- trailing; comment\n            select \"x;y\" from [a;b]",
+                "/* a ; comment */\n            create temp trigger t after insert on foo begin
+                insert into bar(a) values (new.a);
+                update baz set end_ = 1;
+            end",
+                "select 1"
+            ]
+        );
+    }
+
+    #[test]
+    fn split_trigger_with_case() {
+        let script = "create trigger t after insert on foo begin
+                update bar set a = case when new.a > 0 then 1 else 0 end;
+                insert into baz(a) values (case new.a when 1 then 'one' end);
+            end;
+            select case when 1 then 2 end;
+            select 3";
+        assert_eq!(
+            split_queries(script).collect::<Vec<_>>(),
+            vec![
+                "create trigger t after insert on foo begin
+                update bar set a = case when new.a > 0 then 1 else 0 end;
+                insert into baz(a) values (case new.a when 1 then 'one' end);
+            end",
+                "select case when 1 then 2 end",
+                "select 3"
+            ]
+        );
+    }
+
+    #[test]
+    fn quote_and_validate_identifiers() {
+        assert_eq!(quote_ident("order"), "\"order\"");
+        assert_eq!(quote_ident("my \"table\""), "\"my \"\"table\"\"\"");
+        assert!(is_keyword("Order") && !is_keyword("orders"));
+        assert!(needs_quoting("group"));
+        assert!(needs_quoting("first name"));
+        assert!(needs_quoting("1st"));
+        assert!(!needs_quoting("first_name"));
+
+        assert!(validate_ident("first name").is_ok());
+        for ident in ["", "a\0b", "sqlite_master", " padded"] {
+            assert!(validate_ident(ident).is_err(), "Accepted {:?}", ident);
+        }
+
+        let db = rusqlite::Connection::open_in_memory().expect("Failed to open connection");
+        for ident in ["order", "my \"table\"", "with space", "select"] {
+            let res = db.execute_batch(&format!(
+                "create table {t}( {t} ); insert into {t}({t}) values (1);",
+                t = quote_ident(ident)
+            ));
+            assert!(res.is_ok(), "Failed to use {:?}: {:?}", ident, res);
+        }
     }
 }
@@ -0,0 +1,181 @@
+use std::marker::PhantomData;
+
+use rusqlite::{Connection, Row};
+use thiserror::Error;
+
+use crate::{execute::Executor, row::Columns, util::quote_ident};
+
+/// A SQL view backing the read-model struct `T`. Creating the view checks
+/// that it has every column `T` reads, so the struct and the view's query
+/// can't silently drift apart.
+#[derive(Clone, Debug)]
+pub struct View<T> {
+    name: String,
+    select: String,
+    _marker: PhantomData<T>,
+}
+
+impl<T> View<T>
+where
+    for<'r, 'stmt> T: TryFrom<&'r Row<'stmt>, Error = rusqlite::Error> + Columns,
+{
+    /// A view `name` defined by the query `select`.
+    pub fn new(name: &str, select: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            select: select.trim().trim_end_matches(';').to_string(),
+            _marker: PhantomData,
+        }
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn create_sql(&self) -> String {
+        format!(
+            "create view if not exists {} as {}",
+            quote_ident(&self.name),
+            self.select
+        )
+    }
+    pub fn drop_sql(&self) -> String {
+        format!("drop view if exists {}", quote_ident(&self.name))
+    }
+
+    /// Create the view if it does not already exist, and validate it.
+    pub fn create<E: Executor + ?Sized>(&self, exec: &E) -> Result<(), Error> {
+        exec.run_batch(&self.create_sql())?;
+        if exec.is_dry_run() {
+            return Ok(());
+        }
+        self.validate(exec.connection())
+    }
+    /// Drop and recreate the view with the current definition. If the new
+    /// definition doesn't match `T`, the previous view is left in place.
+    pub fn replace<E: Executor + ?Sized>(&self, exec: &E) -> Result<(), Error> {
+        exec.run_batch("savepoint replace_view")?;
+        let res = exec
+            .run_batch(&self.drop_sql())
+            .map_err(Error::from)
+            .and_then(|_| self.create(exec));
+        match res {
+            Ok(()) => exec.run_batch("release replace_view")?,
+            Err(_) => exec.run_batch("rollback to replace_view; release replace_view")?,
+        }
+        res
+    }
+
+    /// Check that the view has every column `T` reads.
+    pub fn validate(&self, conn: &Connection) -> Result<(), Error> {
+        let stmt = conn.prepare(&format!("select * from {}", quote_ident(&self.name)))?;
+        let actual = stmt.column_names();
+        let missing: Vec<_> = T::COLUMNS
+            .iter()
+            .filter(|c| !actual.iter().any(|a| a.eq_ignore_ascii_case(c)))
+            .map(|c| c.to_string())
+            .collect();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::MissingColumns {
+                view: self.name.clone(),
+                columns: missing,
+            })
+        }
+    }
+
+    /// All rows of the view.
+    pub fn all(&self, conn: &Connection) -> rusqlite::Result<Vec<T>> {
+        let mut stmt = conn.prepare(&format!("select * from {}", quote_ident(&self.name)))?;
+        let rows = stmt.query_map((), |row| T::try_from(row))?.collect();
+        rows
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("View {view} is missing columns {columns:?}")]
+    MissingColumns { view: String, columns: Vec<String> },
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::TryFromRow;
+
+    #[derive(TryFromRow, Debug, PartialEq)]
+    struct Total {
+        customer: String,
+        total: i64,
+    }
+
+    fn setup() -> Connection {
+        let db = Connection::open_in_memory().expect("Failed to open connection");
+        db.execute_batch(
+            "create table orders( customer text, amount integer );
+            insert into orders values ('a', 1), ('a', 2), ('b', 5);",
+        )
+        .expect("Failed to set up database");
+        db
+    }
+
+    #[test]
+    fn create_and_query_view() {
+        let db = setup();
+        let view: View<Total> = View::new(
+            "totals",
+            "select customer, sum(amount) as total from orders group by customer",
+        );
+        let res = view.create(&db);
+        assert!(res.is_ok(), "Failed to create view: {:?}", res);
+        assert!(
+            view.create(&db).is_ok(),
+            "Creating the view is not idempotent"
+        );
+
+        let res = view.all(&db);
+        assert!(res.is_ok(), "Failed to query view: {:?}", res);
+        assert_eq!(
+            res.unwrap(),
+            vec![
+                Total {
+                    customer: "a".into(),
+                    total: 3
+                },
+                Total {
+                    customer: "b".into(),
+                    total: 5
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn mismatched_view_is_rejected() {
+        let db = setup();
+        View::<Total>::new(
+            "totals",
+            "select customer, sum(amount) as total from orders group by customer",
+        )
+        .create(&db)
+        .expect("Failed to create view");
+
+        let res = View::<Total>::new(
+            "totals",
+            "select customer, sum(amount) as amount from orders group by customer",
+        )
+        .replace(&db);
+        assert!(
+            matches!(&res, Err(Error::MissingColumns { columns, .. }) if columns == &["total"]),
+            "Expected a missing column: {:?}",
+            res
+        );
+        let res = db.query_row("select total from totals where customer = 'b'", (), |row| {
+            row.get::<_, i64>(0)
+        });
+        assert_eq!(res.ok(), Some(5), "Previous view was not restored");
+    }
+}